@@ -0,0 +1,373 @@
+//! Tree-walking evaluator: every node maps an input collection to an output
+//! collection.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+use super::functions;
+use super::parser::{BinOp, Node, TypeOp};
+use super::value::{self, choice_type, Decimal, Item, Quantity, Temporal};
+use super::{Context, EvalError};
+
+pub(super) type Collection<'a> = Vec<Item<'a>>;
+
+/// What `$this`, `$index` and `$total` are bound to.
+#[derive(Debug, Clone)]
+pub(super) struct Frame<'a> {
+    pub this: Collection<'a>,
+    pub index: Option<usize>,
+    pub total: Option<Collection<'a>>,
+}
+
+pub(super) struct Evaluator<'c, 'a> {
+    pub ctx: &'c Context<'a>,
+}
+
+impl<'a> Evaluator<'_, 'a> {
+    pub fn eval(
+        &self,
+        node: &Node,
+        input: &[Item<'a>],
+        frame: &Frame<'a>,
+    ) -> Result<Collection<'a>, EvalError> {
+        let literal = |parsed: Option<Item<'a>>, text: &str| {
+            parsed.map(|item| vec![item]).ok_or_else(|| EvalError(format!("invalid literal {text}")))
+        };
+        match node {
+            Node::Empty => Ok(Vec::new()),
+            Node::Boolean(b) => Ok(vec![Item::Boolean(*b)]),
+            Node::String(s) => Ok(vec![Item::String(s.clone())]),
+            Node::Integer(i) => Ok(vec![Item::Integer(*i)]),
+            Node::Decimal(s) => literal(Decimal::parse(s).map(Item::Decimal), s),
+            Node::Date(s) => literal(Temporal::parse_date(s).map(Item::Date), s),
+            Node::DateTime(s) => literal(Temporal::parse_date_time(s).map(Item::DateTime), s),
+            Node::Time(s) => literal(Temporal::parse_time(s).map(Item::Time), s),
+            Node::Quantity(v, unit) => {
+                literal(Decimal::parse(v).map(|d| Item::Quantity(Quantity::new(d, unit))), v)
+            }
+            Node::Identifier(name) => Ok(navigate(input, name)),
+            Node::Member(focus, name) => Ok(navigate(&self.eval(focus, input, frame)?, name)),
+            Node::Variable(name) => self
+                .ctx
+                .variable(name)
+                .ok_or_else(|| EvalError(format!("unknown variable %{name}"))),
+            Node::This => Ok(frame.this.clone()),
+            Node::Index => Ok(frame.index.map(|i| vec![Item::Integer(i as i64)]).unwrap_or_default()),
+            Node::Total => Ok(frame.total.clone().unwrap_or_default()),
+            Node::Type(name) => Err(EvalError(format!("type name {name} used as a value"))),
+            Node::Call { focus, name, args } => match focus {
+                Some(focus) => {
+                    let input = self.eval(focus, input, frame)?;
+                    functions::call(self, name, input, true, args, frame)
+                }
+                None => functions::call(self, name, input.to_vec(), false, args, frame),
+            },
+            Node::Indexer(focus, index) => {
+                let items = self.eval(focus, input, frame)?;
+                let index = self.eval(index, &frame.this, frame)?;
+                match singleton(&index, "indexer")?.map(|i| i.to_system()) {
+                    None => Ok(Vec::new()),
+                    Some(Item::Integer(i)) => {
+                        Ok(usize::try_from(i).ok().and_then(|i| items.into_iter().nth(i)).into_iter().collect())
+                    }
+                    Some(_) => Err(EvalError("an indexer must be an Integer".into())),
+                }
+            }
+            Node::Negate(operand) => {
+                let operand = self.eval(operand, input, frame)?;
+                let Some(item) = singleton(&operand, "unary -")? else { return Ok(Vec::new()) };
+                let negated = match item.to_system() {
+                    Item::Integer(i) => Item::Integer(-i),
+                    Item::Decimal(d) => Item::Decimal(d.negated()),
+                    Item::Quantity(q) => Item::Quantity(Quantity { value: q.value.negated(), unit: q.unit }),
+                    other => {
+                        return Err(EvalError(format!("cannot negate a {}", other.type_name().1)));
+                    }
+                };
+                Ok(vec![negated])
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, input, frame)?;
+                let rhs = self.eval(rhs, input, frame)?;
+                binary(*op, lhs, rhs)
+            }
+            Node::TypeOp(op, operand, ty) => {
+                let items = self.eval(operand, input, frame)?;
+                match op {
+                    TypeOp::Is => Ok(singleton(&items, "is")?
+                        .map(|item| vec![Item::Boolean(item.is_type(ty))])
+                        .unwrap_or_default()),
+                    // `as` filters rather than failing on several items: base
+                    // search parameters apply it to repeating elements, e.g.
+                    // `Observation.component.value as Quantity`.
+                    TypeOp::As => Ok(items.into_iter().filter(|item| item.is_type(ty)).collect()),
+                }
+            }
+        }
+    }
+}
+
+/// The single item of a collection, `None` when it is empty, or an error
+/// naming `what` when there are several.
+pub(super) fn singleton<'i, 'a>(
+    items: &'i [Item<'a>],
+    what: &str,
+) -> Result<Option<&'i Item<'a>>, EvalError> {
+    match items {
+        [] => Ok(None),
+        [item] => Ok(Some(item)),
+        _ => Err(EvalError(format!("{what} expects a single item, got {}", items.len()))),
+    }
+}
+
+/// Singleton evaluation as a Boolean: a Boolean item is itself, any other
+/// single item is `true`, and an empty collection is unknown.
+pub(super) fn to_bool(items: &[Item], what: &str) -> Result<Option<bool>, EvalError> {
+    Ok(singleton(items, what)?.map(|item| item.as_boolean().unwrap_or(true)))
+}
+
+/// Member access, flattening arrays. A type name matching a resource's type
+/// selects the resource itself (`Patient.name`); a choice element is reached
+/// by its base name (`Observation.value` finds `valueQuantity`).
+pub(super) fn navigate<'a>(items: &[Item<'a>], name: &str) -> Collection<'a> {
+    let mut out = Vec::new();
+    for item in items {
+        let Item::Element(element) = item else { continue };
+        if name.starts_with(char::is_uppercase)
+            && element.value.get("resourceType").and_then(Value::as_str) == Some(name)
+        {
+            out.push(item.clone());
+            continue;
+        }
+        match &element.value {
+            Cow::Borrowed(v) => out.extend(
+                children(v, name).into_iter().map(|(c, ty)| Item::typed_element(Cow::Borrowed(c), ty)),
+            ),
+            Cow::Owned(v) => out.extend(
+                children(v, name)
+                    .into_iter()
+                    .map(|(c, ty)| Item::typed_element(Cow::Owned(c.clone()), ty)),
+            ),
+        }
+    }
+    out
+}
+
+fn children<'v>(value: &'v Value, name: &str) -> Vec<(&'v Value, Option<String>)> {
+    let Some(object) = value.as_object() else { return Vec::new() };
+    let (found, type_name) = match object.get(name) {
+        Some(child) => (child, None),
+        None => {
+            let choice = object.iter().find_map(|(key, child)| {
+                key.strip_prefix(name).and_then(choice_type).map(|ty| (child, Some(ty)))
+            });
+            match choice {
+                Some(choice) => choice,
+                None => return Vec::new(),
+            }
+        }
+    };
+    match found {
+        Value::Array(items) => items.iter().map(|c| (c, type_name.clone())).collect(),
+        Value::Null => Vec::new(),
+        child => vec![(child, type_name)],
+    }
+}
+
+/// All child nodes of an element, for `children()`.
+pub(super) fn all_children<'a>(item: &Item<'a>) -> Collection<'a> {
+    let Item::Element(element) = item else { return Vec::new() };
+    let Some(object) = element.value.as_object() else { return Vec::new() };
+    let keys: Vec<&String> = object.keys().filter(|k| *k != "resourceType" && !k.starts_with('_')).collect();
+    keys.into_iter().flat_map(|key| navigate(std::slice::from_ref(item), key)).collect()
+}
+
+pub(super) fn contains_item(items: &[Item], item: &Item) -> bool {
+    items.iter().any(|other| value::equals(other, item) == Some(true))
+}
+
+pub(super) fn distinct<'a>(items: Collection<'a>) -> Collection<'a> {
+    let mut out: Collection<'a> = Vec::new();
+    for item in items {
+        if !contains_item(&out, &item) {
+            out.push(item);
+        }
+    }
+    out
+}
+
+fn boolean<'a>(b: Option<bool>) -> Collection<'a> {
+    b.map(|b| vec![Item::Boolean(b)]).unwrap_or_default()
+}
+
+fn binary<'a>(op: BinOp, lhs: Collection<'a>, rhs: Collection<'a>) -> Result<Collection<'a>, EvalError> {
+    match op {
+        BinOp::And | BinOp::Or | BinOp::Xor | BinOp::Implies => {
+            let (a, b) = (to_bool(&lhs, "a boolean operator")?, to_bool(&rhs, "a boolean operator")?);
+            Ok(boolean(match op {
+                BinOp::And => match (a, b) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                },
+                BinOp::Or => match (a, b) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+                BinOp::Xor => a.zip(b).map(|(a, b)| a != b),
+                _ => match (a, b) {
+                    (Some(false), _) | (_, Some(true)) => Some(true),
+                    (Some(true), Some(false)) => Some(false),
+                    _ => None,
+                },
+            }))
+        }
+        BinOp::Union => Ok(distinct(lhs.into_iter().chain(rhs).collect())),
+        BinOp::Eq | BinOp::Ne => {
+            if lhs.is_empty() || rhs.is_empty() {
+                return Ok(Vec::new());
+            }
+            let equal = if lhs.len() != rhs.len() {
+                Some(false)
+            } else {
+                lhs.iter().zip(&rhs).try_fold(true, |all, (a, b)| Some(all && value::equals(a, b)?))
+            };
+            Ok(boolean(equal.map(|e| e == (op == BinOp::Eq))))
+        }
+        BinOp::Equiv | BinOp::NotEquiv => {
+            let mut unmatched: Vec<&Item> = rhs.iter().collect();
+            let equivalent = lhs.len() == rhs.len()
+                && lhs.iter().all(|a| match unmatched.iter().position(|b| value::equivalent(a, b)) {
+                    Some(i) => {
+                        unmatched.swap_remove(i);
+                        true
+                    }
+                    None => false,
+                });
+            Ok(vec![Item::Boolean(equivalent == (op == BinOp::Equiv))])
+        }
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+            let (Some(a), Some(b)) = (singleton(&lhs, "a comparison")?, singleton(&rhs, "a comparison")?)
+            else {
+                return Ok(Vec::new());
+            };
+            let ordering = value::compare(a, b).map_err(EvalError)?;
+            Ok(boolean(ordering.map(|o| match op {
+                BinOp::Lt => o == Ordering::Less,
+                BinOp::Le => o != Ordering::Greater,
+                BinOp::Gt => o == Ordering::Greater,
+                _ => o != Ordering::Less,
+            })))
+        }
+        BinOp::In | BinOp::Contains => {
+            let (element, collection) = if op == BinOp::In { (&lhs, &rhs) } else { (&rhs, &lhs) };
+            match singleton(element, "in/contains")? {
+                None => Ok(Vec::new()),
+                Some(item) => Ok(vec![Item::Boolean(contains_item(collection, item))]),
+            }
+        }
+        BinOp::Concat => {
+            let text = |items: &[Item], what| -> Result<String, EvalError> {
+                Ok(match singleton(items, what)?.map(Item::to_system) {
+                    None => String::new(),
+                    Some(Item::String(s)) => s,
+                    Some(other) => {
+                        return Err(EvalError(format!("& expects strings, got {}", other.type_name().1)));
+                    }
+                })
+            };
+            Ok(vec![Item::String(text(&lhs, "&")? + &text(&rhs, "&")?)])
+        }
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::IntDiv | BinOp::Mod => {
+            let (Some(a), Some(b)) = (singleton(&lhs, "arithmetic")?, singleton(&rhs, "arithmetic")?)
+            else {
+                return Ok(Vec::new());
+            };
+            // A date element is a plain string until it meets a duration.
+            let a = match (a.to_system(), b.to_system()) {
+                (Item::String(s), Item::Quantity(_)) => value::date_from_string(&s).unwrap_or(Item::String(s)),
+                (a, _) => a,
+            };
+            arithmetic(op, a, b.to_system()).map(|item| item.into_iter().collect())
+        }
+    }
+}
+
+/// One arithmetic operation; `Ok(None)` for an empty result (division by
+/// zero, overflow, incompatible units).
+fn arithmetic<'a>(op: BinOp, a: Item<'a>, b: Item<'a>) -> Result<Option<Item<'a>>, EvalError> {
+    let result = match (op, &a, &b) {
+        (BinOp::Add, Item::String(x), Item::String(y)) => Some(Item::String(format!("{x}{y}"))),
+        (BinOp::Add, Item::Integer(x), Item::Integer(y)) => x.checked_add(*y).map(Item::Integer),
+        (BinOp::Sub, Item::Integer(x), Item::Integer(y)) => x.checked_sub(*y).map(Item::Integer),
+        (BinOp::Mul, Item::Integer(x), Item::Integer(y)) => x.checked_mul(*y).map(Item::Integer),
+        (BinOp::IntDiv, Item::Integer(x), Item::Integer(y)) => x.checked_div(*y).map(Item::Integer),
+        (BinOp::Mod, Item::Integer(x), Item::Integer(y)) => x.checked_rem(*y).map(Item::Integer),
+        (_, Item::Integer(_) | Item::Decimal(_), Item::Integer(_) | Item::Decimal(_)) => {
+            let (Some(x), Some(y)) = (value::to_decimal(&a), value::to_decimal(&b)) else { return Ok(None) };
+            match op {
+                BinOp::Add => x.checked_add(y).map(Item::Decimal),
+                BinOp::Sub => x.checked_sub(y).map(Item::Decimal),
+                BinOp::Mul => x.checked_mul(y).map(Item::Decimal),
+                BinOp::Div => x.checked_div(y).map(Item::Decimal),
+                BinOp::IntDiv => x.int_div(y).and_then(|d| d.to_i64()).map(Item::Integer),
+                _ => x.checked_rem(y).map(Item::Decimal),
+            }
+        }
+        (BinOp::Add | BinOp::Sub, Item::Date(t) | Item::DateTime(t) | Item::Time(t), Item::Quantity(q)) => {
+            let unit = q.calendar_unit().ok_or_else(|| {
+                EvalError(format!("cannot add '{}' to a date or time", q.unit))
+            })?;
+            let amount = if op == BinOp::Sub { q.value.negated() } else { q.value };
+            t.add(amount, unit).map(|t| match &a {
+                Item::Date(_) => Item::Date(t),
+                Item::DateTime(_) => Item::DateTime(t),
+                _ => Item::Time(t),
+            })
+        }
+        (BinOp::Add | BinOp::Sub, Item::Quantity(x), Item::Quantity(y)) if x.unit == y.unit => {
+            let value = if op == BinOp::Add {
+                x.value.checked_add(y.value)
+            } else {
+                x.value.checked_sub(y.value)
+            };
+            value.map(|v| Item::Quantity(Quantity { value: v, unit: x.unit.clone() }))
+        }
+        (BinOp::Add | BinOp::Sub, Item::Quantity(_), Item::Quantity(_)) => None,
+        (BinOp::Mul | BinOp::Div, Item::Quantity(x), Item::Integer(_) | Item::Decimal(_)) => {
+            let y = value::to_decimal(&b).unwrap_or(Decimal::from_i64(1));
+            let value = if op == BinOp::Mul { x.value.checked_mul(y) } else { x.value.checked_div(y) };
+            value.map(|v| Item::Quantity(Quantity { value: v, unit: x.unit.clone() }))
+        }
+        (BinOp::Mul, Item::Integer(_) | Item::Decimal(_), Item::Quantity(y)) => {
+            let x = value::to_decimal(&a).unwrap_or(Decimal::from_i64(1));
+            x.checked_mul(y.value).map(|v| Item::Quantity(Quantity { value: v, unit: y.unit.clone() }))
+        }
+        (BinOp::Mul, Item::Quantity(x), Item::Quantity(y)) => x.value.checked_mul(y.value).map(|v| {
+            let unit = match (x.unit.as_str(), y.unit.as_str()) {
+                ("1", u) | (u, "1") => u.to_string(),
+                (u, w) => format!("{u}.{w}"),
+            };
+            Item::Quantity(Quantity { value: v, unit })
+        }),
+        (BinOp::Div, Item::Quantity(x), Item::Quantity(y)) => x.value.checked_div(y.value).map(|v| {
+            let unit = match (x.unit.as_str(), y.unit.as_str()) {
+                (u, w) if u == w => "1".to_string(),
+                (u, "1") => u.to_string(),
+                (u, w) => format!("{u}/{w}"),
+            };
+            Item::Quantity(Quantity { value: v, unit })
+        }),
+        _ => {
+            return Err(EvalError(format!(
+                "operator {op:?} is not defined for {} and {}",
+                a.type_name().1,
+                b.type_name().1
+            )));
+        }
+    };
+    Ok(result)
+}
@@ -0,0 +1,586 @@
+//! The FHIRPath function library, plus the FHIR additions `extension()`,
+//! `hasValue()`, `getValue()` and `resolve()`.
+
+use std::borrow::Cow;
+
+use serde_json::{json, Value};
+
+use super::eval::{all_children, contains_item, distinct, singleton, to_bool, Collection, Evaluator, Frame};
+use super::parser::Node;
+use super::value::{Decimal, Item, Quantity, Temporal};
+use super::{Context, EvalError, ParseError};
+
+/// Reject unknown functions and wrong argument counts at parse time.
+pub(super) fn check_arity(name: &str, count: usize) -> Result<(), ParseError> {
+    let (min, max) = match name {
+        "empty" | "count" | "distinct" | "isDistinct" | "allTrue" | "anyTrue" | "allFalse"
+        | "anyFalse" | "single" | "first" | "last" | "tail" | "not" | "toBoolean"
+        | "convertsToBoolean" | "toInteger" | "convertsToInteger" | "toDecimal"
+        | "convertsToDecimal" | "toString" | "convertsToString" | "toDate" | "convertsToDate"
+        | "toDateTime" | "convertsToDateTime" | "toTime" | "convertsToTime" | "upper" | "lower"
+        | "length" | "toChars" | "trim" | "abs" | "ceiling" | "exp" | "floor" | "ln" | "sqrt"
+        | "truncate" | "children" | "descendants" | "now" | "timeOfDay" | "today" | "hasValue"
+        | "getValue" | "resolve" => (0, 0),
+        "exists" | "round" | "toQuantity" | "convertsToQuantity" | "join" => (0, 1),
+        "all" | "where" | "select" | "repeat" | "ofType" | "is" | "as" | "skip" | "take"
+        | "intersect" | "exclude" | "union" | "combine" | "subsetOf" | "supersetOf" | "indexOf"
+        | "lastIndexOf" | "startsWith" | "endsWith" | "contains" | "matches" | "split" | "log"
+        | "power" | "extension" => (1, 1),
+        "substring" | "trace" | "aggregate" => (1, 2),
+        "replace" | "replaceMatches" => (2, 2),
+        "iif" => (2, 3),
+        _ => return Err(ParseError(format!("unknown function '{name}()'"))),
+    };
+    if count < min || count > max {
+        let expected = if min == max { min.to_string() } else { format!("{min} to {max}") };
+        return Err(ParseError(format!(
+            "{name}() takes {expected} argument(s), got {count}"
+        )));
+    }
+    Ok(())
+}
+
+/// Call `name` on `input`. `focused` tells whether the call had an explicit
+/// focus (`x.iif(...)`) rather than applying to the current input.
+pub(super) fn call<'a>(
+    ev: &Evaluator<'_, 'a>,
+    name: &str,
+    input: Collection<'a>,
+    focused: bool,
+    args: &[Node],
+    frame: &Frame<'a>,
+) -> Result<Collection<'a>, EvalError> {
+    // Argument helpers: plain arguments are evaluated against `$this`;
+    // criteria and projections once per input item.
+    let arg = |i: usize| ev.eval(&args[i], &frame.this, frame);
+    let per_item = |item: &Item<'a>, index: usize, expr: &Node| {
+        let frame = Frame { this: vec![item.clone()], index: Some(index), total: frame.total.clone() };
+        ev.eval(expr, &frame.this, &frame)
+    };
+    let type_arg = || match &args[0] {
+        Node::Type(name) => Ok(name.as_str()),
+        _ => Err(EvalError(format!("{name}() expects a type name"))),
+    };
+    let one = |b: bool| Ok(vec![Item::Boolean(b)]);
+
+    match name {
+        // Existence
+        "empty" => one(input.is_empty()),
+        "exists" => match args.first() {
+            None => one(!input.is_empty()),
+            Some(criteria) => one(!filter(&input, criteria, &per_item)?.is_empty()),
+        },
+        "all" => {
+            for (i, item) in input.iter().enumerate() {
+                if to_bool(&per_item(item, i, &args[0])?, "all()")? != Some(true) {
+                    return one(false);
+                }
+            }
+            one(true)
+        }
+        "allTrue" | "anyTrue" | "allFalse" | "anyFalse" => {
+            let mut values = Vec::new();
+            for item in &input {
+                values.push(
+                    item.as_boolean()
+                        .ok_or_else(|| EvalError(format!("{name}() expects Booleans")))?,
+                );
+            }
+            one(match name {
+                "allTrue" => values.iter().all(|b| *b),
+                "anyTrue" => values.iter().any(|b| *b),
+                "allFalse" => values.iter().all(|b| !b),
+                _ => values.iter().any(|b| !b),
+            })
+        }
+        "subsetOf" => {
+            let other = arg(0)?;
+            one(input.iter().all(|item| contains_item(&other, item)))
+        }
+        "supersetOf" => {
+            let other = arg(0)?;
+            one(other.iter().all(|item| contains_item(&input, item)))
+        }
+        "count" => Ok(vec![Item::Integer(input.len() as i64)]),
+        "distinct" => Ok(distinct(input)),
+        "isDistinct" => {
+            let len = input.len();
+            one(distinct(input).len() == len)
+        }
+
+        // Filtering and projection
+        "where" => filter(&input, &args[0], &per_item),
+        "select" => {
+            let mut out = Vec::new();
+            for (i, item) in input.iter().enumerate() {
+                out.extend(per_item(item, i, &args[0])?);
+            }
+            Ok(out)
+        }
+        "repeat" => {
+            let mut out: Collection<'a> = Vec::new();
+            let mut pending = input;
+            while !pending.is_empty() {
+                let mut next = Vec::new();
+                for (i, item) in pending.iter().enumerate() {
+                    for found in per_item(item, i, &args[0])? {
+                        if !contains_item(&out, &found) {
+                            out.push(found.clone());
+                            next.push(found);
+                        }
+                    }
+                }
+                pending = next;
+            }
+            Ok(out)
+        }
+        "ofType" => {
+            let ty = type_arg()?;
+            Ok(input.into_iter().filter(|item| item.is_type(ty)).collect())
+        }
+
+        // Subsetting
+        "single" => Ok(singleton(&input, "single()")?.cloned().into_iter().collect()),
+        "first" => Ok(input.into_iter().take(1).collect()),
+        "last" => Ok(input.into_iter().last().into_iter().collect()),
+        "tail" => Ok(input.into_iter().skip(1).collect()),
+        "skip" | "take" => {
+            let n = integer_arg(&arg(0)?, name)?.unwrap_or(0).max(0) as usize;
+            Ok(if name == "skip" {
+                input.into_iter().skip(n).collect()
+            } else {
+                input.into_iter().take(n).collect()
+            })
+        }
+        "intersect" => {
+            let other = arg(0)?;
+            Ok(distinct(input.into_iter().filter(|item| contains_item(&other, item)).collect()))
+        }
+        "exclude" => {
+            let other = arg(0)?;
+            Ok(input.into_iter().filter(|item| !contains_item(&other, item)).collect())
+        }
+
+        // Combining
+        "union" => Ok(distinct(input.into_iter().chain(arg(0)?).collect())),
+        "combine" => Ok(input.into_iter().chain(arg(0)?).collect()),
+
+        // Conversion
+        "iif" => {
+            let frame = if focused {
+                if input.len() > 1 {
+                    return Err(EvalError("iif() expects a single item".into()));
+                }
+                Frame { this: input.clone(), index: None, total: frame.total.clone() }
+            } else {
+                frame.clone()
+            };
+            let branch = match to_bool(&ev.eval(&args[0], &frame.this, &frame)?, "iif()")? {
+                Some(true) => &args[1],
+                _ => match args.get(2) {
+                    Some(otherwise) => otherwise,
+                    None => return Ok(Vec::new()),
+                },
+            };
+            ev.eval(branch, &frame.this, &frame)
+        }
+        "toBoolean" | "toInteger" | "toDecimal" | "toString" | "toDate" | "toDateTime"
+        | "toTime" | "toQuantity" => {
+            let Some(item) = singleton(&input, name)? else { return Ok(Vec::new()) };
+            let unit = match args.first() {
+                Some(_) => string_arg(&arg(0)?, name)?,
+                None => None,
+            };
+            Ok(convert(name, item, unit.as_deref()).into_iter().collect())
+        }
+        "convertsToBoolean" | "convertsToInteger" | "convertsToDecimal" | "convertsToString"
+        | "convertsToDate" | "convertsToDateTime" | "convertsToTime" | "convertsToQuantity" => {
+            let Some(item) = singleton(&input, name)? else { return Ok(Vec::new()) };
+            let unit = match args.first() {
+                Some(_) => string_arg(&arg(0)?, name)?,
+                None => None,
+            };
+            let target = format!("to{}", &name["convertsTo".len()..]);
+            one(convert(&target, item, unit.as_deref()).is_some())
+        }
+
+        // Strings
+        "indexOf" | "lastIndexOf" | "startsWith" | "endsWith" | "contains" | "matches" | "split"
+        | "substring" | "replace" | "replaceMatches" | "upper" | "lower" | "length" | "toChars"
+        | "trim" => {
+            let Some(s) = string_input(&input, name)? else { return Ok(Vec::new()) };
+            let mut strings = Vec::new();
+            for i in 0..args.len() {
+                strings.push(arg(i)?);
+            }
+            string_function(name, &s, &strings)
+        }
+        "join" => {
+            let separator = match args.first() {
+                Some(_) => string_arg(&arg(0)?, name)?.unwrap_or_default(),
+                None => String::new(),
+            };
+            let mut parts = Vec::new();
+            for item in &input {
+                match item.to_system() {
+                    Item::String(s) => parts.push(s),
+                    _ => return Err(EvalError("join() expects strings".into())),
+                }
+            }
+            Ok(vec![Item::String(parts.join(&separator))])
+        }
+
+        // Math
+        "abs" | "ceiling" | "exp" | "floor" | "ln" | "sqrt" | "truncate" | "round" | "log"
+        | "power" => {
+            let Some(item) = singleton(&input, name)? else { return Ok(Vec::new()) };
+            let operand = match args.first() {
+                Some(_) => match singleton(&arg(0)?, name)? {
+                    Some(item) => Some(item.to_system()),
+                    None => return Ok(Vec::new()),
+                },
+                None => None,
+            };
+            math(name, item.to_system(), operand).map(|item| item.into_iter().collect())
+        }
+
+        // Tree navigation
+        "children" => Ok(input.iter().flat_map(all_children).collect()),
+        "descendants" => {
+            let mut out = Vec::new();
+            let mut pending: Collection<'a> = input.iter().flat_map(all_children).collect();
+            while !pending.is_empty() {
+                let next = pending.iter().flat_map(all_children).collect();
+                out.append(&mut pending);
+                pending = next;
+            }
+            Ok(out)
+        }
+
+        // Utility
+        "trace" => {
+            let label = string_arg(&arg(0)?, name)?.unwrap_or_default();
+            let shown = match args.get(1) {
+                Some(projection) => {
+                    let mut shown = Vec::new();
+                    for (i, item) in input.iter().enumerate() {
+                        shown.extend(per_item(item, i, projection)?);
+                    }
+                    shown
+                }
+                None => input.clone(),
+            };
+            let values: Vec<_> = shown.iter().map(Item::to_json).collect();
+            tracing::debug!(label = %label, values = ?values, "FHIRPath trace");
+            Ok(input)
+        }
+        "now" => Ok(vec![Item::DateTime(Temporal::now())]),
+        "today" => Ok(vec![Item::Date(Temporal::today())]),
+        "timeOfDay" => Ok(vec![Item::Time(Temporal::time_of_day())]),
+        "aggregate" => {
+            let mut total = match args.get(1) {
+                Some(init) => ev.eval(init, &frame.this, frame)?,
+                None => Vec::new(),
+            };
+            for (i, item) in input.iter().enumerate() {
+                let frame = Frame { this: vec![item.clone()], index: Some(i), total: Some(total) };
+                total = ev.eval(&args[0], &frame.this, &frame)?;
+            }
+            Ok(total)
+        }
+
+        // Types
+        "is" => {
+            let ty = type_arg()?;
+            Ok(singleton(&input, "is()")?.map(|item| vec![Item::Boolean(item.is_type(ty))]).unwrap_or_default())
+        }
+        "as" => {
+            let ty = type_arg()?;
+            Ok(input.into_iter().filter(|item| item.is_type(ty)).collect())
+        }
+        "not" => Ok(to_bool(&input, "not()")?.map(|b| vec![Item::Boolean(!b)]).unwrap_or_default()),
+
+        // FHIR additions
+        "extension" => {
+            let Some(url) = string_arg(&arg(0)?, name)? else { return Ok(Vec::new()) };
+            let extensions = super::eval::navigate(&input, "extension");
+            Ok(extensions
+                .into_iter()
+                .filter(|ext| match ext {
+                    Item::Element(e) => e.value.get("url").and_then(|u| u.as_str()) == Some(url.as_str()),
+                    _ => false,
+                })
+                .collect())
+        }
+        "hasValue" => one(matches!(
+            singleton(&input, name)?,
+            Some(Item::Element(e)) if !e.value.is_object() && !e.value.is_array()
+        )),
+        "getValue" => Ok(match singleton(&input, name)? {
+            Some(item @ Item::Element(e)) if !e.value.is_object() && !e.value.is_array() => {
+                vec![item.to_system()]
+            }
+            _ => Vec::new(),
+        }),
+        "resolve" => Ok(input.iter().filter_map(|item| resolve(ev.ctx, item)).collect()),
+
+        _ => Err(EvalError(format!("unknown function '{name}()'"))),
+    }
+}
+
+/// The resource a Reference (or a reference string) points at; see the module
+/// docs for the order in which targets are looked for.
+fn resolve<'a>(ctx: &Context<'a>, item: &Item<'a>) -> Option<Item<'a>> {
+    let (reference, declared_type) = match item {
+        Item::Element(e) => match &*e.value {
+            Value::String(s) => (Some(s.as_str()), None),
+            Value::Object(o) => (
+                o.get("reference").and_then(Value::as_str),
+                o.get("type").and_then(Value::as_str),
+            ),
+            _ => return None,
+        },
+        Item::String(s) => (Some(s.as_str()), None),
+        _ => return None,
+    };
+    if let Some(local) = reference.and_then(|r| r.strip_prefix('#')) {
+        if local.is_empty() {
+            return Some(Item::element(ctx.resource));
+        }
+        return ctx
+            .resource
+            .get("contained")?
+            .as_array()?
+            .iter()
+            .find(|c| c.get("id").and_then(Value::as_str) == Some(local))
+            .map(Item::element);
+    }
+    if let Some(found) = reference.and_then(|r| ctx.resolver.and_then(|resolver| resolver(r))) {
+        return Some(Item::typed_element(Cow::Owned(found), None));
+    }
+    let stand_in = match reference.and_then(super::reference_target) {
+        Some((resource_type, id)) => json!({"resourceType": resource_type, "id": id}),
+        None => json!({"resourceType": declared_type?}),
+    };
+    Some(Item::typed_element(Cow::Owned(stand_in), None))
+}
+
+fn filter<'a>(
+    input: &[Item<'a>],
+    criteria: &Node,
+    per_item: &impl Fn(&Item<'a>, usize, &Node) -> Result<Collection<'a>, EvalError>,
+) -> Result<Collection<'a>, EvalError> {
+    let mut out = Vec::new();
+    for (i, item) in input.iter().enumerate() {
+        if to_bool(&per_item(item, i, criteria)?, "where()")? == Some(true) {
+            out.push(item.clone());
+        }
+    }
+    Ok(out)
+}
+
+fn string_input(input: &[Item], name: &str) -> Result<Option<String>, EvalError> {
+    match singleton(input, name)?.map(Item::to_system) {
+        None => Ok(None),
+        Some(Item::String(s)) => Ok(Some(s)),
+        Some(other) => Err(EvalError(format!("{name}() expects a String, got {}", other.type_name().1))),
+    }
+}
+
+fn string_arg(items: &[Item], name: &str) -> Result<Option<String>, EvalError> {
+    string_input(items, name)
+}
+
+fn integer_arg(items: &[Item], name: &str) -> Result<Option<i64>, EvalError> {
+    match singleton(items, name)?.map(Item::to_system) {
+        None => Ok(None),
+        Some(Item::Integer(i)) => Ok(Some(i)),
+        Some(other) => Err(EvalError(format!("{name}() expects an Integer, got {}", other.type_name().1))),
+    }
+}
+
+/// The string functions, given the input string and each argument's
+/// collection. An empty argument makes the result empty.
+fn string_function<'a>(name: &str, s: &str, args: &[Collection<'a>]) -> Result<Collection<'a>, EvalError> {
+    let mut strings = Vec::new();
+    if !matches!(name, "substring") {
+        for arg in args {
+            match string_arg(arg, name)? {
+                Some(a) => strings.push(a),
+                None => return Ok(Vec::new()),
+            }
+        }
+    }
+    let regex = |pattern: &str| {
+        regex::Regex::new(pattern).map_err(|e| EvalError(format!("invalid regular expression: {e}")))
+    };
+    let chars: Vec<char> = s.chars().collect();
+    let char_index = |byte: usize| s[..byte].chars().count() as i64;
+    let item = match name {
+        "indexOf" => Item::Integer(s.find(strings[0].as_str()).map(char_index).unwrap_or(-1)),
+        "lastIndexOf" => Item::Integer(s.rfind(strings[0].as_str()).map(char_index).unwrap_or(-1)),
+        "startsWith" => Item::Boolean(s.starts_with(strings[0].as_str())),
+        "endsWith" => Item::Boolean(s.ends_with(strings[0].as_str())),
+        "contains" => Item::Boolean(s.contains(strings[0].as_str())),
+        "matches" => Item::Boolean(regex(&strings[0])?.is_match(s)),
+        "replace" => Item::String(s.replace(strings[0].as_str(), &strings[1])),
+        "replaceMatches" => {
+            if strings[0].is_empty() {
+                Item::String(s.to_string())
+            } else {
+                Item::String(regex(&strings[0])?.replace_all(s, strings[1].as_str()).into_owned())
+            }
+        }
+        "upper" => Item::String(s.to_uppercase()),
+        "lower" => Item::String(s.to_lowercase()),
+        "length" => Item::Integer(chars.len() as i64),
+        "trim" => Item::String(s.trim().to_string()),
+        "toChars" => return Ok(chars.iter().map(|c| Item::String(c.to_string())).collect()),
+        "split" => return Ok(s.split(strings[0].as_str()).map(|p| Item::String(p.to_string())).collect()),
+        _ => {
+            // substring(start [, length])
+            let Some(start) = integer_arg(&args[0], name)? else { return Ok(Vec::new()) };
+            let length = match args.get(1) {
+                Some(arg) => integer_arg(arg, name)?,
+                None => None,
+            };
+            if start < 0 || start as usize >= chars.len() {
+                return Ok(Vec::new());
+            }
+            let end = match length {
+                Some(length) => (start + length.max(0)).min(chars.len() as i64) as usize,
+                None => chars.len(),
+            };
+            Item::String(chars[start as usize..end].iter().collect())
+        }
+    };
+    Ok(vec![item])
+}
+
+/// `toX()` conversions; `None` when the item does not convert.
+fn convert<'a>(name: &str, item: &Item<'a>, unit: Option<&str>) -> Option<Item<'a>> {
+    let item = item.to_system();
+    match name {
+        "toBoolean" => Some(Item::Boolean(match &item {
+            Item::Boolean(b) => *b,
+            Item::Integer(1) => true,
+            Item::Integer(0) => false,
+            Item::Decimal(d) => match d.to_i64()? {
+                1 => true,
+                0 => false,
+                _ => return None,
+            },
+            Item::String(s) => match s.to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "1" | "1.0" => true,
+                "false" | "f" | "no" | "n" | "0" | "0.0" => false,
+                _ => return None,
+            },
+            _ => return None,
+        })),
+        "toInteger" => match &item {
+            Item::Integer(_) => Some(item),
+            Item::Boolean(b) => Some(Item::Integer(*b as i64)),
+            Item::String(s) => {
+                let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                s.parse().ok().map(Item::Integer)
+            }
+            _ => None,
+        },
+        "toDecimal" => match &item {
+            Item::Integer(i) => Some(Item::Decimal(Decimal::from_i64(*i))),
+            Item::Decimal(_) => Some(item),
+            Item::Boolean(b) => Some(Item::Decimal(Decimal::from_i64(*b as i64))),
+            Item::String(s) if !s.contains(['e', 'E']) => Decimal::parse(s).map(Item::Decimal),
+            _ => None,
+        },
+        "toString" => item.to_string_value().map(Item::String),
+        "toDate" => match &item {
+            Item::Date(_) => Some(item),
+            Item::DateTime(t) => Some(Item::Date(t.date_part())),
+            Item::String(s) => Temporal::parse_date_time(s).map(|t| Item::Date(t.date_part())),
+            _ => None,
+        },
+        "toDateTime" => match &item {
+            Item::DateTime(_) => Some(item),
+            Item::Date(t) => Some(Item::DateTime(t.clone())),
+            Item::String(s) => Temporal::parse_date_time(s).map(Item::DateTime),
+            _ => None,
+        },
+        "toTime" => match &item {
+            Item::Time(_) => Some(item),
+            Item::String(s) => Temporal::parse_time(s).map(Item::Time),
+            _ => None,
+        },
+        "toQuantity" => {
+            let quantity = match &item {
+                Item::Quantity(q) => q.clone(),
+                Item::Integer(i) => Quantity::new(Decimal::from_i64(*i), "1"),
+                Item::Decimal(d) => Quantity::new(*d, "1"),
+                Item::Boolean(b) => Quantity::new(Decimal::from_i64(*b as i64), "1"),
+                Item::String(s) => Quantity::parse(s)?,
+                _ => return None,
+            };
+            match unit {
+                None => Some(Item::Quantity(quantity)),
+                Some(unit) if Quantity::new(quantity.value, unit).unit == quantity.unit => {
+                    Some(Item::Quantity(quantity))
+                }
+                Some(unit) => {
+                    // Convert through the canonical form of one `unit`.
+                    let (value, canonical) = crate::ucum::canonicalize(quantity.value.to_f64(), &quantity.unit)?;
+                    let (per_unit, target) = crate::ucum::canonicalize(1.0, unit)?;
+                    (canonical == target).then(|| {
+                        let converted = Decimal::from_f64(value / per_unit)?.round(8);
+                        Some(Item::Quantity(Quantity::new(converted, unit)))
+                    })?
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+fn math<'a>(name: &str, item: Item<'a>, operand: Option<Item<'a>>) -> Result<Option<Item<'a>>, EvalError> {
+    let number = |item: &Item| match item {
+        Item::Integer(i) => Ok(Decimal::from_i64(*i)),
+        Item::Decimal(d) => Ok(*d),
+        other => Err(EvalError(format!("{name}() expects a number, got {}", other.type_name().1))),
+    };
+    let real = |f: f64| Decimal::from_f64(f).map(|d| Item::Decimal(d.round(8)));
+    Ok(match (name, &item) {
+        ("abs", Item::Integer(i)) => i.checked_abs().map(Item::Integer),
+        ("abs", Item::Quantity(q)) => Some(Item::Quantity(Quantity { value: q.value.abs(), unit: q.unit.clone() })),
+        ("abs", _) => Some(Item::Decimal(number(&item)?.abs())),
+        ("ceiling", _) => number(&item)?.ceiling().to_i64().map(Item::Integer),
+        ("floor", _) => number(&item)?.floor().to_i64().map(Item::Integer),
+        ("truncate", _) => number(&item)?.truncate().to_i64().map(Item::Integer),
+        ("round", _) => {
+            let places = match &operand {
+                Some(Item::Integer(p)) if *p >= 0 => *p as u32,
+                Some(_) => return Err(EvalError("round() precision must be a non-negative Integer".into())),
+                None => 0,
+            };
+            Some(Item::Decimal(number(&item)?.round(places)))
+        }
+        ("exp", _) => real(number(&item)?.to_f64().exp()),
+        ("ln", _) => real(number(&item)?.to_f64().ln()),
+        ("sqrt", _) => real(number(&item)?.to_f64().sqrt()),
+        ("log", _) => {
+            let base = number(operand.as_ref().unwrap_or(&Item::Integer(10)))?.to_f64();
+            real(number(&item)?.to_f64().ln() / base.ln())
+        }
+        ("power", _) => {
+            let exponent = operand.unwrap_or(Item::Integer(1));
+            match (&item, &exponent) {
+                (Item::Integer(base), Item::Integer(exp)) if *exp >= 0 => {
+                    u32::try_from(*exp).ok().and_then(|exp| base.checked_pow(exp)).map(Item::Integer)
+                }
+                _ => real(number(&item)?.to_f64().powf(number(&exponent)?.to_f64())),
+            }
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,235 @@
+//! FHIRPath tokenizer.
+
+use super::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Tok {
+    /// Plain identifier; keywords (`and`, `div`, `true`, …) included, the
+    /// parser decides by position.
+    Ident(String),
+    /// `` `delimited identifier` `` — never a keyword.
+    Delimited(String),
+    Str(String),
+    /// Integer or decimal literal text.
+    Number(String),
+    /// `@2014-01-25`
+    Date(String),
+    /// `@2014-01-25T14:30:14.559Z`, `@2014T`
+    DateTime(String),
+    /// `@T14:30`
+    Time(String),
+    /// `%name`, `%'name'`, ``%`name` ``
+    Env(String),
+    /// `$this`, `$index`, `$total`
+    Dollar(String),
+    Sym(&'static str),
+}
+
+const SYMBOLS: [&str; 22] = [
+    "<=", ">=", "!=", "!~", ".", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "&", "|",
+    "=", "~", "<", ">",
+];
+
+pub(super) fn tokenize(s: &str) -> Result<Vec<Tok>, ParseError> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut toks = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        // Comments.
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(ParseError("unterminated comment".into()));
+            }
+            i += 2;
+            continue;
+        }
+        match c {
+            '\'' => {
+                let (text, next) = quoted(&chars, i, '\'')?;
+                toks.push(Tok::Str(text));
+                i = next;
+            }
+            '`' => {
+                let (text, next) = quoted(&chars, i, '`')?;
+                toks.push(Tok::Delimited(text));
+                i = next;
+            }
+            '%' => {
+                i += 1;
+                match chars.get(i) {
+                    Some(&q @ ('\'' | '`')) => {
+                        let (text, next) = quoted(&chars, i, q)?;
+                        toks.push(Tok::Env(text));
+                        i = next;
+                    }
+                    _ => {
+                        let (name, next) = identifier(&chars, i);
+                        if name.is_empty() {
+                            return Err(ParseError("expected a variable name after '%'".into()));
+                        }
+                        toks.push(Tok::Env(name));
+                        i = next;
+                    }
+                }
+            }
+            '$' => {
+                let (name, next) = identifier(&chars, i + 1);
+                if !matches!(name.as_str(), "this" | "index" | "total") {
+                    return Err(ParseError(format!("unknown special variable '${name}'")));
+                }
+                toks.push(Tok::Dollar(name));
+                i = next;
+            }
+            '@' => {
+                let (tok, next) = temporal(&chars, i + 1)?;
+                toks.push(tok);
+                i = next;
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                // A '.' is only part of the number when a digit follows, so
+                // `1.convertsToInteger()` stays an invocation.
+                if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+                    i += 1;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                toks.push(Tok::Number(chars[start..i].iter().collect()));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let (name, next) = identifier(&chars, i);
+                toks.push(Tok::Ident(name));
+                i = next;
+            }
+            _ => {
+                let sym = SYMBOLS.iter().find(|sym| {
+                    sym.chars().enumerate().all(|(k, sc)| chars.get(i + k) == Some(&sc))
+                });
+                match sym {
+                    Some(sym) => {
+                        toks.push(Tok::Sym(sym));
+                        i += sym.len();
+                    }
+                    None => return Err(ParseError(format!("unexpected character '{c}'"))),
+                }
+            }
+        }
+    }
+    Ok(toks)
+}
+
+fn identifier(chars: &[char], mut i: usize) -> (String, usize) {
+    let start = i;
+    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        i += 1;
+    }
+    (chars[start..i].iter().collect(), i)
+}
+
+/// A quoted string or delimited identifier starting at `chars[start]` (the
+/// opening quote). Returns the unescaped text and the index after the close.
+fn quoted(chars: &[char], start: usize, quote: char) -> Result<(String, usize), ParseError> {
+    let mut out = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => return Err(ParseError("unterminated string literal".into())),
+            Some(&c) if c == quote => return Ok((out, i + 1)),
+            Some('\\') => {
+                i += 1;
+                match chars.get(i) {
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.get(i + 1..i + 5).unwrap_or_default().iter().collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| ParseError(format!("invalid unicode escape '\\u{hex}'")))?;
+                        out.push(code);
+                        i += 4;
+                    }
+                    Some(&c @ ('\'' | '"' | '`' | '\\' | '/')) => out.push(c),
+                    other => return Err(ParseError(format!("invalid escape '\\{}'", other.copied().unwrap_or(' ')))),
+                }
+                i += 1;
+            }
+            Some(&c) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The literal after an `@`: a date, a dateTime (any date followed by `T`),
+/// or a time (`T` first). The text is validated by the parser.
+fn temporal(chars: &[char], mut i: usize) -> Result<(Tok, usize), ParseError> {
+    let start = i;
+    // A '.' only continues the literal as a fraction separator; otherwise it
+    // is member access on the literal (`@T14.is(Time)`).
+    let time_char = |i: usize| {
+        chars[i].is_ascii_digit()
+            || chars[i] == ':'
+            || (chars[i] == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+    };
+    if chars.get(i) == Some(&'T') {
+        i += 1;
+        while i < chars.len() && time_char(i) {
+            i += 1;
+        }
+        return Ok((Tok::Time(chars[start + 1..i].iter().collect()), i));
+    }
+    while i < chars.len()
+        && (chars[i].is_ascii_digit()
+            || (chars[i] == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+    {
+        i += 1;
+    }
+    if i == start {
+        return Err(ParseError("expected a date or time after '@'".into()));
+    }
+    if chars.get(i) != Some(&'T') {
+        return Ok((Tok::Date(chars[start..i].iter().collect()), i));
+    }
+    i += 1;
+    let time_start = i;
+    while i < chars.len() && time_char(i) {
+        i += 1;
+    }
+    if i > time_start {
+        match chars.get(i) {
+            Some('Z') => i += 1,
+            Some('+' | '-')
+                if chars.get(i + 1..i + 6).is_some_and(|tz| {
+                    tz[0].is_ascii_digit() && tz[1].is_ascii_digit() && tz[2] == ':'
+                }) =>
+            {
+                i += 6
+            }
+            _ => {}
+        }
+    }
+    Ok((Tok::DateTime(chars[start..i].iter().collect()), i))
+}
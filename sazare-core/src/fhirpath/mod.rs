@@ -0,0 +1,447 @@
+//! FHIRPath engine, shared by custom search parameters and anything else that
+//! evaluates FHIRPath (see the [FHIRPath specification]).
+//!
+//! The full grammar is supported: literals (including `@date`/`@dateTime`/
+//! `@Ttime` and quantities such as `5 'mg'` and `3 days`), member access with
+//! choice-type navigation (`Observation.value` reaches `valueQuantity`),
+//! indexers, `$this`/`$index`/`$total`, `%resource`/`%context`/`%rootResource`
+//! and the other environment variables, and every operator level from `.`
+//! down to `implies`, with three-valued Boolean logic. The standard function
+//! library is implemented apart from `type()` and the terminology functions;
+//! `extension()`, `hasValue()`, `getValue()` and `resolve()` are added from the
+//! FHIR profile. Unknown functions and wrong argument counts are parse errors,
+//! so an unusable expression is rejected where it is loaded.
+//!
+//! Values are typed ([`Item`]): resource nodes are kept by reference, and
+//! computed results are System values — decimals keep their precision, dates
+//! and times their partial precision, and quantities their unit, with UCUM
+//! conversion for comparisons and date arithmetic for calendar durations.
+//!
+//! There is no structure model: a node's FHIR type comes from its
+//! `resourceType`, from the suffix of the choice element it was reached
+//! through, or from its JSON value (string-based primitives such as `code` or
+//! `date` match by lexical form in `is`/`ofType`).
+//!
+//! `resolve()` finds `#id` references among `%resource.contained`, and hands
+//! other references to the context's [`Resolver`] when it has one (the server
+//! reads the store). Failing that, a literal reference such as `Patient/123`
+//! resolves to a stand-in `{"resourceType": "Patient", "id": "123"}`, which is
+//! all `resolve() is Patient` needs; so does a logical reference's `type`.
+//!
+//! Conformance is checked against cases from the official FHIRPath test suite
+//! in `tests/fixtures/fhirpath/`.
+//!
+//! [FHIRPath specification]: https://hl7.org/fhirpath/
+
+mod eval;
+mod functions;
+mod lexer;
+mod parser;
+mod value;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+pub use value::{Decimal, Element, Item, Quantity, Temporal};
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    root: parser::Node,
+}
+
+/// Parse failure — carries a human-readable reason. Surfaced at SearchParameter
+/// load time so invalid expressions fail loudly, never silently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(pub String);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid FHIRPath expression: {}", self.0)
+    }
+}
+
+/// Evaluation failure, e.g. a collection of several items where one was
+/// required (`Patient.name.given.single()`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError(pub String);
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FHIRPath evaluation failed: {}", self.0)
+    }
+}
+
+/// Looks up the target of a reference string for `resolve()`, e.g. from the
+/// resource store.
+pub type Resolver<'r> = dyn Fn(&str) -> Option<Value> + 'r;
+
+/// The resource an expression is evaluated against, plus any additional
+/// `%variables` and a [`Resolver`] for `resolve()`.
+pub struct Context<'a> {
+    resource: &'a Value,
+    variables: HashMap<String, Vec<Item<'a>>>,
+    resolver: Option<&'a Resolver<'a>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(resource: &'a Value) -> Self {
+        Self { resource, variables: HashMap::new(), resolver: None }
+    }
+
+    /// Let `resolve()` look references up with `resolver`.
+    pub fn with_resolver(mut self, resolver: &'a Resolver<'a>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Bind `%name` to `value`.
+    pub fn with_variable(mut self, name: impl Into<String>, value: Vec<Item<'a>>) -> Self {
+        self.variables.insert(name.into(), value);
+        self
+    }
+
+    fn variable(&self, name: &str) -> Option<Vec<Item<'a>>> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+        let string = |s: String| Some(vec![Item::String(s)]);
+        match name {
+            "resource" | "context" | "rootResource" => Some(vec![Item::element(self.resource)]),
+            "ucum" => string("http://unitsofmeasure.org".into()),
+            "sct" => string("http://snomed.info/sct".into()),
+            "loinc" => string("http://loinc.org".into()),
+            _ => {
+                if let Some(id) = name.strip_prefix("vs-") {
+                    string(format!("http://hl7.org/fhir/ValueSet/{id}"))
+                } else if let Some(id) = name.strip_prefix("ext-") {
+                    string(format!("http://hl7.org/fhir/StructureDefinition/{id}"))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Parse an expression into an [`Expr`], or fail loudly.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    Ok(Expr { root: parser::parse(input)? })
+}
+
+/// Evaluate an expression against a resource, returning the resource nodes it
+/// selects. Computed values are dropped and an evaluation error yields
+/// nothing — this is the form index extraction wants, where type-specific
+/// shaping to index values (code+system, reference string, …) is the caller's
+/// job. Use [`evaluate_with`] for the full typed result.
+pub fn evaluate<'a>(expr: &Expr, root: &'a Value) -> Vec<&'a Value> {
+    select(expr, &Context::new(root))
+}
+
+/// [`evaluate`] in a given context, e.g. one with a [`Resolver`].
+pub fn select<'a>(expr: &Expr, ctx: &Context<'a>) -> Vec<&'a Value> {
+    match evaluate_with(expr, ctx) {
+        Ok(items) => items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Element(Element { value: Cow::Borrowed(v), .. }) => Some(v),
+                _ => None,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::debug!("{e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Evaluate an expression, returning the result collection.
+pub fn evaluate_with<'a>(expr: &Expr, ctx: &Context<'a>) -> Result<Vec<Item<'a>>, EvalError> {
+    let input = vec![Item::element(ctx.resource)];
+    let frame = eval::Frame { this: input.clone(), index: None, total: None };
+    eval::Evaluator { ctx }.eval(&expr.root, &input, &frame)
+}
+
+/// The `(type, id)` a literal reference names: `Patient/123` or an absolute
+/// `http://example.org/fhir/Patient/123`, either optionally with
+/// `/_history/<version>`, or a canonical with a `|version`. `None` for local
+/// (`#id`), `urn:` and other references that do not carry a type.
+pub fn reference_target(reference: &str) -> Option<(&str, &str)> {
+    let reference = reference.split('|').next()?;
+    let reference = reference.split("/_history/").next()?;
+    let mut segments = reference.rsplit('/');
+    let id = segments.next()?;
+    let resource_type = segments.next()?;
+    let is_type = resource_type.starts_with(|c: char| c.is_ascii_uppercase())
+        && resource_type.chars().all(|c| c.is_ascii_alphanumeric());
+    let is_id = (1..=64).contains(&id.len())
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    (is_type && is_id).then_some((resource_type, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn strs(nodes: &[&Value]) -> Vec<String> {
+        nodes.iter().filter_map(|v| v.as_str().map(String::from)).collect()
+    }
+
+    /// Evaluate and render each result item as its string form.
+    fn run(expr: &str, resource: &Value) -> Result<Vec<String>, String> {
+        let expr = parse(expr).map_err(|e| e.to_string())?;
+        let items = evaluate_with(&expr, &Context::new(resource)).map_err(|e| e.to_string())?;
+        Ok(items
+            .iter()
+            .map(|item| item.to_string_value().unwrap_or_else(|| item.to_json().to_string()))
+            .collect())
+    }
+
+    #[test]
+    fn path_navigation_and_arrays() {
+        let patient = json!({
+            "resourceType": "Patient",
+            "name": [
+                {"family": "Brown", "given": ["John", "Q"]},
+                {"family": "Smith", "given": ["Amy"]}
+            ]
+        });
+        let e = parse("Patient.name.family").unwrap();
+        assert_eq!(strs(&evaluate(&e, &patient)), vec!["Brown", "Smith"]);
+        let g = parse("Patient.name.given").unwrap();
+        assert_eq!(strs(&evaluate(&g, &patient)), vec!["John", "Q", "Amy"]);
+        let wrong = parse("Observation.name.given").unwrap();
+        assert!(evaluate(&wrong, &patient).is_empty());
+    }
+
+    #[test]
+    fn choice_type_navigation_oftype_and_as() {
+        let obs = json!({
+            "resourceType": "Observation",
+            "valueQuantity": {"value": 9.5, "unit": "kg"}
+        });
+        for expr in [
+            "Observation.value.ofType(Quantity)",
+            "Observation.value as Quantity",
+            "(Observation.value as Quantity)",
+            "Observation.value",
+        ] {
+            let e = parse(expr).unwrap();
+            let r = evaluate(&e, &obs);
+            assert_eq!(r.len(), 1, "{expr}");
+            assert_eq!(r[0].get("unit").unwrap(), "kg", "{expr}");
+        }
+        let e = parse("Observation.value.ofType(string)").unwrap();
+        assert!(evaluate(&e, &obs).is_empty());
+    }
+
+    #[test]
+    fn union_where_and_extension() {
+        let patient = json!({
+            "resourceType": "Patient",
+            "telecom": [
+                {"system": "phone", "value": "555-1234"},
+                {"system": "email", "value": "a@b.com"}
+            ],
+            "extension": [{"url": "http://example.org/x", "valueString": "ext"}]
+        });
+        let e = parse("Patient.telecom.where(system='phone').value | Patient.extension('http://example.org/x').value.ofType(string)").unwrap();
+        assert_eq!(strs(&evaluate(&e, &patient)), vec!["555-1234", "ext"]);
+    }
+
+    #[test]
+    fn operators_and_functions() {
+        let patient = json!({
+            "resourceType": "Patient",
+            "active": true,
+            "birthDate": "1974-12-25",
+            "name": [{"use": "official", "given": ["Peter", "James"]}, {"use": "usual", "given": ["Jim"]}]
+        });
+        let cases = [
+            ("Patient.name.given.count() = 3 and Patient.active", vec!["true"]),
+            ("Patient.name.where(use = 'usual').exists() xor false", vec!["true"]),
+            ("Patient.name[1].given", vec!["Jim"]),
+            ("Patient.name.given.select($this.length()).distinct()", vec!["5", "3"]),
+            ("Patient.birthDate < @2000-01-01", vec!["true"]),
+            ("Patient.birthDate + 1 year", vec!["1975-12-25"]),
+            ("iif(Patient.active, 'yes', 'no')", vec!["yes"]),
+            ("(1 | 2 | 3).aggregate($this + $total, 0)", vec!["6"]),
+            ("0.1 + 0.2 = 0.3", vec!["true"]),
+            ("1.0 / 3", vec!["0.33333333"]),
+            ("5 'mg' = 0.005 'g'", vec!["true"]),
+            ("@2012-04-15 = @2012-04-15T10:00:00", vec![]),
+            ("{} and false", vec!["false"]),
+            ("'abc'.matches('^a.c$') and 'a-b'.replaceMatches('-', '+') = 'a+b'", vec!["true"]),
+            ("%resource.name.first().use", vec!["official"]),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(run(expr, &patient).unwrap(), expected, "{expr}");
+        }
+        assert!(run("Patient.name.given.single()", &patient).is_err());
+        assert!(run("%unknown", &patient).is_err());
+    }
+
+    #[test]
+    fn real_jp_core_extension_expression() {
+        // jp-insured-personnumber, verbatim from JP Core 1.2.0.
+        let cov = json!({
+            "resourceType": "Coverage",
+            "extension": [{
+                "url": "http://jpfhir.jp/fhir/core/Extension/StructureDefinition/JP_Coverage_InsuredPersonNumber",
+                "valueString": "12345678"
+            }]
+        });
+        let e = parse("Coverage.extension('http://jpfhir.jp/fhir/core/Extension/StructureDefinition/JP_Coverage_InsuredPersonNumber').value.ofType(string)").unwrap();
+        assert_eq!(strs(&evaluate(&e, &cov)), vec!["12345678"]);
+    }
+
+    #[test]
+    fn real_jp_core_nested_extension_choice_member() {
+        // jp-medication-start: dosageInstruction.extension('url').value.ofType(Period).start
+        let mr = json!({
+            "resourceType": "MedicationRequest",
+            "dosageInstruction": [{
+                "extension": [{
+                    "url": "http://jpfhir.jp/fhir/core/Extension/StructureDefinition/JP_MedicationDosage_PeriodOfUse",
+                    "valuePeriod": {"start": "2026-06-15", "end": "2026-06-30"}
+                }]
+            }]
+        });
+        let e = parse("MedicationRequest.dosageInstruction.extension('http://jpfhir.jp/fhir/core/Extension/StructureDefinition/JP_MedicationDosage_PeriodOfUse').value.ofType(Period).start").unwrap();
+        assert_eq!(strs(&evaluate(&e, &mr)), vec!["2026-06-15"]);
+    }
+
+    #[test]
+    fn resolve_literal_contained_and_resolver_references() {
+        let obs = json!({
+            "resourceType": "Observation",
+            "contained": [{"resourceType": "Device", "id": "d1"}],
+            "subject": {"reference": "Patient/p1"},
+            "performer": [
+                {"reference": "http://example.org/fhir/Practitioner/dr/_history/2"},
+                {"reference": "#d1"},
+                {"type": "Organization", "identifier": {"value": "org-9"}},
+                {"reference": "urn:uuid:0c3151bd-1cbf-4d64-b04d-cd9187a4c6e0"}
+            ]
+        });
+        let select_refs = |expr: &str, ctx: &Context| -> Vec<String> {
+            select(&parse(expr).unwrap(), ctx)
+                .iter()
+                .map(|r| r.get("reference").or(r.get("type")).unwrap().as_str().unwrap().to_string())
+                .collect()
+        };
+        let ctx = Context::new(&obs);
+        assert_eq!(select_refs("Observation.subject.where(resolve() is Patient)", &ctx), ["Patient/p1"]);
+        assert!(select_refs("Observation.subject.where(resolve() is Group)", &ctx).is_empty());
+        assert_eq!(
+            select_refs("Observation.performer.where(resolve() is Practitioner)", &ctx),
+            ["http://example.org/fhir/Practitioner/dr/_history/2"]
+        );
+        assert_eq!(select_refs("Observation.performer.where(resolve() is Device)", &ctx), ["#d1"]);
+        assert_eq!(select_refs("Observation.performer.where(resolve() is Organization)", &ctx), ["Organization"]);
+        assert_eq!(run("Observation.performer.resolve().count()", &obs).unwrap(), ["3"]);
+        assert_eq!(run("Observation.subject.resolve().id", &obs).unwrap(), ["p1"]);
+
+        // A resolver supplies the whole target, and the references whose
+        // literal does not name a type.
+        let resolver = |reference: &str| match reference {
+            "Patient/p1" => Some(json!({"resourceType": "Patient", "id": "p1", "active": true})),
+            r if r.starts_with("urn:uuid:") => Some(json!({"resourceType": "RelatedPerson"})),
+            _ => None,
+        };
+        let ctx = Context::new(&obs).with_resolver(&resolver);
+        assert_eq!(select_refs("Observation.subject.where(resolve().active)", &ctx), ["Patient/p1"]);
+        assert_eq!(
+            select_refs("Observation.performer.where(resolve() is RelatedPerson)", &ctx),
+            ["urn:uuid:0c3151bd-1cbf-4d64-b04d-cd9187a4c6e0"]
+        );
+    }
+
+    #[test]
+    fn literal_reference_targets() {
+        assert_eq!(reference_target("Patient/123"), Some(("Patient", "123")));
+        assert_eq!(reference_target("Patient/123/_history/4"), Some(("Patient", "123")));
+        assert_eq!(reference_target("https://example.org/fhir/Encounter/e.1"), Some(("Encounter", "e.1")));
+        assert_eq!(
+            reference_target("http://example.org/Questionnaire/q1|2.0"),
+            Some(("Questionnaire", "q1"))
+        );
+        assert_eq!(reference_target("#p1"), None);
+        assert_eq!(reference_target("urn:uuid:0c3151bd-1cbf-4d64-b04d-cd9187a4c6e0"), None);
+        assert_eq!(reference_target("patient/123"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let rejects = [
+            "Patient.name.",
+            "Patient.name.given.frobnicate()",
+            "Patient.name.where()",
+            "'unterminated",
+            "1 +",
+            "@2014-13-01",
+            "Patient.name[0",
+        ];
+        for r in rejects {
+            assert!(parse(r).is_err(), "should reject: {r}");
+        }
+    }
+
+    /// Runs the cases in `tests/fixtures/fhirpath/tests-fhir-r4.json`, taken
+    /// from the official FHIRPath test suite. Each case names an input
+    /// resource, the expression and either the expected outputs (as strings,
+    /// booleans or numbers) or `invalid`: `syntax` or `semantic` for a parse
+    /// error, `execution` for an evaluation error.
+    #[test]
+    fn official_test_suite() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fhirpath");
+        let read = |name: &str| -> Value {
+            serde_json::from_str(&std::fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+        };
+        let suite = read("tests-fhir-r4.json");
+        let mut inputs: HashMap<String, Value> = HashMap::new();
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for group in suite["groups"].as_array().unwrap() {
+            for case in group["tests"].as_array().unwrap() {
+                count += 1;
+                let name = case["name"].as_str().unwrap();
+                let expression = case["expression"].as_str().unwrap();
+                let file = case["inputfile"].as_str().unwrap_or("patient-example.json");
+                let input = inputs.entry(file.to_string()).or_insert_with(|| read(file));
+                let parsed = parse(expression);
+                let outcome = match case["invalid"].as_str() {
+                    Some("syntax" | "semantic") => parsed.is_err().then_some(()).ok_or("parsed".to_string()),
+                    Some(_) => match parsed {
+                        Err(e) => Err(e.to_string()),
+                        Ok(expr) => evaluate_with(&expr, &Context::new(input))
+                            .is_err()
+                            .then_some(())
+                            .ok_or("evaluated without error".to_string()),
+                    },
+                    None => {
+                        let expected: Vec<String> = case["output"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|v| v.as_str().map(String::from).unwrap_or_else(|| v.to_string()))
+                            .collect();
+                        match run(expression, input) {
+                            Ok(actual) if actual == expected => Ok(()),
+                            Ok(actual) => Err(format!("got {actual:?}, expected {expected:?}")),
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                if let Err(why) = outcome {
+                    failures.push(format!("{name}: `{expression}`: {why}"));
+                }
+            }
+        }
+        assert!(count > 0);
+        assert!(failures.is_empty(), "{} of {count} failed:\n{}", failures.len(), failures.join("\n"));
+    }
+}
@@ -0,0 +1,377 @@
+//! FHIRPath parser: precedence climbing over the grammar's operator levels,
+//! producing a [`Node`] tree.
+
+use super::functions;
+use super::lexer::{tokenize, Tok};
+use super::value::{Decimal, Temporal};
+use super::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinOp {
+    Mul,
+    Div,
+    IntDiv,
+    Mod,
+    Add,
+    Sub,
+    Concat,
+    Union,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Equiv,
+    NotEquiv,
+    In,
+    Contains,
+    And,
+    Or,
+    Xor,
+    Implies,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TypeOp {
+    Is,
+    As,
+}
+
+/// Expression tree. Numeric literals keep their source text so the tree stays
+/// `Eq` (search parameter definitions compare by value).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Node {
+    Empty,
+    Boolean(bool),
+    String(String),
+    Integer(i64),
+    Decimal(String),
+    Date(String),
+    DateTime(String),
+    Time(String),
+    /// Value text and unit: a UCUM code or a calendar duration keyword.
+    Quantity(String, String),
+    /// Member access on the input collection.
+    Identifier(String),
+    /// `%name`
+    Variable(String),
+    This,
+    Index,
+    Total,
+    /// The type argument of `is()`, `as()` and `ofType()`.
+    Type(String),
+    /// `focus.name`
+    Member(Box<Node>, String),
+    /// `focus.name(args)`, or `name(args)` on the input collection.
+    Call {
+        focus: Option<Box<Node>>,
+        name: String,
+        args: Vec<Node>,
+    },
+    /// `focus[index]`
+    Indexer(Box<Node>, Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    TypeOp(TypeOp, Box<Node>, String),
+}
+
+/// Calendar duration keywords that may follow a number in a quantity literal.
+const CALENDAR_UNITS: [&str; 16] = [
+    "year", "years", "month", "months", "week", "weeks", "day", "days", "hour", "hours", "minute",
+    "minutes", "second", "seconds", "millisecond", "milliseconds",
+];
+
+pub(super) fn parse(input: &str) -> Result<Node, ParseError> {
+    let toks = tokenize(input)?;
+    if toks.is_empty() {
+        return Err(ParseError("empty expression".into()));
+    }
+    let mut p = Parser { toks, pos: 0 };
+    let node = p.expression()?;
+    if let Some(tok) = p.peek() {
+        return Err(ParseError(format!("unexpected {tok:?} after the end of the expression")));
+    }
+    Ok(node)
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+
+    fn bump(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Sym(s)) if *s == sym) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<(), ParseError> {
+        if self.eat_sym(sym) {
+            Ok(())
+        } else {
+            Err(ParseError(format!("expected '{sym}', found {:?}", self.peek())))
+        }
+    }
+
+    fn is_keyword(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Ident(s)) if s == word)
+    }
+
+    fn expression(&mut self) -> Result<Node, ParseError> {
+        self.implies()
+    }
+
+    /// One left-associative binary level: `operand (op operand)*`.
+    fn binary_level(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        operand: fn(&mut Self) -> Result<Node, ParseError>,
+    ) -> Result<Node, ParseError> {
+        let mut lhs = operand(self)?;
+        loop {
+            let op = ops.iter().find(|(text, _)| match self.peek() {
+                Some(Tok::Sym(s)) => s == text,
+                Some(Tok::Ident(s)) => s == text,
+                _ => false,
+            });
+            let Some(&(_, op)) = op else { break };
+            self.pos += 1;
+            let rhs = operand(self)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn implies(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[("implies", BinOp::Implies)], Self::or)
+    }
+
+    fn or(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[("or", BinOp::Or), ("xor", BinOp::Xor)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[("and", BinOp::And)], Self::membership)
+    }
+
+    fn membership(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[("in", BinOp::In), ("contains", BinOp::Contains)], Self::equality)
+    }
+
+    fn equality(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[("=", BinOp::Eq), ("!=", BinOp::Ne), ("~", BinOp::Equiv), ("!~", BinOp::NotEquiv)],
+            Self::inequality,
+        )
+    }
+
+    fn inequality(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+            Self::union,
+        )
+    }
+
+    fn union(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[("|", BinOp::Union)], Self::type_expression)
+    }
+
+    fn type_expression(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.additive()?;
+        loop {
+            let op = if self.is_keyword("is") {
+                TypeOp::Is
+            } else if self.is_keyword("as") {
+                TypeOp::As
+            } else {
+                break;
+            };
+            self.pos += 1;
+            let ty = self.type_specifier()?;
+            node = Node::TypeOp(op, Box::new(node), ty);
+        }
+        Ok(node)
+    }
+
+    fn additive(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[("+", BinOp::Add), ("-", BinOp::Sub), ("&", BinOp::Concat)],
+            Self::multiplicative,
+        )
+    }
+
+    fn multiplicative(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("div", BinOp::IntDiv), ("mod", BinOp::Mod)],
+            Self::polarity,
+        )
+    }
+
+    fn polarity(&mut self) -> Result<Node, ParseError> {
+        if self.eat_sym("-") {
+            return Ok(Node::Negate(Box::new(self.polarity()?)));
+        }
+        if self.eat_sym("+") {
+            return self.polarity();
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.term()?;
+        loop {
+            if self.eat_sym(".") {
+                node = self.invocation(Some(node))?;
+            } else if self.eat_sym("[") {
+                let index = self.expression()?;
+                self.expect_sym("]")?;
+                node = Node::Indexer(Box::new(node), Box::new(index));
+            } else {
+                break;
+            }
+        }
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<Node, ParseError> {
+        match self.peek().cloned() {
+            Some(Tok::Sym("(")) => {
+                self.pos += 1;
+                let node = self.expression()?;
+                self.expect_sym(")")?;
+                Ok(node)
+            }
+            Some(Tok::Sym("{")) => {
+                self.pos += 1;
+                self.expect_sym("}")?;
+                Ok(Node::Empty)
+            }
+            Some(Tok::Str(s)) => {
+                self.pos += 1;
+                Ok(Node::String(s))
+            }
+            Some(Tok::Number(n)) => {
+                self.pos += 1;
+                self.number(n)
+            }
+            Some(Tok::Date(s)) => {
+                self.pos += 1;
+                Temporal::parse_date(&s).ok_or_else(|| ParseError(format!("invalid date literal @{s}")))?;
+                Ok(Node::Date(s))
+            }
+            Some(Tok::DateTime(s)) => {
+                self.pos += 1;
+                Temporal::parse_date_time(&s)
+                    .ok_or_else(|| ParseError(format!("invalid dateTime literal @{s}")))?;
+                Ok(Node::DateTime(s))
+            }
+            Some(Tok::Time(s)) => {
+                self.pos += 1;
+                Temporal::parse_time(&s).ok_or_else(|| ParseError(format!("invalid time literal @T{s}")))?;
+                Ok(Node::Time(s))
+            }
+            Some(Tok::Env(name)) => {
+                self.pos += 1;
+                Ok(Node::Variable(name))
+            }
+            Some(Tok::Dollar(name)) => {
+                self.pos += 1;
+                Ok(match name.as_str() {
+                    "this" => Node::This,
+                    "index" => Node::Index,
+                    _ => Node::Total,
+                })
+            }
+            Some(Tok::Ident(word)) if word == "true" || word == "false" => {
+                self.pos += 1;
+                Ok(Node::Boolean(word == "true"))
+            }
+            Some(Tok::Ident(_) | Tok::Delimited(_)) => self.invocation(None),
+            other => Err(ParseError(format!("unexpected {other:?}"))),
+        }
+    }
+
+    /// A number, or a quantity when a unit string or calendar keyword follows.
+    fn number(&mut self, text: String) -> Result<Node, ParseError> {
+        let unit = match self.peek() {
+            Some(Tok::Str(unit)) => Some(unit.clone()),
+            Some(Tok::Ident(word)) if CALENDAR_UNITS.contains(&word.as_str()) => Some(word.clone()),
+            _ => None,
+        };
+        if let Some(unit) = unit {
+            self.pos += 1;
+            return Ok(Node::Quantity(text, unit));
+        }
+        if text.contains('.') {
+            Decimal::parse(&text).ok_or_else(|| ParseError(format!("invalid decimal {text}")))?;
+            Ok(Node::Decimal(text))
+        } else {
+            text.parse()
+                .map(Node::Integer)
+                .map_err(|_| ParseError(format!("integer {text} is out of range")))
+        }
+    }
+
+    /// An identifier or function call, applied to `focus` when given.
+    fn invocation(&mut self, focus: Option<Node>) -> Result<Node, ParseError> {
+        let name = match self.bump() {
+            Some(Tok::Ident(s) | Tok::Delimited(s)) => s,
+            Some(Tok::Dollar(name)) if focus.is_some() && name == "this" => {
+                return Err(ParseError("'$this' cannot follow '.'".into()));
+            }
+            other => return Err(ParseError(format!("expected an identifier, found {other:?}"))),
+        };
+        if !self.eat_sym("(") {
+            return Ok(match focus {
+                Some(focus) => Node::Member(Box::new(focus), name),
+                None => Node::Identifier(name),
+            });
+        }
+        let mut args = Vec::new();
+        if !self.eat_sym(")") {
+            loop {
+                if matches!(name.as_str(), "is" | "as" | "ofType") {
+                    args.push(Node::Type(self.type_specifier()?));
+                } else {
+                    args.push(self.expression()?);
+                }
+                if self.eat_sym(")") {
+                    break;
+                }
+                self.expect_sym(",")?;
+            }
+        }
+        functions::check_arity(&name, args.len())?;
+        Ok(Node::Call { focus: focus.map(Box::new), name, args })
+    }
+
+    /// `Name` or `Namespace.Name`.
+    fn type_specifier(&mut self) -> Result<String, ParseError> {
+        let mut name = match self.bump() {
+            Some(Tok::Ident(s) | Tok::Delimited(s)) => s,
+            other => return Err(ParseError(format!("expected a type name, found {other:?}"))),
+        };
+        if matches!(name.as_str(), "FHIR" | "System") && self.eat_sym(".") {
+            match self.bump() {
+                Some(Tok::Ident(s) | Tok::Delimited(s)) => name = format!("{name}.{s}"),
+                other => return Err(ParseError(format!("expected a type name, found {other:?}"))),
+            }
+        }
+        Ok(name)
+    }
+}
@@ -0,0 +1,1011 @@
+//! FHIRPath values: resource elements plus the System primitives (Boolean,
+//! Integer, Decimal, String, Date, DateTime, Time, Quantity), with the
+//! equality, equivalence and ordering rules of the specification.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{Duration, Months, NaiveDate, NaiveDateTime, Timelike};
+use serde_json::Value;
+
+/// One item of a FHIRPath collection.
+#[derive(Debug, Clone)]
+pub enum Item<'a> {
+    /// A node of the input resource (or of a resource reached from it).
+    Element(Element<'a>),
+    Boolean(bool),
+    Integer(i64),
+    Decimal(Decimal),
+    String(String),
+    Date(Temporal),
+    DateTime(Temporal),
+    Time(Temporal),
+    Quantity(Quantity),
+}
+
+/// A JSON node with the FHIR type it was reached as, when known: the
+/// `resourceType` of a resource, or the suffix of a choice element
+/// (`valueQuantity` is a `Quantity`). Without a structure model, other types
+/// are inferred from the JSON value itself.
+#[derive(Debug, Clone)]
+pub struct Element<'a> {
+    pub value: Cow<'a, Value>,
+    pub type_name: Option<String>,
+}
+
+impl<'a> Item<'a> {
+    /// Wrap a JSON node, typing it by its `resourceType` if it is a resource.
+    pub fn element(value: &'a Value) -> Self {
+        Self::typed_element(Cow::Borrowed(value), None)
+    }
+
+    pub(super) fn typed_element(value: Cow<'a, Value>, type_name: Option<String>) -> Self {
+        let type_name = type_name.or_else(|| {
+            value.get("resourceType").and_then(Value::as_str).map(String::from)
+        });
+        Item::Element(Element { value, type_name })
+    }
+
+    /// The item's type as `(namespace, name)`: `("FHIR", "Patient")`,
+    /// `("FHIR", "string")`, `("System", "Integer")`.
+    pub fn type_name(&self) -> (&'static str, Cow<'_, str>) {
+        let system = |name: &'static str| ("System", Cow::Borrowed(name));
+        match self {
+            Item::Boolean(_) => system("Boolean"),
+            Item::Integer(_) => system("Integer"),
+            Item::Decimal(_) => system("Decimal"),
+            Item::String(_) => system("String"),
+            Item::Date(_) => system("Date"),
+            Item::DateTime(_) => system("DateTime"),
+            Item::Time(_) => system("Time"),
+            Item::Quantity(_) => system("Quantity"),
+            Item::Element(e) => match &e.type_name {
+                Some(name) => ("FHIR", Cow::Borrowed(name.as_str())),
+                None => (
+                    "FHIR",
+                    Cow::Borrowed(match &*e.value {
+                        Value::Bool(_) => "boolean",
+                        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+                        Value::Number(_) => "decimal",
+                        Value::String(_) => "string",
+                        _ => "Element",
+                    }),
+                ),
+            },
+        }
+    }
+
+    /// Whether the item is of type `spec` (`Quantity`, `FHIR.string`,
+    /// `System.Integer`). An unqualified name matches either namespace. FHIR
+    /// string-based primitives (`code`, `date`, `uri`, …) are recognised by
+    /// their lexical form, since plain JSON doesn't record them.
+    pub fn is_type(&self, spec: &str) -> bool {
+        let (namespace, name) = match spec.split_once('.') {
+            Some((ns, name)) => (Some(ns), name),
+            None => (None, spec),
+        };
+        let (own_ns, own_name) = self.type_name();
+        if namespace.is_some_and(|ns| ns != own_ns) {
+            return false;
+        }
+        if own_name == name || (own_ns == "FHIR" && is_subtype(&own_name, name)) {
+            return true;
+        }
+        match self {
+            Item::Element(e) if e.type_name.is_none() => match (&*e.value, name) {
+                (Value::String(s), _) => string_has_type(s, name),
+                (Value::Number(n), "positiveInt") => n.as_i64().is_some_and(|n| n > 0),
+                (Value::Number(n), "unsignedInt") => n.as_i64().is_some_and(|n| n >= 0),
+                (Value::Number(n), "integer64") => n.is_i64(),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The System value of a primitive element; other items are returned
+    /// unchanged. Elements that are not primitives (nor a Quantity) stay
+    /// elements.
+    pub fn to_system(&self) -> Item<'a> {
+        let Item::Element(e) = self else { return self.clone() };
+        let hint = e.type_name.as_deref();
+        match &*e.value {
+            Value::Bool(b) => Item::Boolean(*b),
+            Value::Number(n) => match (n.as_i64(), hint) {
+                (Some(i), hint) if hint != Some("decimal") => Item::Integer(i),
+                _ => Decimal::from_number(n).map(Item::Decimal).unwrap_or_else(|| self.clone()),
+            },
+            Value::String(s) => match hint {
+                Some("date") => Temporal::parse_date(s).map(Item::Date),
+                Some("dateTime" | "instant") => Temporal::parse_date_time(s).map(Item::DateTime),
+                Some("time") => Temporal::parse_time(s).map(Item::Time),
+                _ => None,
+            }
+            .unwrap_or_else(|| Item::String(s.clone())),
+            Value::Object(_) if hint.is_some_and(is_quantity_type) => {
+                Quantity::from_element(&e.value).map(Item::Quantity).unwrap_or_else(|| self.clone())
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// The JSON form of the item, as it would appear in a resource.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Item::Element(e) => e.value.clone().into_owned(),
+            Item::Boolean(b) => Value::Bool(*b),
+            Item::Integer(i) => Value::from(*i),
+            Item::Decimal(d) => serde_json::from_str(&d.to_string()).unwrap_or(Value::Null),
+            Item::String(s) => Value::String(s.clone()),
+            Item::Date(t) | Item::DateTime(t) | Item::Time(t) => Value::String(t.to_string()),
+            Item::Quantity(q) => q.to_json(),
+        }
+    }
+
+    /// `toString()`: the string form of a primitive, or `None` for an element
+    /// that is not one.
+    pub fn to_string_value(&self) -> Option<String> {
+        Some(match self.to_system() {
+            Item::Element(_) => return None,
+            Item::Boolean(b) => b.to_string(),
+            Item::Integer(i) => i.to_string(),
+            Item::Decimal(d) => d.to_string(),
+            Item::String(s) => s,
+            Item::Date(t) | Item::DateTime(t) | Item::Time(t) => t.to_string(),
+            Item::Quantity(q) => q.to_string(),
+        })
+    }
+
+    /// Singleton evaluation of a collection as a Boolean: `Some(b)` for a
+    /// Boolean, `Some(true)` for any other single item.
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self.to_system() {
+            Item::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+const FHIR_PRIMITIVES: [&str; 20] = [
+    "base64Binary", "boolean", "canonical", "code", "date", "dateTime", "decimal", "id", "instant",
+    "integer", "integer64", "markdown", "oid", "positiveInt", "string", "time", "unsignedInt", "uri",
+    "url", "uuid",
+];
+
+const FHIR_COMPLEX_TYPES: [&str; 43] = [
+    "Address", "Age", "Annotation", "Attachment", "Availability", "CodeableConcept",
+    "CodeableReference", "Coding", "ContactDetail", "ContactPoint", "Contributor", "Count",
+    "DataRequirement", "Distance", "Dosage", "Duration", "Expression", "ExtendedContactDetail",
+    "Extension", "HumanName", "Identifier", "Meta", "Money", "MoneyQuantity", "MonetaryComponent",
+    "Narrative", "ParameterDefinition", "Period", "Quantity", "Range", "Ratio", "RatioRange",
+    "Reference", "RelatedArtifact", "SampledData", "Signature", "SimpleQuantity", "Timing",
+    "TriggerDefinition", "UsageContext", "VirtualServiceDetail", "Element", "BackboneElement",
+];
+
+/// The FHIR type named by the suffix of a choice element (`Quantity` in
+/// `valueQuantity`, `dateTime` in `effectiveDateTime`), if it is one.
+pub(super) fn choice_type(suffix: &str) -> Option<String> {
+    if FHIR_COMPLEX_TYPES.contains(&suffix) {
+        return Some(suffix.to_string());
+    }
+    let mut chars = suffix.chars();
+    let first = chars.next()?;
+    let primitive = first.to_lowercase().collect::<String>() + chars.as_str();
+    FHIR_PRIMITIVES.contains(&primitive.as_str()).then_some(primitive)
+}
+
+fn is_quantity_type(name: &str) -> bool {
+    matches!(
+        name,
+        "Quantity" | "Age" | "Count" | "Distance" | "Duration" | "SimpleQuantity" | "MoneyQuantity"
+    )
+}
+
+/// FHIR type specialisations that matter for `is`/`ofType`.
+fn is_subtype(own: &str, ancestor: &str) -> bool {
+    match ancestor {
+        "Quantity" => is_quantity_type(own),
+        "string" => own == "markdown",
+        "uri" => matches!(own, "url" | "canonical" | "oid" | "uuid"),
+        "integer" => matches!(own, "positiveInt" | "unsignedInt"),
+        "Resource" | "DomainResource" => own.starts_with(char::is_uppercase) && !FHIR_COMPLEX_TYPES.contains(&own),
+        "Element" => !own.starts_with(char::is_uppercase) || FHIR_COMPLEX_TYPES.contains(&own),
+        _ => false,
+    }
+}
+
+/// Whether a JSON string is a lexically valid value of the FHIR primitive
+/// `name`.
+fn string_has_type(s: &str, name: &str) -> bool {
+    match name {
+        "string" | "markdown" => true,
+        "code" => !s.is_empty() && s.trim() == s,
+        "id" => {
+            (1..=64).contains(&s.len())
+                && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        }
+        "uri" | "url" | "canonical" => !s.contains(char::is_whitespace),
+        "oid" => s.starts_with("urn:oid:"),
+        "uuid" => s.starts_with("urn:uuid:"),
+        "base64Binary" => s.chars().all(|c| c.is_ascii_alphanumeric() || "+/= ".contains(c)),
+        "date" => Temporal::parse_date(s).is_some(),
+        "dateTime" => Temporal::parse_date_time(s).is_some() || Temporal::parse_date(s).is_some(),
+        "instant" => Temporal::parse_date_time(s).is_some_and(|t| t.parts.len() >= 6 && t.tz.is_some()),
+        "time" => Temporal::parse_time(s).is_some(),
+        _ => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decimal
+// ---------------------------------------------------------------------------
+
+/// A base-10 decimal (`mantissa × 10^-scale`) that keeps its precision, so
+/// `0.1 + 0.2 = 0.3` holds and `1.0` prints as `1.0`.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+/// Digits kept after the point by division and the transcendental functions.
+const DIVISION_SCALE: u32 = 8;
+/// Largest scale kept by multiplication.
+const MAX_SCALE: u32 = 28;
+
+impl Decimal {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (body, exponent) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+            None => (s, 0),
+        };
+        let (negative, body) = match body.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, body.strip_prefix('+').unwrap_or(body)),
+        };
+        let (int, frac) = body.split_once('.').unwrap_or((body, ""));
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut mantissa: i128 = format!("{int}{frac}").parse().ok()?;
+        let mut scale = frac.len() as i32 - exponent;
+        while scale < 0 {
+            mantissa = mantissa.checked_mul(10)?;
+            scale += 1;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        Some(Self { mantissa, scale: scale as u32 }.limit(MAX_SCALE))
+    }
+
+    pub fn from_i64(i: i64) -> Self {
+        Self { mantissa: i as i128, scale: 0 }
+    }
+
+    pub fn from_f64(f: f64) -> Option<Self> {
+        f.is_finite().then(|| Self::parse(&format!("{f}"))).flatten()
+    }
+
+    fn from_number(n: &serde_json::Number) -> Option<Self> {
+        Self::parse(&n.to_string())
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// Number of digits after the point.
+    pub fn scale(self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.mantissa % 10i128.pow(self.scale) == 0
+    }
+
+    /// The value as an Integer when it has no fractional part.
+    pub fn to_i64(self) -> Option<i64> {
+        if self.is_integer() {
+            i64::try_from(self.mantissa / 10i128.pow(self.scale)).ok()
+        } else {
+            None
+        }
+    }
+
+    fn rescale(self, scale: u32) -> Option<i128> {
+        self.mantissa.checked_mul(10i128.checked_pow(scale.checked_sub(self.scale)?)?)
+    }
+
+    fn aligned(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescale(scale)?, other.rescale(scale)?, scale))
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self { mantissa: a.checked_add(b)?, scale })
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.checked_add(other.negated())
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        Some(
+            Self { mantissa: self.mantissa.checked_mul(other.mantissa)?, scale: self.scale + other.scale }
+                .limit(MAX_SCALE),
+        )
+    }
+
+    /// Division to eight places, trailing zeros dropped. `None` on division by
+    /// zero.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        // self / other = (ms · 10^so) / (mo · 10^ss), scaled by 10^(8+1) and
+        // then rounded to 8 places.
+        let num = self.mantissa.checked_mul(10i128.checked_pow(other.scale + DIVISION_SCALE + 1)?)?;
+        let den = other.mantissa.checked_mul(10i128.checked_pow(self.scale)?)?;
+        Some(Self { mantissa: num / den, scale: DIVISION_SCALE + 1 }.round(DIVISION_SCALE).trimmed())
+    }
+
+    /// Truncated division (`div`).
+    pub fn int_div(self, other: Self) -> Option<Self> {
+        let (a, b, _) = self.aligned(other)?;
+        (b != 0).then(|| Self { mantissa: a / b, scale: 0 })
+    }
+
+    /// Remainder of truncated division (`mod`).
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        (b != 0).then(|| Self { mantissa: a % b, scale })
+    }
+
+    pub fn negated(self) -> Self {
+        Self { mantissa: -self.mantissa, scale: self.scale }
+    }
+
+    pub fn abs(self) -> Self {
+        Self { mantissa: self.mantissa.abs(), scale: self.scale }
+    }
+
+    /// Round half away from zero to `places` digits after the point.
+    pub fn round(self, places: u32) -> Self {
+        if places >= self.scale {
+            return self;
+        }
+        let divisor = 10i128.pow(self.scale - places);
+        let q = self.mantissa / divisor;
+        let r = self.mantissa % divisor;
+        let q = if r.abs() * 2 >= divisor { q + self.mantissa.signum() } else { q };
+        Self { mantissa: q, scale: places }
+    }
+
+    fn limit(self, scale: u32) -> Self {
+        self.round(scale)
+    }
+
+    fn trimmed(mut self) -> Self {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    pub fn truncate(self) -> Self {
+        Self { mantissa: self.mantissa / 10i128.pow(self.scale), scale: 0 }
+    }
+
+    pub fn floor(self) -> Self {
+        let t = self.truncate();
+        if self.mantissa < 0 && !self.is_integer() {
+            Self { mantissa: t.mantissa - 1, scale: 0 }
+        } else {
+            t
+        }
+    }
+
+    pub fn ceiling(self) -> Self {
+        let t = self.truncate();
+        if self.mantissa > 0 && !self.is_integer() {
+            Self { mantissa: t.mantissa + 1, scale: 0 }
+        } else {
+            t
+        }
+    }
+
+    pub fn cmp_value(self, other: Self) -> Ordering {
+        match self.aligned(other) {
+            Some((a, b, _)) => a.cmp(&b),
+            None => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+
+    /// Equivalence: equal when rounded to the precision of the less precise
+    /// operand.
+    pub fn equivalent(self, other: Self) -> bool {
+        let scale = self.scale.min(other.scale);
+        self.round(scale).cmp_value(other.round(scale)) == Ordering::Equal
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{sign}{digits}");
+        }
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        write!(f, "{sign}{int}.{frac}")
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Date, DateTime, Time
+// ---------------------------------------------------------------------------
+
+/// A partial date, dateTime or time: the components present, most
+/// significant first — year, month, day, hour, minute, second, millisecond
+/// for dates; hour onwards for times — and the timezone offset in minutes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Temporal {
+    pub parts: Vec<u32>,
+    pub tz: Option<i32>,
+    /// Whether `parts` start at the hour (a Time).
+    time_only: bool,
+}
+
+/// Index of each unit in a date's `parts`.
+const YEAR: usize = 0;
+const MONTH: usize = 1;
+const DAY: usize = 2;
+const HOUR: usize = 3;
+const MINUTE: usize = 4;
+const SECOND: usize = 5;
+const MILLISECOND: usize = 6;
+
+impl Temporal {
+    /// `YYYY[-MM[-DD]]`
+    pub fn parse_date(s: &str) -> Option<Self> {
+        let parts = parse_date_parts(s)?;
+        Some(Self { parts, tz: None, time_only: false })
+    }
+
+    /// `YYYY[-MM[-DD]]T[hh[:mm[:ss[.fff]]]][Z|±hh:mm]`, the `T` optional
+    /// after a full date with a time.
+    pub fn parse_date_time(s: &str) -> Option<Self> {
+        let (date, rest) = match s.find('T') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let mut parts = parse_date_parts(date)?;
+        if rest.is_empty() {
+            return Some(Self { parts, tz: None, time_only: false });
+        }
+        if parts.len() != 3 {
+            return None;
+        }
+        let (time, tz) = split_timezone(rest)?;
+        parts.extend(parse_time_parts(time)?);
+        Some(Self { parts, tz, time_only: false })
+    }
+
+    /// `hh[:mm[:ss[.fff]]]`
+    pub fn parse_time(s: &str) -> Option<Self> {
+        Some(Self { parts: parse_time_parts(s)?, tz: None, time_only: true })
+    }
+
+    fn from_naive(dt: NaiveDateTime, precision: usize, tz: Option<i32>) -> Self {
+        let date = dt.date();
+        let all = [
+            chrono::Datelike::year(&date) as u32,
+            chrono::Datelike::month(&date),
+            chrono::Datelike::day(&date),
+            dt.hour(),
+            dt.minute(),
+            dt.second(),
+            dt.nanosecond() / 1_000_000,
+        ];
+        Self { parts: all[..precision].to_vec(), tz, time_only: false }
+    }
+
+    /// The current instant at millisecond precision, in UTC.
+    pub fn now() -> Self {
+        Self::from_naive(chrono::Utc::now().naive_utc(), 7, Some(0))
+    }
+
+    pub fn today() -> Self {
+        Self::from_naive(chrono::Local::now().naive_local(), 3, None)
+    }
+
+    pub fn time_of_day() -> Self {
+        let now = Self::from_naive(chrono::Local::now().naive_local(), 7, None);
+        Self { parts: now.parts[HOUR..].to_vec(), tz: None, time_only: true }
+    }
+
+    /// Components as a date, with times placed after a dummy date.
+    fn date_parts(&self) -> Vec<u32> {
+        if self.time_only {
+            [vec![2000, 1, 1], self.parts.clone()].concat()
+        } else {
+            self.parts.clone()
+        }
+    }
+
+    fn to_naive(&self) -> Option<NaiveDateTime> {
+        let p = self.date_parts();
+        let get = |i: usize, default: u32| p.get(i).copied().unwrap_or(default);
+        NaiveDate::from_ymd_opt(get(YEAR, 1) as i32, get(MONTH, 1), get(DAY, 1))?.and_hms_milli_opt(
+            get(HOUR, 0),
+            get(MINUTE, 0),
+            get(SECOND, 0),
+            get(MILLISECOND, 0),
+        )
+    }
+
+    /// Shift to UTC, when both the offset and a time are known.
+    fn in_utc(&self) -> Self {
+        match self.tz {
+            Some(offset) if offset != 0 && self.parts.len() > HOUR && !self.time_only => {
+                match self.to_naive() {
+                    Some(dt) => Self::from_naive(dt - Duration::minutes(offset as i64), self.parts.len(), Some(0)),
+                    None => self.clone(),
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// Component-wise comparison. Seconds and milliseconds are one precision.
+    /// `None` when the values agree as far as both go but one is more
+    /// precise: the result is then unknown.
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        let (a, b) = if self.tz.is_some() && other.tz.is_some() {
+            (self.in_utc(), other.in_utc())
+        } else {
+            (self.clone(), other.clone())
+        };
+        let second = if a.time_only { SECOND - HOUR } else { SECOND };
+        let merge = |parts: &[u32]| -> Vec<u32> {
+            let mut merged = parts.to_vec();
+            if merged.len() > second {
+                let millis = merged.get(second + 1).copied().unwrap_or(0);
+                merged.truncate(second + 1);
+                merged[second] = merged[second] * 1000 + millis;
+            }
+            merged
+        };
+        let (a, b) = (merge(&a.parts), merge(&b.parts));
+        for (x, y) in a.iter().zip(&b) {
+            if x != y {
+                return Some(x.cmp(y));
+            }
+        }
+        (a.len() == b.len()).then_some(Ordering::Equal)
+    }
+
+    /// Add a calendar duration (`unit` one of year … millisecond). A unit
+    /// finer than the value's precision is converted to that precision and
+    /// truncated, so `@2014 + 24 months` is `@2016`.
+    pub fn add(&self, amount: Decimal, unit: &str) -> Option<Self> {
+        const STEP_TO_COARSER: [f64; 7] = [1.0, 12.0, 30.0, 24.0, 60.0, 60.0, 1000.0];
+        let offset = if self.time_only { HOUR } else { 0 };
+        let mut index = match unit {
+            "year" => YEAR,
+            "month" => MONTH,
+            "week" | "day" => DAY,
+            "hour" => HOUR,
+            "minute" => MINUTE,
+            "second" => SECOND,
+            "millisecond" => MILLISECOND,
+            _ => return None,
+        };
+        if index < offset {
+            return None;
+        }
+        let mut amount = amount.to_f64();
+        if unit == "week" {
+            amount *= 7.0;
+        }
+        // Seconds keep their fraction as milliseconds.
+        if index == SECOND && self.parts.len() + offset > MILLISECOND {
+            amount *= 1000.0;
+            index = MILLISECOND;
+        }
+        let precision = self.parts.len() + offset - 1;
+        while index > precision {
+            amount /= STEP_TO_COARSER[index];
+            index -= 1;
+        }
+        let amount = amount.trunc() as i64;
+        let dt = self.to_naive()?;
+        let shifted = match index {
+            YEAR | MONTH => {
+                let months = if index == YEAR { amount * 12 } else { amount };
+                let delta = Months::new(months.unsigned_abs() as u32);
+                if months >= 0 { dt.checked_add_months(delta)? } else { dt.checked_sub_months(delta)? }
+            }
+            DAY => dt + Duration::days(amount),
+            HOUR => dt + Duration::hours(amount),
+            MINUTE => dt + Duration::minutes(amount),
+            SECOND => dt + Duration::seconds(amount),
+            _ => dt + Duration::milliseconds(amount),
+        };
+        let mut result = Self::from_naive(shifted, self.parts.len() + offset, self.tz);
+        if self.time_only {
+            result.parts.drain(..HOUR);
+            result.time_only = true;
+        }
+        Some(result)
+    }
+
+    /// The date of a dateTime: year to day, without the offset.
+    pub fn date_part(&self) -> Self {
+        Self { parts: self.parts[..self.parts.len().min(HOUR)].to_vec(), tz: None, time_only: false }
+    }
+
+    /// Precision as the number of components present.
+    pub fn precision(&self) -> usize {
+        self.parts.len()
+    }
+}
+
+impl fmt::Display for Temporal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (date, time): (&[u32], &[u32]) = if self.time_only {
+            (&[], &self.parts)
+        } else {
+            let split = self.parts.len().min(HOUR);
+            (&self.parts[..split], &self.parts[split..])
+        };
+        for (i, part) in date.iter().enumerate() {
+            match i {
+                0 => write!(f, "{part:04}")?,
+                _ => write!(f, "-{part:02}")?,
+            }
+        }
+        if !self.time_only && time.is_empty() {
+            return Ok(());
+        }
+        if !self.time_only {
+            f.write_str("T")?;
+        }
+        for (i, part) in time.iter().enumerate() {
+            match i {
+                0 => write!(f, "{part:02}")?,
+                3 => write!(f, ".{part:03}")?,
+                _ => write!(f, ":{part:02}")?,
+            }
+        }
+        match self.tz {
+            Some(0) => f.write_str("Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                write!(f, "{sign}{:02}:{:02}", offset.abs() / 60, offset.abs() % 60)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_date_parts(s: &str) -> Option<Vec<u32>> {
+    let mut parts = Vec::new();
+    for (i, piece) in s.split('-').enumerate() {
+        let width = if i == 0 { 4 } else { 2 };
+        if i > 2 || piece.len() != width || !piece.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        parts.push(piece.parse().ok()?);
+    }
+    let valid = match parts.as_slice() {
+        [_] => true,
+        [_, m] => (1..=12).contains(m),
+        [y, m, d] => NaiveDate::from_ymd_opt(*y as i32, *m, *d).is_some(),
+        _ => false,
+    };
+    valid.then_some(parts)
+}
+
+fn parse_time_parts(s: &str) -> Option<Vec<u32>> {
+    let (hms, fraction) = match s.split_once('.') {
+        Some((hms, fraction)) => (hms, Some(fraction)),
+        None => (s, None),
+    };
+    let mut parts = Vec::new();
+    for (i, piece) in hms.split(':').enumerate() {
+        if i > 2 || piece.len() != 2 || !piece.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let value: u32 = piece.parse().ok()?;
+        let limit = if i == 0 { 23 } else { 59 };
+        if value > limit {
+            return None;
+        }
+        parts.push(value);
+    }
+    if let Some(fraction) = fraction {
+        if parts.len() != 3 || fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let millis: String = fraction.chars().chain("00".chars()).take(3).collect();
+        parts.push(millis.parse().ok()?);
+    }
+    Some(parts)
+}
+
+/// Split `hh:mm:ss+09:00` into the time and the offset in minutes.
+fn split_timezone(s: &str) -> Option<(&str, Option<i32>)> {
+    if let Some(time) = s.strip_suffix('Z') {
+        return Some((time, Some(0)));
+    }
+    match s.rfind(['+', '-']) {
+        Some(i) => {
+            let (time, tz) = s.split_at(i);
+            let sign = if tz.starts_with('-') { -1 } else { 1 };
+            let (h, m) = tz[1..].split_once(':')?;
+            if h.len() != 2 || m.len() != 2 {
+                return None;
+            }
+            let minutes = h.parse::<i32>().ok()? * 60 + m.parse::<i32>().ok()?;
+            Some((time, Some(sign * minutes)))
+        }
+        None => Some((s, None)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Quantity
+// ---------------------------------------------------------------------------
+
+/// A value with a unit: a UCUM code, or a calendar duration keyword
+/// (`year` … `millisecond`, always singular here).
+#[derive(Debug, Clone)]
+pub struct Quantity {
+    pub value: Decimal,
+    pub unit: String,
+}
+
+impl Quantity {
+    pub fn new(value: Decimal, unit: &str) -> Self {
+        let unit = match unit.strip_suffix('s') {
+            Some(singular) if is_calendar_unit(singular) => singular,
+            _ => unit,
+        };
+        Self { value, unit: unit.to_string() }
+    }
+
+    /// A Quantity element: `value` with `code`, else `unit`, else `1`.
+    pub fn from_element(v: &Value) -> Option<Self> {
+        let value = v.get("value")?.as_number().and_then(Decimal::from_number)?;
+        let unit = v
+            .get("code")
+            .or_else(|| v.get("unit"))
+            .and_then(Value::as_str)
+            .unwrap_or("1");
+        Some(Self::new(value, unit))
+    }
+
+    /// Parse `toQuantity()` input: `5`, `5 'mg'`, `5 days`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (number, unit) = match s.find(' ') {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => (s, "'1'"),
+        };
+        let value = Decimal::parse(number)?;
+        let unit = match unit.strip_prefix('\'').and_then(|u| u.strip_suffix('\'')) {
+            Some(code) => code,
+            None if is_calendar_unit(unit.trim_end_matches('s')) => unit,
+            None => return None,
+        };
+        Some(Self::new(value, unit))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut q = serde_json::json!({ "value": Item::Decimal(self.value).to_json() });
+        match ucum_for_calendar(&self.unit) {
+            Some(code) => {
+                q["unit"] = Value::from(self.unit.as_str());
+                q["system"] = Value::from("http://unitsofmeasure.org");
+                q["code"] = Value::from(code);
+            }
+            None if is_calendar_unit(&self.unit) => q["unit"] = Value::from(self.unit.as_str()),
+            None => {
+                q["system"] = Value::from("http://unitsofmeasure.org");
+                q["code"] = Value::from(self.unit.as_str());
+            }
+        }
+        q
+    }
+
+    /// The calendar unit this quantity can be added to a date with, if any.
+    pub fn calendar_unit(&self) -> Option<&'static str> {
+        Some(match self.unit.as_str() {
+            "year" | "a" => "year",
+            "month" | "mo" => "month",
+            "week" | "wk" => "week",
+            "day" | "d" => "day",
+            "hour" | "h" => "hour",
+            "minute" | "min" => "minute",
+            "second" | "s" => "second",
+            "millisecond" | "ms" => "millisecond",
+            _ => return None,
+        })
+    }
+
+    /// The two values in a common unit, converting through UCUM when the units
+    /// differ. Calendar durations of a week or less equal their UCUM units.
+    fn commensurate(&self, other: &Self) -> Option<(Decimal, Decimal)> {
+        let unit = |q: &Self| ucum_for_calendar(&q.unit).map(String::from).unwrap_or_else(|| q.unit.clone());
+        let (a, b) = (unit(self), unit(other));
+        if a == b {
+            return Some((self.value, other.value));
+        }
+        if is_calendar_unit(&a) || is_calendar_unit(&b) {
+            return None;
+        }
+        let (va, ua) = crate::ucum::canonicalize(self.value.to_f64(), &a)?;
+        let (vb, ub) = crate::ucum::canonicalize(other.value.to_f64(), &b)?;
+        if ua != ub {
+            return None;
+        }
+        // Conversion factors are binary floats: compare at 12 significant
+        // digits.
+        let round = |v: f64| {
+            let digits = 12 - v.abs().log10().ceil().max(0.0) as i32;
+            Decimal::from_f64(v).map(|d| d.round(digits.max(0) as u32))
+        };
+        Some((round(va)?, round(vb)?))
+    }
+
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        let (a, b) = self.commensurate(other)?;
+        Some(a.cmp_value(b))
+    }
+
+    pub fn equivalent(&self, other: &Self) -> bool {
+        let unit = |q: &Self| q.calendar_unit().map(String::from).unwrap_or_else(|| q.unit.clone());
+        if unit(self) == unit(other) {
+            return self.value.equivalent(other.value);
+        }
+        self.commensurate(other).is_some_and(|(a, b)| a.equivalent(b))
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if is_calendar_unit(&self.unit) {
+            write!(f, "{} {}", self.value, self.unit)
+        } else {
+            write!(f, "{} '{}'", self.value, self.unit)
+        }
+    }
+}
+
+fn is_calendar_unit(unit: &str) -> bool {
+    matches!(unit, "year" | "month" | "week" | "day" | "hour" | "minute" | "second" | "millisecond")
+}
+
+/// UCUM code equal to a calendar duration; years and months have none.
+fn ucum_for_calendar(unit: &str) -> Option<&'static str> {
+    Some(match unit {
+        "week" => "wk",
+        "day" => "d",
+        "hour" => "h",
+        "minute" => "min",
+        "second" => "s",
+        "millisecond" => "ms",
+        _ => return None,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Equality, equivalence, ordering
+// ---------------------------------------------------------------------------
+
+/// Bring a String operand to the temporal type it is compared with, so
+/// `Patient.birthDate = @1974-12-25` compares dates.
+fn coerce<'a>(a: Item<'a>, b: Item<'a>) -> (Item<'a>, Item<'a>) {
+    fn temporal_like<'a>(s: &str, like: &Item<'a>) -> Option<Item<'a>> {
+        match like {
+            Item::Date(_) | Item::DateTime(_) => date_from_string(s),
+            Item::Time(_) => Temporal::parse_time(s).map(Item::Time),
+            _ => None,
+        }
+    }
+    match (&a, &b) {
+        (Item::String(s), other) => match temporal_like(s, other) {
+            Some(t) => (t, b),
+            None => (a, b),
+        },
+        (other, Item::String(s)) => match temporal_like(s, other) {
+            Some(t) => (a, t),
+            None => (a, b),
+        },
+        _ => (a, b),
+    }
+}
+
+/// A date or dateTime element read as a plain string: a Date up to day
+/// precision, a DateTime beyond.
+pub(super) fn date_from_string<'a>(s: &str) -> Option<Item<'a>> {
+    Temporal::parse_date_time(s)
+        .map(|t| if t.parts.len() <= DAY + 1 { Item::Date(t) } else { Item::DateTime(t) })
+}
+
+/// `=` on two items: `Some(bool)`, or `None` when the result is unknown
+/// (dates of different precision).
+pub fn equals(a: &Item, b: &Item) -> Option<bool> {
+    let (a, b) = coerce(a.to_system(), b.to_system());
+    Some(match (&a, &b) {
+        (Item::Boolean(x), Item::Boolean(y)) => x == y,
+        (Item::String(x), Item::String(y)) => x == y,
+        (Item::Integer(x), Item::Integer(y)) => x == y,
+        (Item::Integer(_) | Item::Decimal(_), Item::Integer(_) | Item::Decimal(_)) => {
+            to_decimal(&a)?.cmp_value(to_decimal(&b)?) == Ordering::Equal
+        }
+        (Item::Date(x) | Item::DateTime(x), Item::Date(y) | Item::DateTime(y))
+        | (Item::Time(x), Item::Time(y)) => x.compare(y)? == Ordering::Equal,
+        (Item::Quantity(x), Item::Quantity(y)) => x.compare(y)? == Ordering::Equal,
+        (Item::Element(x), Item::Element(y)) => x.value == y.value,
+        _ => false,
+    })
+}
+
+/// `~` on two items.
+pub fn equivalent(a: &Item, b: &Item) -> bool {
+    let (a, b) = coerce(a.to_system(), b.to_system());
+    match (&a, &b) {
+        (Item::String(x), Item::String(y)) => normalize_whitespace(x) == normalize_whitespace(y),
+        (Item::Integer(_) | Item::Decimal(_), Item::Integer(_) | Item::Decimal(_)) => {
+            matches!((to_decimal(&a), to_decimal(&b)), (Some(x), Some(y)) if x.equivalent(y))
+        }
+        (Item::Date(x) | Item::DateTime(x), Item::Date(y) | Item::DateTime(y))
+        | (Item::Time(x), Item::Time(y)) => x.compare(y) == Some(Ordering::Equal),
+        (Item::Quantity(x), Item::Quantity(y)) => x.equivalent(y),
+        _ => equals(&a, &b) == Some(true),
+    }
+}
+
+fn normalize_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Ordering for `<`, `>`, … . `Err` for types that cannot be ordered,
+/// `Ok(None)` when the result is unknown.
+pub fn compare(a: &Item, b: &Item) -> Result<Option<Ordering>, String> {
+    let (a, b) = coerce(a.to_system(), b.to_system());
+    Ok(match (&a, &b) {
+        (Item::String(x), Item::String(y)) => Some(x.cmp(y)),
+        (Item::Integer(x), Item::Integer(y)) => Some(x.cmp(y)),
+        (Item::Integer(_) | Item::Decimal(_), Item::Integer(_) | Item::Decimal(_)) => {
+            to_decimal(&a).zip(to_decimal(&b)).map(|(x, y)| x.cmp_value(y))
+        }
+        (Item::Date(x) | Item::DateTime(x), Item::Date(y) | Item::DateTime(y))
+        | (Item::Time(x), Item::Time(y)) => x.compare(y),
+        (Item::Quantity(x), Item::Quantity(y)) => x.compare(y),
+        _ => {
+            return Err(format!(
+                "cannot compare {} with {}",
+                a.type_name().1,
+                b.type_name().1
+            ));
+        }
+    })
+}
+
+pub(super) fn to_decimal(item: &Item) -> Option<Decimal> {
+    match item {
+        Item::Integer(i) => Some(Decimal::from_i64(*i)),
+        Item::Decimal(d) => Some(*d),
+        _ => None,
+    }
+}
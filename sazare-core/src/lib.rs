@@ -7,6 +7,7 @@ pub mod resource;
 pub mod resource_filter;
 pub mod search_param;
 pub mod search_param_registry;
+pub mod ucum;
 pub mod validation;
 
 pub use error::{Result, SazareError};
@@ -15,7 +16,8 @@ pub use operation_outcome::{
 };
 pub use resource::{Meta, Resource};
 pub use search_param::{
-    ChainParameter, HasParameter, SearchParamType, SearchParameter, SearchQuery, SummaryMode,
+    ChainLink, ChainParameter, ContainedMode, ContainedType, HasLink, HasParameter, SearchParamType, SearchParameter, SearchQuery, SortKey,
+    SummaryMode, TextToken, TotalMode,
 };
 pub use search_param_registry::{ExtractionMode, SearchParamDef, SearchParamRegistry};
 pub use compartment::CompartmentDef;
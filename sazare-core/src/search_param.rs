@@ -1,3 +1,6 @@
+use crate::search_param_registry::SearchParamRegistry;
+use crate::validation::TerminologyRegistry;
+
 /// FHIR search query parsed from HTTP query parameters
 #[derive(Debug, Clone)]
 pub struct SearchQuery {
@@ -6,48 +9,80 @@ pub struct SearchQuery {
     pub has_parameters: Vec<HasParameter>,
     pub include: Vec<String>,
     pub revinclude: Vec<String>,
+    /// `_include:iterate` specs, applied to the matches and then repeatedly
+    /// to what they include.
+    pub include_iterate: Vec<String>,
+    /// `_revinclude:iterate` specs, likewise.
+    pub revinclude_iterate: Vec<String>,
     pub count: Option<usize>,
     pub offset: Option<usize>,
     pub summary: Option<SummaryMode>,
     pub elements: Vec<String>,
+    /// `_sort` keys in priority order (`_sort=date,-_lastUpdated`).
+    pub sort: Vec<SortKey>,
+    /// `_page_token`: the opaque cursor of a next/previous page link. The
+    /// server signs and interprets it; the parser only carries it through.
+    pub page_token: Option<String>,
+    /// `_total`: how (or whether) to compute `Bundle.total`. `None` when the
+    /// client didn't ask, leaving the choice to the server's default.
+    pub total: Option<TotalMode>,
+    /// `_contained`: whether resources contained in other resources match.
+    pub contained: ContainedMode,
+    /// `_containedType`: what a contained match returns.
+    pub contained_type: ContainedType,
 }
 
-/// A reverse-chained `_has` search parameter (one level).
+/// One `_sort` key. A leading `-` in the query value sorts descending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    /// Search parameter name to order by (e.g. "date", "_lastUpdated").
+    pub name: String,
+    pub descending: bool,
+    /// Inferred type of the parameter; decides which index column is compared.
+    pub param_type: SearchParamType,
+}
+
+/// A reverse-chained `_has` search parameter.
 ///
 /// `Patient?_has:Observation:patient:code=1234-5` reads as "Patients that are
 /// referenced by an Observation whose `code` is 1234-5". It is the mirror image
 /// of a forward chain: instead of filtering the searched type by a property of
 /// what it points to, it filters the searched type by a property of resources
-/// that point *at* it.
+/// that point *at* it. Nested:
+/// `Patient?_has:Observation:patient:_has:AuditEvent:entity:agent=Practitioner/1`
+/// — Patients with an Observation that an AuditEvent by that agent refers to.
 #[derive(Debug, Clone)]
 pub struct HasParameter {
+    /// Reverse hops from the searched type inward (always at least one).
+    pub links: Vec<HasLink>,
+    /// The search parameter the innermost source type must match, with its
+    /// own modifier and prefix (e.g. `date=ge2024-01-01`, `code:text=sugar`).
+    pub param: SearchParameter,
+}
+
+/// One reverse hop within a `_has` search.
+#[derive(Debug, Clone)]
+pub struct HasLink {
     /// The resource type that holds the back-reference (e.g. "Observation").
     pub source_type: String,
-    /// The reference parameter on the source pointing back at the searched type
-    /// (e.g. "patient").
+    /// The reference parameter on the source pointing back at the previous
+    /// hop's type (e.g. "patient").
     pub reference_param: String,
-    /// The search parameter applied to the source type (e.g. "code").
-    pub target_param: String,
-    /// The value the source's `target_param` must match.
-    pub value: String,
-    /// Inferred type of `target_param` on the source resource.
-    pub target_param_type: SearchParamType,
 }
 
 /// A chained search parameter. One level: `subject:Patient.name=Doe`.
 /// Multi-level: `subject:Patient.organization:Organization.name=Acme` — the
 /// `links` walk references outward from the source resource, and the terminal
-/// `target_param`/`value` apply to the final target type.
+/// `param` applies to the final target type. A hop may leave its type out
+/// (`subject.name=Doe`); [`ChainParameter::resolve_targets`] then fills in
+/// every type the reference can point at, and the search fans out across them.
 #[derive(Debug, Clone)]
 pub struct ChainParameter {
     /// Reference hops from the source resource outward (always at least one).
     pub links: Vec<ChainLink>,
-    /// The search parameter on the final target resource (e.g. "name")
-    pub target_param: String,
-    /// The search value (e.g. "Doe")
-    pub value: String,
-    /// Inferred type of the terminal parameter
-    pub target_param_type: SearchParamType,
+    /// The search parameter on the final target resource, with its own
+    /// modifier and prefix (e.g. `name:exact=Doe`, `birthdate=lt1950`).
+    pub param: SearchParameter,
 }
 
 /// One reference hop within a chained search.
@@ -55,8 +90,49 @@ pub struct ChainParameter {
 pub struct ChainLink {
     /// The reference parameter on the current resource (e.g. "subject")
     pub reference_param: String,
-    /// The resource type it points to (e.g. "Patient")
-    pub target_type: String,
+    /// The resource types it points to (e.g. ["Patient"]). Empty until
+    /// resolved when the query didn't name one.
+    pub target_types: Vec<String>,
+}
+
+impl ChainParameter {
+    /// Fill in the target types of hops that didn't name one from the
+    /// registry's reference targets, and type the terminal parameter by the
+    /// final target type. Fails when a hop's targets can't be told, so the
+    /// client can name the type instead.
+    pub fn resolve_targets(
+        &mut self,
+        registry: &SearchParamRegistry,
+        resource_type: &str,
+    ) -> Result<(), String> {
+        let mut source_types = vec![resource_type.to_string()];
+        for link in &mut self.links {
+            if link.target_types.is_empty() {
+                for source_type in &source_types {
+                    for target in registry.reference_targets(source_type, &link.reference_param) {
+                        if !link.target_types.contains(&target) {
+                            link.target_types.push(target);
+                        }
+                    }
+                }
+                if link.target_types.is_empty() {
+                    return Err(format!(
+                        "Cannot tell which resource type '{}' refers to in this chain; \
+                         name it, e.g. '{}:Patient'",
+                        link.reference_param, link.reference_param
+                    ));
+                }
+            }
+            source_types = link.target_types.clone();
+        }
+        if let Some(t) = source_types
+            .iter()
+            .find_map(|t| registry.lookup_param_type(t, &self.param.name))
+        {
+            self.param.retype(t);
+        }
+        Ok(())
+    }
 }
 
 /// _summary parameter modes
@@ -69,23 +145,172 @@ pub enum SummaryMode {
     Data,
 }
 
+/// `_total` modes: whether `Bundle.total` is omitted, estimated, or counted
+/// exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TotalMode {
+    None,
+    Estimate,
+    Accurate,
+}
+
+/// `_contained` modes: match only top-level resources (the default), only
+/// resources contained in another, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContainedMode {
+    #[default]
+    False,
+    True,
+    Both,
+}
+
+/// `_containedType`: return a contained match inside its container (the
+/// default) or as the contained resource itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContainedType {
+    #[default]
+    Container,
+    Contained,
+}
+
 /// A single search parameter
 #[derive(Debug, Clone)]
 pub struct SearchParameter {
     pub name: String,
     pub value: String,
     pub modifier: Option<String>,
-    pub prefix: Option<String>,  // For date searches: ge, le, gt, lt, eq
+    pub prefix: Option<String>,  // For date/number/quantity searches: ge, le, gt, lt, eq
     pub param_type: SearchParamType,
 }
 
+impl SearchParameter {
+    /// Check the modifier against those this parameter's type supports, so an
+    /// unsupported one is refused instead of quietly read as a plain match.
+    /// Reference parameters also take a target type (`subject:Patient`).
+    pub fn check_modifier(&self) -> Result<(), String> {
+        let Some(modifier) = self.modifier.as_deref() else {
+            return Ok(());
+        };
+        let supported: &[&str] = match &self.param_type {
+            _ if self.name == "_text" || self.name == "_content" => &["missing", "not"],
+            SearchParamType::Token => {
+                &["missing", "not", "text", "of-type", "in", "not-in", "above", "below"]
+            }
+            SearchParamType::String => &["missing", "not", "exact", "contains"],
+            SearchParamType::Uri => &["missing", "not", "above", "below"],
+            SearchParamType::Reference => {
+                if modifier.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    return Ok(());
+                }
+                &["missing", "not", "identifier"]
+            }
+            SearchParamType::Date
+            | SearchParamType::Number
+            | SearchParamType::Quantity
+            | SearchParamType::Composite(_) => &["missing", "not"],
+        };
+        if !supported.contains(&modifier) {
+            return Err(format!(
+                "Modifier ':{modifier}' is not supported on '{}' (supported: {})",
+                self.name,
+                supported.iter().map(|m| format!(":{m}")).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        if modifier == "of-type" {
+            for v in self.value.split(',') {
+                if v.splitn(3, '|').count() != 3 {
+                    return Err(format!(
+                        "'{}:of-type' expects [type system]|[type code]|[value], got '{v}'",
+                        self.name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the terminology-based token modifiers into plain code matches:
+    /// `:in` / `:not-in` a ValueSet into (not) any of its codes, and `:below` /
+    /// `:above` a `system|code` into that code and those beneath / above it in
+    /// the CodeSystem's hierarchy. Fails when the ValueSet or CodeSystem isn't
+    /// loaded.
+    pub fn resolve_terminology(&mut self, terminology: &TerminologyRegistry) -> Result<(), String> {
+        if self.param_type != SearchParamType::Token {
+            return Ok(());
+        }
+        let modifier = self.modifier.clone().unwrap_or_default();
+        let mut codes: Vec<String> = Vec::new();
+        for v in self.value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match modifier.as_str() {
+                "in" | "not-in" => {
+                    let found = terminology
+                        .value_set_codes(v)
+                        .ok_or_else(|| format!("ValueSet '{v}' for '{}:{modifier}' is not loaded", self.name))?;
+                    codes.extend(found.iter().cloned());
+                }
+                "above" | "below" => {
+                    let (system, code) = v.split_once('|').filter(|(s, _)| !s.is_empty()).ok_or_else(|| {
+                        format!("'{}:{modifier}' expects system|code, got '{v}'", self.name)
+                    })?;
+                    let related = if modifier == "below" {
+                        terminology.descendants_or_self(system, code)
+                    } else {
+                        terminology.ancestors_or_self(system, code)
+                    };
+                    let related = related.ok_or_else(|| {
+                        format!("Code '{code}' is not in a loaded CodeSystem '{system}'")
+                    })?;
+                    codes.extend(related.into_iter().map(|c| format!("{system}|{c}")));
+                }
+                _ => return Ok(()),
+            }
+        }
+        self.value = codes.join(",");
+        self.modifier = (modifier == "not-in").then(|| "not".to_string());
+        Ok(())
+    }
+
+    /// Re-type a parameter once its authoritative type is known (e.g. from the
+    /// server's live registry rather than the parser's built-in one), moving the
+    /// comparator prefix into or out of `prefix` to match the new type.
+    pub fn retype(&mut self, param_type: SearchParamType) {
+        if self.param_type == param_type {
+            return;
+        }
+        let raw = match self.prefix.take() {
+            Some(p) => format!("{p}{}", self.value),
+            None => std::mem::take(&mut self.value),
+        };
+        let (prefix, value) = if param_type.takes_prefix() {
+            parse_prefix(&raw)
+        } else {
+            (None, raw)
+        };
+        self.prefix = prefix;
+        self.value = value;
+        self.param_type = param_type;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SearchParamType {
     Token,    // identifier, code
     String,   // name, family
     Date,     // birthdate, date
     Reference, // subject, patient
-    Number,   // _count, _offset
+    Number,   // probability, dose-number
+    Quantity, // value-quantity
+    Uri,      // url
+    /// code-value-quantity; the part types, in order (values are `$`-joined)
+    Composite(Vec<SearchParamType>),
+}
+
+impl SearchParamType {
+    /// Whether values of this type may carry a comparator prefix (`ge`, `lt`, …).
+    pub fn takes_prefix(&self) -> bool {
+        matches!(self, Self::Date | Self::Number | Self::Quantity)
+    }
 }
 
 impl SearchQuery {
@@ -103,10 +328,17 @@ impl SearchQuery {
         let mut has_parameters = Vec::new();
         let mut include = Vec::new();
         let mut revinclude = Vec::new();
+        let mut include_iterate = Vec::new();
+        let mut revinclude_iterate = Vec::new();
         let mut count = None;
         let mut offset = None;
         let mut summary = None;
         let mut elements = Vec::new();
+        let mut sort = Vec::new();
+        let mut page_token = None;
+        let mut total = None;
+        let mut contained = ContainedMode::default();
+        let mut contained_type = ContainedType::default();
 
         if query_string.is_empty() {
             return Ok(Self {
@@ -115,10 +347,17 @@ impl SearchQuery {
                 has_parameters,
                 include,
                 revinclude,
+                include_iterate,
+                revinclude_iterate,
                 count,
                 offset,
                 summary,
                 elements,
+                sort,
+                page_token,
+                total,
+                contained,
+                contained_type,
             });
         }
 
@@ -153,6 +392,17 @@ impl SearchQuery {
                 continue;
             }
 
+            // `:recurse` is the pre-R4 spelling of `:iterate`.
+            if key == "_include:iterate" || key == "_include:recurse" {
+                include_iterate.push(value.to_string());
+                continue;
+            }
+
+            if key == "_revinclude:iterate" || key == "_revinclude:recurse" {
+                revinclude_iterate.push(value.to_string());
+                continue;
+            }
+
             if key == "_count" {
                 count = value.parse().ok();
                 continue;
@@ -163,6 +413,11 @@ impl SearchQuery {
                 continue;
             }
 
+            if key == "_page_token" {
+                page_token = Some(value.to_string());
+                continue;
+            }
+
             if key == "_summary" {
                 summary = match value.as_ref() {
                     "true" => Some(SummaryMode::True),
@@ -175,18 +430,73 @@ impl SearchQuery {
                 continue;
             }
 
+            if key == "_total" {
+                total = Some(match value.as_ref() {
+                    "none" => TotalMode::None,
+                    "estimate" => TotalMode::Estimate,
+                    "accurate" => TotalMode::Accurate,
+                    other => {
+                        return Err(format!(
+                            "_total must be none, estimate or accurate, got '{other}'"
+                        ))
+                    }
+                });
+                continue;
+            }
+
+            if key == "_contained" {
+                contained = match value.as_ref() {
+                    "false" => ContainedMode::False,
+                    "true" => ContainedMode::True,
+                    "both" => ContainedMode::Both,
+                    other => {
+                        return Err(format!("_contained must be true, false or both, got '{other}'"))
+                    }
+                };
+                continue;
+            }
+
+            if key == "_containedType" {
+                contained_type = match value.as_ref() {
+                    "container" => ContainedType::Container,
+                    "contained" => ContainedType::Contained,
+                    other => {
+                        return Err(format!(
+                            "_containedType must be container or contained, got '{other}'"
+                        ))
+                    }
+                };
+                continue;
+            }
+
             if key == "_elements" {
                 elements = value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
                 continue;
             }
 
+            if key == "_sort" {
+                sort.extend(parse_sort(&value, resource_type));
+                continue;
+            }
+
             // Skip other standard result parameters that start with "_"
-            // (e.g. _sort, _total, _contained, _containedType)
+            // (e.g. _format, _pretty)
             // These are not search filters and should be ignored if unsupported.
             // Allowlist underscore-prefixed params that ARE search filters.
             const UNDERSCORE_SEARCH_PARAMS: &[&str] =
-                &["_id", "_lastUpdated", "_profile", "_tag", "_security"];
-            if key.starts_with('_') && !UNDERSCORE_SEARCH_PARAMS.contains(&key.as_ref()) {
+                &["_id", "_lastUpdated", "_profile", "_tag", "_security", "_text", "_content"];
+            let base_name = key.split(':').next().unwrap_or_default();
+            if key.starts_with('_') && !UNDERSCORE_SEARCH_PARAMS.contains(&base_name) {
+                continue;
+            }
+
+            // Detect chain search: hops separated by '.' (e.g. "subject.name",
+            // "subject:Patient.name", or multi-level
+            // "subject:Patient.organization:Organization.name").
+            if key.contains('.')
+                && let Some(chain) = parse_chain(&key, &value)
+            {
+                chain_parameters.push(chain);
                 continue;
             }
 
@@ -198,22 +508,20 @@ impl SearchQuery {
                 (key.to_string(), None)
             };
 
-            // Detect chain search: modifier contains "." (e.g. "Patient.name",
-            // or multi-level "Patient.organization:Organization.name").
-            if let Some(ref mod_str) = modifier
-                && mod_str.contains('.')
-                && let Some(chain) = parse_chain(&param_name, mod_str, &value)
-            {
-                chain_parameters.push(chain);
-                continue;
+            // Full-text queries are checked here so a malformed one is the
+            // client's error; each comma-separated alternative stands alone.
+            if param_name == "_text" || param_name == "_content" {
+                for alternative in value.split(',').filter(|v| !v.trim().is_empty()) {
+                    parse_text_query(alternative)?;
+                }
             }
 
             // Infer parameter type from name (registry-aware when resource_type is provided)
             let param_type = infer_param_type_for_resource(resource_type, &param_name);
 
-            // Parse date prefix (ge, le, gt, lt, eq)
-            let (prefix, actual_value) = if param_type == SearchParamType::Date {
-                parse_date_prefix(&value)
+            // Parse comparator prefix (ge, le, gt, lt, eq, …)
+            let (prefix, actual_value) = if param_type.takes_prefix() {
+                parse_prefix(&value)
             } else {
                 (None, value.to_string())
             };
@@ -227,16 +535,32 @@ impl SearchQuery {
             });
         }
 
+        // Type-less chain hops are resolved against the built-in definitions
+        // where they can be; the server re-resolves the rest against its live
+        // registry and reports the ones that still can't be.
+        if let Some(rt) = resource_type {
+            for chain in &mut chain_parameters {
+                let _ = chain.resolve_targets(&DEFAULT_REGISTRY, rt);
+            }
+        }
+
         Ok(Self {
             parameters,
             chain_parameters,
             has_parameters,
             include,
             revinclude,
+            include_iterate,
+            revinclude_iterate,
             count,
             offset,
             summary,
             elements,
+            sort,
+            page_token,
+            total,
+            contained,
+            contained_type,
         })
     }
 
@@ -246,97 +570,127 @@ impl SearchQuery {
     }
 }
 
-/// Parse a (possibly multi-level) chained search parameter.
-///
-/// `reference_param` is the first reference param (left of the first `:`), and
-/// `modifier` is everything after it, e.g. `Patient.organization:Organization.name`.
-/// Each hop has the shape `<TargetType>.<rest>` where `rest` is either another
-/// hop (`<ref>:<Type>.…`) or the terminal search param.
-fn parse_chain(reference_param: &str, modifier: &str, value: &str) -> Option<ChainParameter> {
-    let mut links = Vec::new();
-    let mut current_ref = reference_param.to_string();
-    let mut rest = modifier;
-
-    loop {
-        // Each hop starts with the target type, then '.'.
-        let dot = rest.find('.')?;
-        let target_type = rest[..dot].to_string();
-        if target_type.is_empty() {
-            return None;
-        }
-        let after = &rest[dot + 1..];
-
-        // `after` is a further hop iff it contains "<ref>:<Type>." — i.e. a ':'
-        // whose suffix still has a '.'. Otherwise it is the terminal param (a
-        // bare param name, possibly with its own ':modifier').
-        if let Some(colon) = after.find(':') {
-            let post = &after[colon + 1..];
-            if post.contains('.') {
-                let next_ref = after[..colon].to_string();
-                if next_ref.is_empty() {
-                    return None;
-                }
-                links.push(ChainLink { reference_param: current_ref, target_type });
-                current_ref = next_ref;
-                rest = post;
-                continue;
+/// Parse a `_sort` value into ordered keys: `date,-_lastUpdated,family`.
+/// Repeated `_sort` parameters append, so earlier keys keep priority.
+fn parse_sort(value: &str, resource_type: Option<&str>) -> Vec<SortKey> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .filter_map(|k| {
+            let (descending, name) = match k.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, k),
+            };
+            if name.is_empty() {
+                return None;
             }
-        }
+            Some(SortKey {
+                name: name.to_string(),
+                descending,
+                param_type: infer_param_type_for_resource(resource_type, name),
+            })
+        })
+        .collect()
+}
 
-        if after.is_empty() {
+/// Parse a chained parameter key: reference hops separated by '.', each
+/// optionally naming its target type (`subject:Patient`), then the terminal
+/// parameter with an optional modifier, e.g.
+/// `subject:Patient.organization.name:exact`. A hop without a type is left
+/// for [`ChainParameter::resolve_targets`].
+fn parse_chain(key: &str, value: &str) -> Option<ChainParameter> {
+    let mut hops: Vec<&str> = key.split('.').collect();
+    let terminal = hops.pop()?;
+    if hops.is_empty() {
+        return None;
+    }
+    let mut links = Vec::with_capacity(hops.len());
+    for hop in hops {
+        let (reference_param, target_type) = match hop.split_once(':') {
+            Some((r, t)) => (r, Some(t)),
+            None => (hop, None),
+        };
+        if reference_param.is_empty() || target_type.is_some_and(|t| t.is_empty() || t.contains(':')) {
             return None;
         }
-        links.push(ChainLink { reference_param: current_ref, target_type });
-        return Some(ChainParameter {
-            links,
-            target_param: after.to_string(),
-            value: value.to_string(),
-            target_param_type: infer_param_type(after),
+        links.push(ChainLink {
+            reference_param: reference_param.to_string(),
+            target_types: target_type.map(|t| vec![t.to_string()]).unwrap_or_default(),
         });
     }
+    let (name, modifier) = match terminal.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier.to_string())),
+        None => (terminal, None),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let last_type = links.last().and_then(|l| l.target_types.first()).map(String::as_str);
+    let param_type = infer_param_type_for_resource(last_type, name);
+    let (prefix, value) = if param_type.takes_prefix() {
+        parse_prefix(value)
+    } else {
+        (None, value.to_string())
+    };
+    Some(ChainParameter {
+        links,
+        param: SearchParameter { name: name.to_string(), value, modifier, prefix, param_type },
+    })
 }
 
-/// Parse a one-level `_has` parameter key of the form
-/// `_has:<SourceType>:<reference-param>:<search-param>`.
+/// Parse a `_has` parameter key of the form
+/// `_has:<SourceType>:<reference-param>:<search-param>[:<modifier>]`, where the
+/// search-param may itself be a further `_has:<SourceType>:<reference-param>:…`.
 ///
-/// Nested reverse chains (`_has:…:_has:…`) are not yet supported and return
-/// `None` so the parameter is ignored rather than mis-parsed.
+/// Malformed keys return `None` so the parameter is ignored rather than
+/// mis-parsed.
 fn parse_has(key: &str, value: &str) -> Option<HasParameter> {
     // key == "_has:Observation:patient:code"
-    let rest = key.strip_prefix("_has:")?;
-    let parts: Vec<&str> = rest.split(':').collect();
-    if parts.len() != 3 {
-        return None;
-    }
-    let source_type = parts[0];
-    let reference_param = parts[1];
-    let target_param = parts[2];
-    if source_type.is_empty() || reference_param.is_empty() || target_param.is_empty() {
-        return None;
-    }
-    // Nested `_has` (target_param == "_has") is out of scope for now.
-    if target_param == "_has" {
-        return None;
+    let mut parts = key.strip_prefix("_has:")?.split(':');
+    let mut links = Vec::new();
+    loop {
+        let source_type = parts.next().filter(|s| !s.is_empty())?;
+        let reference_param = parts.next().filter(|s| !s.is_empty())?;
+        links.push(HasLink {
+            source_type: source_type.to_string(),
+            reference_param: reference_param.to_string(),
+        });
+        let name = parts.next().filter(|s| !s.is_empty())?;
+        if name == "_has" {
+            continue;
+        }
+        let modifier = parts.next().map(str::to_string);
+        if parts.next().is_some() {
+            return None;
+        }
+        let param_type = infer_param_type_for_resource(Some(source_type), name);
+        let (prefix, value) = if param_type.takes_prefix() {
+            parse_prefix(value)
+        } else {
+            (None, value.to_string())
+        };
+        return Some(HasParameter {
+            links,
+            param: SearchParameter { name: name.to_string(), value, modifier, prefix, param_type },
+        });
     }
-    Some(HasParameter {
-        source_type: source_type.to_string(),
-        reference_param: reference_param.to_string(),
-        target_param: target_param.to_string(),
-        value: value.to_string(),
-        target_param_type: infer_param_type_for_resource(Some(source_type), target_param),
-    })
 }
 
-/// Parse date prefix from value (ge2020-01-01 -> (Some("ge"), "2020-01-01")).
+/// Parse a comparator prefix from a date, number or quantity value
+/// (ge2020-01-01 -> (Some("ge"), "2020-01-01"), gt5.4 -> (Some("gt"), "5.4")).
 /// Recognizes all FHIR comparator prefixes: eq, ne, gt, lt, ge, le, sa, eb, ap.
-fn parse_date_prefix(value: &str) -> (Option<String>, String) {
+pub fn parse_prefix(value: &str) -> (Option<String>, String) {
     const PREFIXES: [&str; 9] = ["eq", "ne", "gt", "lt", "ge", "le", "sa", "eb", "ap"];
-    // A prefix only counts if what follows looks like a date (starts with a
-    // digit), so a literal value that happens to start with these letters isn't
-    // misparsed.
+    // A prefix only counts if what follows looks like a date or number (starts
+    // with a digit, sign or decimal point), so a literal value that happens to
+    // start with these letters isn't misparsed.
     for prefix in PREFIXES {
         if let Some(rest) = value.strip_prefix(prefix)
-            && rest.chars().next().is_some_and(|c| c.is_ascii_digit())
+            && rest
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.'))
         {
             return (Some(prefix.to_string()), rest.to_string());
         }
@@ -344,19 +698,118 @@ fn parse_date_prefix(value: &str) -> (Option<String>, String) {
     (Some("eq".to_string()), value.to_string())
 }
 
-/// Infer search parameter type from parameter name (backward-compatible, no resource context)
-fn infer_param_type(name: &str) -> SearchParamType {
-    infer_param_type_for_resource(None, name)
+/// One token of a `_text` / `_content` full-text query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextToken {
+    /// A bare word.
+    Term(String),
+    /// A word ending in `*`, matching any word it begins.
+    Prefix(String),
+    /// A `"quoted phrase"`: its words adjacent and in order.
+    Phrase(String),
+    And,
+    Or,
+    /// Binary: `a NOT b` matches `a` without `b`.
+    Not,
+    Open,
+    Close,
 }
 
+/// Tokenize and check a full-text query. Adjacent operands are implicitly
+/// ANDed, `AND`/`OR`/`NOT` (upper case) join operands, and parentheses group.
+/// Errors on unbalanced quotes or parentheses and misplaced operators.
+pub fn parse_text_query(value: &str) -> Result<Vec<TextToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = value.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { TextToken::Open } else { TextToken::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => phrase.push(c),
+                        None => return Err(format!("Unbalanced quote in text search '{value}'")),
+                    }
+                }
+                if !phrase.trim().is_empty() {
+                    tokens.push(TextToken::Phrase(phrase.trim().to_string()));
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => TextToken::And,
+                    "OR" => TextToken::Or,
+                    "NOT" => TextToken::Not,
+                    w => match w.strip_suffix('*') {
+                        Some(stem) if !stem.is_empty() => TextToken::Prefix(stem.to_string()),
+                        _ => TextToken::Term(w.to_string()),
+                    },
+                });
+            }
+        }
+    }
+
+    // Operands and operators must alternate (adjacent operands are an
+    // implicit AND), and every group must hold an expression.
+    let mut depth = 0usize;
+    let mut expect_operand = true;
+    for token in &tokens {
+        match token {
+            TextToken::Term(_) | TextToken::Prefix(_) | TextToken::Phrase(_) => expect_operand = false,
+            TextToken::Open => {
+                depth += 1;
+                expect_operand = true;
+            }
+            TextToken::Close => {
+                if expect_operand || depth == 0 {
+                    return Err(format!("Unbalanced or empty parentheses in text search '{value}'"));
+                }
+                depth -= 1;
+            }
+            TextToken::And | TextToken::Or | TextToken::Not => {
+                if expect_operand {
+                    return Err(format!("Misplaced operator in text search '{value}'"));
+                }
+                expect_operand = true;
+            }
+        }
+    }
+    if tokens.is_empty() {
+        return Err("Empty text search".to_string());
+    }
+    if expect_operand {
+        return Err(format!("Text search '{value}' ends with an operator"));
+    }
+    if depth > 0 {
+        return Err(format!("Unbalanced parentheses in text search '{value}'"));
+    }
+    Ok(tokens)
+}
+
+/// The built-in parameter definitions the parser types parameters by.
+static DEFAULT_REGISTRY: std::sync::LazyLock<SearchParamRegistry> =
+    std::sync::LazyLock::new(SearchParamRegistry::new);
+
 /// Infer search parameter type, optionally using resource-specific registry definitions.
 /// Falls back to name-based heuristics if no registry match is found.
 pub fn infer_param_type_for_resource(resource_type: Option<&str>, name: &str) -> SearchParamType {
-    use crate::search_param_registry::SearchParamRegistry;
-
-    static DEFAULT_REGISTRY: std::sync::LazyLock<SearchParamRegistry> =
-        std::sync::LazyLock::new(SearchParamRegistry::new);
-
     // Try registry lookup if resource_type is provided
     if let Some(rt) = resource_type
         && let Some(pt) = DEFAULT_REGISTRY.lookup_param_type(rt, name)
@@ -420,6 +873,19 @@ mod tests {
         assert_eq!(query.include[0], "Patient:organization");
     }
 
+    #[test]
+    fn test_parse_include_iterate() {
+        let query = SearchQuery::parse(
+            "_include=*&_include:iterate=Observation:performer&_revinclude:iterate=Observation:encounter&_include:recurse=MedicationRequest:medication",
+        )
+        .unwrap();
+        assert_eq!(query.include, vec!["*"]);
+        assert_eq!(query.include_iterate, vec!["Observation:performer", "MedicationRequest:medication"]);
+        assert_eq!(query.revinclude_iterate, vec!["Observation:encounter"]);
+        assert!(query.revinclude.is_empty());
+        assert!(query.parameters.is_empty());
+    }
+
     #[test]
     fn test_parse_count_offset() {
         let query = SearchQuery::parse("_count=10&_offset=20").unwrap();
@@ -427,6 +893,24 @@ mod tests {
         assert_eq!(query.offset, Some(20));
     }
 
+    #[test]
+    fn test_parse_total() {
+        assert_eq!(SearchQuery::parse("").unwrap().total, None);
+        assert_eq!(SearchQuery::parse("_total=none").unwrap().total, Some(TotalMode::None));
+        assert_eq!(
+            SearchQuery::parse("_total=estimate").unwrap().total,
+            Some(TotalMode::Estimate)
+        );
+        assert!(SearchQuery::parse("_total=exact").is_err());
+    }
+
+    #[test]
+    fn test_parse_page_token() {
+        let query = SearchQuery::parse("status=final&_page_token=abc.def").unwrap();
+        assert_eq!(query.page_token.as_deref(), Some("abc.def"));
+        assert_eq!(query.parameters.len(), 1);
+    }
+
     #[test]
     fn test_parse_summary() {
         let query = SearchQuery::parse("_summary=true").unwrap();
@@ -461,10 +945,10 @@ mod tests {
         let chain = &query.chain_parameters[0];
         assert_eq!(chain.links.len(), 1);
         assert_eq!(chain.links[0].reference_param, "subject");
-        assert_eq!(chain.links[0].target_type, "Patient");
-        assert_eq!(chain.target_param, "name");
-        assert_eq!(chain.value, "Doe");
-        assert_eq!(chain.target_param_type, SearchParamType::String);
+        assert_eq!(chain.links[0].target_types, vec!["Patient"]);
+        assert_eq!(chain.param.name, "name");
+        assert_eq!(chain.param.value, "Doe");
+        assert_eq!(chain.param.param_type, SearchParamType::String);
     }
 
     #[test]
@@ -476,11 +960,11 @@ mod tests {
         let chain = &query.chain_parameters[0];
         assert_eq!(chain.links.len(), 2);
         assert_eq!(chain.links[0].reference_param, "subject");
-        assert_eq!(chain.links[0].target_type, "Patient");
+        assert_eq!(chain.links[0].target_types, vec!["Patient"]);
         assert_eq!(chain.links[1].reference_param, "organization");
-        assert_eq!(chain.links[1].target_type, "Organization");
-        assert_eq!(chain.target_param, "name");
-        assert_eq!(chain.value, "Acme");
+        assert_eq!(chain.links[1].target_types, vec!["Organization"]);
+        assert_eq!(chain.param.name, "name");
+        assert_eq!(chain.param.value, "Acme");
     }
 
     #[test]
@@ -492,8 +976,51 @@ mod tests {
         let chain = &query.chain_parameters[0];
         assert_eq!(chain.links.len(), 3);
         assert_eq!(chain.links[2].reference_param, "partof");
-        assert_eq!(chain.links[2].target_type, "Organization");
-        assert_eq!(chain.target_param, "name");
+        assert_eq!(chain.links[2].target_types, vec!["Organization"]);
+        assert_eq!(chain.param.name, "name");
+    }
+
+    #[test]
+    fn test_parse_chain_prefix_modifier_and_typeless() {
+        let query = SearchQuery::parse(
+            "subject:Patient.birthdate=lt1950&subject.name:exact=Doe&subject.organization:Organization.name=Acme",
+        )
+        .unwrap();
+        assert_eq!(query.chain_parameters.len(), 3);
+        let dated = &query.chain_parameters[0].param;
+        assert_eq!(dated.param_type, SearchParamType::Date);
+        assert_eq!(dated.prefix.as_deref(), Some("lt"));
+        assert_eq!(dated.value, "1950");
+
+        let exact = &query.chain_parameters[1];
+        assert!(exact.links[0].target_types.is_empty());
+        assert_eq!(exact.param.name, "name");
+        assert_eq!(exact.param.modifier.as_deref(), Some("exact"));
+
+        let mixed = &query.chain_parameters[2];
+        assert!(mixed.links[0].target_types.is_empty());
+        assert_eq!(mixed.links[1].target_types, vec!["Organization"]);
+    }
+
+    #[test]
+    fn test_resolve_chain_targets() {
+        let registry = SearchParamRegistry::new();
+        let mut query = SearchQuery::parse("subject.birthdate=lt1950&patient.name=Doe&code.name=x").unwrap();
+
+        let typeless = &mut query.chain_parameters[0];
+        typeless.resolve_targets(&registry, "Observation").unwrap();
+        assert_eq!(typeless.links[0].target_types, vec!["Patient", "Group", "Device", "Location"]);
+        // Typed by the target's registry once it is known.
+        assert_eq!(typeless.param.param_type, SearchParamType::Date);
+        assert_eq!(typeless.param.prefix.as_deref(), Some("lt"));
+        assert_eq!(typeless.param.value, "1950");
+
+        let patient = &mut query.chain_parameters[1];
+        patient.resolve_targets(&registry, "Observation").unwrap();
+        assert_eq!(patient.links[0].target_types, vec!["Patient"]);
+
+        // `code` is not a reference: nothing to chain through.
+        assert!(query.chain_parameters[2].resolve_targets(&registry, "Observation").is_err());
     }
 
     #[test]
@@ -502,11 +1029,12 @@ mod tests {
         let query = SearchQuery::parse("_has:Observation:patient:code=1234-5").unwrap();
         assert_eq!(query.has_parameters.len(), 1);
         let has = &query.has_parameters[0];
-        assert_eq!(has.source_type, "Observation");
-        assert_eq!(has.reference_param, "patient");
-        assert_eq!(has.target_param, "code");
-        assert_eq!(has.value, "1234-5");
-        assert_eq!(has.target_param_type, SearchParamType::Token);
+        assert_eq!(has.links.len(), 1);
+        assert_eq!(has.links[0].source_type, "Observation");
+        assert_eq!(has.links[0].reference_param, "patient");
+        assert_eq!(has.param.name, "code");
+        assert_eq!(has.param.value, "1234-5");
+        assert_eq!(has.param.param_type, SearchParamType::Token);
         // It is not mistaken for a regular or chain parameter.
         assert!(query.parameters.is_empty());
         assert!(query.chain_parameters.is_empty());
@@ -518,17 +1046,43 @@ mod tests {
         assert_eq!(query.parameters.len(), 1);
         assert_eq!(query.parameters[0].name, "gender");
         assert_eq!(query.has_parameters.len(), 1);
-        assert_eq!(query.has_parameters[0].source_type, "Observation");
+        assert_eq!(query.has_parameters[0].links[0].source_type, "Observation");
+    }
+
+    #[test]
+    fn test_parse_has_nested_with_prefix_and_modifier() {
+        let query = SearchQuery::parse(
+            "_has:Observation:patient:_has:AuditEvent:entity:date=ge2024-01-01&_has:Observation:subject:code:text=sugar",
+        )
+        .unwrap();
+        assert_eq!(query.has_parameters.len(), 2);
+        let nested = &query.has_parameters[0];
+        let hops: Vec<(&str, &str)> = nested
+            .links
+            .iter()
+            .map(|l| (l.source_type.as_str(), l.reference_param.as_str()))
+            .collect();
+        assert_eq!(hops, vec![("Observation", "patient"), ("AuditEvent", "entity")]);
+        assert_eq!(nested.param.name, "date");
+        assert_eq!(nested.param.prefix.as_deref(), Some("ge"));
+        assert_eq!(nested.param.value, "2024-01-01");
+        assert_eq!(nested.param.param_type, SearchParamType::Date);
+
+        let modified = &query.has_parameters[1];
+        assert_eq!(modified.param.name, "code");
+        assert_eq!(modified.param.modifier.as_deref(), Some("text"));
     }
 
     #[test]
     fn test_parse_has_malformed_ignored() {
-        // Missing the search-param segment -> not a valid one-level _has.
+        // Missing the search-param segment -> not a valid _has.
         let query = SearchQuery::parse("_has:Observation:patient=x").unwrap();
         assert!(query.has_parameters.is_empty());
-        // Nested _has is out of scope for now and is dropped, not mis-parsed.
+        // A nested _has without its own type and reference is dropped too.
         let nested = SearchQuery::parse("_has:Group:member:_has=y").unwrap();
         assert!(nested.has_parameters.is_empty());
+        let extra = SearchQuery::parse("_has:Observation:patient:code:text:extra=y").unwrap();
+        assert!(extra.has_parameters.is_empty());
     }
 
     #[test]
@@ -537,7 +1091,7 @@ mod tests {
         assert_eq!(query.parameters.len(), 1);
         assert_eq!(query.parameters[0].name, "status");
         assert_eq!(query.chain_parameters.len(), 1);
-        assert_eq!(query.chain_parameters[0].target_param, "gender");
+        assert_eq!(query.chain_parameters[0].param.name, "gender");
     }
 
     #[test]
@@ -579,6 +1133,119 @@ mod tests {
     fn test_parse_unknown_underscore_param_skipped() {
         let query = SearchQuery::parse("_sort=name").unwrap();
         assert_eq!(query.parameters.len(), 0);
+        let query = SearchQuery::parse("_format=json").unwrap();
+        assert_eq!(query.parameters.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_text_query() {
+        use TextToken::*;
+        assert_eq!(
+            parse_text_query(r#"chest "sharp pain" OR (cardi* NOT angina)"#).unwrap(),
+            vec![
+                Term("chest".into()),
+                Phrase("sharp pain".into()),
+                Or,
+                Open,
+                Prefix("cardi".into()),
+                Not,
+                Term("angina".into()),
+                Close,
+            ]
+        );
+        // Lower-case operators are words.
+        assert_eq!(parse_text_query("salt and pepper").unwrap().len(), 3);
+        for bad in ["", "OR pain", "pain AND", "(pain", "pain)", "()", "\"unterminated"] {
+            assert!(parse_text_query(bad).is_err(), "{bad}");
+        }
+
+        let query = SearchQuery::parse("_content=diabetes&_text=foot*").unwrap();
+        assert_eq!(query.parameters.len(), 2);
+        assert_eq!(query.parameters[1].name, "_text");
+        assert!(SearchQuery::parse("_content=diabetes%20AND").is_err());
+    }
+
+    #[test]
+    fn test_check_modifier() {
+        let param = |q: &str| SearchQuery::parse_for_resource(q, Some("Observation")).unwrap().parameters[0].clone();
+        for ok in [
+            "code:text=glucose",
+            "code:in=http://hl7.org/fhir/ValueSet/observation-status",
+            "code:below=http://snomed.info/sct|73211009",
+            "subject:Patient=123",
+            "subject:missing=true",
+            "subject:identifier=http://hospital.org/mrn|12345",
+            "date:missing=false",
+            "_text:not=pain",
+        ] {
+            assert!(param(ok).check_modifier().is_ok(), "{ok}");
+        }
+        for bad in ["code:exact=x", "code:contains=x", "date:above=2020", "subject:above=x", "code:of-type=MR|123"] {
+            assert!(param(bad).check_modifier().is_err(), "{bad}");
+        }
+        assert!(param("identifier:of-type=http://terminology.hl7.org/CodeSystem/v2-0203|MR|123").check_modifier().is_ok());
+    }
+
+    #[test]
+    fn test_resolve_terminology() {
+        let mut terms = TerminologyRegistry::new();
+        terms.load_code_system_resource(
+            &serde_json::json!({
+                "resourceType": "CodeSystem",
+                "url": "http://example.org/cs",
+                "concept": [{"code": "a", "concept": [{"code": "a1"}]}]
+            })
+            .to_string(),
+        );
+        let resolved = |q: &str| {
+            let mut p = SearchQuery::parse_for_resource(q, Some("Observation")).unwrap().parameters[0].clone();
+            p.resolve_terminology(&terms).map(|()| (p.modifier, p.value))
+        };
+
+        let (modifier, value) = resolved("status:in=http://hl7.org/fhir/ValueSet/observation-status").unwrap();
+        assert_eq!(modifier, None);
+        assert!(value.split(',').any(|c| c == "final"));
+        let (modifier, _) = resolved("status:not-in=http://hl7.org/fhir/ValueSet/observation-status").unwrap();
+        assert_eq!(modifier.as_deref(), Some("not"));
+        assert_eq!(
+            resolved("code:below=http://example.org/cs|a").unwrap(),
+            (None, "http://example.org/cs|a,http://example.org/cs|a1".to_string())
+        );
+        assert_eq!(
+            resolved("code:above=http://example.org/cs|a1").unwrap(),
+            (None, "http://example.org/cs|a1,http://example.org/cs|a".to_string())
+        );
+        assert!(resolved("code:in=http://example.org/unknown-vs").is_err());
+        assert!(resolved("code:below=a").is_err());
+        // Other modifiers are left alone.
+        assert_eq!(resolved("code:text=sugar").unwrap(), (Some("text".to_string()), "sugar".to_string()));
+    }
+
+    #[test]
+    fn test_parse_contained() {
+        let query = SearchQuery::parse("code=x").unwrap();
+        assert_eq!(query.contained, ContainedMode::False);
+        assert_eq!(query.contained_type, ContainedType::Container);
+        let query = SearchQuery::parse("_contained=both&_containedType=contained").unwrap();
+        assert_eq!(query.contained, ContainedMode::Both);
+        assert_eq!(query.contained_type, ContainedType::Contained);
+        assert_eq!(query.parameters.len(), 0);
+        assert!(SearchQuery::parse("_contained=yes").is_err());
+        assert!(SearchQuery::parse("_containedType=parent").is_err());
+    }
+
+    #[test]
+    fn test_parse_sort_multi_key() {
+        let query =
+            SearchQuery::parse_for_resource("_sort=date,-_lastUpdated,&_sort=status", Some("Observation"))
+                .unwrap();
+        assert!(query.parameters.is_empty());
+        let keys: Vec<(&str, bool)> =
+            query.sort.iter().map(|k| (k.name.as_str(), k.descending)).collect();
+        assert_eq!(keys, vec![("date", false), ("_lastUpdated", true), ("status", false)]);
+        assert_eq!(query.sort[0].param_type, SearchParamType::Date);
+        assert_eq!(query.sort[1].param_type, SearchParamType::Date);
+        assert_eq!(query.sort[2].param_type, SearchParamType::Token);
     }
 
     #[test]
@@ -590,21 +1257,45 @@ mod tests {
             ("ap", "ap2024-01-01"),
             ("gt", "gt2024-01-01"),
         ] {
-            let (prefix, val) = parse_date_prefix(raw);
+            let (prefix, val) = parse_prefix(raw);
             assert_eq!(prefix.as_deref(), Some(p));
             assert_eq!(val, "2024-01-01");
         }
         // No prefix → defaults to eq, value untouched.
-        let (prefix, val) = parse_date_prefix("2024-01-01");
+        let (prefix, val) = parse_prefix("2024-01-01");
         assert_eq!(prefix.as_deref(), Some("eq"));
         assert_eq!(val, "2024-01-01");
     }
 
+    #[test]
+    fn test_parse_quantity_and_number_prefixes() {
+        let q = SearchQuery::parse_for_resource(
+            "value-quantity=gt5.4|http://unitsofmeasure.org|mmol/L",
+            Some("Observation"),
+        )
+        .unwrap();
+        let p = &q.parameters[0];
+        assert_eq!(p.param_type, SearchParamType::Quantity);
+        assert_eq!(p.prefix.as_deref(), Some("gt"));
+        assert_eq!(p.value, "5.4|http://unitsofmeasure.org|mmol/L");
+
+        // A negative or fractional number still splits off its prefix.
+        assert_eq!(parse_prefix("le-0.5"), (Some("le".to_string()), "-0.5".to_string()));
+        assert_eq!(parse_prefix("ap.5"), (Some("ap".to_string()), ".5".to_string()));
+
+        // Re-typing a parameter the parser guessed as a string moves the prefix.
+        let mut p = SearchQuery::parse("probability=ge0.8").unwrap().parameters.remove(0);
+        assert_eq!(p.prefix, None);
+        p.retype(SearchParamType::Number);
+        assert_eq!(p.prefix.as_deref(), Some("ge"));
+        assert_eq!(p.value, "0.8");
+    }
+
     #[test]
     fn test_infer_param_type() {
-        assert_eq!(infer_param_type("identifier"), SearchParamType::Token);
-        assert_eq!(infer_param_type("family"), SearchParamType::String);
-        assert_eq!(infer_param_type("birthdate"), SearchParamType::Date);
-        assert_eq!(infer_param_type("patient"), SearchParamType::Reference);
+        assert_eq!(infer_param_type_for_resource(None, "identifier"), SearchParamType::Token);
+        assert_eq!(infer_param_type_for_resource(None, "family"), SearchParamType::String);
+        assert_eq!(infer_param_type_for_resource(None, "birthdate"), SearchParamType::Date);
+        assert_eq!(infer_param_type_for_resource(None, "patient"), SearchParamType::Reference);
     }
 }
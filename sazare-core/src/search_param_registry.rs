@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::search_param::SearchParamType;
 
@@ -52,6 +52,21 @@ pub enum ExtractionMode {
     /// `path[0]` is the extension container ("extension"), `path[1]` the
     /// extension URL; yields the extension's `valueDateTime`.
     ExtensionDate,
+    /// Quantity datatype reached by walking `path`, fanning out over any array
+    /// along the way (e.g. `["component", "valueQuantity"]`). Yields the value
+    /// and unit code (the `unit` text when there is no code) plus the unit
+    /// system.
+    Quantity,
+    /// Composite parameter: each element selected by `elements` (a FHIRPath
+    /// expression) or, when that is `None`, by walking `path` (fanning out over
+    /// arrays; an empty path is the resource itself) is one group, and each
+    /// part is extracted relative to it. A composite search matches only when
+    /// every part matches within the same group, e.g. the same
+    /// `Observation.component`.
+    Composite {
+        elements: Option<crate::fhirpath::Expr>,
+        parts: Vec<SearchParamDef>,
+    },
     /// A FHIRPath expression evaluated against the resource. Used by custom
     /// search parameters loaded at runtime from a `SearchParameter` resource;
     /// `path` is unused. See [`crate::fhirpath`].
@@ -59,7 +74,7 @@ pub enum ExtractionMode {
 }
 
 /// Definition of a single search parameter
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchParamDef {
     /// Search parameter name (e.g. "family", "code")
     pub name: String,
@@ -71,11 +86,21 @@ pub struct SearchParamDef {
     pub extraction: ExtractionMode,
     /// Alias names that should also be indexed (e.g. "patient" for "subject")
     pub aliases: Vec<String>,
+    /// For a reference parameter, the resource types it can point at (the
+    /// SearchParameter's `target`); empty when it can point at any type
+    pub targets: Vec<String>,
 }
 
 /// Registry of search parameter definitions per resource type
+#[derive(Clone)]
 pub struct SearchParamRegistry {
     definitions: HashMap<String, Vec<SearchParamDef>>,
+    /// Types of the runtime-registered parameters by canonical `url`, so a
+    /// later composite can resolve its `component.definition`s.
+    urls: HashMap<String, SearchParamType>,
+    /// The runtime-registered parameters by base resource type and code, so
+    /// re-registering one replaces it and only these can be unregistered.
+    runtime: HashSet<(String, String)>,
 }
 
 impl SearchParamRegistry {
@@ -88,6 +113,7 @@ impl SearchParamRegistry {
         definitions.insert("Encounter".to_string(), encounter_definitions());
         definitions.insert("Condition".to_string(), condition_definitions());
         definitions.insert("MedicationRequest".to_string(), medication_request_definitions());
+        definitions.insert("Medication".to_string(), medication_definitions());
         definitions.insert("Procedure".to_string(), procedure_definitions());
         definitions.insert("AllergyIntolerance".to_string(), allergy_intolerance_definitions());
         definitions.insert("DiagnosticReport".to_string(), diagnostic_report_definitions());
@@ -111,6 +137,8 @@ impl SearchParamRegistry {
         definitions.insert("MedicationDispense".to_string(), medication_dispense_definitions());
         definitions.insert("DocumentReference".to_string(), document_reference_definitions());
         definitions.insert("QuestionnaireResponse".to_string(), questionnaire_response_definitions());
+        definitions.insert("ValueSet".to_string(), value_set_definitions());
+        definitions.insert("CodeSystem".to_string(), code_system_definitions());
 
         // Append FHIR-common parameters (e.g. _profile) to every resource-specific list
         let common = common_fhir_params();
@@ -120,7 +148,11 @@ impl SearchParamRegistry {
             }
         }
 
-        Self { definitions }
+        Self {
+            definitions,
+            urls: HashMap::new(),
+            runtime: HashSet::new(),
+        }
     }
 
     /// Get search parameter definitions for a resource type.
@@ -144,7 +176,8 @@ impl SearchParamRegistry {
     /// (loaded at runtime). The `expression` is compiled with the bounded
     /// FHIRPath evaluator; an expression outside the supported subset is
     /// rejected here, at load time, rather than producing wrong results at query
-    /// time. The parameter is added to every `base` resource type it declares.
+    /// time. The parameter is added to every `base` resource type it declares,
+    /// replacing one registered earlier with the same base and code.
     pub fn register_search_parameter(&mut self, sp: &serde_json::Value) -> Result<(), String> {
         let code = sp
             .get("code")
@@ -154,14 +187,6 @@ impl SearchParamRegistry {
             .get("type")
             .and_then(|v| v.as_str())
             .ok_or_else(|| format!("{code}: SearchParameter has no type"))?;
-        let param_type = match type_str {
-            "token" => SearchParamType::Token,
-            "string" => SearchParamType::String,
-            "date" => SearchParamType::Date,
-            "reference" => SearchParamType::Reference,
-            "number" => SearchParamType::Number,
-            other => return Err(format!("{code}: unsupported search parameter type '{other}'")),
-        };
         let expr_str = sp
             .get("expression")
             .and_then(|v| v.as_str())
@@ -175,19 +200,138 @@ impl SearchParamRegistry {
         if bases.is_empty() {
             return Err(format!("{code}: SearchParameter has no base resource type"));
         }
+        let (param_type, extraction) = match type_str {
+            "token" => (SearchParamType::Token, ExtractionMode::FhirPath(expr)),
+            "string" => (SearchParamType::String, ExtractionMode::FhirPath(expr)),
+            "date" => (SearchParamType::Date, ExtractionMode::FhirPath(expr)),
+            "reference" => (SearchParamType::Reference, ExtractionMode::FhirPath(expr)),
+            "number" => (SearchParamType::Number, ExtractionMode::FhirPath(expr)),
+            "quantity" => (SearchParamType::Quantity, ExtractionMode::FhirPath(expr)),
+            "uri" => (SearchParamType::Uri, ExtractionMode::FhirPath(expr)),
+            "composite" => {
+                let parts = self.composite_parts(code, sp, &bases[0])?;
+                let types = parts.iter().map(|p| p.param_type.clone()).collect();
+                let extraction = ExtractionMode::Composite { elements: Some(expr), parts };
+                (SearchParamType::Composite(types), extraction)
+            }
+            other => return Err(format!("{code}: unsupported search parameter type '{other}'")),
+        };
+        if let Some(url) = sp.get("url").and_then(|v| v.as_str()) {
+            self.urls.insert(url.to_string(), param_type.clone());
+        }
+        let targets = sp
+            .get("target")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|t| t.as_str().map(String::from)).collect())
+            .unwrap_or_default();
         let def = SearchParamDef {
             name: code.to_string(),
             param_type,
             path: Vec::new(),
-            extraction: ExtractionMode::FhirPath(expr),
+            extraction,
             aliases: Vec::new(),
+            targets,
         };
         for base in bases {
-            self.definitions.entry(base).or_default().push(def.clone());
+            let key = (base.clone(), code.to_string());
+            let defs = self.definitions.entry(base).or_default();
+            if self.runtime.contains(&key)
+                && let Some(pos) = defs.iter().rposition(|d| d.name == code)
+            {
+                defs.remove(pos);
+            }
+            defs.push(def.clone());
+            self.runtime.insert(key);
         }
         Ok(())
     }
 
+    /// Remove a parameter registered by [`Self::register_search_parameter`]
+    /// from the `base` resource types the `SearchParameter` declares. Built-in
+    /// parameters are never removed. Returns the base types it was removed
+    /// from.
+    pub fn unregister_search_parameter(&mut self, sp: &serde_json::Value) -> Vec<String> {
+        let Some(code) = sp.get("code").and_then(|v| v.as_str()) else {
+            return Vec::new();
+        };
+        if let Some(url) = sp.get("url").and_then(|v| v.as_str()) {
+            self.urls.remove(url);
+        }
+        let bases = sp.get("base").and_then(|v| v.as_array()).into_iter().flatten();
+        let mut removed = Vec::new();
+        for base in bases.filter_map(|b| b.as_str()) {
+            let key = (base.to_string(), code.to_string());
+            if !self.runtime.remove(&key) {
+                continue;
+            }
+            if let Some(defs) = self.definitions.get_mut(base) {
+                // A runtime parameter is always appended after the built-in
+                // ones, so one sharing a built-in's code is the last of them.
+                if let Some(pos) = defs.iter().rposition(|d| d.name == code) {
+                    defs.remove(pos);
+                }
+            }
+            removed.push(base.to_string());
+        }
+        removed
+    }
+
+    /// Build the parts of a composite `SearchParameter` from its `component`s.
+    /// Each component's `definition` is resolved to a type by its canonical
+    /// URL: first among runtime-registered parameters, then by the base
+    /// specification's `.../SearchParameter/<Resource>-<code>` convention
+    /// against the built-in definitions. Its `expression` is relative to the
+    /// composite's element.
+    fn composite_parts(
+        &self,
+        code: &str,
+        sp: &serde_json::Value,
+        base: &str,
+    ) -> Result<Vec<SearchParamDef>, String> {
+        let components = sp
+            .get("component")
+            .and_then(|v| v.as_array())
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| format!("{code}: composite SearchParameter has no component"))?;
+        let mut parts = Vec::with_capacity(components.len());
+        for component in components {
+            let definition = component
+                .get("definition")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| format!("{code}: composite component has no definition"))?;
+            let param_type = self
+                .urls
+                .get(definition)
+                .cloned()
+                .or_else(|| {
+                    let tail = definition.rsplit('/').next()?;
+                    let (resource, param) = tail.split_once('-')?;
+                    let resource = if resource == "Resource" { base } else { resource };
+                    self.lookup_param_type(resource, param)
+                })
+                .ok_or_else(|| {
+                    format!("{code}: cannot resolve composite component '{definition}'")
+                })?;
+            if matches!(param_type, SearchParamType::Composite(_)) {
+                return Err(format!("{code}: a composite component cannot itself be composite"));
+            }
+            let expression = component
+                .get("expression")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| format!("{code}: composite component has no expression"))?;
+            let expr = crate::fhirpath::parse(expression).map_err(|e| format!("{code}: {e}"))?;
+            parts.push(SearchParamDef {
+                name: definition.rsplit('/').next().unwrap_or(definition).to_string(),
+                param_type,
+                path: Vec::new(),
+                extraction: ExtractionMode::FhirPath(expr),
+                aliases: Vec::new(),
+                targets: Vec::new(),
+            });
+        }
+        Ok(parts)
+    }
+
     /// The top-level JSON element a reference search parameter reads, used to
     /// resolve `_include`/`_revinclude` where the parameter name differs from the
     /// element (e.g. `Observation:patient` → `subject`, `general-practitioner` →
@@ -203,6 +347,38 @@ impl SearchParamRegistry {
         None
     }
 
+    /// The resource types a reference search parameter can point at, for a
+    /// chain that doesn't name one (`subject.name=Doe`): its definition's
+    /// declared [`targets`](SearchParamDef::targets), the last definition
+    /// winning as a runtime parameter replaces a built-in one. Empty when
+    /// unknown, or when the reference can point at any type.
+    pub fn reference_targets(&self, resource_type: &str, param_name: &str) -> Vec<String> {
+        let Some(def) = self.get_definitions(resource_type).iter().rev().find(|def| {
+            def.param_type == SearchParamType::Reference
+                && (def.name == param_name || def.aliases.iter().any(|a| a == param_name))
+        }) else {
+            return Vec::new();
+        };
+        // `patient` narrows an element that may hold other subjects.
+        if param_name == "patient" && (def.targets.is_empty() || def.targets.iter().any(|t| t == "Patient")) {
+            return vec!["Patient".to_string()];
+        }
+        def.targets.clone()
+    }
+
+    /// The resource types with a reference search parameter named (or
+    /// aliased) `param_name`, sorted — what `_revinclude=*:param` reads from.
+    pub fn types_with_reference_param(&self, param_name: &str) -> Vec<String> {
+        let mut types: Vec<String> = self
+            .definitions
+            .keys()
+            .filter(|rt| self.lookup_param_type(rt, param_name) == Some(SearchParamType::Reference))
+            .cloned()
+            .collect();
+        types.sort();
+        types
+    }
+
     /// Look up the SearchParamType for a given resource type and parameter name.
     /// Checks aliases as well. Returns None if not found.
     pub fn lookup_param_type(
@@ -239,6 +415,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "death-date".to_string(),
@@ -246,6 +423,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["deceasedDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "family".to_string(),
@@ -253,6 +431,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "family".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "given".to_string(),
@@ -260,6 +439,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "given".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         // US Core `name`: combined search across all HumanName components.
         // Five defs share the same param name so values are indexed under
@@ -271,6 +451,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "family".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -278,6 +459,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "given".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -285,6 +467,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "text".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -292,6 +475,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "prefix".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -299,6 +483,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "suffix".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "birthdate".to_string(),
@@ -306,6 +491,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["birthDate".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "gender".to_string(),
@@ -313,6 +499,7 @@ fn patient_definitions() -> Vec<SearchParamDef> {
             path: vec!["gender".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -325,6 +512,7 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -332,6 +520,7 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -339,6 +528,7 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -346,6 +536,23 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string(), "Device".to_string(), "Location".to_string()],
+        },
+        SearchParamDef {
+            name: "encounter".to_string(),
+            param_type: SearchParamType::Reference,
+            path: vec!["encounter".to_string()],
+            extraction: ExtractionMode::Reference,
+            aliases: vec![],
+            targets: vec!["Encounter".to_string()],
+        },
+        SearchParamDef {
+            name: "performer".to_string(),
+            param_type: SearchParamType::Reference,
+            path: vec!["performer".to_string()],
+            extraction: ExtractionMode::Reference,
+            aliases: vec![],
+            targets: vec!["Practitioner".to_string(), "PractitionerRole".to_string(), "Organization".to_string(), "CareTeam".to_string(), "Patient".to_string(), "RelatedPerson".to_string()],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -353,6 +560,7 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["effectiveDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         // Observation.effective[x] may be an effectivePeriod (e.g. average blood
         // pressure). Index it as a full date range so range-aware date searches
@@ -363,6 +571,7 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["effectivePeriod".to_string()],
             extraction: ExtractionMode::PeriodRange,
             aliases: vec![],
+            targets: vec![],
         },
         // `combo-code` searches Observation.code OR Observation.component.code.
         // Component-level access requires array-of-CodeableConcept walking and is
@@ -374,10 +583,68 @@ fn observation_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "value-quantity".to_string(),
+            param_type: SearchParamType::Quantity,
+            path: vec!["valueQuantity".to_string()],
+            extraction: ExtractionMode::Quantity,
+            aliases: vec![],
+            targets: vec![],
         },
+        SearchParamDef {
+            name: "component-value-quantity".to_string(),
+            param_type: SearchParamType::Quantity,
+            path: vec!["component".to_string(), "valueQuantity".to_string()],
+            extraction: ExtractionMode::Quantity,
+            aliases: vec![],
+            targets: vec![],
+        },
+        composite_definition("code-value-quantity", &[], code_and_value_quantity()),
+        composite_definition(
+            "component-code-value-quantity",
+            &["component"],
+            code_and_value_quantity(),
+        ),
     ]
 }
 
+/// The `code` + `value-quantity` parts shared by Observation's composites,
+/// relative to the Observation or one of its `component`s.
+fn code_and_value_quantity() -> Vec<SearchParamDef> {
+    vec![
+        SearchParamDef {
+            name: "code".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["code".to_string()],
+            extraction: ExtractionMode::CodeableConcept,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "value-quantity".to_string(),
+            param_type: SearchParamType::Quantity,
+            path: vec!["valueQuantity".to_string()],
+            extraction: ExtractionMode::Quantity,
+            aliases: vec![],
+            targets: vec![],
+        },
+    ]
+}
+
+/// A composite parameter whose groups are the elements at `path`.
+fn composite_definition(name: &str, path: &[&str], parts: Vec<SearchParamDef>) -> SearchParamDef {
+    SearchParamDef {
+        name: name.to_string(),
+        param_type: SearchParamType::Composite(parts.iter().map(|p| p.param_type.clone()).collect()),
+        path: path.iter().map(|s| s.to_string()).collect(),
+        extraction: ExtractionMode::Composite { elements: None, parts },
+        aliases: vec![],
+        targets: vec![],
+    }
+}
+
 fn encounter_definitions() -> Vec<SearchParamDef> {
     vec![
         SearchParamDef {
@@ -386,6 +653,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -393,6 +661,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -400,6 +669,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["period".to_string(), "start".to_string()],
             extraction: ExtractionMode::PeriodStart,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "class".to_string(),
@@ -407,6 +677,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["class".to_string()],
             extraction: ExtractionMode::Coding,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -414,6 +685,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -421,6 +693,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "discharge-disposition".to_string(),
@@ -428,6 +701,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["hospitalization".to_string(), "dischargeDisposition".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         // Encounter.location.location — `location` is an array of BackboneElements,
         // each carrying a `location` Reference.
@@ -437,6 +711,7 @@ fn encounter_definitions() -> Vec<SearchParamDef> {
             path: vec!["location".to_string(), "location".to_string()],
             extraction: ExtractionMode::NestedReference,
             aliases: vec![],
+            targets: vec!["Location".to_string()],
         },
     ]
 }
@@ -449,6 +724,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -456,6 +732,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -463,6 +740,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "clinical-status".to_string(),
@@ -470,6 +748,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["clinicalStatus".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "onset-date".to_string(),
@@ -477,6 +756,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["onsetDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "recorded-date".to_string(),
@@ -484,6 +764,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["recordedDate".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "abatement-date".to_string(),
@@ -491,6 +772,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["abatementDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "encounter".to_string(),
@@ -498,6 +780,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             path: vec!["encounter".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Encounter".to_string()],
         },
         SearchParamDef {
             name: "asserted-date".to_string(),
@@ -508,6 +791,7 @@ fn condition_definitions() -> Vec<SearchParamDef> {
             ],
             extraction: ExtractionMode::ExtensionDate,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -520,6 +804,7 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -527,6 +812,7 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "intent".to_string(),
@@ -534,6 +820,7 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["intent".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -541,6 +828,7 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "authoredon".to_string(),
@@ -548,6 +836,7 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["authoredOn".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "encounter".to_string(),
@@ -555,6 +844,52 @@ fn medication_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["encounter".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Encounter".to_string()],
+        },
+        SearchParamDef {
+            name: "medication".to_string(),
+            param_type: SearchParamType::Reference,
+            path: vec!["medicationReference".to_string()],
+            extraction: ExtractionMode::Reference,
+            aliases: vec![],
+            targets: vec!["Medication".to_string()],
+        },
+    ]
+}
+
+fn medication_definitions() -> Vec<SearchParamDef> {
+    vec![
+        SearchParamDef {
+            name: "code".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["code".to_string()],
+            extraction: ExtractionMode::CodeableConcept,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "status".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["status".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "form".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["form".to_string()],
+            extraction: ExtractionMode::CodeableConcept,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "identifier".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["identifier".to_string()],
+            extraction: ExtractionMode::Identifier,
+            aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -567,6 +902,7 @@ fn procedure_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -574,6 +910,7 @@ fn procedure_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "code".to_string(),
@@ -581,6 +918,7 @@ fn procedure_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -588,6 +926,7 @@ fn procedure_definitions() -> Vec<SearchParamDef> {
             path: vec!["performedDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -595,6 +934,7 @@ fn procedure_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -607,6 +947,7 @@ fn allergy_intolerance_definitions() -> Vec<SearchParamDef> {
             path: vec!["patient".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Patient".to_string()],
         },
         SearchParamDef {
             name: "clinical-status".to_string(),
@@ -614,6 +955,7 @@ fn allergy_intolerance_definitions() -> Vec<SearchParamDef> {
             path: vec!["clinicalStatus".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec!["status".to_string()],
+            targets: vec![],
         },
         SearchParamDef {
             name: "code".to_string(),
@@ -621,6 +963,7 @@ fn allergy_intolerance_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -628,6 +971,7 @@ fn allergy_intolerance_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -640,6 +984,7 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -647,6 +992,15 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string(), "Device".to_string(), "Location".to_string()],
+        },
+        SearchParamDef {
+            name: "result".to_string(),
+            param_type: SearchParamType::Reference,
+            path: vec!["result".to_string()],
+            extraction: ExtractionMode::Reference,
+            aliases: vec![],
+            targets: vec!["Observation".to_string()],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -654,6 +1008,7 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "code".to_string(),
@@ -661,6 +1016,7 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -668,6 +1024,7 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["effectiveDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -675,6 +1032,7 @@ fn diagnostic_report_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -687,6 +1045,7 @@ fn immunization_definitions() -> Vec<SearchParamDef> {
             path: vec!["patient".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Patient".to_string()],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -694,6 +1053,7 @@ fn immunization_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -701,6 +1061,7 @@ fn immunization_definitions() -> Vec<SearchParamDef> {
             path: vec!["occurrenceDateTime".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "vaccine-code".to_string(),
@@ -708,6 +1069,7 @@ fn immunization_definitions() -> Vec<SearchParamDef> {
             path: vec!["vaccineCode".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -715,6 +1077,7 @@ fn immunization_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -727,6 +1090,7 @@ fn task_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -734,6 +1098,7 @@ fn task_definitions() -> Vec<SearchParamDef> {
             path: vec!["for".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec![],
         },
         SearchParamDef {
             name: "owner".to_string(),
@@ -741,6 +1106,7 @@ fn task_definitions() -> Vec<SearchParamDef> {
             path: vec!["owner".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Practitioner".to_string(), "PractitionerRole".to_string(), "Organization".to_string(), "CareTeam".to_string(), "HealthcareService".to_string(), "Patient".to_string(), "Device".to_string(), "RelatedPerson".to_string()],
         },
         SearchParamDef {
             name: "code".to_string(),
@@ -748,6 +1114,7 @@ fn task_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -755,6 +1122,7 @@ fn task_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -767,6 +1135,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "family".to_string(),
@@ -774,6 +1143,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "family".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "given".to_string(),
@@ -781,6 +1151,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "given".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -788,6 +1159,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "family".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -795,6 +1167,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "given".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -802,6 +1175,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "text".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -809,6 +1183,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "prefix".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -816,6 +1191,7 @@ fn practitioner_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "suffix".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -828,6 +1204,7 @@ fn organization_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -835,6 +1212,7 @@ fn organization_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -842,6 +1220,7 @@ fn organization_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address".to_string(),
@@ -849,6 +1228,7 @@ fn organization_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -861,6 +1241,7 @@ fn practitioner_role_definitions() -> Vec<SearchParamDef> {
             path: vec!["practitioner".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Practitioner".to_string()],
         },
         SearchParamDef {
             name: "specialty".to_string(),
@@ -868,6 +1249,7 @@ fn practitioner_role_definitions() -> Vec<SearchParamDef> {
             path: vec!["specialty".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "role".to_string(),
@@ -875,6 +1257,7 @@ fn practitioner_role_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -887,6 +1270,7 @@ fn goal_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["subject".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string(), "Organization".to_string()],
         },
         SearchParamDef {
             name: "lifecycle-status".to_string(),
@@ -894,6 +1278,7 @@ fn goal_definitions() -> Vec<SearchParamDef> {
             path: vec!["lifecycleStatus".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "target-date".to_string(),
@@ -901,6 +1286,7 @@ fn goal_definitions() -> Vec<SearchParamDef> {
             path: vec!["target".to_string(), "dueDate".to_string()],
             extraction: ExtractionMode::NestedScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "description".to_string(),
@@ -908,6 +1294,7 @@ fn goal_definitions() -> Vec<SearchParamDef> {
             path: vec!["description".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -920,6 +1307,7 @@ fn coverage_definitions() -> Vec<SearchParamDef> {
             path: vec!["beneficiary".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["beneficiary".to_string()],
+            targets: vec!["Patient".to_string()],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -927,6 +1315,7 @@ fn coverage_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -934,6 +1323,7 @@ fn coverage_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -946,6 +1336,7 @@ fn device_definitions() -> Vec<SearchParamDef> {
             path: vec!["patient".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Patient".to_string()],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -953,6 +1344,7 @@ fn device_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -960,6 +1352,7 @@ fn device_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -972,6 +1365,7 @@ fn medication_dispense_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["subject".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -979,6 +1373,7 @@ fn medication_dispense_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -986,6 +1381,7 @@ fn medication_dispense_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -998,6 +1394,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["subject".to_string()],
+            targets: vec!["Patient".to_string(), "Practitioner".to_string(), "Group".to_string(), "Device".to_string()],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -1005,6 +1402,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -1012,6 +1410,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -1019,6 +1418,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -1026,6 +1426,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["date".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -1033,6 +1434,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "period".to_string(),
@@ -1040,6 +1442,7 @@ fn document_reference_definitions() -> Vec<SearchParamDef> {
             path: vec!["context".to_string(), "period".to_string(), "start".to_string()],
             extraction: ExtractionMode::PeriodStart,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1052,6 +1455,7 @@ fn questionnaire_response_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["subject".to_string()],
+            targets: vec![],
         },
         SearchParamDef {
             // QuestionnaireResponse.questionnaire is a canonical (plain string),
@@ -1061,6 +1465,7 @@ fn questionnaire_response_definitions() -> Vec<SearchParamDef> {
             path: vec!["questionnaire".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec!["Questionnaire".to_string()],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -1068,6 +1473,7 @@ fn questionnaire_response_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "authored".to_string(),
@@ -1075,6 +1481,7 @@ fn questionnaire_response_definitions() -> Vec<SearchParamDef> {
             path: vec!["authored".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1087,6 +1494,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address".to_string(),
@@ -1094,6 +1502,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address-city".to_string(),
@@ -1101,6 +1510,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string(), "city".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address-state".to_string(),
@@ -1108,6 +1518,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string(), "state".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address-postalcode".to_string(),
@@ -1115,6 +1526,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string(), "postalCode".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "address-country".to_string(),
@@ -1122,6 +1534,7 @@ fn location_definitions() -> Vec<SearchParamDef> {
             path: vec!["address".to_string(), "country".to_string()],
             extraction: ExtractionMode::Address,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1134,6 +1547,7 @@ fn related_person_definitions() -> Vec<SearchParamDef> {
             path: vec!["patient".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Patient".to_string()],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -1141,6 +1555,7 @@ fn related_person_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         // RelatedPerson.name is a HumanName array, indexed like Patient.name so that
         // `name=` matches family, given, or the formatted text.
@@ -1150,6 +1565,7 @@ fn related_person_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "family".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -1157,6 +1573,7 @@ fn related_person_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "given".to_string()],
             extraction: ExtractionMode::NestedArrayScalar,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "name".to_string(),
@@ -1164,6 +1581,7 @@ fn related_person_definitions() -> Vec<SearchParamDef> {
             path: vec!["name".to_string(), "text".to_string()],
             extraction: ExtractionMode::ArrayField,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1176,6 +1594,7 @@ fn bundle_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -1183,6 +1602,7 @@ fn bundle_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1195,6 +1615,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -1202,6 +1623,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -1209,6 +1631,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string(), "Location".to_string(), "Device".to_string()],
         },
         SearchParamDef {
             name: "code".to_string(),
@@ -1216,6 +1639,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["code".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "intent".to_string(),
@@ -1223,6 +1647,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["intent".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "priority".to_string(),
@@ -1230,6 +1655,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["priority".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "based-on".to_string(),
@@ -1237,6 +1663,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["basedOn".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["CarePlan".to_string(), "ServiceRequest".to_string(), "MedicationRequest".to_string()],
         },
         SearchParamDef {
             name: "encounter".to_string(),
@@ -1244,6 +1671,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["encounter".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Encounter".to_string()],
         },
         SearchParamDef {
             name: "requester".to_string(),
@@ -1251,6 +1679,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["requester".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec![],
+            targets: vec!["Practitioner".to_string(), "PractitionerRole".to_string(), "Organization".to_string(), "Patient".to_string(), "RelatedPerson".to_string(), "Device".to_string()],
         },
         SearchParamDef {
             name: "requisition".to_string(),
@@ -1258,6 +1687,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["requisition".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -1265,6 +1695,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "authored".to_string(),
@@ -1272,6 +1703,7 @@ fn service_request_definitions() -> Vec<SearchParamDef> {
             path: vec!["authoredOn".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1284,6 +1716,7 @@ fn appointment_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -1291,6 +1724,7 @@ fn appointment_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -1298,6 +1732,7 @@ fn appointment_definitions() -> Vec<SearchParamDef> {
             path: vec!["start".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1310,6 +1745,7 @@ fn specimen_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "identifier".to_string(),
@@ -1317,6 +1753,7 @@ fn specimen_definitions() -> Vec<SearchParamDef> {
             path: vec!["identifier".to_string()],
             extraction: ExtractionMode::Identifier,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "subject".to_string(),
@@ -1324,6 +1761,7 @@ fn specimen_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string(), "Device".to_string(), "Substance".to_string(), "Location".to_string()],
         },
         SearchParamDef {
             name: "type".to_string(),
@@ -1331,6 +1769,7 @@ fn specimen_definitions() -> Vec<SearchParamDef> {
             path: vec!["type".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1343,6 +1782,7 @@ fn care_plan_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "category".to_string(),
@@ -1350,6 +1790,7 @@ fn care_plan_definitions() -> Vec<SearchParamDef> {
             path: vec!["category".to_string()],
             extraction: ExtractionMode::CodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -1357,6 +1798,7 @@ fn care_plan_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "date".to_string(),
@@ -1364,6 +1806,7 @@ fn care_plan_definitions() -> Vec<SearchParamDef> {
             path: vec!["period".to_string(), "start".to_string()],
             extraction: ExtractionMode::PeriodStart,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1376,6 +1819,7 @@ fn care_team_definitions() -> Vec<SearchParamDef> {
             path: vec!["subject".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec!["Patient".to_string(), "Group".to_string()],
         },
         SearchParamDef {
             name: "status".to_string(),
@@ -1383,6 +1827,7 @@ fn care_team_definitions() -> Vec<SearchParamDef> {
             path: vec!["status".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         // CareTeam.participant.role — `participant` is an array, so the role
         // CodeableConcept is reached through one intermediate array element.
@@ -1392,6 +1837,7 @@ fn care_team_definitions() -> Vec<SearchParamDef> {
             path: vec!["participant".to_string(), "role".to_string()],
             extraction: ExtractionMode::NestedCodeableConcept,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1404,6 +1850,7 @@ fn provenance_definitions() -> Vec<SearchParamDef> {
             path: vec!["target".to_string()],
             extraction: ExtractionMode::Reference,
             aliases: vec!["patient".to_string()],
+            targets: vec![],
         },
         SearchParamDef {
             name: "recorded".to_string(),
@@ -1411,6 +1858,7 @@ fn provenance_definitions() -> Vec<SearchParamDef> {
             path: vec!["recorded".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1421,6 +1869,70 @@ fn common_definitions() -> Vec<SearchParamDef> {
     common_fhir_params()
 }
 
+/// Canonical-resource parameters shared by ValueSet and CodeSystem.
+fn canonical_resource_definitions() -> Vec<SearchParamDef> {
+    vec![
+        SearchParamDef {
+            name: "url".to_string(),
+            param_type: SearchParamType::Uri,
+            path: vec!["url".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "version".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["version".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "name".to_string(),
+            param_type: SearchParamType::String,
+            path: vec!["name".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "title".to_string(),
+            param_type: SearchParamType::String,
+            path: vec!["title".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+        SearchParamDef {
+            name: "status".to_string(),
+            param_type: SearchParamType::Token,
+            path: vec!["status".to_string()],
+            extraction: ExtractionMode::Simple,
+            aliases: vec![],
+            targets: vec![],
+        },
+    ]
+}
+
+fn value_set_definitions() -> Vec<SearchParamDef> {
+    canonical_resource_definitions()
+}
+
+fn code_system_definitions() -> Vec<SearchParamDef> {
+    let mut defs = canonical_resource_definitions();
+    // CodeSystem?system= is the CodeSystem's own url.
+    defs.push(SearchParamDef {
+        name: "system".to_string(),
+        param_type: SearchParamType::Uri,
+        path: vec!["url".to_string()],
+        extraction: ExtractionMode::Simple,
+        aliases: vec![],
+        targets: vec![],
+    });
+    defs
+}
+
 /// Parameters defined by the base FHIR spec that apply to every resource
 /// (`_id`, `_lastUpdated`, `_profile`, `_tag`, `_security`).
 /// Appended to every resource-specific list.
@@ -1432,6 +1944,7 @@ fn common_fhir_params() -> Vec<SearchParamDef> {
             path: vec!["id".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "_lastUpdated".to_string(),
@@ -1439,6 +1952,7 @@ fn common_fhir_params() -> Vec<SearchParamDef> {
             path: vec!["meta".to_string(), "lastUpdated".to_string()],
             extraction: ExtractionMode::Simple,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "_profile".to_string(),
@@ -1446,6 +1960,7 @@ fn common_fhir_params() -> Vec<SearchParamDef> {
             path: vec!["meta".to_string(), "profile".to_string()],
             extraction: ExtractionMode::UriArray,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "_tag".to_string(),
@@ -1453,6 +1968,7 @@ fn common_fhir_params() -> Vec<SearchParamDef> {
             path: vec!["meta".to_string(), "tag".to_string()],
             extraction: ExtractionMode::CodingArray,
             aliases: vec![],
+            targets: vec![],
         },
         SearchParamDef {
             name: "_security".to_string(),
@@ -1460,6 +1976,7 @@ fn common_fhir_params() -> Vec<SearchParamDef> {
             path: vec!["meta".to_string(), "security".to_string()],
             extraction: ExtractionMode::CodingArray,
             aliases: vec![],
+            targets: vec![],
         },
     ]
 }
@@ -1488,6 +2005,74 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reference_targets() {
+        let mut registry = SearchParamRegistry::new();
+        assert_eq!(registry.reference_targets("Observation", "subject"), vec!["Patient", "Group", "Device", "Location"]);
+        assert_eq!(registry.reference_targets("Observation", "patient"), vec!["Patient"]);
+        assert_eq!(registry.reference_targets("Condition", "encounter"), vec!["Encounter"]);
+        assert_eq!(registry.reference_targets("DiagnosticReport", "result"), vec!["Observation"]);
+        assert_eq!(registry.reference_targets("MedicationRequest", "medication"), vec!["Medication"]);
+        assert_eq!(registry.reference_targets("Goal", "subject"), vec!["Patient", "Group", "Organization"]);
+        assert_eq!(registry.reference_targets("Goal", "patient"), vec!["Patient"]);
+        // Any-typed references and non-references have no fixed targets.
+        assert!(registry.reference_targets("Provenance", "target").is_empty());
+        assert!(registry.reference_targets("Observation", "code").is_empty());
+
+        registry
+            .register_search_parameter(&serde_json::json!({
+                "resourceType": "SearchParameter",
+                "code": "custodian",
+                "base": ["Patient"],
+                "type": "reference",
+                "expression": "Patient.extension.value",
+                "target": ["Organization"]
+            }))
+            .unwrap();
+        assert_eq!(registry.reference_targets("Patient", "custodian"), vec!["Organization"]);
+        assert!(registry.types_with_reference_param("custodian").contains(&"Patient".to_string()));
+    }
+
+    #[test]
+    fn test_types_with_reference_param() {
+        let registry = SearchParamRegistry::new();
+        let types = registry.types_with_reference_param("subject");
+        for rt in ["Condition", "Observation", "Encounter"] {
+            assert!(types.contains(&rt.to_string()), "{rt}");
+        }
+        assert!(!types.contains(&"Patient".to_string()));
+        assert!(registry.types_with_reference_param("code").is_empty());
+    }
+
+    #[test]
+    fn test_reregister_and_unregister_search_parameter() {
+        let mut registry = SearchParamRegistry::new();
+        let builtin = registry.get_definitions("Patient").len();
+        let mut sp = serde_json::json!({
+            "resourceType": "SearchParameter",
+            "url": "http://example.org/SearchParameter/patient-nickname",
+            "code": "nickname",
+            "base": ["Patient"],
+            "type": "string",
+            "expression": "Patient.name.given"
+        });
+        registry.register_search_parameter(&sp).unwrap();
+        sp["type"] = serde_json::json!("token");
+        registry.register_search_parameter(&sp).unwrap();
+        assert_eq!(registry.get_definitions("Patient").len(), builtin + 1);
+        assert_eq!(registry.lookup_param_type("Patient", "nickname"), Some(SearchParamType::Token));
+
+        assert_eq!(registry.unregister_search_parameter(&sp), vec!["Patient"]);
+        assert_eq!(registry.get_definitions("Patient").len(), builtin);
+        assert_eq!(registry.lookup_param_type("Patient", "nickname"), None);
+        assert!(registry.unregister_search_parameter(&sp).is_empty());
+
+        // A built-in parameter is never removed.
+        let family = serde_json::json!({"code": "family", "base": ["Patient"]});
+        assert!(registry.unregister_search_parameter(&family).is_empty());
+        assert!(registry.lookup_param_type("Patient", "family").is_some());
+    }
+
     #[test]
     fn test_provenance_target_param() {
         let registry = SearchParamRegistry::new();
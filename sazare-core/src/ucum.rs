@@ -0,0 +1,427 @@
+//! UCUM unit canonicalization for quantity search.
+//!
+//! Converts a value in a UCUM unit (`mg/dL`, `mmol/L`, `10*9/L`, `mm[Hg]`, …)
+//! to a canonical value in base units, so that quantities recorded in different
+//! but commensurable units compare equal: `0.0054 mol/L` and `5.4 mmol/L` both
+//! canonicalize to `5.4 m-3.mol`.
+//!
+//! The canonical unit is a product of base dimensions written in UCUM syntax
+//! with the atoms sorted (`g.m-3`, `m-3.mol`, `s-1`). Base dimensions are the
+//! UCUM base units (`m`, `s`, `g`, `rad`, `K`, `C`, `cd`) plus `mol`, which
+//! UCUM itself defines as a dimensionless count but which is kept as its own
+//! dimension here so amount-of-substance concentrations don't collapse into
+//! number concentrations. Arbitrary units (`[IU]`, `[arb'U]`) are their own,
+//! inconvertible dimensions. Annotations (`{cells}`) are dimensionless.
+//!
+//! Mass and amount-of-substance concentrations are only commensurable through
+//! the analyte's molar mass, which the unit alone doesn't carry. See
+//! [`molar_mass_for_loinc`] and [`molar_counterpart`].
+//!
+//! Only the subset of UCUM seen in clinical data is covered: metric prefixes on
+//! metric atoms, `.` and `/` with integer exponents, parentheses, `10*n`
+//! factors, and the common customary, pressure, time and enzyme units. Anything
+//! else returns `None` and the caller falls back to exact unit matching.
+
+use std::collections::BTreeMap;
+
+/// The UCUM code system URI carried by `Quantity.system`.
+pub const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+
+/// A unit expression reduced to a factor onto base units and the exponents of
+/// each base dimension.
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    factor: f64,
+    dims: BTreeMap<String, i32>,
+}
+
+impl Term {
+    fn scalar(factor: f64) -> Self {
+        Self { factor, dims: BTreeMap::new() }
+    }
+
+    fn base(symbol: &str) -> Self {
+        Self { factor: 1.0, dims: BTreeMap::from([(symbol.to_string(), 1)]) }
+    }
+
+    fn pow(mut self, exp: i32) -> Self {
+        self.factor = self.factor.powi(exp);
+        for e in self.dims.values_mut() {
+            *e *= exp;
+        }
+        self
+    }
+
+    fn mul(mut self, other: Term) -> Self {
+        self.factor *= other.factor;
+        for (k, e) in other.dims {
+            *self.dims.entry(k).or_insert(0) += e;
+        }
+        self.dims.retain(|_, e| *e != 0);
+        self
+    }
+
+    fn unit(&self) -> String {
+        format_dims(&self.dims)
+    }
+}
+
+fn format_dims(dims: &BTreeMap<String, i32>) -> String {
+    if dims.is_empty() {
+        return "1".to_string();
+    }
+    dims.iter()
+        .map(|(k, e)| if *e == 1 { k.clone() } else { format!("{k}{e}") })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Convert `value` expressed in the UCUM unit `code` to `(canonical value,
+/// canonical unit)`. Returns `None` when the unit isn't understood.
+pub fn canonicalize(value: f64, code: &str) -> Option<(f64, String)> {
+    let code = code.trim();
+    // Temperatures on an offset scale only convert as a whole unit.
+    match code {
+        "Cel" => return Some((value + 273.15, "K".to_string())),
+        "[degF]" => return Some(((value - 32.0) * 5.0 / 9.0 + 273.15, "K".to_string())),
+        _ => {}
+    }
+    let term = parse(code)?;
+    Some((value * term.factor, term.unit()))
+}
+
+/// The canonical unit on the other side of the mass / amount-of-substance
+/// divide, and the power of the analyte's molar mass (g/mol) that converts a
+/// value in that counterpart unit into the given one.
+///
+/// For `m-3.mol` (a molar concentration) this is `("g.m-3", -1)`: a stored
+/// mass concentration divided by the molar mass is the molar concentration.
+/// For `g.m-3` it is `("m-3.mol", 1)`. Units with no single `g` or `mol`
+/// factor have no counterpart.
+pub fn molar_counterpart(canonical_unit: &str) -> Option<(String, i32)> {
+    let mut dims = parse_canonical(canonical_unit)?;
+    let (from, to, power) = match (dims.get("g"), dims.get("mol")) {
+        (None, Some(1)) => ("mol", "g", -1),
+        (Some(1), None) => ("g", "mol", 1),
+        _ => return None,
+    };
+    dims.remove(from);
+    dims.insert(to.to_string(), 1);
+    Some((format_dims(&dims), power))
+}
+
+/// Parse a canonical unit string produced by [`canonicalize`] back into
+/// dimension exponents.
+fn parse_canonical(unit: &str) -> Option<BTreeMap<String, i32>> {
+    if unit == "1" {
+        return Some(BTreeMap::new());
+    }
+    let mut dims = BTreeMap::new();
+    for part in unit.split('.') {
+        let split = part
+            .find(|c: char| c == '-' || c.is_ascii_digit())
+            .unwrap_or(part.len());
+        let (sym, exp) = part.split_at(split);
+        let exp = if exp.is_empty() { 1 } else { exp.parse().ok()? };
+        dims.insert(sym.to_string(), exp);
+    }
+    Some(dims)
+}
+
+/// Molar mass in g/mol of the analyte measured by a LOINC-coded laboratory
+/// test, for the common chemistry analytes that labs report in either mass or
+/// molar units. Both the mass- and the molar-unit LOINC codes are listed.
+pub fn molar_mass_for_loinc(code: &str) -> Option<f64> {
+    let mass = match code {
+        // Glucose
+        "2339-0" | "2345-7" | "15074-8" | "14749-6" | "2340-8" | "14743-9" => 180.156,
+        // Creatinine
+        "2160-0" | "14682-9" | "38483-4" | "59826-8" => 113.12,
+        // Cholesterol (total, HDL, LDL)
+        "2093-3" | "14647-2" | "2085-9" | "14646-4" | "13457-7" | "2089-1" | "22748-8"
+        | "18262-6" => 386.65,
+        // Triglycerides (as triolein)
+        "2571-8" | "14927-8" => 885.7,
+        // Urea nitrogen
+        "3094-0" | "14937-7" | "6299-2" => 28.014,
+        // Urate
+        "3084-1" | "14933-6" => 168.11,
+        // Calcium
+        "17861-6" | "2000-8" => 40.078,
+        // Bilirubin (total, direct)
+        "1975-2" | "14631-6" | "1968-7" | "14629-0" => 584.66,
+        _ => return None,
+    };
+    Some(mass)
+}
+
+// --- Parsing ---
+
+/// Parse a UCUM unit expression. `.` and `/` share one precedence and
+/// associate left to right; a leading `/` is a reciprocal.
+fn parse(code: &str) -> Option<Term> {
+    let mut p = Parser { s: code.as_bytes(), pos: 0 };
+    let term = p.expression()?;
+    (p.pos == p.s.len()).then_some(term)
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn expression(&mut self) -> Option<Term> {
+        let mut term = if self.peek() == Some(b'/') {
+            self.pos += 1;
+            self.component()?.pow(-1)
+        } else {
+            self.component()?
+        };
+        loop {
+            match self.peek() {
+                Some(b'.') => {
+                    self.pos += 1;
+                    term = term.mul(self.component()?);
+                }
+                Some(b'/') => {
+                    self.pos += 1;
+                    term = term.mul(self.component()?.pow(-1));
+                }
+                _ => return Some(term),
+            }
+        }
+    }
+
+    /// An annotated, exponentiated atom, a parenthesized expression, a factor,
+    /// or a bare annotation.
+    fn component(&mut self) -> Option<Term> {
+        let term = match self.peek()? {
+            b'(' => {
+                self.pos += 1;
+                let inner = self.expression()?;
+                if self.peek() != Some(b')') {
+                    return None;
+                }
+                self.pos += 1;
+                inner
+            }
+            b'{' => Term::scalar(1.0),
+            c if c.is_ascii_digit() => {
+                let n = self.integer()?;
+                // `10*3` / `10^3` is a power of ten.
+                if matches!(self.peek(), Some(b'*' | b'^')) {
+                    self.pos += 1;
+                    let exp = self.signed_integer()?;
+                    Term::scalar(10f64.powi(exp))
+                } else {
+                    Term::scalar(n as f64)
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c == b'[' {
+                        // Bracketed atom parts (`[IU]`, `mm[Hg]`) may contain
+                        // characters that are operators elsewhere.
+                        let close = self.s[self.pos..].iter().position(|&b| b == b']')?;
+                        self.pos += close + 1;
+                    } else if c.is_ascii_alphabetic() || c == b'%' || c == b'\'' || c == b'_' {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let symbol = std::str::from_utf8(&self.s[start..self.pos]).ok()?;
+                let atom = atom_with_prefix(symbol)?;
+                match self.peek() {
+                    Some(c) if c == b'-' || c == b'+' || c.is_ascii_digit() => {
+                        let exp = self.signed_integer()?;
+                        atom.pow(exp)
+                    }
+                    _ => atom,
+                }
+            }
+        };
+        // Trailing annotation, e.g. `mL{total}`.
+        if self.peek() == Some(b'{') {
+            let close = self.s[self.pos..].iter().position(|&b| b == b'}')?;
+            self.pos += close + 1;
+        }
+        Some(term)
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn signed_integer(&mut self) -> Option<i32> {
+        let negative = match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                true
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                false
+            }
+            _ => false,
+        };
+        let n = i32::try_from(self.integer()?).ok()?;
+        Some(if negative { -n } else { n })
+    }
+}
+
+/// Resolve an atom symbol, with an optional metric prefix on metric atoms.
+/// An exact atom match wins over a prefix reading (`cd` is candela, not
+/// centi-day; `h` is hour, not hecto-).
+fn atom_with_prefix(symbol: &str) -> Option<Term> {
+    if let Some((term, _)) = atom(symbol) {
+        return Some(term);
+    }
+    const PREFIXES: &[(&str, f64)] = &[
+        ("da", 1e1),
+        ("Y", 1e24),
+        ("Z", 1e21),
+        ("E", 1e18),
+        ("P", 1e15),
+        ("T", 1e12),
+        ("G", 1e9),
+        ("M", 1e6),
+        ("k", 1e3),
+        ("h", 1e2),
+        ("d", 1e-1),
+        ("c", 1e-2),
+        ("m", 1e-3),
+        ("u", 1e-6),
+        ("n", 1e-9),
+        ("p", 1e-12),
+        ("f", 1e-15),
+        ("a", 1e-18),
+    ];
+    for (prefix, factor) in PREFIXES {
+        if let Some(rest) = symbol.strip_prefix(prefix)
+            && let Some((term, true)) = atom(rest)
+        {
+            return Some(Term::scalar(*factor).mul(term));
+        }
+    }
+    None
+}
+
+/// An atom's definition in base units, and whether it is metric (accepts a
+/// prefix).
+fn atom(symbol: &str) -> Option<(Term, bool)> {
+    let base = |s: &str| Term::base(s);
+    let pa = || Term::scalar(1000.0).mul(base("g")).mul(base("m").pow(-1)).mul(base("s").pow(-2));
+    let joule = || Term::scalar(1000.0).mul(base("g")).mul(base("m").pow(2)).mul(base("s").pow(-2));
+    let litre = || Term::scalar(1e-3).mul(base("m").pow(3));
+    let mol_per_s = || base("mol").mul(base("s").pow(-1));
+    let defined = match symbol {
+        "m" | "s" | "g" | "rad" | "K" | "C" | "cd" | "mol" => (base(symbol), true),
+        "1" => (Term::scalar(1.0), false),
+        "%" => (Term::scalar(0.01), false),
+        "[ppm]" => (Term::scalar(1e-6), false),
+        "[ppb]" => (Term::scalar(1e-9), false),
+        "L" | "l" => (litre(), true),
+        "eq" => (base("mol"), true),
+        "kat" => (mol_per_s(), true),
+        // Enzyme unit: 1 umol/min.
+        "U" => (Term::scalar(1e-6 / 60.0).mul(mol_per_s()), true),
+        "min" => (Term::scalar(60.0).mul(base("s")), false),
+        "h" => (Term::scalar(3600.0).mul(base("s")), false),
+        "d" => (Term::scalar(86_400.0).mul(base("s")), false),
+        "wk" => (Term::scalar(604_800.0).mul(base("s")), false),
+        "mo" => (Term::scalar(2_629_800.0).mul(base("s")), false),
+        "a" => (Term::scalar(31_557_600.0).mul(base("s")), false),
+        "Hz" => (base("s").pow(-1), true),
+        "N" => (Term::scalar(1000.0).mul(base("g")).mul(base("m")).mul(base("s").pow(-2)), true),
+        "Pa" => (pa(), true),
+        "bar" => (Term::scalar(1e5).mul(pa()), true),
+        "atm" => (Term::scalar(101_325.0).mul(pa()), false),
+        "m[Hg]" => (Term::scalar(133_322.387_415).mul(pa()), true),
+        "m[H2O]" => (Term::scalar(9_806.65).mul(pa()), true),
+        "J" => (joule(), true),
+        "W" => (joule().mul(base("s").pow(-1)), true),
+        "cal" => (Term::scalar(4.184).mul(joule()), true),
+        "[Cal]" => (Term::scalar(4184.0).mul(joule()), false),
+        "t" => (Term::scalar(1e6).mul(base("g")), true),
+        "[in_i]" => (Term::scalar(0.0254).mul(base("m")), false),
+        "[ft_i]" => (Term::scalar(0.3048).mul(base("m")), false),
+        "[lb_av]" => (Term::scalar(453.592_37).mul(base("g")), false),
+        "[oz_av]" => (Term::scalar(28.349_523_125).mul(base("g")), false),
+        "[gal_us]" => (Term::scalar(3.785_411_784).mul(litre()), false),
+        "[foz_us]" => (Term::scalar(0.029_573_529_562_5).mul(litre()), false),
+        // Arbitrary units are commensurable only with themselves.
+        "[IU]" | "[iU]" => (base("[IU]"), true),
+        s if s.starts_with('[') && s.ends_with("U]") => (base(s), false),
+        _ => return None,
+    };
+    Some(defined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn test_commensurable_units_share_a_canonical_form() {
+        let (a, ua) = canonicalize(5.4, "mmol/L").unwrap();
+        let (b, ub) = canonicalize(0.0054, "mol/L").unwrap();
+        assert_eq!(ua, "m-3.mol");
+        assert_eq!(ua, ub);
+        assert!(close(a, b) && close(a, 5.4));
+
+        let (mg, u) = canonicalize(97.3, "mg/dL").unwrap();
+        assert_eq!(u, "g.m-3");
+        assert!(close(mg, 973.0));
+
+        let (cells, u) = canonicalize(4.5, "10*9/L").unwrap();
+        assert_eq!(u, "m-3");
+        assert!(close(cells, 4.5e12));
+        let (per_ul, u2) = canonicalize(4.5, "10*3/uL").unwrap();
+        assert!(close(cells, per_ul) && u == u2);
+    }
+
+    #[test]
+    fn test_prefixes_exponents_and_annotations() {
+        assert_eq!(canonicalize(2.0, "cm2").unwrap().1, "m2");
+        assert!(close(canonicalize(2.0, "cm2").unwrap().0, 2e-4));
+        let (v, u) = canonicalize(90.0, "mL/min/{1.73_m2}").unwrap();
+        assert_eq!(u, "m3.s-1");
+        assert!(close(v, 1.5e-6));
+        // `cd` is candela and `h` hour, not prefixed atoms.
+        assert_eq!(canonicalize(1.0, "cd").unwrap().1, "cd");
+        assert_eq!(canonicalize(1.0, "h"), Some((3600.0, "s".to_string())));
+        let (kpa, u) = canonicalize(120.0, "mm[Hg]").unwrap();
+        assert_eq!(u, "g.m-1.s-2");
+        // 1 mm[Hg] = 133.322 Pa = 133 322 g.m-1.s-2.
+        assert!(close(kpa, 120.0 * 133_322.387_415));
+        assert!(close(canonicalize(37.0, "Cel").unwrap().0, 310.15));
+        assert_eq!(canonicalize(1.0, "furlong"), None);
+        assert_eq!(canonicalize(1.0, "mg/"), None);
+    }
+
+    #[test]
+    fn test_molar_counterpart() {
+        assert_eq!(molar_counterpart("m-3.mol"), Some(("g.m-3".to_string(), -1)));
+        assert_eq!(molar_counterpart("g.m-3"), Some(("m-3.mol".to_string(), 1)));
+        assert_eq!(molar_counterpart("m-3"), None);
+        // 97.3 mg/dL of glucose is 5.4 mmol/L.
+        let (mass, _) = canonicalize(97.3, "mg/dL").unwrap();
+        let molar = mass / molar_mass_for_loinc("2345-7").unwrap();
+        assert!((molar - 5.4).abs() < 0.01);
+    }
+}
@@ -98,6 +98,9 @@ pub struct ValueSet {
 pub struct CodeSystem {
     pub url: String,
     pub codes: Vec<String>,
+    /// Each code's direct parents, for hierarchical systems (`:above` /
+    /// `:below` token searches). Empty for flat systems.
+    pub parents: HashMap<String, Vec<String>>,
 }
 
 impl TerminologyRegistry {
@@ -296,6 +299,102 @@ impl TerminologyRegistry {
         }
     }
 
+    /// Load a FHIR `CodeSystem` resource (JSON): its codes and their
+    /// hierarchy, from nested `concept[].concept[]` and from `parent`
+    /// properties. CodeSystems without concepts are ignored.
+    pub fn load_code_system_resource(&mut self, json: &str) {
+        let cs: Value = match serde_json::from_str(json) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let Some(url) = cs.get("url").and_then(|v| v.as_str()) else {
+            return;
+        };
+        fn walk(
+            concepts: &[Value],
+            parent: Option<&str>,
+            codes: &mut Vec<String>,
+            parents: &mut HashMap<String, Vec<String>>,
+        ) {
+            for concept in concepts {
+                let Some(code) = concept.get("code").and_then(|v| v.as_str()) else {
+                    continue;
+                };
+                codes.push(code.to_string());
+                let entry = parents.entry(code.to_string()).or_default();
+                entry.extend(parent.map(str::to_string));
+                let declared = concept
+                    .get("property")
+                    .and_then(|p| p.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter(|p| p.get("code").and_then(|c| c.as_str()) == Some("parent"))
+                    .filter_map(|p| p.get("valueCode").and_then(|c| c.as_str()));
+                entry.extend(declared.map(str::to_string));
+                if let Some(children) = concept.get("concept").and_then(|c| c.as_array()) {
+                    walk(children, Some(code), codes, parents);
+                }
+            }
+        }
+        let mut codes = Vec::new();
+        let mut parents = HashMap::new();
+        if let Some(concepts) = cs.get("concept").and_then(|c| c.as_array()) {
+            walk(concepts, None, &mut codes, &mut parents);
+        }
+        if !codes.is_empty() {
+            self.add_code_system(CodeSystem {
+                url: url.to_string(),
+                codes,
+                parents,
+            });
+        }
+    }
+
+    /// The enumerated codes of a ValueSet, or `None` if it isn't loaded.
+    pub fn value_set_codes(&self, url: &str) -> Option<&[String]> {
+        self.value_sets.get(url).map(|vs| vs.codes.as_slice())
+    }
+
+    /// `code` and every code below it in the system's hierarchy, or `None` if
+    /// the system isn't loaded or doesn't define the code.
+    pub fn descendants_or_self(&self, system: &str, code: &str) -> Option<Vec<String>> {
+        let cs = self.code_systems.get(system)?;
+        cs.codes.iter().any(|c| c == code).then(|| {
+            let mut found = vec![code.to_string()];
+            let mut i = 0;
+            while i < found.len() {
+                let below: Vec<String> = cs
+                    .parents
+                    .iter()
+                    .filter(|(child, parents)| parents.contains(&found[i]) && !found.contains(child))
+                    .map(|(child, _)| child.clone())
+                    .collect();
+                found.extend(below);
+                i += 1;
+            }
+            found
+        })
+    }
+
+    /// `code` and every code above it in the system's hierarchy, or `None` if
+    /// the system isn't loaded or doesn't define the code.
+    pub fn ancestors_or_self(&self, system: &str, code: &str) -> Option<Vec<String>> {
+        let cs = self.code_systems.get(system)?;
+        cs.codes.iter().any(|c| c == code).then(|| {
+            let mut found = vec![code.to_string()];
+            let mut i = 0;
+            while i < found.len() {
+                for parent in cs.parents.get(&found[i]).into_iter().flatten() {
+                    if !found.contains(parent) {
+                        found.push(parent.clone());
+                    }
+                }
+                i += 1;
+            }
+            found
+        })
+    }
+
     pub fn add_value_set(&mut self, value_set: ValueSet) {
         self.value_sets.insert(value_set.url.clone(), value_set);
     }
@@ -379,6 +478,37 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_code_system_hierarchy() {
+        let mut registry = TerminologyRegistry::new();
+        registry.load_code_system_resource(
+            &json!({
+                "resourceType": "CodeSystem",
+                "url": "http://example.org/cs",
+                "concept": [{
+                    "code": "disorder",
+                    "concept": [
+                        {"code": "diabetes", "concept": [{"code": "type-1"}, {"code": "type-2"}]},
+                        {"code": "asthma"}
+                    ]
+                }, {
+                    "code": "gestational",
+                    "property": [{"code": "parent", "valueCode": "diabetes"}]
+                }]
+            })
+            .to_string(),
+        );
+        let mut below = registry.descendants_or_self("http://example.org/cs", "diabetes").unwrap();
+        below.sort();
+        assert_eq!(below, vec!["diabetes", "gestational", "type-1", "type-2"]);
+        assert_eq!(
+            registry.ancestors_or_self("http://example.org/cs", "type-2").unwrap(),
+            vec!["type-2", "diabetes", "disorder"]
+        );
+        assert!(registry.descendants_or_self("http://example.org/cs", "nope").is_none());
+        assert!(registry.ancestors_or_self("http://example.org/other", "diabetes").is_none());
+    }
+
     #[test]
     fn test_validate_codeable_concept() {
         let registry = TerminologyRegistry::new();
@@ -46,6 +46,7 @@ fn parse_operation(op: &str) -> Operation {
         "SEARCH" | "HISTORY" => Operation::Search,
         "TRANSACTION" | "BATCH" | "IMPORT" => Operation::Create,
         "EXPORT" => Operation::Read,
+        "EXPUNGE" => Operation::Expunge,
         _ => Operation::Read, // default fallback
     }
 }
@@ -139,6 +140,51 @@ pub fn log_operation_error(
     });
 }
 
+/// Log an `$expunge` or history pruning run. `summary` (what was asked for
+/// and how much went) is kept as the entry's query string; nothing of the
+/// removed resources is recorded beyond the type and id the run was scoped to.
+pub fn log_expunge(
+    context: &AuditContext,
+    resource_type: Option<&str>,
+    resource_id: Option<&str>,
+    summary: &str,
+    audit_log: &Arc<Mutex<AuditLog>>,
+) {
+    tracing::info!(
+        user_id = context.user_id.as_deref().unwrap_or("anonymous"),
+        client_ip = %context.client_ip,
+        operation = "EXPUNGE",
+        resource_type = resource_type.unwrap_or("*"),
+        resource_id = resource_id.unwrap_or("*"),
+        status = "success",
+        "Audit: EXPUNGE {}",
+        summary
+    );
+
+    let context = context.clone();
+    let resource_type = resource_type.map(|s| s.to_string());
+    let resource_id = resource_id.map(|s| s.to_string());
+    let summary = summary.to_string();
+    let audit_log = Arc::clone(audit_log);
+
+    tokio::spawn(async move {
+        let audit = audit_log.lock().await;
+        if let Err(e) = audit.log(
+            Operation::Expunge,
+            resource_type.as_deref(),
+            resource_id.as_deref(),
+            None,
+            Some(&summary),
+            context.user_id.as_deref(),
+            Some(&context.client_ip),
+            true,
+            None,
+        ) {
+            tracing::error!("Failed to write audit log to database: {}", e);
+        }
+    });
+}
+
 /// Log an authentication attempt
 pub fn log_auth_attempt(client_ip: &str, user_id: Option<&str>, success: bool) {
     if success {
@@ -428,6 +428,7 @@ fn extract_resource_action(method: &Method, path: &str) -> Option<(String, Strin
             | "$export-file"
             | "$import"
             | "$status"
+            | "$fhirpath"
             | ".well-known"
             | "plugins"
     ) {
@@ -438,6 +439,23 @@ fn extract_resource_action(method: &Method, path: &str) -> Option<(String, Strin
     if segments.last() == Some(&"$export") {
         return None;
     }
+    // Polling a reindex job needs the same scope family as starting one.
+    if first == "$reindex-status" {
+        return Some(("$reindex".to_string(), "read".to_string()));
+    }
+    // `$expunge` at any level is an administrator's operation, whatever the
+    // resource type; the handler further insists on the scope by name.
+    if segments.last() == Some(&"$expunge") {
+        return Some(("$expunge".to_string(), "write".to_string()));
+    }
+    // The server's history covers every type, so it takes a wildcard scope.
+    if first == "_history" {
+        return Some(("*".to_string(), "read".to_string()));
+    }
+    // FHIRPath evaluation only reads the resource, even when POSTed.
+    if segments.len() == 3 && segments[2] == "$fhirpath" {
+        return Some((first.to_string(), "read".to_string()));
+    }
 
     let resource_type = first.to_string();
     let action = match *method {
@@ -535,17 +553,17 @@ mod tests {
         let idx_path = dir.path().join("idx.sqlite");
         let audit_path = dir.path().join("audit.sqlite");
         Arc::new(AppState {
-            store: sazare_store::SqliteStore::open(&db_path).unwrap(),
-            index: tokio::sync::Mutex::new(
+            store: Box::new(sazare_store::SqliteStore::open(&db_path).unwrap()),
+            index: tokio::sync::Mutex::new(Box::new(
                 sazare_store::SearchIndex::open(&idx_path).unwrap(),
-            ),
+            )),
             audit: Arc::new(tokio::sync::Mutex::new(
                 sazare_store::AuditLog::open(&audit_path).unwrap(),
             )),
             config,
             profile_registry: sazare_core::validation::ProfileRegistry::new(),
             terminology_registry: sazare_core::validation::TerminologyRegistry::new(),
-            search_param_registry: sazare_core::SearchParamRegistry::new(),
+            search_param_registry: std::sync::RwLock::new(sazare_core::SearchParamRegistry::new()),
             compartment_def: sazare_core::CompartmentDef::patient_compartment(),
             jwk_cache: RwLock::new(JwkCache::new()),
             plugin_names: Vec::new(),
@@ -786,6 +804,29 @@ mod tests {
         assert!(extract_resource_action(&Method::GET, "/health").is_none());
     }
 
+    #[test]
+    fn test_extract_resource_action_system_history_needs_wildcard() {
+        let (resource_type, action) = extract_resource_action(&Method::GET, "/_history").unwrap();
+        assert_eq!((resource_type.as_str(), action.as_str()), ("*", "read"));
+        assert!(check_scope(&["user/*.read".to_string()], &resource_type, &action));
+        assert!(!check_scope(&["user/Patient.read".to_string()], &resource_type, &action));
+        assert_eq!(
+            extract_resource_action(&Method::GET, "/Patient/_history"),
+            Some(("Patient".to_string(), "read".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_extract_resource_action_expunge_is_its_own_scope() {
+        for path in ["/$expunge", "/Patient/$expunge", "/Patient/p1/$expunge"] {
+            assert_eq!(
+                extract_resource_action(&Method::POST, path),
+                Some(("$expunge".to_string(), "write".to_string()))
+            );
+        }
+        assert!(!check_scope(&["user/Patient.write".to_string()], "$expunge", "write"));
+    }
+
     // --- is_patient_scoped tests ---
 
     #[test]
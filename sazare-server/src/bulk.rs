@@ -6,7 +6,7 @@
 
 use crate::audit::{self, AuditContext};
 use crate::auth::AuthUser;
-use crate::handlers::merge_version_meta;
+use crate::handlers::{merge_version_meta, next_version, store_resource};
 use crate::AppState;
 
 use axum::{
@@ -15,7 +15,6 @@ use axum::{
     response::IntoResponse,
 };
 use sazare_core::validation::validate_resource_all_phases;
-use sazare_store::IndexBuilder;
 use serde_json::{json, Value};
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -106,7 +105,10 @@ pub async fn import(
                     .unwrap_or(0);
                 (current + 1).to_string()
             }
-            _ => "1".to_string(),
+            // A deleted id carries on past its tombstone.
+            _ => next_version(
+                state.store.list_versions(&resource_type, &id).ok().and_then(|v| v.into_iter().next()).as_deref(),
+            ),
         };
 
         // Set id and meta (preserve caller-provided meta fields)
@@ -127,26 +129,8 @@ pub async fn import(
                 continue;
             }
         };
-        match state
-            .store
-            .put_with_version(&resource_type, &id, &version_id, &data)
-        {
+        match store_resource(&state, &resource_type, &id, &version_id, &data, &resource).await {
             Ok(()) => {
-                // Index
-                let indices = IndexBuilder::extract_indices_with_registry(&state.search_param_registry, &resource_type, &resource);
-                let index = state.index.lock().await;
-                let _ = index.remove_index(&resource_type, &id);
-                for (param_name, param_type, value, system) in indices {
-                    let _ = index.add_index(
-                        &resource_type,
-                        &id,
-                        &param_name,
-                        &param_type,
-                        Some(&value),
-                        system.as_deref(),
-                    );
-                }
-                drop(index);
                 // Fire subscriptions/webhooks for imported resources too.
                 state.webhook.maybe_task_completed(&resource);
                 {
@@ -2,6 +2,7 @@
 
 use super::{error_entry, BundleEntry};
 use crate::audit::{self, AuditContext};
+use crate::handlers::{next_version, remove_resource, search_parameter, store_resource};
 use crate::{conditional_create_check, ConditionalResult, AppState};
 
 use axum::{
@@ -10,7 +11,6 @@ use axum::{
     Json,
 };
 use sazare_core::validation::validate_resource_all_phases;
-use sazare_store::IndexBuilder;
 use serde_json::{json, Value};
 use std::sync::Arc;
 
@@ -115,6 +115,12 @@ async fn process_batch_entry(
                     }
                 });
             }
+            // A SearchParameter must register before it is stored
+            if entry.resource_type == "SearchParameter"
+                && let Err(e) = search_parameter::check(state, resource)
+            {
+                return error_entry("400 Bad Request", &format!("entry[{}]: {}", index, e));
+            }
 
             let id = uuid::Uuid::new_v4().to_string();
             let version_id = "1".to_string();
@@ -125,26 +131,12 @@ async fn process_batch_entry(
             }
 
             let data = serde_json::to_vec(&resource).unwrap();
-            match state
-                .store
-                .put_with_version(&entry.resource_type, &id, &version_id, &data)
-            {
+            match store_resource(state, &entry.resource_type, &id, &version_id, &data, resource).await {
                 Ok(()) => {
-                    // Index
-                    let indices = IndexBuilder::extract_indices_with_registry(&state.search_param_registry, &entry.resource_type, resource);
-                    let idx = state.index.lock().await;
-                    for (param_name, param_type, value, system) in indices {
-                        let _ = idx.add_index(
-                            &entry.resource_type,
-                            &id,
-                            &param_name,
-                            &param_type,
-                            Some(&value),
-                            system.as_deref(),
-                        );
-                    }
-
                     notify_change(state, &entry.resource_type, &id, resource);
+                    if entry.resource_type == "SearchParameter" {
+                        search_parameter::apply(state, None, Some(resource));
+                    }
                     json!({
                         "response": {
                             "status": "201 Created",
@@ -191,8 +183,15 @@ async fn process_batch_entry(
                     }
                 });
             }
+            // A SearchParameter must register before it is stored
+            if entry.resource_type == "SearchParameter"
+                && let Err(e) = search_parameter::check(state, resource)
+            {
+                return error_entry("400 Bad Request", &format!("entry[{}]: {}", index, e));
+            }
 
             // Determine version
+            let mut previous = None;
             let (is_create, version_id) = match state.store.get(&entry.resource_type, &id) {
                 Ok(Some(existing)) => {
                     let existing: Value = serde_json::from_slice(&existing).unwrap_or(json!({}));
@@ -202,9 +201,14 @@ async fn process_batch_entry(
                         .and_then(|v| v.as_str())
                         .and_then(|s| s.parse().ok())
                         .unwrap_or(0);
+                    previous = Some(existing);
                     (false, (current + 1).to_string())
                 }
-                Ok(None) => (true, "1".to_string()),
+                Ok(None) => match state.store.list_versions(&entry.resource_type, &id) {
+                    // A deleted id carries on past its tombstone.
+                    Ok(versions) => (true, next_version(versions.first().map(String::as_str))),
+                    Err(e) => return error_entry("500 Internal Server Error", &e.to_string()),
+                },
                 Err(e) => {
                     return error_entry("500 Internal Server Error", &e.to_string());
                 }
@@ -216,27 +220,12 @@ async fn process_batch_entry(
             }
 
             let data = serde_json::to_vec(&resource).unwrap();
-            match state
-                .store
-                .put_with_version(&entry.resource_type, &id, &version_id, &data)
-            {
+            match store_resource(state, &entry.resource_type, &id, &version_id, &data, resource).await {
                 Ok(()) => {
-                    // Re-index
-                    let indices = IndexBuilder::extract_indices_with_registry(&state.search_param_registry, &entry.resource_type, resource);
-                    let idx = state.index.lock().await;
-                    let _ = idx.remove_index(&entry.resource_type, &id);
-                    for (param_name, param_type, value, system) in indices {
-                        let _ = idx.add_index(
-                            &entry.resource_type,
-                            &id,
-                            &param_name,
-                            &param_type,
-                            Some(&value),
-                            system.as_deref(),
-                        );
-                    }
-
                     notify_change(state, &entry.resource_type, &id, resource);
+                    if entry.resource_type == "SearchParameter" {
+                        search_parameter::apply(state, previous.as_ref(), Some(resource));
+                    }
                     let status = if is_create {
                         "201 Created"
                     } else {
@@ -266,12 +255,20 @@ async fn process_batch_entry(
                 }
             };
 
-            match state.store.delete(&entry.resource_type, &id) {
-                Ok(_) => {
-                    // Remove indices
-                    let idx = state.index.lock().await;
-                    let _ = idx.remove_index(&entry.resource_type, &id);
+            let existing = if entry.resource_type == "SearchParameter" {
+                match state.store.get(&entry.resource_type, &id) {
+                    Ok(data) => data.and_then(|data| serde_json::from_slice::<Value>(&data).ok()),
+                    Err(e) => return error_entry("500 Internal Server Error", &e.to_string()),
+                }
+            } else {
+                None
+            };
 
+            match remove_resource(state, &entry.resource_type, &id, None).await {
+                Ok(removed) => {
+                    if removed && existing.is_some() {
+                        search_parameter::apply(state, existing.as_ref(), None);
+                    }
                     json!({
                         "response": { "status": "204 No Content" }
                     })
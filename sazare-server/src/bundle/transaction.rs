@@ -2,6 +2,9 @@
 
 use super::{resolve_references, BundleEntry};
 use crate::audit::{self, AuditContext};
+use crate::handlers::{
+    delete_version, next_version, search_parameter, transaction_resolver, update_search_index, write_index,
+};
 use crate::{conditional_create_check, ConditionalResult, AppState};
 
 use axum::{
@@ -14,7 +17,6 @@ use sazare_core::{
     validation::validate_resource_all_phases,
     OperationOutcome,
 };
-use sazare_store::IndexBuilder;
 use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -68,6 +70,13 @@ pub(super) async fn process_transaction(
                     );
                     return (StatusCode::BAD_REQUEST, Json(json!(outcome))).into_response();
                 }
+                // A SearchParameter must register before it is stored
+                if entry.resource_type == "SearchParameter"
+                    && let Err(e) = search_parameter::check(state, resource)
+                {
+                    let outcome = OperationOutcome::error(IssueType::Invalid, format!("entry[{}]: {}", i, e));
+                    return (StatusCode::BAD_REQUEST, Json(json!(outcome))).into_response();
+                }
             }
             "DELETE" => {}
             _ => {
@@ -181,10 +190,16 @@ pub(super) async fn process_transaction(
         }
     }
 
-    // Phase 4: Execute all operations in a single SQLite transaction
+    // Phase 4: Execute all operations in a single store transaction — with
+    // `storage.single_database` or on PostgreSQL, their search index entries
+    // included.
     let mut resources_for_index: Vec<(String, String, Value)> = Vec::new();
     let mut deleted_for_index: Vec<(String, String)> = Vec::new();
+    // SearchParameters written or deleted: (previous, current) for the registry.
+    let mut search_params_changed: Vec<(Option<Value>, Option<Value>)> = Vec::new();
     let mut response_entries: Vec<Value> = Vec::with_capacity(entries.len());
+    let atomic_index = state.config.storage.atomic_index_writes();
+    let index = state.index.lock().await;
 
     #[allow(clippy::result_large_err)]
     let tx_result = state.store.in_transaction(|ops| {
@@ -217,6 +232,9 @@ pub(super) async fn process_transaction(
                     })?;
                     ops.put_with_version(resource_type, id, &version_id, &data)?;
 
+                    if resource_type == "SearchParameter" {
+                        search_params_changed.push((None, Some(resource.clone())));
+                    }
                     resources_for_index.push((
                         resource_type.clone(),
                         id.clone(),
@@ -233,7 +251,8 @@ pub(super) async fn process_transaction(
                     let resource = entry.resource.as_mut().unwrap();
 
                     // Determine version from existing resource
-                    let version_id = match ops.get(resource_type, id)? {
+                    let mut previous = None;
+                    let (is_create, version_id) = match ops.get(resource_type, id)? {
                         Some(existing) => {
                             let existing: Value =
                                 serde_json::from_slice(&existing).unwrap_or(json!({}));
@@ -243,13 +262,13 @@ pub(super) async fn process_transaction(
                                 .and_then(|v| v.as_str())
                                 .and_then(|s| s.parse().ok())
                                 .unwrap_or(0);
-                            (current + 1).to_string()
+                            previous = Some(existing);
+                            (false, (current + 1).to_string())
                         }
-                        None => "1".to_string(),
+                        // A deleted id carries on past its tombstone.
+                        None => (true, next_version(ops.latest_version(resource_type, id)?.as_deref())),
                     };
 
-                    let is_create = version_id == "1";
-
                     if let Some(obj) = resource.as_object_mut() {
                         obj.insert("id".to_string(), json!(id));
                         crate::handlers::merge_version_meta(obj, &version_id);
@@ -260,6 +279,9 @@ pub(super) async fn process_transaction(
                     })?;
                     ops.put_with_version(resource_type, id, &version_id, &data)?;
 
+                    if resource_type == "SearchParameter" {
+                        search_params_changed.push((previous, Some(resource.clone())));
+                    }
                     resources_for_index.push((
                         resource_type.clone(),
                         id.clone(),
@@ -279,7 +301,15 @@ pub(super) async fn process_transaction(
                     }));
                 }
                 "DELETE" => {
-                    let _existed = ops.delete(resource_type, id)?;
+                    let existing = match resource_type.as_str() {
+                        "SearchParameter" => ops.get(resource_type, id)?,
+                        _ => None,
+                    };
+                    if delete_version(ops, resource_type, id)?
+                        && let Some(existing) = existing.and_then(|e| serde_json::from_slice(&e).ok())
+                    {
+                        search_params_changed.push((Some(existing), None));
+                    }
                     deleted_for_index.push((resource_type.clone(), id.clone()));
                     response_entries.push(json!({
                         "response": { "status": "204 No Content" }
@@ -288,6 +318,17 @@ pub(super) async fn process_transaction(
                 _ => unreachable!(),
             }
         }
+        if atomic_index {
+            let writer = ops.index();
+            let resolver = transaction_resolver(ops);
+            let registry = state.search_params();
+            for (resource_type, id) in &deleted_for_index {
+                writer.remove_index(resource_type, id)?;
+            }
+            for (resource_type, id, resource) in &resources_for_index {
+                write_index(&*writer, &resolver, &registry, resource_type, id, resource)?;
+            }
+        }
         Ok(())
     });
 
@@ -300,22 +341,22 @@ pub(super) async fn process_transaction(
         return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!(outcome))).into_response();
     }
 
-    // Phase 5: Update indices (outside SQLite transaction — separate DB)
-    {
-        let index = state.index.lock().await;
+    // Phase 5: Update indices (outside the store transaction — separate DB)
+    if !atomic_index {
         // Drop index entries for deleted resources — otherwise searches keep
         // matching them and Bundle.total / _summary=count are inflated.
         for (resource_type, id) in &deleted_for_index {
             let _ = index.remove_index(resource_type, id);
         }
         for (resource_type, id, resource) in &resources_for_index {
-            let _ = index.remove_index(resource_type, id);
-            let indices = IndexBuilder::extract_indices_with_registry(&state.search_param_registry, resource_type, resource);
-            for (param_name, param_type, value, system) in indices {
-                let _ = index.add_index(resource_type, id, &param_name, &param_type, Some(&value), system.as_deref());
-            }
+            update_search_index(&**index, &*state.store, &state.search_params(), resource_type, id, resource);
         }
     }
+    drop(index);
+
+    for (previous, current) in &search_params_changed {
+        search_parameter::apply(state, previous.as_ref(), current.as_ref());
+    }
 
     // Fire subscription notifications and lifecycle webhooks for every created
     // or updated resource — transaction is the most common ingestion path, and
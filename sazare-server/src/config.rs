@@ -1,3 +1,4 @@
+use sazare_core::TotalMode;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -11,6 +12,7 @@ pub struct ServerConfig {
     pub log: LogSettings,
     pub webhook: WebhookSettings,
     pub plugins: PluginSettings,
+    pub search: SearchSettings,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +21,10 @@ pub struct ServerSettings {
     pub host: String,
     pub port: u16,
     pub tls: Option<TlsSettings>,
+    /// HMAC key for search `_page_token` links. Unset: a random key per
+    /// process, so paging links don't survive a restart and aren't shared
+    /// between replicas.
+    pub page_token_secret: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -98,6 +104,38 @@ pub struct StorageSettings {
     pub resources_db: String,
     pub search_index_db: String,
     pub audit_db: String,
+    /// Keep the search index in the resources database, so a resource, its
+    /// history row and its index entries commit in one transaction.
+    /// `search_index_db` is then unused.
+    pub single_database: bool,
+    /// Where resources and the search index are kept.
+    pub backend: StorageBackend,
+    /// Connection string for `backend: postgres`, either `key=value` pairs
+    /// (`host=db user=sazare dbname=fhir`) or a `postgres://` URL.
+    pub postgres_url: Option<String>,
+    /// Prune history versions older than this many days (a stored
+    /// resource's current version is always kept). Unset keeps all history.
+    pub history_retention_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// SQLite files under `data_dir`.
+    #[default]
+    Sqlite,
+    /// A PostgreSQL database holding resources and the index together;
+    /// needs a server built with the `postgres` feature. The audit log
+    /// stays in `audit_db`.
+    Postgres,
+}
+
+impl StorageSettings {
+    /// Whether a resource write and its index entries commit in one
+    /// transaction: with `single_database`, and always on PostgreSQL.
+    pub fn atomic_index_writes(&self) -> bool {
+        self.single_database || self.backend == StorageBackend::Postgres
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -127,12 +165,34 @@ pub struct PluginSettings {
     pub dir: Option<PathBuf>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    /// How `Bundle.total` is computed when the client sends no `_total`:
+    /// `accurate` (count every match), `estimate` (from index statistics) or
+    /// `none` (omit it).
+    pub default_total: TotalMode,
+    /// Most resources `_include` / `_revinclude` (with `:iterate`) add to one
+    /// searchset page; past it the Bundle carries a warning instead.
+    pub max_included: usize,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        Self {
+            default_total: TotalMode::Accurate,
+            max_included: 1000,
+        }
+    }
+}
+
 impl Default for ServerSettings {
     fn default() -> Self {
         Self {
             host: "0.0.0.0".to_string(),
             port: 8080,
             tls: None,
+            page_token_secret: None,
         }
     }
 }
@@ -144,6 +204,10 @@ impl Default for StorageSettings {
             resources_db: "resources.sqlite".to_string(),
             search_index_db: "search_index.sqlite".to_string(),
             audit_db: "audit.sqlite".to_string(),
+            single_database: false,
+            backend: StorageBackend::Sqlite,
+            postgres_url: None,
+            history_retention_days: None,
         }
     }
 }
@@ -187,6 +251,10 @@ impl ServerConfig {
             config.storage.data_dir = PathBuf::from(data_dir);
         }
 
+        if let Ok(url) = std::env::var("SAZARE_POSTGRES_URL") {
+            config.storage.postgres_url = Some(url);
+        }
+
         if let Ok(plugin_dir) = std::env::var("SAZARE_PLUGIN_DIR") {
             config.plugins.dir = Some(PathBuf::from(plugin_dir));
         }
@@ -232,6 +300,7 @@ mod tests {
         assert_eq!(config.server.port, 8080);
         assert_eq!(config.server.host, "0.0.0.0");
         assert!(!config.auth.enabled);
+        assert_eq!(config.storage.history_retention_days, None);
     }
 
     #[test]
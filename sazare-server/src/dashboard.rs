@@ -3,6 +3,9 @@
 //! GET /           — HTML dashboard (when Accept is not application/json)
 //! GET /$status    — JSON API for dashboard polling
 //!
+//! The FHIRPath panel runs expressions against stored resources through
+//! `GET /{type}/{id}/$fhirpath`.
+//!
 //! UI text is localized client-side via a small message catalog (default
 //! English, auto-detecting Japanese from the browser, switchable). Server API
 //! responses (OperationOutcome, etc.) remain English-only.
@@ -212,6 +215,9 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
   .chip { display: inline-block; background: #eef3f8; color: #2980b9; border-radius: 12px;
           padding: 2px 10px; font-size: 12px; margin: 2px 4px 2px 0; cursor: pointer; }
   .chip:hover { background: #dceaf6; }
+  .search-row input#fhirpath-target { width: 240px; }
+  .search-row input#fhirpath-expr { flex: 1; min-width: 240px;
+                                    font-family: "SF Mono", Menlo, Consolas, monospace; }
 </style>
 </head>
 <body>
@@ -269,6 +275,22 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
     </table>
   </div>
 
+  <div class="card">
+    <h2 data-i18n="fhirpath.title">FHIRPath</h2>
+    <div class="search-row">
+      <input id="fhirpath-target" data-i18n-ph="fhirpath.target" placeholder="Patient/123">
+      <input id="fhirpath-expr" data-i18n-ph="fhirpath.expr" placeholder="name.where(use = 'official').family"
+             onkeydown="if(event.key==='Enter')runFhirPath()">
+      <button class="search-btn" data-i18n="fhirpath.btn" onclick="runFhirPath()">Evaluate</button>
+    </div>
+    <div class="search-hint" data-i18n="fhirpath.hint">Evaluate an expression against a stored resource. Each result is listed with its type.</div>
+    <div class="search-url hidden" id="fhirpath-url"></div>
+    <table class="resource-table hidden" id="fhirpath-results-table" style="margin-top:10px;">
+      <thead><tr><th data-i18n="fhirpath.type">Type</th><th data-i18n="fhirpath.value">Value</th></tr></thead>
+      <tbody id="fhirpath-results-body"></tbody>
+    </table>
+  </div>
+
   <div class="card hidden" id="resource-list">
     <div class="panel-header">
       <button class="back-btn" data-i18n="list.back" onclick="hideResourceList()">&larr; Back</button>
@@ -315,6 +337,7 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
       <li><code>DELETE /{type}/{id}</code> Delete</li>
       <li><code>GET /{type}/{id}/_history</code> History</li>
       <li><code>POST /{type}/$validate</code> Validate</li>
+      <li><code>GET /{type}/{id}/$fhirpath?expression=</code> FHIRPath</li>
       <li><code>POST /</code> Bundle (transaction / batch)</li>
       <li><code>GET /$export</code> Export (NDJSON)</li>
       <li><code>POST /$import</code> Import (NDJSON)</li>
@@ -338,6 +361,11 @@ const I18N = {
     "search.btn": "Search",
     "search.hint": "Tip: pick a resource type and a parameter, e.g. Patient with name=Brown. The FHIR URL is shown so you can learn it.",
     "search.results": "results", "search.none": "No matches", "search.error": "Search error",
+    "fhirpath.title": "FHIRPath", "fhirpath.target": "Patient/123",
+    "fhirpath.expr": "name.where(use = 'official').family", "fhirpath.btn": "Evaluate",
+    "fhirpath.hint": "Evaluate an expression against a stored resource. Each result is listed with its type.",
+    "fhirpath.type": "Type", "fhirpath.value": "Value", "fhirpath.results": "results",
+    "fhirpath.none": "Empty collection", "fhirpath.error": "Evaluation error",
     "activity.title": "Recent Activity", "activity.time": "Time", "activity.op": "Operation",
     "activity.resource": "Resource", "activity.result": "Result",
     "activity.loading": "Loading...", "activity.none": "No activity yet",
@@ -480,6 +508,53 @@ async function runSearch() {
   }
 }
 
+// --- FHIRPath playground: evaluate against a stored resource. ---
+async function runFhirPath() {
+  const target = (document.getElementById('fhirpath-target').value || '').trim().replace(/^\/+/, '');
+  const expr = (document.getElementById('fhirpath-expr').value || '').trim();
+  if (!target || !expr) return;
+  const url = '/' + target + '/$fhirpath?expression=' + encodeURIComponent(expr);
+  const urlEl = document.getElementById('fhirpath-url');
+  const tableEl = document.getElementById('fhirpath-results-table');
+  const bodyEl = document.getElementById('fhirpath-results-body');
+  const esc = s => String(s).replace(/&/g,'&amp;').replace(/</g,'&lt;');
+
+  urlEl.classList.remove('hidden');
+  urlEl.innerHTML = 'GET <code>' + esc(url) + '</code>';
+  tableEl.classList.remove('hidden');
+  bodyEl.innerHTML = '<tr><td colspan="2" style="color:#bbb">' + t('list.loading') + '</td></tr>';
+
+  try {
+    const res = await fetch(url, { headers: { 'Accept': 'application/fhir+json' } });
+    const data = await res.json();
+    if (!res.ok) {
+      const msg = (data.issue && data.issue[0] && data.issue[0].diagnostics) || ('HTTP ' + res.status);
+      bodyEl.innerHTML = '<tr><td colspan="2" style="color:#c0392b">' + t('fhirpath.error') + ': ' + esc(msg) + '</td></tr>';
+      return;
+    }
+    const result = (data.parameter || []).find(p => p.name === 'result') || {};
+    const parts = result.part || [];
+    urlEl.innerHTML += ' &middot; <strong>' + parts.length + '</strong> ' + t('fhirpath.results');
+    if (parts.length === 0) {
+      bodyEl.innerHTML = '<tr><td colspan="2" style="color:#bbb">' + t('fhirpath.none') + '</td></tr>';
+      return;
+    }
+    bodyEl.innerHTML = parts.map(p => {
+      const key = Object.keys(p).find(k => k === 'resource' || k.startsWith('value'));
+      let value = key ? p[key] : '';
+      if (key === 'valueString' && p.name === 'Element') {
+        try { value = JSON.parse(value); } catch (e) {}
+      }
+      const shown = typeof value === 'object'
+        ? '<pre class="json-view" style="max-height:240px">' + highlightJson(value, 0) + '</pre>'
+        : '<code>' + esc(value) + '</code>';
+      return '<tr><td>' + esc(p.name) + '</td><td>' + shown + '</td></tr>';
+    }).join('');
+  } catch (err) {
+    bodyEl.innerHTML = '<tr><td colspan="2" style="color:#c0392b">' + t('fhirpath.error') + ': ' + esc(err.message) + '</td></tr>';
+  }
+}
+
 let currentType = '';
 let currentOffset = 0;
 const PAGE_SIZE = 20;
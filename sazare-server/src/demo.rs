@@ -10,9 +10,9 @@ use std::sync::Arc;
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use sazare_core::validation::validate_resource_all_phases;
-use sazare_store::IndexBuilder;
 use serde_json::{json, Value};
 
+use crate::handlers::store_resource;
 use crate::AppState;
 
 /// Curated demo resources. Hand-written to be valid and readable, with stable
@@ -205,18 +205,10 @@ async fn load_resources_into(state: &AppState, resources: &[Value]) -> (usize, V
                 continue;
             }
         };
-        if let Err(e) = state.store.put_with_version(&rt, &id, "1", &data) {
+        if let Err(e) = store_resource(state, &rt, &id, "1", &data, &stored).await {
             errors.push(format!("{rt}/{id}: {e}"));
             continue;
         }
-
-        let indices = IndexBuilder::extract_indices_with_registry(&state.search_param_registry, &rt, &stored);
-        let index = state.index.lock().await;
-        let _ = index.remove_index(&rt, &id);
-        for (param_name, param_type, value, system) in indices {
-            let _ = index.add_index(&rt, &id, &param_name, &param_type, Some(&value), system.as_deref());
-        }
-        drop(index);
         loaded += 1;
     }
 
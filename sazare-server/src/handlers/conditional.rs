@@ -13,7 +13,7 @@ use sazare_store::SearchExecutor;
 use serde_json::{json, Value};
 use std::sync::Arc;
 
-use super::{response_with_etag, update_search_index};
+use super::{remove_resource, response_with_etag, search_parameter, store_resource};
 use crate::audit::{self, AuditContext};
 use crate::auth::AuthUser;
 use crate::compartment_check::check_compartment_access;
@@ -74,7 +74,7 @@ pub async fn conditional_update(
     // Search for matching resources
     let (match_id, is_create) = {
         let index = state.index.lock().await;
-        let executor = SearchExecutor::new(&state.store, &index);
+        let executor = SearchExecutor::new(&*state.store, &**index);
         let ids = executor.search(&resource_type, &query).map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -109,6 +109,16 @@ pub async fn conditional_update(
         return Err((StatusCode::BAD_REQUEST, Json(json!(outcome))));
     }
 
+    // A SearchParameter must register before it is stored
+    if resource_type == "SearchParameter"
+        && let Err(e) = search_parameter::check(&state, &body_value)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+        ));
+    }
+
     let mut resource: Resource = serde_json::from_value(body_value).map_err(|e| {
         (
             StatusCode::BAD_REQUEST,
@@ -152,9 +162,9 @@ pub async fn conditional_update(
             )
         })?;
 
-        state
-            .store
-            .put_with_version(&resource_type, &id, &version_id, &json_bytes)
+        let resource_value = serde_json::to_value(&resource).unwrap_or_default();
+        store_resource(&state, &resource_type, &id, &version_id, &json_bytes, &resource_value)
+            .await
             .map_err(|e| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -162,31 +172,28 @@ pub async fn conditional_update(
                 )
             })?;
 
-        let resource_value = serde_json::to_value(&resource).unwrap_or_default();
-        {
-            let index = state.index.lock().await;
-            update_search_index(&index, &state.search_param_registry, &resource_type, &id, &resource_value);
-        }
-
         audit::log_operation_success(&audit_ctx, "CREATE", &resource_type, &id, &state.audit);
+
+        if resource_type == "SearchParameter" {
+            search_parameter::apply(&state, None, Some(&resource_value));
+        }
         Ok(response_with_etag(StatusCode::CREATED, resource_value).into_response())
     } else {
         // 1 match → update
         let id = match_id.unwrap();
 
-        let new_version = match state.store.get(&resource_type, &id) {
-            Ok(Some(data)) => {
-                let existing: Value = serde_json::from_slice(&data).unwrap_or_default();
-                let current_ver: i32 = existing
-                    .get("meta")
-                    .and_then(|m| m.get("versionId"))
-                    .and_then(|v| v.as_str())
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(0);
-                (current_ver + 1).to_string()
-            }
-            _ => "1".to_string(),
+        let previous: Option<Value> = match state.store.get(&resource_type, &id) {
+            Ok(Some(data)) => serde_json::from_slice(&data).ok(),
+            _ => None,
         };
+        let current_ver: i32 = previous
+            .as_ref()
+            .and_then(|p| p.get("meta"))
+            .and_then(|m| m.get("versionId"))
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let new_version = (current_ver + 1).to_string();
 
         resource.id = Some(id.clone());
         let mut meta = resource.meta.take().unwrap_or_default();
@@ -201,9 +208,9 @@ pub async fn conditional_update(
             )
         })?;
 
-        state
-            .store
-            .put_with_version(&resource_type, &id, &new_version, &json_bytes)
+        let resource_value = serde_json::to_value(&resource).unwrap_or_default();
+        store_resource(&state, &resource_type, &id, &new_version, &json_bytes, &resource_value)
+            .await
             .map_err(|e| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -211,13 +218,11 @@ pub async fn conditional_update(
                 )
             })?;
 
-        let resource_value = serde_json::to_value(&resource).unwrap_or_default();
-        {
-            let index = state.index.lock().await;
-            update_search_index(&index, &state.search_param_registry, &resource_type, &id, &resource_value);
-        }
-
         audit::log_operation_success(&audit_ctx, "UPDATE", &resource_type, &id, &state.audit);
+
+        if resource_type == "SearchParameter" {
+            search_parameter::apply(&state, previous.as_ref(), Some(&resource_value));
+        }
         Ok(response_with_etag(StatusCode::OK, resource_value).into_response())
     }
 }
@@ -256,7 +261,7 @@ pub async fn conditional_delete(
 
     let (ids, resource_to_check) = {
         let index = state.index.lock().await;
-        let executor = SearchExecutor::new(&state.store, &index);
+        let executor = SearchExecutor::new(&*state.store, &**index);
         let ids = executor.search(&resource_type, &query).map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -284,18 +289,18 @@ pub async fn conditional_delete(
                 check_compartment_access(auth_user.as_ref(), &state.compartment_def, &resource_type, resource)?;
             }
 
-            state.store.delete(&resource_type, id).map_err(|e| {
+            remove_resource(&state, &resource_type, id, None).await.map_err(|e| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!(OperationOutcome::storage_error(e.to_string()))),
                 )
             })?;
 
-            // Remove from index
-            let index = state.index.lock().await;
-            let _ = index.remove_index(&resource_type, id);
-
             audit::log_operation_success(&audit_ctx, "DELETE", &resource_type, id, &state.audit);
+
+            if resource_type == "SearchParameter" {
+                search_parameter::apply(&state, resource_to_check.as_ref(), None);
+            }
             Ok(StatusCode::NO_CONTENT)
         }
         _ => Err((
@@ -1,6 +1,6 @@
 use axum::{
     extract::{Path, Request, State},
-    http::{header, StatusCode},
+    http::StatusCode,
     response::{IntoResponse, Json, Response},
 };
 use http_body_util::BodyExt;
@@ -18,8 +18,8 @@ use crate::compartment_check::check_compartment_access;
 use crate::subscription::{self, SubscriptionManager};
 use crate::{AppState, ConditionalResult};
 use super::{
-    base_url_from_headers, extract_version, response_with_etag, response_with_headers,
-    update_search_index, version_location,
+    base_url_from_headers, extract_version, if_match, next_version, read_response, remove_resource, response_with_etag,
+    response_with_headers, search_parameter, store_resource, store_resource_cas, version_location,
 };
 
 /// Extract headers and JSON body from a Request
@@ -95,7 +95,17 @@ pub async fn create(
 
     // Subscription-specific validation
     if resource_type == "Subscription"
-        && let Err(e) = subscription::validate_subscription(&body, &state.search_param_registry)
+        && let Err(e) = subscription::validate_subscription(&body, &state.search_params())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+        ));
+    }
+
+    // A SearchParameter must register before it is stored
+    if resource_type == "SearchParameter"
+        && let Err(e) = search_parameter::check(&state, &body)
     {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -175,23 +185,9 @@ pub async fn create(
         )
     })?;
 
-    // Update the search index *before* persisting the resource. The store and
-    // index are separate SQLite databases, so the two writes can't share a
-    // transaction. Indexing first means a crash between them leaves at most a
-    // stale index entry for a resource that isn't stored — harmless, since
-    // search results are fetched from the store and a missing resource is
-    // dropped. The reverse order risks a committed resource being invisible to
-    // search until the next reindex (a false negative). reindex cleans up any
-    // stale entries.
     let resource_value = serde_json::to_value(&resource).unwrap_or_default();
-    {
-        let index = state.index.lock().await;
-        update_search_index(&index, &state.search_param_registry, &resource_type, &id, &resource_value);
-    }
-
-    state
-        .store
-        .put_with_version(&resource_type, &id, &version_id, &json_bytes)
+    store_resource(&state, &resource_type, &id, &version_id, &json_bytes, &resource_value)
+        .await
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -202,6 +198,10 @@ pub async fn create(
     // Audit log
     audit::log_operation_success(&audit_ctx, "CREATE", &resource_type, &id, &state.audit);
 
+    if resource_type == "SearchParameter" {
+        search_parameter::apply(&state, None, Some(&resource_value));
+    }
+
     // Subscription notification (background)
     {
         let state = state.clone();
@@ -242,7 +242,7 @@ pub async fn read(
             check_compartment_access(auth_user.as_ref(), &state.compartment_def, &resource_type, &resource)?;
 
             audit::log_operation_success(&audit_ctx, "READ", &resource_type, &id, &state.audit);
-            Ok(response_with_etag(StatusCode::OK, resource).into_response())
+            Ok(read_response(request.headers(), resource))
         }
         Ok(None) => {
             // A previously-deleted resource is Gone (410), not merely Not Found.
@@ -288,7 +288,17 @@ pub async fn update(
 
     // Subscription-specific validation
     if resource_type == "Subscription"
-        && let Err(e) = subscription::validate_subscription(&body, &state.search_param_registry)
+        && let Err(e) = subscription::validate_subscription(&body, &state.search_params())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+        ));
+    }
+
+    // A SearchParameter must register before it is stored
+    if resource_type == "SearchParameter"
+        && let Err(e) = search_parameter::check(&state, &body)
     {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -304,14 +314,12 @@ pub async fn update(
     })?;
 
     // If-Match header (optimistic locking)
-    let if_match = headers
-        .get(header::IF_MATCH)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim_matches('"').trim_start_matches("W/\"").trim_end_matches('"').to_string());
+    let if_match = if_match(&headers);
 
     // If-Match without a matching resource → 412. Get existing resource and
     // compute the new version, tracking whether this PUT creates a new resource
     // (update-as-create) so we can return the correct 201 vs 200 status.
+    let mut previous = None;
     let (new_version, is_create, expected_current) = match state.store.get(&resource_type, &id) {
         Ok(Some(data)) => {
             let existing: Value = serde_json::from_slice(&data).unwrap_or_default();
@@ -343,6 +351,7 @@ pub async fn update(
             }
 
             let current_ver: i32 = current_ver_str.parse().unwrap_or(0);
+            previous = Some(existing);
             ((current_ver + 1).to_string(), false, Some(current_ver_str))
         }
         Ok(None) => {
@@ -356,7 +365,14 @@ pub async fn update(
                     ))),
                 ));
             }
-            ("1".to_string(), true, None)
+            // A deleted id carries on past its tombstone.
+            let latest = state.store.list_versions(&resource_type, &id).map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!(OperationOutcome::storage_error(e.to_string()))),
+                )
+            })?;
+            (next_version(latest.first().map(String::as_str)), true, None)
         }
         Err(e) => {
             return Err((
@@ -379,35 +395,26 @@ pub async fn update(
         )
     })?;
 
-    // Reindex before persisting (see the create handler: indexing first keeps a
-    // committed resource from ever being invisible to search after a crash).
-    let resource_value = serde_json::to_value(&resource).unwrap_or_default();
-    {
-        let index = state.index.lock().await;
-        update_search_index(&index, &state.search_param_registry, &resource_type, &id, &resource_value);
-    }
-
     // Compare-and-swap on the version we read: if a concurrent writer changed
-    // the resource since, the write is refused (no lost update). On refusal,
-    // restore the index to reflect the actually-stored resource before returning.
-    let written = state
-        .store
-        .put_with_version_cas(&resource_type, &id, expected_current.as_deref(), &new_version, &json_bytes)
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!(OperationOutcome::storage_error(e.to_string()))),
-            )
-        })?;
+    // the resource since, the write is refused (no lost update).
+    let resource_value = serde_json::to_value(&resource).unwrap_or_default();
+    let written = store_resource_cas(
+        &state,
+        &resource_type,
+        &id,
+        expected_current.as_deref(),
+        &new_version,
+        &json_bytes,
+        &resource_value,
+    )
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!(OperationOutcome::storage_error(e.to_string()))),
+        )
+    })?;
     if !written {
-        let index = state.index.lock().await;
-        if let Ok(Some(cur)) = state.store.get(&resource_type, &id) {
-            if let Ok(cur_val) = serde_json::from_slice::<Value>(&cur) {
-                update_search_index(&index, &state.search_param_registry, &resource_type, &id, &cur_val);
-            }
-        } else {
-            let _ = index.remove_index(&resource_type, &id);
-        }
         return Err((
             StatusCode::CONFLICT,
             Json(json!(OperationOutcome::error(
@@ -419,6 +426,10 @@ pub async fn update(
 
     audit::log_operation_success(&audit_ctx, "UPDATE", &resource_type, &id, &state.audit);
 
+    if resource_type == "SearchParameter" {
+        search_parameter::apply(&state, previous.as_ref(), Some(&resource_value));
+    }
+
     // Subscription notification (background)
     {
         let state = state.clone();
@@ -471,15 +482,13 @@ pub async fn patch_resource(
             Json(json!(OperationOutcome::storage_error(e.to_string()))),
         )
     })?;
+    let previous = (resource_type == "SearchParameter").then(|| resource.clone());
 
     // Compartment check on existing resource
     check_compartment_access(auth_user.as_ref(), &state.compartment_def, &resource_type, &resource)?;
 
     // If-Match check
-    let if_match = headers
-        .get(header::IF_MATCH)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim_matches('"').trim_start_matches("W/\"").trim_end_matches('"').to_string());
+    let if_match = if_match(&headers);
 
     let current_ver_str = extract_version(&resource).unwrap_or_else(|| "0".to_string());
 
@@ -524,6 +533,14 @@ pub async fn patch_resource(
     ) {
         return Err((StatusCode::BAD_REQUEST, Json(json!(outcome))));
     }
+    if previous.is_some()
+        && let Err(e) = search_parameter::check(&state, &resource)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+        ));
+    }
 
     // Update version
     let current_ver: i32 = current_ver_str.parse().unwrap_or(0);
@@ -545,17 +562,8 @@ pub async fn patch_resource(
         )
     })?;
 
-    // Reindex before persisting, consistent with create/update: a crash between
-    // the two writes then leaves only a harmless stale index entry rather than a
-    // committed resource that's invisible to search.
-    {
-        let index = state.index.lock().await;
-        update_search_index(&index, &state.search_param_registry, &resource_type, &id, &resource);
-    }
-
-    state
-        .store
-        .put_with_version(&resource_type, &id, &new_version, &json_bytes)
+    store_resource(&state, &resource_type, &id, &new_version, &json_bytes, &resource)
+        .await
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -565,6 +573,10 @@ pub async fn patch_resource(
 
     audit::log_operation_success(&audit_ctx, "PATCH", &resource_type, &id, &state.audit);
 
+    if previous.is_some() {
+        search_parameter::apply(&state, previous.as_ref(), Some(&resource));
+    }
+
     // Subscription notification (background)
     {
         let state = state.clone();
@@ -592,22 +604,49 @@ pub async fn delete_resource(
     let auth_user = request.extensions().get::<AuthUser>().cloned();
 
     // Compartment check: load existing resource first
+    let mut existing = None;
     if let Ok(Some(data)) = state.store.get(&resource_type, &id)
         && let Ok(resource) = serde_json::from_slice::<Value>(&data)
     {
         check_compartment_access(auth_user.as_ref(), &state.compartment_def, &resource_type, &resource)?;
+        existing = Some(resource);
     }
 
-    match state.store.delete(&resource_type, &id) {
-        Ok(true) => {
-            // Remove search index
-            let index = state.index.lock().await;
-            let _ = index.remove_index(&resource_type, &id);
-            drop(index);
+    // If-Match: delete only the version the client last saw, else 412.
+    let if_match = if_match(request.headers());
+    if let Some(ref expected) = if_match {
+        let current = existing.as_ref().map(|e| extract_version(e).unwrap_or_else(|| "0".to_string()));
+        if current.as_ref() != Some(expected) {
+            return Err((
+                StatusCode::PRECONDITION_FAILED,
+                Json(json!(OperationOutcome::error(
+                    IssueType::Conflict,
+                    match current {
+                        Some(current) => format!("Version conflict: expected {}, current is {}", expected, current),
+                        None => "If-Match supplied but resource does not exist".to_string(),
+                    }
+                ))),
+            ));
+        }
+    }
 
+    match remove_resource(&state, &resource_type, &id, if_match.as_deref()).await {
+        Ok(true) => {
             audit::log_operation_success(&audit_ctx, "DELETE", &resource_type, &id, &state.audit);
+
+            if resource_type == "SearchParameter" {
+                search_parameter::apply(&state, existing.as_ref(), None);
+            }
             Ok(StatusCode::NO_CONTENT)
         }
+        // Changed or deleted since it was checked above.
+        Ok(false) if if_match.is_some() => Err((
+            StatusCode::PRECONDITION_FAILED,
+            Json(json!(OperationOutcome::error(
+                IssueType::Conflict,
+                "Resource was modified concurrently; If-Match no longer holds"
+            ))),
+        )),
         Ok(false) => Err((
             StatusCode::NOT_FOUND,
             Json(json!(OperationOutcome::not_found(&resource_type, &id))),
@@ -0,0 +1,278 @@
+//! `$expunge`: physically remove history, deleted resources, or everything
+//! from one resource, a resource type, or the whole server.
+//!
+//! A delete only adds a tombstone version, so without this every version a
+//! resource ever had stays in the store — no good when a patient exercises a
+//! right to erasure. The options, taken from a `Parameters` body or the query
+//! string, are `expungePreviousVersions` (all but each resource's current
+//! version), `expungeDeletedResources` (the whole history of each deleted
+//! resource) and `expungeEverything` (current resources too). Each run leaves
+//! an audit entry with its scope, options and counts, never resource content.
+//!
+//! `storage.history_retention_days` prunes old versions the same way on a
+//! schedule (see [`spawn_retention`]).
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use sazare_core::{operation_outcome::IssueType, OperationOutcome};
+use sazare_store::{ExpungeMode, Expunged};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audit::{self, AuditContext};
+use crate::auth::{AuthType, AuthUser};
+use crate::AppState;
+use super::search_parameter;
+
+/// How often the history retention policy runs.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExpungeParams {
+    #[serde(rename = "expungePreviousVersions")]
+    previous_versions: Option<bool>,
+    #[serde(rename = "expungeDeletedResources")]
+    deleted_resources: Option<bool>,
+    #[serde(rename = "expungeEverything")]
+    everything: Option<bool>,
+}
+
+type ErrorResponse = (StatusCode, Json<Value>);
+type ExpungeResult = Result<Response, ErrorResponse>;
+
+/// Expunge across the server (POST /$expunge)
+pub async fn system_expunge(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    auth_user: Option<Extension<AuthUser>>,
+    Query(params): Query<ExpungeParams>,
+    body: String,
+) -> ExpungeResult {
+    expunge(&state, addr, auth_user, None, None, params, &body).await
+}
+
+/// Expunge a resource type (POST /{resource_type}/$expunge)
+pub async fn type_expunge(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Path(resource_type): Path<String>,
+    auth_user: Option<Extension<AuthUser>>,
+    Query(params): Query<ExpungeParams>,
+    body: String,
+) -> ExpungeResult {
+    expunge(&state, addr, auth_user, Some(&resource_type), None, params, &body).await
+}
+
+/// Expunge one resource (POST /{resource_type}/{id}/$expunge)
+pub async fn instance_expunge(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Path((resource_type, id)): Path<(String, String)>,
+    auth_user: Option<Extension<AuthUser>>,
+    Query(params): Query<ExpungeParams>,
+    body: String,
+) -> ExpungeResult {
+    expunge(&state, addr, auth_user, Some(&resource_type), Some(&id), params, &body).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn expunge(
+    state: &Arc<AppState>,
+    addr: SocketAddr,
+    auth_user: Option<Extension<AuthUser>>,
+    resource_type: Option<&str>,
+    id: Option<&str>,
+    params: ExpungeParams,
+    body: &str,
+) -> ExpungeResult {
+    authorize(&auth_user)?;
+    let modes = modes(params, body)?;
+    let audit_ctx = AuditContext::new(auth_user.map(|u| u.user_id.clone()), addr.ip().to_string());
+    let storage_error = |e: sazare_store::StoreError| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!(OperationOutcome::storage_error(e.to_string()))),
+        )
+    };
+
+    let mut total = Expunged::default();
+    for (option, mode) in &modes {
+        let index = state.index.lock().await;
+        // Everything takes current resources with it: note what they were, to
+        // drop their index entries and unregister SearchParameters after.
+        let (ids, search_params) = if *mode == ExpungeMode::Everything {
+            let ids = match (resource_type, id) {
+                (Some(_), Some(id)) => vec![id.to_string()],
+                (Some(rt), None) => state.store.list_ids(rt).map_err(storage_error)?,
+                _ => Vec::new(),
+            };
+            (ids, stored_search_parameters(state, resource_type, id).map_err(storage_error)?)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        let expunged = state.store.expunge(resource_type, id, *mode).map_err(storage_error)?;
+        if *mode == ExpungeMode::Everything {
+            match resource_type {
+                Some(rt) => {
+                    for id in &ids {
+                        index.remove_index(rt, id).map_err(storage_error)?;
+                    }
+                }
+                None => index.clear_all().map_err(storage_error)?,
+            }
+        }
+        drop(index);
+        for sp in &search_params {
+            search_parameter::apply(state, Some(sp), None);
+        }
+
+        audit::log_expunge(
+            &audit_ctx,
+            resource_type,
+            id,
+            &format!("{option}: {} resources, {} versions", expunged.resources, expunged.versions),
+            &state.audit,
+        );
+        total.resources += expunged.resources;
+        total.versions += expunged.versions;
+    }
+
+    let parameters = json!({
+        "resourceType": "Parameters",
+        "parameter": [
+            {"name": "count", "valueInteger": total.versions},
+            {"name": "resources", "valueInteger": total.resources}
+        ]
+    });
+    Ok((StatusCode::OK, Json(parameters)).into_response())
+}
+
+/// Only the server's administrators may expunge: API key and Basic users,
+/// or a JWT holding `system/$expunge.write` by name. The auth middleware
+/// lets any `$expunge` write scope through, wildcards included; those are
+/// not enough for removing data beyond recovery.
+fn authorize(auth: &Option<Extension<AuthUser>>) -> Result<(), ErrorResponse> {
+    let Some(Extension(user)) = auth.as_ref() else {
+        return Ok(());
+    };
+    if user.auth_type != AuthType::Jwt {
+        return Ok(());
+    }
+    if user.scopes.iter().any(|s| s == "system/$expunge.write" || s == "system/$expunge.*") {
+        return Ok(());
+    }
+    Err((
+        StatusCode::FORBIDDEN,
+        Json(json!(OperationOutcome::forbidden(
+            "$expunge requires the system/$expunge.write scope"
+        ))),
+    ))
+}
+
+/// The modes asked for, in the order they run, with the option naming each.
+/// A `Parameters` body overrides the query string.
+fn modes(
+    mut params: ExpungeParams,
+    body: &str,
+) -> Result<Vec<(&'static str, ExpungeMode)>, ErrorResponse> {
+    let invalid = |msg: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(IssueType::Invalid, msg))),
+        )
+    };
+    if !body.trim().is_empty() {
+        let parameters: Value =
+            serde_json::from_str(body).map_err(|e| invalid(format!("Invalid JSON: {e}")))?;
+        if parameters.get("resourceType").and_then(|v| v.as_str()) != Some("Parameters") {
+            return Err(invalid("$expunge takes a Parameters resource".to_string()));
+        }
+        for parameter in parameters.get("parameter").and_then(|v| v.as_array()).into_iter().flatten() {
+            let value = parameter.get("valueBoolean").and_then(|v| v.as_bool());
+            let slot = match parameter.get("name").and_then(|v| v.as_str()) {
+                Some("expungePreviousVersions") => &mut params.previous_versions,
+                Some("expungeDeletedResources") => &mut params.deleted_resources,
+                Some("expungeEverything") => &mut params.everything,
+                Some(name) => return Err(invalid(format!("Unknown $expunge parameter '{name}'"))),
+                None => return Err(invalid("$expunge parameter without a name".to_string())),
+            };
+            *slot = Some(value.ok_or_else(|| invalid("$expunge parameters take a valueBoolean".to_string()))?);
+        }
+    }
+
+    // Everything covers the other two.
+    if params.everything == Some(true) {
+        return Ok(vec![("expungeEverything", ExpungeMode::Everything)]);
+    }
+    let mut modes = Vec::new();
+    if params.previous_versions == Some(true) {
+        modes.push(("expungePreviousVersions", ExpungeMode::PreviousVersions));
+    }
+    if params.deleted_resources == Some(true) {
+        modes.push(("expungeDeletedResources", ExpungeMode::DeletedResources));
+    }
+    if modes.is_empty() {
+        return Err(invalid(
+            "$expunge needs expungePreviousVersions, expungeDeletedResources or expungeEverything".to_string(),
+        ));
+    }
+    Ok(modes)
+}
+
+/// The stored SearchParameters in an expunge's scope.
+fn stored_search_parameters(
+    state: &AppState,
+    resource_type: Option<&str>,
+    id: Option<&str>,
+) -> sazare_store::Result<Vec<Value>> {
+    if resource_type.is_some_and(|rt| rt != "SearchParameter") {
+        return Ok(Vec::new());
+    }
+    let stored = match id {
+        Some(id) => state.store.get("SearchParameter", id)?.into_iter().collect(),
+        None => state
+            .store
+            .list_all(Some("SearchParameter"))?
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect::<Vec<_>>(),
+    };
+    Ok(stored.iter().filter_map(|value| serde_json::from_slice(value).ok()).collect())
+}
+
+/// Prune history past `storage.history_retention_days`, now and then hourly:
+/// every version older than that goes, except a stored resource's current
+/// one. Does nothing when no retention is configured.
+pub fn spawn_retention(state: Arc<AppState>) {
+    let Some(days) = state.config.storage.history_retention_days else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            let before = (chrono::Utc::now() - chrono::Duration::days(i64::from(days))).to_rfc3339();
+            match state.store.prune_history(&before) {
+                Ok(0) => {}
+                Ok(versions) => {
+                    tracing::info!("History retention: pruned {} versions before {}", versions, before);
+                    audit::log_expunge(
+                        &AuditContext::new(None, "internal".to_string()),
+                        None,
+                        None,
+                        &format!("historyRetentionDays={days}: {versions} versions"),
+                        &state.audit,
+                    );
+                }
+                Err(e) => tracing::error!("History retention failed: {}", e),
+            }
+        }
+    });
+}
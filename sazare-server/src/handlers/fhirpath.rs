@@ -0,0 +1,203 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use http_body_util::BodyExt;
+use sazare_core::fhirpath::{self, Context, Item};
+use sazare_core::{operation_outcome::IssueType, OperationOutcome};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::audit::{self, AuditContext};
+use crate::auth::AuthUser;
+use crate::compartment_check::check_compartment_access;
+use crate::AppState;
+
+#[derive(Deserialize, Default)]
+pub struct FhirPathParams {
+    #[serde(default)]
+    pub expression: Option<String>,
+}
+
+/// $fhirpath on a posted resource (POST /$fhirpath)
+///
+/// The body is a Parameters resource with `expression` (valueString) and
+/// `resource` parameters, or the resource itself with `?expression=` in the
+/// query. Returns the result collection as Parameters (see [`result_parameters`]).
+/// `resolve()` only sees the posted resource: `#id` references into its
+/// `contained`, and the type named by other references.
+pub async fn evaluate(
+    Query(params): Query<FhirPathParams>,
+    request: Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let body = read_body(request).await?;
+    let Some(body) = body else {
+        return Err(bad_request(IssueType::Required, "a resource to evaluate against is required"));
+    };
+    let (expression, resource) = if is_parameters(&body) {
+        let resource = parameter(&body, "resource")
+            .and_then(|p| p.get("resource"))
+            .cloned()
+            .ok_or_else(|| bad_request(IssueType::Required, "the Parameters have no 'resource'"))?;
+        (params.expression.or_else(|| string_parameter(&body, "expression")), resource)
+    } else {
+        (params.expression, body)
+    };
+    let expression = expression.ok_or_else(|| bad_request(IssueType::Required, "an expression is required"))?;
+    run(&expression, &resource)
+}
+
+/// $fhirpath on a stored resource (GET/POST /{resource_type}/{id}/$fhirpath?expression=...)
+///
+/// The expression comes from the query or, for POST, a Parameters body.
+pub async fn evaluate_instance(
+    State(state): State<Arc<AppState>>,
+    Path((resource_type, id)): Path<(String, String)>,
+    Query(params): Query<FhirPathParams>,
+    request: Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let audit_ctx = AuditContext::from_request(&request);
+    let auth_user = request.extensions().get::<AuthUser>().cloned();
+    let body = read_body(request).await?;
+    let expression = params
+        .expression
+        .or_else(|| body.as_ref().and_then(|b| string_parameter(b, "expression")))
+        .ok_or_else(|| bad_request(IssueType::Required, "an expression is required"))?;
+
+    let data = state
+        .store
+        .get(&resource_type, &id)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!(OperationOutcome::storage_error(e.to_string()))),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!(OperationOutcome::not_found(&resource_type, &id))),
+            )
+        })?;
+    let resource: Value = serde_json::from_slice(&data).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!(OperationOutcome::storage_error(e.to_string()))),
+        )
+    })?;
+
+    // Compartment check
+    check_compartment_access(auth_user.as_ref(), &state.compartment_def, &resource_type, &resource)?;
+
+    let response = run(&expression, &resource)?;
+    audit::log_operation_success(&audit_ctx, "FHIRPATH", &resource_type, &id, &state.audit);
+    Ok(response)
+}
+
+fn run(expression: &str, resource: &Value) -> Result<Response, (StatusCode, Json<Value>)> {
+    let expr = fhirpath::parse(expression).map_err(|e| bad_request(IssueType::Invalid, e.to_string()))?;
+    let items = fhirpath::evaluate_with(&expr, &Context::new(resource))
+        .map_err(|e| bad_request(IssueType::Processing, e.to_string()))?;
+    Ok((StatusCode::OK, Json(result_parameters(expression, &items))).into_response())
+}
+
+/// The result as Parameters: a `parameters` parameter echoing the evaluator
+/// and expression, then one `result` parameter with a part per item, named by
+/// the item's type and carrying it as `value[x]` — or as `resource` for a
+/// resource. A JSON object of unknown type (e.g. `Patient.name`, with no
+/// structure model to name it) is carried as its JSON text in `valueString`.
+fn result_parameters(expression: &str, items: &[Item]) -> Value {
+    let parts: Vec<Value> = items.iter().map(result_part).collect();
+    let mut result = json!({"name": "result"});
+    if !parts.is_empty() {
+        result["part"] = Value::Array(parts);
+    }
+    json!({
+        "resourceType": "Parameters",
+        "parameter": [
+            {
+                "name": "parameters",
+                "part": [
+                    {"name": "evaluator", "valueString": format!("sazare-{}", env!("CARGO_PKG_VERSION"))},
+                    {"name": "expression", "valueString": expression}
+                ]
+            },
+            result
+        ]
+    })
+}
+
+fn result_part(item: &Item) -> Value {
+    let (_, type_name) = item.type_name();
+    let type_name = match item {
+        // System types are reported by their FHIR counterparts.
+        Item::Boolean(_) => "boolean".into(),
+        Item::Integer(_) => "integer".into(),
+        Item::Decimal(_) => "decimal".into(),
+        Item::String(_) => "string".into(),
+        Item::Date(_) => "date".into(),
+        Item::DateTime(_) => "dateTime".into(),
+        Item::Time(_) => "time".into(),
+        _ => type_name,
+    };
+    let value = item.to_json();
+    if value.get("resourceType").is_some() {
+        return json!({"name": type_name, "resource": value});
+    }
+    if value.is_object() && type_name == "Element" {
+        return json!({"name": type_name, "valueString": value.to_string()});
+    }
+    let mut key = String::from("value");
+    let mut chars = type_name.chars();
+    if let Some(first) = chars.next() {
+        key.extend(first.to_uppercase());
+        key.push_str(chars.as_str());
+    }
+    let mut part = json!({"name": type_name});
+    part[key] = value;
+    part
+}
+
+async fn read_body(request: Request) -> Result<Option<Value>, (StatusCode, Json<Value>)> {
+    let bytes = request
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| bad_request(IssueType::Invalid, e.to_string()))?
+        .to_bytes();
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    serde_json::from_slice(&bytes)
+        .map(Some)
+        .map_err(|e| bad_request(IssueType::Invalid, e.to_string()))
+}
+
+fn is_parameters(value: &Value) -> bool {
+    value.get("resourceType").and_then(|v| v.as_str()) == Some("Parameters")
+}
+
+fn parameter<'a>(params: &'a Value, name: &str) -> Option<&'a Value> {
+    params
+        .get("parameter")
+        .and_then(|p| p.as_array())?
+        .iter()
+        .find(|p| p.get("name").and_then(|n| n.as_str()) == Some(name))
+}
+
+fn string_parameter(params: &Value, name: &str) -> Option<String> {
+    if !is_parameters(params) {
+        return None;
+    }
+    parameter(params, name)
+        .and_then(|p| p.get("valueString"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
+
+fn bad_request(code: IssueType, diagnostics: impl Into<String>) -> (StatusCode, Json<Value>) {
+    (StatusCode::BAD_REQUEST, Json(json!(OperationOutcome::error(code, diagnostics))))
+}
+
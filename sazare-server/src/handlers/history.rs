@@ -1,77 +1,259 @@
+//! The `_history` interactions: a resource's versions, a type's, or the
+//! whole server's, newest first.
+//!
+//! `_since` keeps the versions written at or after an instant and `_at` those
+//! current at one, so a downstream system can poll for what changed since it
+//! last looked. Pages are keyset-paged like searchsets: the `next` link
+//! carries a signed `_page_token` (see `crate::page_token`) naming the last
+//! version on the page, so versions written between fetches neither shift
+//! nor repeat entries.
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, Request, State},
     http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Json, Response},
+    response::{Json, Response},
 };
+use chrono::{DateTime, NaiveDate, Utc};
 use sazare_core::{operation_outcome::IssueType, OperationOutcome};
+use sazare_store::sqlite_index::{PageCursor, PageStart};
+use sazare_store::{HistoryKey, HistoryQuery};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
 
+use crate::auth::AuthUser;
+use crate::compartment_check::filter_by_compartment;
+use crate::page_token;
 use crate::AppState;
-use super::{base_url_from_headers, response_with_etag};
+use super::{base_url_from_headers, read_response};
+
+/// The largest history page `_count` can ask for.
+const MAX_COUNT: usize = 1000;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryParams {
+    #[serde(rename = "_since")]
+    since: Option<String>,
+    #[serde(rename = "_at")]
+    at: Option<String>,
+    #[serde(rename = "_count")]
+    count: Option<usize>,
+    #[serde(rename = "_page_token")]
+    page_token: Option<String>,
+}
 
 /// Get history (GET /{resource_type}/{id}/_history)
 pub async fn history(
     State(state): State<Arc<AppState>>,
     Path((resource_type, id)): Path<(String, String)>,
-    headers: HeaderMap,
+    Query(params): Query<HistoryParams>,
+    request: Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    history_bundle(&state, Some(&resource_type), Some(&id), params, request)
+}
+
+/// Get a type's history (GET /{resource_type}/_history)
+pub async fn type_history(
+    State(state): State<Arc<AppState>>,
+    Path(resource_type): Path<String>,
+    Query(params): Query<HistoryParams>,
+    request: Request,
 ) -> Result<Response, (StatusCode, Json<Value>)> {
-    let versions = state
+    history_bundle(&state, Some(&resource_type), None, params, request)
+}
+
+/// Get the whole server's history (GET /_history)
+pub async fn system_history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+    request: Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    history_bundle(&state, None, None, params, request)
+}
+
+fn history_bundle(
+    state: &AppState,
+    resource_type: Option<&str>,
+    id: Option<&str>,
+    params: HistoryParams,
+    request: Request,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let auth_user = request.extensions().get::<AuthUser>().cloned();
+    let invalid = |e: String| (StatusCode::BAD_REQUEST, Json(json!(OperationOutcome::error(IssueType::Invalid, e))));
+    let since = params.since.as_deref().map(|v| parse_instant("_since", v)).transpose().map_err(invalid)?;
+    let at = params.at.as_deref().map(|v| parse_instant("_at", v)).transpose().map_err(invalid)?;
+    // `_count=0` asks for the Bundle without entries.
+    let count = params.count.unwrap_or(super::search::DEFAULT_COUNT).min(MAX_COUNT);
+
+    let path = match (resource_type, id) {
+        (Some(resource_type), Some(id)) => format!("{resource_type}/{id}/_history"),
+        (Some(resource_type), None) => format!("{resource_type}/_history"),
+        _ => "_history".to_string(),
+    };
+    let mut filter = Vec::new();
+    if let Some(since) = &since {
+        filter.push(("_since", since.as_str()));
+    }
+    if let Some(at) = &at {
+        filter.push(("_at", at.as_str()));
+    }
+    let filter = serde_urlencoded::to_string(&filter).unwrap_or_default();
+
+    // The token is signed over the history it pages through, filters included.
+    let token_scope = format!("{path}?{filter}");
+    let token_secret = state.config.server.page_token_secret.as_deref();
+    let after = match &params.page_token {
+        Some(token) => match page_token::decode(token_secret, &token_scope, token).map_err(invalid)? {
+            (PageStart::After(cursor), _) => Some(history_key(cursor).ok_or_else(|| invalid("Invalid _page_token".to_string()))?),
+            _ => return Err(invalid("Invalid _page_token".to_string())),
+        },
+        None => None,
+    };
+
+    // One row past the page tells whether there is a next one.
+    let mut versions = state
         .store
-        .list_versions(&resource_type, &id)
+        .history(&HistoryQuery {
+            resource_type,
+            id,
+            since: since.as_deref(),
+            at: at.as_deref(),
+            after,
+            limit: count.saturating_add(1),
+        })
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!(OperationOutcome::storage_error(e.to_string()))),
             )
         })?;
+    let more = versions.len() > count;
+    versions.truncate(count);
+    let last = versions.last().map(|v| v.key.clone());
 
-    let base = base_url_from_headers(&headers);
+    let base = base_url_from_headers(request.headers());
     let mut entries = Vec::new();
-    for ver in versions {
-        if let Ok(Some(data)) = state.store.get_version(&resource_type, &id, &ver)
-            && let Ok(resource) = serde_json::from_slice::<Value>(&data)
-        {
-            let last_updated = resource
-                .get("meta")
-                .and_then(|m| m.get("lastUpdated"))
-                .and_then(|v| v.as_str())
-                .unwrap_or("");
-            // Version "1" was the create (POST); later versions are updates (PUT).
-            // entry.response is mandatory in a history bundle (invariant bdl-4).
-            let method = if ver == "1" { "POST" } else { "PUT" };
+    for version in versions {
+        let HistoryKey { resource_type, id, version_id, last_updated } = version.key;
+        // A tombstone is the deletion itself, with no resource to show (or
+        // to check a patient compartment against).
+        if version.deleted {
+            if auth_user.as_ref().is_some_and(|user| user.is_patient_scoped())
+                && state.compartment_def.is_in_compartment(&resource_type)
+            {
+                continue;
+            }
             entries.push(json!({
                 "fullUrl": format!("{base}/{resource_type}/{id}"),
-                "resource": resource,
                 "request": {
-                    "method": method,
+                    "method": "DELETE",
                     "url": format!("{resource_type}/{id}")
                 },
                 "response": {
-                    "status": "200",
-                    "etag": format!("W/\"{ver}\""),
+                    "status": "204",
+                    "etag": format!("W/\"{version_id}\""),
                     "lastModified": last_updated
                 }
             }));
+            continue;
         }
+        let Ok(resource) = serde_json::from_slice::<Value>(&version.value) else {
+            continue;
+        };
+        // Patient-scoped tokens only see the versions in their compartment.
+        let Some(resource) = filter_by_compartment(auth_user.as_ref(), &state.compartment_def, &resource_type, vec![resource]).pop() else {
+            continue;
+        };
+        // Version "1" was the create (POST); later versions are updates (PUT).
+        // entry.response is mandatory in a history bundle (invariant bdl-4).
+        let method = if version_id == "1" { "POST" } else { "PUT" };
+        entries.push(json!({
+            "fullUrl": format!("{base}/{resource_type}/{id}"),
+            "resource": resource,
+            "request": {
+                "method": method,
+                "url": format!("{resource_type}/{id}")
+            },
+            "response": {
+                "status": "200",
+                "etag": format!("W/\"{version_id}\""),
+                "lastModified": last_updated
+            }
+        }));
+    }
+
+    let page_url = if filter.is_empty() {
+        format!("{base}/{path}?_count={count}")
+    } else {
+        format!("{base}/{path}?{filter}&_count={count}")
+    };
+    let self_url = match &params.page_token {
+        Some(token) => format!("{page_url}&_page_token={token}"),
+        None => page_url.clone(),
+    };
+    let mut links = vec![json!({"relation": "self", "url": self_url})];
+    if more && let Some(last) = last {
+        let token = page_token::encode(token_secret, &token_scope, &page_cursor(last), false, Utc::now());
+        links.push(json!({"relation": "next", "url": format!("{page_url}&_page_token={token}")}));
     }
 
-    Ok(super::fhir_json(StatusCode::OK, json!({
+    let mut bundle = json!({
         "resourceType": "Bundle",
         "type": "history",
-        "total": entries.len(),
-        "link": [{
-            "relation": "self",
-            "url": format!("{base}/{resource_type}/{id}/_history")
-        }],
-        "entry": entries
-    })))
+        "link": links,
+    });
+    // The total is only known when this one page is the whole history.
+    if !more && params.page_token.is_none() {
+        bundle["total"] = json!(entries.len());
+    }
+    // Omit `entry` entirely when empty — FHIR JSON forbids empty arrays.
+    if !entries.is_empty() {
+        bundle["entry"] = json!(entries);
+    }
+    Ok(super::fhir_json(StatusCode::OK, bundle))
+}
+
+/// Parse a `_since`/`_at` value, an instant or a date (midnight UTC), into
+/// the form the server writes `meta.lastUpdated` in, which the store compares
+/// as text. A `+` offset that arrived unencoded reads as a space.
+fn parse_instant(name: &str, value: &str) -> Result<String, String> {
+    let value = value.trim().replace(' ', "+");
+    let instant = DateTime::parse_from_rfc3339(&value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        })
+        .map_err(|_| format!("Invalid {name}: '{value}' is not an instant such as 2024-01-01T00:00:00Z"))?;
+    Ok(instant.to_rfc3339())
+}
+
+/// A history page boundary as a page token cursor: the version's
+/// lastUpdated, type and version id as keys, and its id.
+fn page_cursor(key: HistoryKey) -> PageCursor {
+    PageCursor {
+        keys: vec![json!(key.last_updated), json!(key.resource_type), json!(key.version_id)],
+        id: key.id,
+    }
+}
+
+fn history_key(cursor: PageCursor) -> Option<HistoryKey> {
+    let [last_updated, resource_type, version_id] = cursor.keys.as_slice() else {
+        return None;
+    };
+    Some(HistoryKey {
+        last_updated: last_updated.as_str()?.to_string(),
+        resource_type: resource_type.as_str()?.to_string(),
+        id: cursor.id,
+        version_id: version_id.as_str()?.to_string(),
+    })
 }
 
 /// Read specific version (GET /{resource_type}/{id}/_history/{vid})
 pub async fn vread(
     State(state): State<Arc<AppState>>,
     Path((resource_type, id, vid)): Path<(String, String, String)>,
+    headers: HeaderMap,
 ) -> Result<Response, (StatusCode, Json<Value>)> {
     match state.store.get_version(&resource_type, &id, &vid) {
         Ok(Some(data)) => {
@@ -81,8 +263,16 @@ pub async fn vread(
                     Json(json!(OperationOutcome::storage_error(e.to_string()))),
                 )
             })?;
-            Ok(response_with_etag(StatusCode::OK, resource).into_response())
+            Ok(read_response(&headers, resource))
         }
+        // A deletion's tombstone is a version with no resource: Gone.
+        Ok(None) if state.store.list_versions(&resource_type, &id).is_ok_and(|v| v.contains(&vid)) => Err((
+            StatusCode::GONE,
+            Json(json!(OperationOutcome::error(
+                IssueType::Deleted,
+                format!("{}/{}/_history/{} is a deletion", resource_type, id, vid),
+            ))),
+        )),
         Ok(None) => Err((
             StatusCode::NOT_FOUND,
             Json(json!(OperationOutcome::error(
@@ -108,6 +108,7 @@ pub async fn capability_statement(
         json!({"code": "delete"}),
         json!({"code": "search-type"}),
         json!({"code": "history-instance"}),
+        json!({"code": "history-type"}),
     ];
 
     let resources: Vec<Value> = SUPPORTED_RESOURCE_TYPES
@@ -118,8 +119,9 @@ pub async fn capability_statement(
                 "versioning": "versioned",
                 "readHistory": true,
                 "conditionalCreate": true,
+                "conditionalRead": "full-support",
                 "interaction": interactions,
-                "searchParam": get_search_params_from_registry(&state.search_param_registry, rt),
+                "searchParam": get_search_params_from_registry(&state.search_params(), rt),
             });
             let profiles = state.profile_registry.supported_profile_urls(rt);
             if !profiles.is_empty() {
@@ -147,10 +149,13 @@ pub async fn capability_statement(
         "interaction": [
             {"code": "transaction"},
             {"code": "batch"},
+            {"code": "history-system"},
         ],
         "operation": [
             {"name": "export", "definition": "http://hl7.org/fhir/uv/bulkdata/OperationDefinition/export"},
             {"name": "import", "definition": "http://sazare.dev/OperationDefinition/import"},
+            {"name": "fhirpath", "definition": "http://sazare.dev/OperationDefinition/fhirpath"},
+            {"name": "expunge", "definition": "http://sazare.dev/OperationDefinition/expunge"},
         ]
     });
     if let Some(sec) = security {
@@ -328,6 +333,9 @@ fn get_search_params_from_registry(registry: &SearchParamRegistry, resource_type
             sazare_core::SearchParamType::Date => "date",
             sazare_core::SearchParamType::Reference => "reference",
             sazare_core::SearchParamType::Number => "number",
+            sazare_core::SearchParamType::Quantity => "quantity",
+            sazare_core::SearchParamType::Uri => "uri",
+            sazare_core::SearchParamType::Composite(_) => "composite",
         };
         if seen.insert(def.name.clone()) {
             params.push(json!({"name": def.name, "type": type_str}));
@@ -1,19 +1,25 @@
 pub mod conditional;
 pub mod crud;
 pub mod everything;
+pub mod expunge;
+pub mod fhirpath;
 pub mod history;
 pub mod metadata;
 pub mod reindex;
 pub mod search;
+pub mod search_parameter;
 pub mod validate;
 
 use axum::{
     http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json, Response},
 };
+use chrono::{DateTime, Utc};
 use serde_json::Value;
-use sazare_core::SearchParamRegistry;
-use sazare_store::{IndexBuilder, SearchIndex};
+use sazare_core::{fhirpath::Resolver, SearchParamRegistry};
+use sazare_store::{IndexBuilder, IndexWrite, ResourceStore, ResourceTransaction, SearchBackend};
+
+use crate::AppState;
 
 /// Extract version from meta for ETag
 pub fn extract_version(resource: &Value) -> Option<String> {
@@ -29,14 +35,81 @@ pub fn response_with_etag(status: StatusCode, resource: Value) -> impl IntoRespo
     response_with_headers(status, resource, None)
 }
 
-/// Convert an RFC3339 `meta.lastUpdated` into an HTTP-date for `Last-Modified`.
-fn http_date(resource: &Value) -> Option<String> {
+/// A resource's `meta.lastUpdated`, for `Last-Modified`.
+fn last_modified(resource: &Value) -> Option<DateTime<Utc>> {
     let raw = resource
         .get("meta")
         .and_then(|m| m.get("lastUpdated"))
         .and_then(|v| v.as_str())?;
     let dt = chrono::DateTime::parse_from_rfc3339(raw).ok()?;
-    Some(dt.with_timezone(&chrono::Utc).format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    Some(dt.with_timezone(&Utc))
+}
+
+/// Format a time as an HTTP-date.
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The version an entity tag names: `W/"3"` (or `"3"`) is version `3`.
+fn etag_version(tag: &str) -> &str {
+    let tag = tag.trim();
+    tag.strip_prefix("W/").unwrap_or(tag).trim_matches('"')
+}
+
+/// The version an `If-Match` header asks for, if any.
+pub fn if_match(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| etag_version(s).to_string())
+}
+
+/// Whether a conditional read can answer `304 Not Modified`: an
+/// `If-None-Match` tag matches `etag`, or, when there is no `If-None-Match`
+/// (which takes precedence), `last_modified` is no later than
+/// `If-Modified-Since`. Tags compare weakly, as `W/"3"` and `"3"` name the
+/// same version.
+pub fn not_modified(headers: &HeaderMap, etag: Option<&str>, last_modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(tags) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        let Some(etag) = etag else {
+            return false;
+        };
+        return tags
+            .split(',')
+            .any(|tag| tag.trim() == "*" || etag_version(tag) == etag_version(etag));
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    match (since, last_modified) {
+        // An HTTP-date has whole seconds.
+        (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+/// `304 Not Modified`, carrying the validators a `200` would have.
+pub fn not_modified_response(etag: Option<&str>, last_modified: Option<DateTime<Utc>>) -> Response {
+    let mut headers = HeaderMap::new();
+    if let Some(val) = etag.and_then(|etag| etag.parse().ok()) {
+        headers.insert(header::ETAG, val);
+    }
+    if let Some(val) = last_modified.and_then(|lm| http_date(lm).parse().ok()) {
+        headers.insert(header::LAST_MODIFIED, val);
+    }
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+}
+
+/// Answer a read of `resource`: `304 Not Modified` when the request's
+/// validators say the client's copy is current, otherwise the resource.
+pub fn read_response(headers: &HeaderMap, resource: Value) -> Response {
+    let etag = extract_version(&resource).map(|v| format!("W/\"{}\"", v));
+    let modified = last_modified(&resource);
+    if not_modified(headers, etag.as_deref(), modified) {
+        return not_modified_response(etag.as_deref(), modified);
+    }
+    response_with_etag(StatusCode::OK, resource).into_response()
 }
 
 /// Build a response carrying `ETag`, `Last-Modified`, FHIR content type, and an
@@ -54,7 +127,7 @@ pub fn response_with_headers(
     {
         headers.insert(header::ETAG, val);
     }
-    if let Some(lm) = http_date(&resource)
+    if let Some(lm) = last_modified(&resource).map(http_date)
         && let Ok(val) = lm.parse()
     {
         headers.insert(header::LAST_MODIFIED, val);
@@ -119,24 +192,217 @@ pub fn fhir_json(status: StatusCode, body: Value) -> Response {
     (status, headers, Json(body)).into_response()
 }
 
-/// Update search index for a resource (synchronous — must not be async)
+/// Target lookup for `resolve()` in FHIRPath search parameters: a relative
+/// literal reference (`Patient/123`) is read from the store. Anything else —
+/// absolute URLs naming another server, `urn:uuid:` — is left to what the
+/// reference itself says.
+pub fn store_resolver(store: &dyn ResourceStore) -> impl Fn(&str) -> Option<Value> + '_ {
+    move |reference| {
+        if reference.contains("://") {
+            return None;
+        }
+        let (resource_type, id) = sazare_core::fhirpath::reference_target(reference)?;
+        let bytes = store.get(resource_type, id).ok()??;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// [`store_resolver`] for a write in progress: references are read through
+/// the transaction, so they see what it has written so far.
+pub fn transaction_resolver<'a>(ops: &'a dyn ResourceTransaction) -> impl Fn(&str) -> Option<Value> + 'a {
+    move |reference| {
+        if reference.contains("://") {
+            return None;
+        }
+        let (resource_type, id) = sazare_core::fhirpath::reference_target(reference)?;
+        let bytes = ops.get(resource_type, id).ok()??;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// Replace a resource's search index entries (parameters and full text).
+pub fn write_index(
+    index: &dyn IndexWrite,
+    resolver: &Resolver<'_>,
+    registry: &SearchParamRegistry,
+    resource_type: &str,
+    id: &str,
+    resource: &Value,
+) -> sazare_store::Result<()> {
+    index.remove_index(resource_type, id)?;
+    let indices = IndexBuilder::extract_indices_with_resolver(registry, resource_type, resource, Some(resolver));
+    for (param_name, param_type, value, system) in indices {
+        index.add_index(resource_type, id, &param_name, &param_type, Some(&value), system.as_deref())?;
+    }
+    let (narrative, content) = IndexBuilder::extract_text(resource);
+    index.index_text(resource_type, id, &narrative, &content)
+}
+
+/// Update search index (parameters and full text) for a resource
+/// (synchronous — must not be async)
 pub fn update_search_index(
-    index: &SearchIndex,
+    index: &dyn SearchBackend,
+    store: &dyn ResourceStore,
     registry: &SearchParamRegistry,
     resource_type: &str,
     id: &str,
     resource: &Value,
 ) {
-    let _ = index.remove_index(resource_type, id);
-    let indices = IndexBuilder::extract_indices_with_registry(registry, resource_type, resource);
-    for (param_name, param_type, value, system) in indices {
-        let _ = index.add_index(
-            resource_type,
-            id,
-            &param_name,
-            &param_type,
-            Some(&value),
-            system.as_deref(),
-        );
+    let resolver = store_resolver(store);
+    if let Err(e) = write_index(index, &resolver, registry, resource_type, id, resource) {
+        tracing::warn!("Failed to index {}/{}: {}", resource_type, id, e);
+    }
+}
+
+/// Store a new version of a resource (`data` is its serialized form) and
+/// index it.
+///
+/// With `storage.single_database`, or on PostgreSQL, the resource row, its
+/// history row and its index entries commit in one transaction. Otherwise the
+/// store and index are separate databases and the store is written first, as
+/// a transaction Bundle does: a failed index write is logged, and a crash
+/// between the two leaves the resource missing from or stale in search until
+/// the startup consistency check (see [`reindex::check_consistency`])
+/// reindexes it. The index lock is held throughout, so concurrent writes of
+/// one resource reach the index in the order they were stored.
+pub async fn store_resource(
+    state: &AppState,
+    resource_type: &str,
+    id: &str,
+    version_id: &str,
+    data: &[u8],
+    resource: &Value,
+) -> sazare_store::Result<()> {
+    // Taken in both layouts, so index writes stay serialized with reindex
+    // runs whichever connection makes them.
+    let index = state.index.lock().await;
+    if state.config.storage.atomic_index_writes() {
+        let registry = state.search_params();
+        return state.store.in_transaction(|ops| {
+            ops.put_with_version(resource_type, id, version_id, data)?;
+            write_index(&*ops.index(), &transaction_resolver(ops), &registry, resource_type, id, resource)
+        });
+    }
+    state.store.put_with_version(resource_type, id, version_id, data)?;
+    update_search_index(&**index, &*state.store, &state.search_params(), resource_type, id, resource);
+    Ok(())
+}
+
+/// [`store_resource`] as a compare-and-swap on the current version (see
+/// `ResourceStore::put_with_version_cas`). Returns `false`, with nothing
+/// written, when the resource changed since it was read.
+pub async fn store_resource_cas(
+    state: &AppState,
+    resource_type: &str,
+    id: &str,
+    expected_current: Option<&str>,
+    version_id: &str,
+    data: &[u8],
+    resource: &Value,
+) -> sazare_store::Result<bool> {
+    let index = state.index.lock().await;
+    if state.config.storage.atomic_index_writes() {
+        let registry = state.search_params();
+        return state.store.in_transaction(|ops| {
+            if !ops.put_with_version_cas(resource_type, id, expected_current, version_id, data)? {
+                return Ok(false);
+            }
+            write_index(&*ops.index(), &transaction_resolver(ops), &registry, resource_type, id, resource)?;
+            Ok(true)
+        });
+    }
+    let written = state
+        .store
+        .put_with_version_cas(resource_type, id, expected_current, version_id, data)?;
+    if written {
+        update_search_index(&**index, &*state.store, &state.search_params(), resource_type, id, resource);
     }
+    Ok(written)
+}
+
+/// Delete a resource (see [`delete_version`]) and its search index entries,
+/// in one transaction with `storage.single_database` or on PostgreSQL.
+/// Returns `false` if there was nothing to delete, or with `if_match`, if the
+/// current version isn't that one.
+pub async fn remove_resource(
+    state: &AppState,
+    resource_type: &str,
+    id: &str,
+    if_match: Option<&str>,
+) -> sazare_store::Result<bool> {
+    let index = state.index.lock().await;
+    let atomic = state.config.storage.atomic_index_writes();
+    let deleted = state.store.in_transaction(|ops| {
+        let deleted = match if_match {
+            Some(version) => delete_if_version(ops, resource_type, id, version)?,
+            None => delete_version(ops, resource_type, id)?,
+        };
+        if !deleted {
+            return Ok(false);
+        }
+        if atomic {
+            ops.index().remove_index(resource_type, id)?;
+        }
+        Ok(true)
+    })?;
+    if deleted && !atomic {
+        let _ = index.remove_index(resource_type, id);
+    }
+    Ok(deleted)
+}
+
+/// Delete a resource's current version within `ops`, recording a tombstone
+/// one version past it in history. Returns `false` if it isn't stored.
+pub fn delete_version(ops: &dyn ResourceTransaction, resource_type: &str, id: &str) -> sazare_store::Result<bool> {
+    // On PostgreSQL a concurrent update can commit between the read and the
+    // delete; the delete then sees the version move on and reads again.
+    for _ in 0..3 {
+        let Some(current) = ops.get(resource_type, id)? else {
+            return Ok(false);
+        };
+        let current = serde_json::from_slice::<Value>(&current)
+            .ok()
+            .and_then(|resource| extract_version(&resource))
+            .unwrap_or_default();
+        let version_id = next_version(Some(&current));
+        if ops.delete_with_version_cas(resource_type, id, &current, &version_id, &tombstone(resource_type, id, &version_id))? {
+            return Ok(true);
+        }
+    }
+    Err(sazare_store::StoreError::Other(format!(
+        "{resource_type}/{id} was modified concurrently"
+    )))
+}
+
+/// Delete a resource within `ops` only if `version` is its current version,
+/// recording a tombstone like [`delete_version`]. Returns `false` otherwise.
+pub fn delete_if_version(
+    ops: &dyn ResourceTransaction,
+    resource_type: &str,
+    id: &str,
+    version: &str,
+) -> sazare_store::Result<bool> {
+    let version_id = next_version(Some(version));
+    ops.delete_with_version_cas(resource_type, id, version, &version_id, &tombstone(resource_type, id, &version_id))
+}
+
+/// The body of a deletion's tombstone version: the bare resource, with the
+/// deletion's version and time.
+fn tombstone(resource_type: &str, id: &str, version_id: &str) -> Vec<u8> {
+    let mut resource = serde_json::Map::new();
+    resource.insert("resourceType".to_string(), Value::String(resource_type.to_string()));
+    resource.insert("id".to_string(), Value::String(id.to_string()));
+    merge_version_meta(&mut resource, version_id);
+    serde_json::to_vec(&resource).unwrap_or_default()
+}
+
+/// The version after `latest` (numeric versions count up; anything else
+/// restarts at "1"). For a write to an id with no current version, `latest`
+/// is the newest in its history, so a resource recreated after a delete
+/// carries on past the tombstone instead of overwriting its old versions.
+pub fn next_version(latest: Option<&str>) -> String {
+    latest
+        .and_then(|v| v.parse::<i64>().ok())
+        .map_or(1, |v| v + 1)
+        .to_string()
 }
@@ -1,30 +1,374 @@
-use axum::{extract::State, http::StatusCode, response::Json};
-use sazare_core::SearchParamRegistry;
-use sazare_store::{IndexBuilder, SearchIndex, SqliteStore};
+//! `$reindex`: rebuild the search index from the resource store.
+//!
+//! Kick-off:  `POST /$reindex[?type=Observation][&parameter=code]`
+//!            -> `202 Accepted` + `Content-Location: <status-url>`
+//! Status:    `GET <status-url>` -> `202` while running (with `X-Progress`),
+//!            `200` with a summary once complete.
+//!
+//! The job streams the store a chunk at a time into a shadow table (see
+//! `SearchBackend::begin_reindex`) and swaps it in when done, so searches keep
+//! answering from the existing index throughout. Each chunk commits with its
+//! cursor, and a job interrupted by a restart resumes from it at startup. One
+//! worker runs a job: it claims the job first and renews the claim with every
+//! chunk, and any other process resuming it waits, taking over only once
+//! those renewals stop (see `SearchBackend::claim_reindex`).
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use sazare_core::{operation_outcome::IssueType, OperationOutcome, SearchParamRegistry};
+use sazare_store::{IndexBuilder, ReindexJob, ReindexStatus, ResourceStore, SearchBackend};
+use serde::Deserialize;
 use serde_json::{json, Value};
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
 
 use crate::AppState;
 
+/// Resources read and indexed per index lock, so writes aren't held up for
+/// long and memory stays flat however large the store.
+const REINDEX_CHUNK: usize = 500;
+
+/// A job whose owner hasn't claimed or checkpointed it for this long is taken
+/// to have stopped, and another worker may take it over.
+const REINDEX_LEASE: Duration = Duration::from_secs(60);
+
+/// How often a worker waiting on another's job checks back.
+const REINDEX_WAIT: Duration = Duration::from_secs(5);
+
+/// This process, as the owner of the jobs it runs.
+static WORKER_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+
 pub struct ReindexSummary {
     pub resources_indexed: usize,
     pub entries_written: usize,
 }
 
-/// Rebuild the search index from the resource store in-place.
-/// Clears existing entries, then re-extracts indices for every resource.
-pub fn perform_reindex(
-    store: &SqliteStore,
-    index: &SearchIndex,
+#[derive(Deserialize, Default)]
+pub struct ReindexParams {
+    /// Only resources of this type.
+    #[serde(rename = "type")]
+    pub resource_type: Option<String>,
+    /// Only this search parameter (of `type`, or of every type).
+    pub parameter: Option<String>,
+}
+
+/// Index the next chunk of the job's resources into the shadow table and move
+/// its cursor past them, as the job's `owner`. Returns `false` once there is
+/// nothing left.
+pub fn reindex_chunk(
+    store: &dyn ResourceStore,
+    index: &dyn SearchBackend,
     registry: &SearchParamRegistry,
-) -> Result<ReindexSummary, String> {
-    index.clear_all().map_err(|e| format!("clear index: {}", e))?;
+    id: &str,
+    owner: &str,
+) -> Result<bool, String> {
+    let job = index
+        .reindex_job(id)
+        .map_err(|e| format!("read reindex job: {}", e))?
+        .ok_or_else(|| format!("unknown reindex job {id}"))?;
+    let after = job.cursor.as_ref().map(|(t, i)| (t.as_str(), i.as_str()));
+    let page = store
+        .list_page(job.resource_type.as_deref(), after, REINDEX_CHUNK)
+        .map_err(|e| format!("list resources: {}", e))?;
+    let Some((last_type, last_id, _)) = page.last() else {
+        return Ok(false);
+    };
+    let cursor = (last_type.clone(), last_id.clone());
+    let param_name = job.param_name.as_deref();
+    let composite_prefix = param_name.map(|p| format!("{p}$"));
+    let resolver = super::store_resolver(store);
 
-    let all = store.list_all(None).map_err(|e| format!("list resources: {}", e))?;
-    let mut resources_indexed = 0usize;
-    let mut entries_written = 0usize;
+    index
+        .in_transaction(|index| {
+            let mut entries_written = 0usize;
+            for (resource_type, id, bytes) in &page {
+                let resource: Value = match serde_json::from_slice(bytes) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("Skipping {}/{}: parse error: {}", resource_type, id, e);
+                        continue;
+                    }
+                };
+                let indices =
+                    IndexBuilder::extract_indices_with_resolver(registry, resource_type, &resource, Some(&resolver));
+                for (name, param_type, value, system) in &indices {
+                    if let (Some(p), Some(prefix)) = (param_name, &composite_prefix)
+                        && name != p
+                        && !name.starts_with(prefix.as_str())
+                    {
+                        continue;
+                    }
+                    index.add_shadow_index(resource_type, id, name, param_type, Some(value), system.as_deref())?;
+                    entries_written += 1;
+                }
+                // Full text isn't per parameter; it is replaced in place.
+                if param_name.is_none() {
+                    let (narrative, content) = IndexBuilder::extract_text(&resource);
+                    index.index_text(resource_type, id, &narrative, &content)?;
+                }
+            }
+            if !index.checkpoint_reindex(&job.id, owner, (&cursor.0, &cursor.1), page.len(), entries_written)? {
+                return Err(sazare_store::StoreError::Other(format!("reindex job {id} is no longer this worker's")));
+            }
+            Ok(())
+        })
+        .map_err(|e| format!("index chunk: {}", e))?;
+    Ok(true)
+}
+
+/// Swap the job's shadow table in and refresh the statistics.
+fn finish(index: &dyn SearchBackend, id: &str, owner: &str) -> Result<ReindexSummary, String> {
+    if !index.finish_reindex(id, owner).map_err(|e| format!("swap index: {}", e))? {
+        return Err(format!("reindex job {id} is no longer this worker's"));
+    }
+    index
+        .refresh_statistics()
+        .map_err(|e| format!("refresh statistics: {}", e))?;
+    let job = index
+        .reindex_job(id)
+        .map_err(|e| format!("read reindex job: {}", e))?
+        .ok_or_else(|| format!("unknown reindex job {id}"))?;
+    Ok(ReindexSummary {
+        resources_indexed: job.resources_indexed,
+        entries_written: job.entries_written,
+    })
+}
+
+/// What [`check_consistency`] found and repaired.
+pub struct ConsistencyReport {
+    /// Resources in the store.
+    pub checked: usize,
+    /// Resources whose index entries were missing or out of date.
+    pub reindexed: usize,
+    /// Resources no longer in the store whose entries were removed.
+    pub removed: usize,
+}
+
+/// Bring the search index back in line with the store after a crash between
+/// the two writes (separate databases), or any other drift: a resource whose
+/// indexed `_lastUpdated` differs from its stored one (or that has no entries)
+/// is reindexed, and entries of resources the store no longer has are
+/// removed. Run at startup when the store and index are separate databases;
+/// both passes go a page at a time.
+pub fn check_consistency(
+    store: &dyn ResourceStore,
+    index: &dyn SearchBackend,
+    registry: &SearchParamRegistry,
+) -> Result<ConsistencyReport, String> {
+    let mut report = ConsistencyReport { checked: 0, reindexed: 0, removed: 0 };
+    let resolver = super::store_resolver(store);
+
+    let mut after: Option<(String, String)> = None;
+    loop {
+        let page = store
+            .list_last_updated_page(after.as_ref().map(|(t, i)| (t.as_str(), i.as_str())), REINDEX_CHUNK)
+            .map_err(|e| format!("list resources: {}", e))?;
+        let Some((last_type, last_id, _)) = page.last() else {
+            break;
+        };
+        after = Some((last_type.clone(), last_id.clone()));
+        report.checked += page.len();
+        for (resource_type, id, last_updated) in page {
+            let indexed = index
+                .indexed_last_updated(&resource_type, &id)
+                .map_err(|e| format!("read index of {resource_type}/{id}: {e}"))?;
+            if indexed == Some(last_updated) {
+                continue;
+            }
+            let Some(bytes) = store.get(&resource_type, &id).map_err(|e| format!("read {resource_type}/{id}: {e}"))?
+            else {
+                continue;
+            };
+            let resource: Value = match serde_json::from_slice(&bytes) {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::warn!("Skipping {}/{}: parse error: {}", resource_type, id, e);
+                    continue;
+                }
+            };
+            index
+                .in_transaction(|index| {
+                    super::write_index(index, &resolver, registry, &resource_type, &id, &resource)
+                })
+                .map_err(|e| format!("index {resource_type}/{id}: {e}"))?;
+            report.reindexed += 1;
+        }
+    }
+
+    let mut after: Option<(String, String)> = None;
+    loop {
+        let keys = index
+            .resource_keys_page(after.as_ref().map(|(t, i)| (t.as_str(), i.as_str())), REINDEX_CHUNK)
+            .map_err(|e| format!("list index entries: {}", e))?;
+        let Some(last) = keys.last() else {
+            break;
+        };
+        after = Some(last.clone());
+        for (resource_type, id) in keys {
+            let stored = store
+                .contains(&resource_type, &id)
+                .map_err(|e| format!("read {resource_type}/{id}: {e}"))?;
+            if !stored {
+                index
+                    .remove_index(&resource_type, &id)
+                    .map_err(|e| format!("remove {resource_type}/{id}: {e}"))?;
+                report.removed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Run a started job to the end in the background (see [`run_reindex`]).
+pub fn spawn_reindex(state: Arc<AppState>, id: String) {
+    tokio::spawn(async move {
+        run_reindex(&state, &id).await;
+    });
+}
+
+/// Run a started job to the end, a chunk per index lock, once this process
+/// has claimed it. While another live worker holds it, waits; returns when
+/// the job has ended, whoever ran it.
+pub async fn run_reindex(state: &AppState, id: &str) {
+    let owner = WORKER_ID.as_str();
+    let mut waiting = false;
+    loop {
+        let stale_before = (chrono::Utc::now() - REINDEX_LEASE).to_rfc3339();
+        let index = state.index.lock().await;
+        match index.claim_reindex(id, owner, &stale_before) {
+            Ok(true) => break,
+            Ok(false) => match index.reindex_job(id) {
+                Ok(Some(job)) if job.status == ReindexStatus::InProgress => {
+                    if !waiting {
+                        tracing::info!("Reindex {} is running in another process; waiting", id);
+                        waiting = true;
+                    }
+                }
+                Ok(_) => return,
+                Err(e) => {
+                    tracing::error!("Reindex {}: could not read the job: {}", id, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                tracing::error!("Reindex {}: could not claim the job: {}", id, e);
+                return;
+            }
+        }
+        drop(index);
+        tokio::time::sleep(REINDEX_WAIT).await;
+    }
 
-    for (resource_type, id, bytes) in all {
+    loop {
+        let index = state.index.lock().await;
+        let step = reindex_chunk(&*state.store, &**index, &state.search_params(), id, owner);
+        let result = match step {
+            Ok(true) => {
+                drop(index);
+                tokio::task::yield_now().await;
+                continue;
+            }
+            Ok(false) => finish(&**index, id, owner),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(summary) => tracing::info!(
+                "Reindex {} complete: {} resources, {} index entries",
+                id,
+                summary.resources_indexed,
+                summary.entries_written
+            ),
+            Err(e) => match index.fail_reindex(id, owner, &e) {
+                Ok(true) => tracing::error!("Reindex {} failed: {}", id, e),
+                Ok(false) => tracing::warn!("Reindex {} stopped: another worker has taken it over", id),
+                Err(fail) => {
+                    tracing::error!("Reindex {} failed: {}", id, e);
+                    tracing::error!("Reindex {}: could not record the failure: {}", id, fail);
+                }
+            },
+        }
+        return;
+    }
+}
+
+/// Fill an empty search index from a non-empty store before the server takes
+/// requests: a fresh index file, or one an upgrade's migration emptied.
+/// Searches, conditional writes and `ifNoneExist` would otherwise answer from
+/// a partial index meanwhile, and create what they should have found. A job
+/// the last shutdown interrupted is finished first; if it covered less than
+/// everything, a full one follows.
+pub async fn rebuild_empty_index(state: &Arc<AppState>) {
+    let active = state.index.lock().await.active_reindex();
+    match active {
+        Ok(Some(job)) => {
+            tracing::info!("Search index is empty; finishing the interrupted reindex {} first", job.id);
+            run_reindex(state, &job.id).await;
+            if job.resource_type.is_none() && job.param_name.is_none() {
+                return;
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("Auto-reindex failed to check for an interrupted reindex: {}", e);
+            return;
+        }
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let started = state
+        .index
+        .lock()
+        .await
+        .begin_reindex(&id, None, None, &chrono::Utc::now().to_rfc3339());
+    match started {
+        Ok(true) => {
+            tracing::info!("Search index is empty; rebuilding from resource store...");
+            run_reindex(state, &id).await;
+        }
+        Ok(false) => tracing::error!("Auto-reindex could not start: another reindex is in progress"),
+        Err(e) => tracing::error!("Auto-reindex failed to start: {}", e),
+    }
+}
+
+/// Resume a job a restart interrupted, if there is one.
+pub async fn resume_reindex(state: &Arc<AppState>) {
+    let active = state.index.lock().await.active_reindex();
+    match active {
+        Ok(Some(job)) => {
+            tracing::info!(
+                "Resuming reindex {} after {} resources",
+                job.id,
+                job.resources_indexed
+            );
+            spawn_reindex(state.clone(), job.id);
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to check for an interrupted reindex: {}", e),
+    }
+}
+
+/// Re-extract one search parameter for the given resources, leaving their
+/// other entries alone. Used after a `SearchParameter` is created, changed
+/// or deleted (see [`super::search_parameter`]); contained resources are only
+/// picked up by a full reindex. Returns the number of entries written.
+pub fn reindex_parameter(
+    store: &dyn ResourceStore,
+    index: &dyn SearchBackend,
+    registry: &SearchParamRegistry,
+    resource_type: &str,
+    param_name: &str,
+    ids: &[String],
+) -> Result<usize, String> {
+    let resolver = super::store_resolver(store);
+    let composite_prefix = format!("{param_name}$");
+    let mut entries_written = 0usize;
+    for id in ids {
+        let Some(bytes) = store.get(resource_type, id).map_err(|e| format!("read {resource_type}/{id}: {e}"))? else {
+            continue;
+        };
         let resource: Value = match serde_json::from_slice(&bytes) {
             Ok(v) => v,
             Err(e) => {
@@ -32,51 +376,124 @@ pub fn perform_reindex(
                 continue;
             }
         };
-        let indices = IndexBuilder::extract_indices_with_registry(registry, &resource_type, &resource);
-        for (param_name, param_type, value, system) in &indices {
-            if let Err(e) = index.add_index(
-                &resource_type,
-                &id,
-                param_name,
-                param_type,
-                Some(value),
-                system.as_deref(),
-            ) {
-                tracing::warn!("add_index {}/{} {}: {}", resource_type, id, param_name, e);
+        index
+            .remove_param_index(resource_type, id, param_name)
+            .map_err(|e| format!("remove {resource_type}/{id} {param_name}: {e}"))?;
+        let indices =
+            IndexBuilder::extract_indices_with_resolver(registry, resource_type, &resource, Some(&resolver));
+        for (name, param_type, value, system) in &indices {
+            if name != param_name && !name.starts_with(&composite_prefix) {
+                continue;
             }
+            if let Err(e) = index.add_index(resource_type, id, name, param_type, Some(value), system.as_deref()) {
+                tracing::warn!("add_index {}/{} {}: {}", resource_type, id, name, e);
+                continue;
+            }
+            entries_written += 1;
         }
-        entries_written += indices.len();
-        resources_indexed += 1;
     }
-
-    Ok(ReindexSummary { resources_indexed, entries_written })
+    Ok(entries_written)
 }
 
-/// POST /$reindex — admin endpoint to rebuild the search index.
+/// POST /$reindex — admin endpoint to rebuild the search index, or one
+/// resource type's or search parameter's part of it, as a background job.
 pub async fn reindex(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    headers: HeaderMap,
+    Query(params): Query<ReindexParams>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    if let (Some(rt), Some(p)) = (&params.resource_type, &params.parameter)
+        && state.search_params().lookup_param_type(rt, p).is_none()
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!(OperationOutcome::error(
+                IssueType::NotSupported,
+                format!("'{p}' is not a search parameter of {rt}")
+            ))),
+        ));
+    }
+
+    let base = super::base_url_from_headers(&headers);
+    let id = uuid::Uuid::new_v4().to_string();
     let index = state.index.lock().await;
-    let summary = perform_reindex(&state.store, &index, &state.search_param_registry)
+    let started = index
+        .begin_reindex(
+            &id,
+            params.resource_type.as_deref(),
+            params.parameter.as_deref(),
+            &chrono::Utc::now().to_rfc3339(),
+        )
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"resourceType": "OperationOutcome",
-                    "issue": [{"severity": "error", "code": "exception", "diagnostics": e}]})),
+                Json(json!(OperationOutcome::storage_error(e.to_string()))),
             )
         })?;
+    if !started {
+        let running = index.active_reindex().ok().flatten().map(|job| job.id).unwrap_or_default();
+        let status_url = format!("{base}/$reindex-status/{running}");
+        return Ok((
+            StatusCode::CONFLICT,
+            [(header::CONTENT_LOCATION, status_url.clone())],
+            Json(json!(OperationOutcome::error(
+                IssueType::Conflict,
+                format!("A reindex is already running; see {status_url}")
+            ))),
+        )
+            .into_response());
+    }
+    drop(index);
+
+    spawn_reindex(state.clone(), id.clone());
+    let status_url = format!("{base}/$reindex-status/{id}");
+    Ok((StatusCode::ACCEPTED, [(header::CONTENT_LOCATION, status_url)]).into_response())
+}
 
-    tracing::info!(
-        "Reindex complete: {} resources, {} index entries",
-        summary.resources_indexed,
-        summary.entries_written
-    );
-
-    Ok(Json(json!({
-        "resourceType": "Parameters",
-        "parameter": [
-            {"name": "resourcesIndexed", "valueInteger": summary.resources_indexed},
-            {"name": "entriesWritten", "valueInteger": summary.entries_written},
-        ]
-    })))
+/// GET /$reindex-status/{job_id} — poll a reindex job.
+pub async fn reindex_status(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let job = state.index.lock().await.reindex_job(&job_id).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!(OperationOutcome::storage_error(e.to_string()))),
+        )
+    })?;
+    let Some(job) = job else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!(OperationOutcome::error(IssueType::NotFound, "Unknown reindex job"))),
+        ));
+    };
+    match &job.status {
+        ReindexStatus::InProgress => Ok((
+            StatusCode::ACCEPTED,
+            [
+                ("X-Progress", format!("{} resources indexed", job.resources_indexed)),
+                ("Retry-After", "1".to_string()),
+            ],
+        )
+            .into_response()),
+        ReindexStatus::Failed(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!(OperationOutcome::error(IssueType::Exception, e.clone()))),
+        )),
+        ReindexStatus::Complete => Ok(Json(summary_parameters(&job)).into_response()),
+    }
+}
+
+fn summary_parameters(job: &ReindexJob) -> Value {
+    let mut parameters = vec![
+        json!({"name": "resourcesIndexed", "valueInteger": job.resources_indexed}),
+        json!({"name": "entriesWritten", "valueInteger": job.entries_written}),
+    ];
+    if let Some(rt) = &job.resource_type {
+        parameters.push(json!({"name": "type", "valueCode": rt}));
+    }
+    if let Some(p) = &job.param_name {
+        parameters.push(json!({"name": "parameter", "valueString": p}));
+    }
+    json!({"resourceType": "Parameters", "parameter": parameters})
 }
@@ -1,13 +1,15 @@
 use axum::{
     extract::{Path, Request, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::{Json, Response},
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use sazare_core::{
-    operation_outcome::IssueType,
+    operation_outcome::{IssueSeverity, IssueType, OperationOutcomeIssue},
     resource_filter::{apply_elements, apply_summary},
-    OperationOutcome, SearchQuery,
+    OperationOutcome, SearchQuery, TotalMode,
 };
+use sazare_store::sqlite_index::{PageCursor, PageStart};
 use sazare_store::SearchExecutor;
 use serde_json::{json, Value};
 use std::sync::Arc;
@@ -15,10 +17,11 @@ use std::sync::Arc;
 use crate::audit::{self, AuditContext};
 use crate::auth::AuthUser;
 use crate::compartment_check::filter_by_compartment;
+use crate::page_token;
 use crate::AppState;
 
 /// Default page size per FHIR spec
-const DEFAULT_COUNT: usize = 100;
+pub(crate) const DEFAULT_COUNT: usize = 100;
 
 /// Reconstruct the externally-visible base URL (scheme + authority).
 /// Honors `X-Forwarded-Proto` and `X-Forwarded-Host` so reverse-proxied deploys
@@ -61,7 +64,8 @@ pub async fn search(
     // HashMap<String,String> would collapse duplicates (last-wins) and a
     // pre-decoded map would be decoded a second time.
     let raw_query = request.uri().query().unwrap_or("").to_string();
-    do_search(state, resource_type, raw_query, auth_user, audit_ctx, base_url).await
+    let headers = request.headers().clone();
+    do_search(state, resource_type, raw_query, auth_user, audit_ctx, base_url, Some(headers)).await
 }
 
 /// Search via POST (POST /{resource_type}/_search) — FHIR alternative to GET search.
@@ -97,26 +101,49 @@ pub async fn search_post(
         )
     })?;
 
-    do_search(state, resource_type, raw_query, auth_user, audit_ctx, base_url).await
+    do_search(state, resource_type, raw_query, auth_user, audit_ctx, base_url, None).await
 }
 
-/// Reconstruct the query string without `_count`/`_offset`, preserving the
-/// original percent-encoding and the order/multiplicity of every other
-/// parameter (so pagination links round-trip exactly).
+/// Reconstruct the query string without `_count`/`_offset`/`_page_token`,
+/// preserving the original percent-encoding and the order/multiplicity of every
+/// other parameter (so pagination links round-trip exactly).
 fn base_params_without_paging(raw_query: &str) -> String {
     raw_query
         .split('&')
         .filter(|pair| !pair.is_empty())
         .filter(|pair| {
-            // `_count`/`_offset` are never percent-encoded by clients, so a raw
+            // The paging params are never percent-encoded by clients, so a raw
             // key comparison is sufficient and avoids a decode dependency.
             let key = pair.split('=').next().unwrap_or("");
-            key != "_count" && key != "_offset"
+            key != "_count" && key != "_offset" && key != "_page_token"
         })
         .collect::<Vec<_>>()
         .join("&")
 }
 
+/// Compartment filtering for loaded search matches. A `_contained` search can
+/// return containers of other types, so each match is checked as its own type.
+fn filter_matches(
+    auth_user: Option<&AuthUser>,
+    state: &AppState,
+    resource_type: &str,
+    matches: Vec<(String, Value)>,
+) -> Vec<(String, Value)> {
+    matches
+        .into_iter()
+        .filter_map(|(reference, resource)| {
+            let match_type = resource
+                .get("resourceType")
+                .and_then(|t| t.as_str())
+                .unwrap_or(resource_type)
+                .to_string();
+            filter_by_compartment(auth_user, &state.compartment_def, &match_type, vec![resource])
+                .pop()
+                .map(|resource| (reference, resource))
+        })
+        .collect()
+}
+
 /// Shared search execution path used by both GET and POST search handlers.
 async fn do_search(
     state: Arc<AppState>,
@@ -125,8 +152,9 @@ async fn do_search(
     auth_user: Option<AuthUser>,
     audit_ctx: AuditContext,
     base_url: String,
+    conditional: Option<HeaderMap>,
 ) -> Result<Response, (StatusCode, Json<Value>)> {
-    let query = SearchQuery::parse_for_resource(&raw_query, Some(&resource_type)).map_err(|e| {
+    let mut query = SearchQuery::parse_for_resource(&raw_query, Some(&resource_type)).map_err(|e| {
         (
             StatusCode::BAD_REQUEST,
             Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
@@ -137,17 +165,20 @@ async fn do_search(
     // silently returning an empty set — but only for resource types we have an
     // explicit parameter registry for, so unmodelled types stay lenient.
     // Underscore result params (`_id`, `_lastUpdated`, …) are already validated
-    // by the parser and pass through here.
-    if state.search_param_registry.has_resource_type(&resource_type) {
-        for p in &query.parameters {
+    // by the parser and pass through here. Known params take their type from
+    // the server's registry, which also holds runtime-loaded definitions the
+    // parser doesn't know about.
+    if state.search_params().has_resource_type(&resource_type) {
+        for p in &mut query.parameters {
             if p.name.starts_with('_') {
                 continue;
             }
-            if state
-                .search_param_registry
+            if let Some(t) = state
+                .search_params()
                 .lookup_param_type(&resource_type, &p.name)
-                .is_none()
             {
+                p.retype(t);
+            } else {
                 return Err((
                     StatusCode::BAD_REQUEST,
                     Json(json!(OperationOutcome::error(
@@ -164,26 +195,96 @@ async fn do_search(
         }
     }
 
-    // If _summary=count, return only the count
-    if query.summary == Some(sazare_core::SummaryMode::Count) {
-        let index = state.index.lock().await;
-        let executor = SearchExecutor::new(&state.store, &index);
-        let ids = executor.search(&resource_type, &query).map_err(|e| {
+    // The inner parameter of a `_has` takes its type from the registry too.
+    for has in &mut query.has_parameters {
+        if let Some(source_type) = has.links.last().map(|l| l.source_type.as_str())
+            && let Some(t) = state.search_params().lookup_param_type(source_type, &has.param.name)
+        {
+            has.param.retype(t);
+        }
+    }
+
+    // Chain hops without a type (`subject.name=Doe`) fan out over every type
+    // the reference can point at.
+    for chain in &mut query.chain_parameters {
+        chain
+            .resolve_targets(&state.search_params(), &resource_type)
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+                )
+            })?;
+    }
+
+    // Modifiers the parameter's type doesn't support are refused, and the
+    // terminology ones (`:in`, `:not-in`, `:above`, `:below`) expanded into
+    // codes from the loaded ValueSets and CodeSystems.
+    let has_params = query.has_parameters.iter_mut().map(|has| &mut has.param);
+    let chain_params = query.chain_parameters.iter_mut().map(|chain| &mut chain.param);
+    for p in query.parameters.iter_mut().chain(has_params).chain(chain_params) {
+        p.check_modifier().map_err(|e| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!(OperationOutcome::storage_error(e))),
+                StatusCode::BAD_REQUEST,
+                Json(json!(OperationOutcome::error(IssueType::NotSupported, e))),
             )
         })?;
+        p.resolve_terminology(&state.terminology_registry).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+            )
+        })?;
+    }
 
+    // `_sort` keys the server can't order by are dropped, not rejected: the
+    // search still runs and the Bundle carries a warning saying which keys
+    // were ignored (in `warnings`, with any other such notes). The registry is
+    // authoritative for the key's type.
+    let mut warnings = Vec::new();
+    query.sort.retain_mut(|key| {
+        if key.name == "_id" {
+            return true;
+        }
+        match state
+            .search_params()
+            .lookup_param_type(&resource_type, &key.name)
+        {
+            Some(t) if SearchExecutor::is_sortable(&t) => {
+                key.param_type = t;
+                true
+            }
+            _ => {
+                warnings.push(format!(
+                    "_sort key '{}' is not supported for {} and was ignored",
+                    key.name, resource_type
+                ));
+                false
+            }
+        }
+    });
+
+    // If _summary=count, return only the count
+    if query.summary == Some(sazare_core::SummaryMode::Count) {
+        let index = state.index.lock().await;
+        let executor = SearchExecutor::new(&*state.store, &**index);
         // For count mode with compartment filtering, we need to load and filter
         if auth_user.as_ref().is_some_and(|u| u.is_patient_scoped()) {
-            let resources = executor.load_resources(&resource_type, &ids).map_err(|e| {
+            let ids = executor.search(&resource_type, &query).map_err(|e| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!(OperationOutcome::storage_error(e))),
                 )
             })?;
-            let filtered = filter_by_compartment(auth_user.as_ref(), &state.compartment_def, &resource_type, resources);
+            let matches = executor
+                .load_matches(&resource_type, &ids, query.contained_type)
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!(OperationOutcome::storage_error(e))),
+                    )
+                })?;
+            let filtered = filter_matches(auth_user.as_ref(), &state, &resource_type, matches);
             return Ok(super::fhir_json(StatusCode::OK, json!({
                 "resourceType": "Bundle",
                 "type": "searchset",
@@ -191,57 +292,120 @@ async fn do_search(
             })));
         }
 
+        // The count is the whole answer here, so `_total=none` doesn't apply.
+        let mode = match query.total {
+            Some(TotalMode::Estimate) => TotalMode::Estimate,
+            _ => TotalMode::Accurate,
+        };
+        let total = executor.count(&resource_type, &query, mode).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!(OperationOutcome::storage_error(e))),
+            )
+        })?;
         return Ok(super::fhir_json(StatusCode::OK, json!({
             "resourceType": "Bundle",
             "type": "searchset",
-            "total": ids.len()
+            "total": total.unwrap_or(0)
         })));
     }
 
-    let index = state.index.lock().await;
-    let executor = SearchExecutor::new(&state.store, &index);
+    // A `_page_token` resumes a paged search where its link's page ended, in
+    // the snapshot of the first page. The token is signed over the search's
+    // own parameters, so an edited or transplanted one is rejected.
+    let base_params = base_params_without_paging(&raw_query);
+    let token_scope = format!("{resource_type}?{base_params}");
+    let token_secret = state.config.server.page_token_secret.as_deref();
+    let (start, snapshot) = match &query.page_token {
+        Some(token) => page_token::decode(token_secret, &token_scope, token).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!(OperationOutcome::error(IssueType::Invalid, e))),
+            )
+        })?,
+        None => (PageStart::First, chrono::Utc::now()),
+    };
 
-    let (ids, total) = executor.search_with_total(&resource_type, &query).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!(OperationOutcome::storage_error(e))),
-        )
-    })?;
+    // When anything of the type last changed — deletions included, which no
+    // entry's `lastUpdated` shows — for `Last-Modified`. Read before the
+    // search, so a write landing meanwhile is never covered by it.
+    let modified = state
+        .store
+        .type_last_updated(&resource_type)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!(OperationOutcome::storage_error(e.to_string()))),
+            )
+        })?
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok())
+        .map(|t| t.with_timezone(&chrono::Utc));
 
-    let resources = executor.load_resources(&resource_type, &ids).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!(OperationOutcome::storage_error(e))),
-        )
-    })?;
+    let index = state.index.lock().await;
+    let executor = SearchExecutor::new(&*state.store, &**index);
+
+    // `_total` if the client asked, else the server default. Skipping the
+    // count (`none`) or estimating it keeps deep result sets cheap to page.
+    let total_mode = query.total.unwrap_or(state.config.search.default_total);
+    let (page, total) = executor
+        .search_page(&resource_type, &query, &start, Some(snapshot), total_mode)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!(OperationOutcome::storage_error(e))),
+            )
+        })?;
+    let ids = &page.ids;
+
+    let matches = executor
+        .load_matches(&resource_type, ids, query.contained_type)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!(OperationOutcome::storage_error(e))),
+            )
+        })?;
 
     // Compartment filtering
-    let mut resources = filter_by_compartment(auth_user.as_ref(), &state.compartment_def, &resource_type, resources);
+    let (references, mut resources): (Vec<String>, Vec<Value>) =
+        filter_matches(auth_user.as_ref(), &state, &resource_type, matches)
+            .into_iter()
+            .unzip();
 
     let total = if auth_user.as_ref().is_some_and(|u| u.is_patient_scoped()) {
         // If compartment-filtered, total is the filtered count
-        resources.len()
+        total.map(|_| resources.len())
     } else {
         total
     };
 
-    // Process _include
-    let included = if !query.include.is_empty() {
-        executor
-            .process_includes(&resources, &query.include, &state.search_param_registry)
-            .unwrap_or_default()
+    // Process _include / _revinclude, iterating where asked.
+    let included = if query.include.is_empty()
+        && query.revinclude.is_empty()
+        && query.include_iterate.is_empty()
+        && query.revinclude_iterate.is_empty()
+    {
+        Default::default()
     } else {
-        Vec::new()
-    };
-
-    // Process _revinclude
-    let revincluded = if !query.revinclude.is_empty() {
+        let matches: Vec<(String, Value)> =
+            references.iter().cloned().zip(resources.iter().cloned()).collect();
         executor
-            .process_revincludes(&resources, &resource_type, &query.revinclude)
+            .resolve_includes(
+                &resource_type,
+                &matches,
+                &query,
+                &state.search_params(),
+                state.config.search.max_included,
+            )
             .unwrap_or_default()
-    } else {
-        Vec::new()
     };
+    if included.truncated {
+        warnings.push(format!(
+            "Included resources were capped at {}; narrow the _include/_revinclude \
+             or page with a smaller _count to see the rest",
+            state.config.search.max_included
+        ));
+    }
 
     // Apply _summary / _elements filtering
     for resource in &mut resources {
@@ -254,17 +418,12 @@ async fn do_search(
     }
 
     // Build Bundle
-    let mut entries: Vec<Value> = resources
+    let mut entries: Vec<Value> = references
         .into_iter()
-        .map(|r| {
-            let full_url = format!(
-                "{}/{}/{}",
-                base_url,
-                resource_type,
-                r.get("id").and_then(|v| v.as_str()).unwrap_or("")
-            );
+        .zip(resources)
+        .map(|(reference, r)| {
             json!({
-                "fullUrl": full_url,
+                "fullUrl": format!("{base_url}/{reference}"),
                 "resource": r,
                 "search": {"mode": "match"}
             })
@@ -272,7 +431,7 @@ async fn do_search(
         .collect();
 
     // Include entries
-    for inc in included {
+    for inc in included.resources {
         let inc_type = inc
             .get("resourceType")
             .and_then(|v| v.as_str())
@@ -285,54 +444,77 @@ async fn do_search(
         }));
     }
 
-    // Revinclude entries
-    for inc in revincluded {
-        let inc_type = inc
-            .get("resourceType")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        let inc_id = inc.get("id").and_then(|v| v.as_str()).unwrap_or("");
+    if !warnings.is_empty() {
+        let mut outcome = OperationOutcome::new(
+            IssueSeverity::Warning,
+            IssueType::NotSupported,
+            warnings.remove(0),
+        );
+        for w in warnings {
+            outcome.add_issue(OperationOutcomeIssue {
+                severity: IssueSeverity::Warning,
+                code: IssueType::NotSupported,
+                diagnostics: Some(w),
+                details: None,
+                expression: None,
+            });
+        }
         entries.push(json!({
-            "fullUrl": format!("{}/{}/{}", base_url, inc_type, inc_id),
-            "resource": inc,
-            "search": {"mode": "include"}
+            "resource": outcome,
+            "search": {"mode": "outcome"}
         }));
     }
 
-    // Pagination links
+    // Pagination links. `next`/`previous` carry a signed keyset cursor of this
+    // page's last/first row rather than an offset, so writes between fetches
+    // can't make a client skip or repeat entries.
     let count = query.count.unwrap_or(DEFAULT_COUNT);
     let offset = query.offset.unwrap_or(0);
     let mut links: Vec<Value> = Vec::new();
 
-    // Build base query without _count and _offset (encoding preserved).
-    let base_params = base_params_without_paging(&raw_query);
-
     let base = if base_params.is_empty() {
         format!("{}/{}?_count={}", base_url, resource_type, count)
     } else {
         format!("{base_url}/{resource_type}?{base_params}&_count={count}")
     };
+    let link = |cursor: &PageCursor, backward: bool| {
+        let token = page_token::encode(token_secret, &token_scope, cursor, backward, snapshot);
+        format!("{base}&_page_token={token}")
+    };
 
     // self link
+    let self_url = match &query.page_token {
+        Some(token) => format!("{base}&_page_token={token}"),
+        None => format!("{}&_offset={}", base, offset),
+    };
     links.push(json!({
         "relation": "self",
-        "url": format!("{}&_offset={}", base, offset)
+        "url": self_url
     }));
 
+    // A backward page was reached from the page after it, and a forward page
+    // from the one before it unless it is the first.
+    let backward = matches!(start, PageStart::Before(_));
+    let has_next = if backward { true } else { page.more };
+    let has_previous = if backward {
+        page.more
+    } else {
+        query.page_token.is_some() || offset > 0
+    };
+
     // next link
-    if offset + count < total {
+    if has_next && let Some(last) = &page.last {
         links.push(json!({
             "relation": "next",
-            "url": format!("{}&_offset={}", base, offset + count)
+            "url": link(last, false)
         }));
     }
 
     // previous link
-    if offset > 0 {
-        let prev_offset = offset.saturating_sub(count);
+    if has_previous && let Some(first) = &page.first {
         links.push(json!({
             "relation": "previous",
-            "url": format!("{}&_offset={}", base, prev_offset)
+            "url": link(first, true)
         }));
     }
 
@@ -340,19 +522,46 @@ async fn do_search(
         &audit_ctx,
         "SEARCH",
         &resource_type,
-        &format!("{} results", total),
+        &match total {
+            Some(total) => format!("{} results", total),
+            None => format!("{} results on this page", page.ids.len()),
+        },
         &state.audit,
     );
 
+    // A GET search answers `304 Not Modified` like a read when the page is
+    // what the client already has.
+    let etag = searchset_etag(total, &entries);
+    if conditional.is_some_and(|headers| super::not_modified(&headers, Some(&etag), modified)) {
+        return Ok(super::not_modified_response(Some(&etag), modified));
+    }
+
     // Omit `entry` entirely when empty — FHIR JSON forbids empty arrays.
     let mut bundle = json!({
         "resourceType": "Bundle",
         "type": "searchset",
-        "total": total,
         "link": links,
     });
+    if let Some(total) = total {
+        bundle["total"] = json!(total);
+    }
     if !entries.is_empty() {
         bundle["entry"] = json!(entries);
     }
-    Ok(super::fhir_json(StatusCode::OK, bundle))
+    let mut response = super::fhir_json(StatusCode::OK, bundle);
+    if let Ok(val) = etag.parse() {
+        response.headers_mut().insert(header::ETAG, val);
+    }
+    if let Some(val) = modified.and_then(|lm| super::http_date(lm).parse().ok()) {
+        response.headers_mut().insert(header::LAST_MODIFIED, val);
+    }
+    Ok(response)
+}
+
+/// A searchset page's weak ETag: a digest of its entries and total. The links
+/// are left out, as a page token is minted afresh on every request.
+fn searchset_etag(total: Option<usize>, entries: &[Value]) -> String {
+    let body = serde_json::to_vec(&json!([total, entries])).unwrap_or_default();
+    let digest = ring::digest::digest(&ring::digest::SHA256, &body);
+    format!("W/\"{}\"", URL_SAFE_NO_PAD.encode(&digest.as_ref()[..16]))
 }
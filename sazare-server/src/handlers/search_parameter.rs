@@ -0,0 +1,102 @@
+//! Runtime SearchParameter management.
+//!
+//! A `SearchParameter` created, updated, patched or deleted through the REST
+//! API — conditionally or in a batch or transaction Bundle included — is
+//! registered with (or removed from) the live registry once it is stored, and the resources of each `base` type are reindexed for just that
+//! parameter in the background. Stored SearchParameters are registered again
+//! at startup (see `main.rs`), after those in `searchparameters/`.
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Resources reindexed per index lock, so a large type doesn't hold up writes.
+const REINDEX_BATCH: usize = 200;
+
+/// Whether `sp` would register: its expression compiles and its type and
+/// composite components resolve. Checked against a copy of the registry
+/// before the resource is stored.
+pub fn check(state: &AppState, sp: &Value) -> Result<(), String> {
+    let mut registry = state.search_params().clone();
+    registry.register_search_parameter(sp)
+}
+
+/// Swap `previous` (the stored version being replaced or deleted) for
+/// `current` (the version just stored) in the registry, then reindex every
+/// (base, code) either of them touched.
+pub fn apply(state: &Arc<AppState>, previous: Option<&Value>, current: Option<&Value>) {
+    let mut affected = BTreeSet::new();
+    {
+        let mut registry = match state.search_param_registry.write() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        };
+        if let Some(sp) = previous {
+            let code = code(sp);
+            for base in registry.unregister_search_parameter(sp) {
+                affected.insert((base, code.clone()));
+            }
+        }
+        if let Some(sp) = current {
+            match registry.register_search_parameter(sp) {
+                Ok(()) => {
+                    let code = code(sp);
+                    for base in bases(sp) {
+                        affected.insert((base, code.clone()));
+                    }
+                }
+                Err(e) => tracing::warn!("SearchParameter not registered: {}", e),
+            }
+        }
+    }
+    for (resource_type, param_name) in affected {
+        let state = state.clone();
+        tokio::spawn(async move {
+            reindex(&state, &resource_type, &param_name).await;
+        });
+    }
+}
+
+/// Reindex `param_name` across every stored `resource_type` resource, a
+/// batch at a time.
+async fn reindex(state: &AppState, resource_type: &str, param_name: &str) {
+    let ids = match state.store.list_ids(resource_type) {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("Reindex of {}:{} failed: {}", resource_type, param_name, e);
+            return;
+        }
+    };
+    let mut entries_written = 0usize;
+    for batch in ids.chunks(REINDEX_BATCH) {
+        let index = state.index.lock().await;
+        let registry = state.search_params();
+        match super::reindex::reindex_parameter(&*state.store, &**index, &registry, resource_type, param_name, batch) {
+            Ok(n) => entries_written += n,
+            Err(e) => {
+                tracing::error!("Reindex of {}:{} failed: {}", resource_type, param_name, e);
+                return;
+            }
+        }
+    }
+    tracing::info!(
+        "Reindexed {}:{}: {} resources, {} entries",
+        resource_type,
+        param_name,
+        ids.len(),
+        entries_written
+    );
+}
+
+fn code(sp: &Value) -> String {
+    sp.get("code").and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+fn bases(sp: &Value) -> Vec<String> {
+    sp.get("base")
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|b| b.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
@@ -12,6 +12,7 @@ pub mod config;
 pub mod dashboard;
 pub mod demo;
 pub mod handlers;
+pub mod page_token;
 pub mod plugins;
 pub mod smart;
 pub mod subscription;
@@ -28,7 +29,7 @@ use sazare_core::{
     validation::{ProfileRegistry, TerminologyRegistry},
     CompartmentDef, SearchParamRegistry, SearchQuery,
 };
-use sazare_store::{AuditLog, SearchExecutor, SearchIndex, SqliteStore};
+use sazare_store::{AuditLog, ResourceStore, SearchBackend, SearchExecutor};
 use serde_json::Value;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -41,13 +42,16 @@ use tower_http::{
 
 /// Application state
 pub struct AppState {
-    pub store: SqliteStore,
-    pub index: Mutex<SearchIndex>,
+    pub store: Box<dyn ResourceStore>,
+    pub index: Mutex<Box<dyn SearchBackend>>,
     pub audit: Arc<Mutex<AuditLog>>,
     pub config: config::ServerConfig,
     pub profile_registry: ProfileRegistry,
     pub terminology_registry: TerminologyRegistry,
-    pub search_param_registry: SearchParamRegistry,
+    /// Built-in, `searchparameters/` and stored `SearchParameter` definitions;
+    /// written when a SearchParameter is created, updated or deleted. Read it
+    /// through [`AppState::search_params`].
+    pub search_param_registry: std::sync::RwLock<SearchParamRegistry>,
     pub compartment_def: CompartmentDef,
     pub jwk_cache: tokio::sync::RwLock<auth::JwkCache>,
     /// Discovered plugin names (for auth bypass and routing)
@@ -63,6 +67,17 @@ pub struct AppState {
     pub seen_jti: std::sync::Mutex<std::collections::HashMap<String, u64>>,
 }
 
+impl AppState {
+    /// The search parameter registry. The guard must not be held across an
+    /// `.await`.
+    pub fn search_params(&self) -> std::sync::RwLockReadGuard<'_, SearchParamRegistry> {
+        match self.search_param_registry.read() {
+            Ok(g) => g,
+            Err(p) => p.into_inner(),
+        }
+    }
+}
+
 /// Conditional create result
 pub enum ConditionalResult {
     NoMatch,
@@ -83,7 +98,7 @@ pub async fn conditional_create_check(
     };
 
     let index = state.index.lock().await;
-    let executor = SearchExecutor::new(&state.store, &index);
+    let executor = SearchExecutor::new(&*state.store, &**index);
 
     match executor.search(resource_type, &query) {
         Ok(ids) if ids.is_empty() => ConditionalResult::NoMatch,
@@ -153,6 +168,11 @@ pub fn build_router(state: Arc<AppState>) -> Router {
         .route("/$import", post(bulk::import))
         // Admin: rebuild search index
         .route("/$reindex", post(handlers::reindex::reindex))
+        .route("/$reindex-status/{job_id}", get(handlers::reindex::reindex_status))
+        // Admin: physically remove history or resources
+        .route("/$expunge", post(handlers::expunge::system_expunge))
+        // FHIRPath evaluation against a posted resource
+        .route("/$fhirpath", post(handlers::fhirpath::evaluate))
         // Metadata
         .route("/metadata", get(handlers::metadata::capability_statement))
         // SMART on FHIR configuration
@@ -161,7 +181,13 @@ pub fn build_router(state: Arc<AppState>) -> Router {
         .route("/ws", get(websocket::ws_handler))
         // Operations (must be before /{resource_type}/{id} to avoid matching as {id})
         .route("/{resource_type}/$validate", post(handlers::validate::validate))
+        .route("/{resource_type}/$expunge", post(handlers::expunge::type_expunge))
+        .route("/{resource_type}/{id}/$expunge", post(handlers::expunge::instance_expunge))
         .route("/{resource_type}/{id}/$everything", get(handlers::everything::patient_everything))
+        .route(
+            "/{resource_type}/{id}/$fhirpath",
+            get(handlers::fhirpath::evaluate_instance).post(handlers::fhirpath::evaluate_instance),
+        )
         // FHIR search-via-POST (alternative to GET search; body is form-encoded params)
         .route("/{resource_type}/_search", post(handlers::search::search_post))
         // CRUD + Search + Conditional
@@ -180,6 +206,8 @@ pub fn build_router(state: Arc<AppState>) -> Router {
                 .delete(handlers::crud::delete_resource),
         )
         // History
+        .route("/_history", get(handlers::history::system_history))
+        .route("/{resource_type}/_history", get(handlers::history::type_history))
         .route("/{resource_type}/{id}/_history", get(handlers::history::history))
         .route("/{resource_type}/{id}/_history/{vid}", get(handlers::history::vread))
         )
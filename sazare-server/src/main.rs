@@ -5,13 +5,18 @@ use sazare_core::{
     validation::{ProfileRegistry, TerminologyRegistry},
     CompartmentDef, SearchParamRegistry,
 };
-use sazare_store::{AuditLog, SearchIndex, SqliteStore};
+use sazare_store::{AuditLog, ResourceStore, SearchBackend, SearchIndex, SqliteStore};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use sazare_server::{build_router, config::ServerConfig, handlers::reindex::perform_reindex, plugins, AppState};
+use sazare_server::{
+    build_router,
+    config::{ServerConfig, StorageBackend},
+    handlers::reindex::{check_consistency, rebuild_empty_index, resume_reindex},
+    plugins, AppState,
+};
 
 #[tokio::main]
 async fn main() {
@@ -50,13 +55,8 @@ async fn main() {
     }
 
     // Initialize stores
-    let store = SqliteStore::open(config.resources_db_path()).unwrap_or_else(|e| {
-        tracing::error!("Failed to open resource store: {}", e);
-        std::process::exit(1);
-    });
-
-    let index = SearchIndex::open(config.search_index_db_path()).unwrap_or_else(|e| {
-        tracing::error!("Failed to open search index: {}", e);
+    let (store, index) = open_storage(&config).unwrap_or_else(|e| {
+        tracing::error!("{}", e);
         std::process::exit(1);
     });
 
@@ -86,8 +86,8 @@ async fn main() {
 
     // Load custom search parameters from searchparameters/ if it exists. Each is
     // a FHIR SearchParameter resource whose `expression` is compiled by the
-    // bounded FHIRPath evaluator; ones outside the supported subset are rejected
-    // loudly here rather than producing wrong results later. This is how JP Core
+    // FHIRPath engine; ones that fail to parse are rejected loudly here rather
+    // than producing wrong results later. This is how JP Core
     // (or any IG) search params are supplied now — drop them in alongside the
     // matching profiles in profiles/.
     let mut search_param_registry = SearchParamRegistry::new();
@@ -102,28 +102,81 @@ async fn main() {
         }
         Err(e) => tracing::warn!("Failed to load custom search parameters: {}", e),
     }
+    // SearchParameters created through the REST API live in the resource store;
+    // registered after the directory's, they replace one with the same base
+    // and code.
+    match store.list_all(Some("SearchParameter")) {
+        Ok(stored) => {
+            for (_, id, bytes) in stored {
+                let registered = serde_json::from_slice(&bytes)
+                    .map_err(|e| e.to_string())
+                    .and_then(|sp| search_param_registry.register_search_parameter(&sp));
+                if let Err(e) = registered {
+                    tracing::warn!("Skipping stored SearchParameter/{}: {}", id, e);
+                }
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load stored search parameters: {}", e),
+    }
+
+    // ValueSets and CodeSystems from terminology/ join the built-in ones, both
+    // for binding validation and for the `:in` / `:not-in` / `:above` /
+    // `:below` token search modifiers.
+    let mut terminology_registry = TerminologyRegistry::new();
+    for (resource_type, kind) in [("ValueSet", "value sets"), ("CodeSystem", "code systems")] {
+        match ProfileLoader::load_resources_from_directory("terminology", resource_type) {
+            Ok(resources) if !resources.is_empty() => {
+                tracing::info!("Loading {} custom {}", resources.len(), kind);
+                for resource in &resources {
+                    if resource_type == "ValueSet" {
+                        terminology_registry.load_value_set_resource(&resource.to_string());
+                    } else {
+                        terminology_registry.load_code_system_resource(&resource.to_string());
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to load custom {}: {}", kind, e),
+        }
+    }
 
     // Auto-reindex if the search index is empty (fresh deploy, or after an index wipe
-    // following a schema change like added common params _id/_profile/_tag/etc.)
+    // following a schema change like added common params _id/_profile/_tag/etc.),
+    // before the port is bound. Otherwise a `$reindex` interrupted by the last
+    // shutdown is resumed in the background, once the server state exists.
+    let interrupted_reindex = index.active_reindex().ok().flatten().is_some();
+    let mut rebuild_index = false;
     match index.row_count() {
         Ok(0) => {
-            let store_has_data = store
-                .list_all(None)
+            rebuild_index = store
+                .list_page(None, None, 1)
                 .map(|v| !v.is_empty())
                 .unwrap_or(false);
-            if store_has_data {
-                tracing::info!("Search index is empty; rebuilding from resource store...");
-                match perform_reindex(&store, &index, &search_param_registry) {
-                    Ok(s) => tracing::info!(
-                        "Auto-reindex complete: {} resources, {} entries",
-                        s.resources_indexed,
-                        s.entries_written
+        }
+        Ok(_) if interrupted_reindex => {}
+        Ok(n) => {
+            tracing::info!("Search index has {} entries", n);
+            // Only separate databases can drift apart: otherwise every write
+            // commits its index entries with it, and a full scan buys nothing.
+            if !config.storage.atomic_index_writes() {
+                match check_consistency(&*store, &*index, &search_param_registry) {
+                    Ok(r) if r.reindexed + r.removed > 0 => tracing::warn!(
+                        "Search index had drifted from the resource store: reindexed {} of {} resources, \
+                         removed the entries of {} no longer stored",
+                        r.reindexed,
+                        r.checked,
+                        r.removed
                     ),
-                    Err(e) => tracing::error!("Auto-reindex failed: {}", e),
+                    Ok(r) => tracing::info!("Search index is consistent with {} stored resources", r.checked),
+                    Err(e) => tracing::warn!("Search index consistency check failed: {}", e),
                 }
             }
+            // `_total=estimate` works from these; they aren't kept current on
+            // every write, so start from fresh ones.
+            if let Err(e) = index.refresh_statistics() {
+                tracing::warn!("Failed to refresh search index statistics: {}", e);
+            }
         }
-        Ok(n) => tracing::info!("Search index has {} entries", n),
         Err(e) => tracing::warn!("Failed to query search index size: {}", e),
     }
 
@@ -137,8 +190,8 @@ async fn main() {
         audit: Arc::new(Mutex::new(audit_log)),
         config: config.clone(),
         profile_registry,
-        terminology_registry: TerminologyRegistry::new(),
-        search_param_registry,
+        terminology_registry,
+        search_param_registry: std::sync::RwLock::new(search_param_registry),
         compartment_def: CompartmentDef::patient_compartment(),
         jwk_cache: tokio::sync::RwLock::new(sazare_server::auth::JwkCache::new()),
         plugin_names,
@@ -150,6 +203,13 @@ async fn main() {
         seen_jti: std::sync::Mutex::new(std::collections::HashMap::new()),
     });
 
+    if rebuild_index {
+        rebuild_empty_index(&state).await;
+    } else {
+        resume_reindex(&state).await;
+    }
+    sazare_server::handlers::expunge::spawn_retention(state.clone());
+
     // `--demo`: load the curated sample dataset so a fresh run has something to
     // explore immediately.
     if want_demo {
@@ -306,3 +366,44 @@ async fn shutdown_signal() {
         _ = terminate => tracing::info!("Received SIGTERM, shutting down..."),
     }
 }
+
+/// The resource store and search index, as `AppState` holds them.
+type Storage = (Box<dyn ResourceStore>, Box<dyn SearchBackend>);
+
+/// Open the resource store and search index of the configured backend.
+fn open_storage(config: &ServerConfig) -> Result<Storage, String> {
+    match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let store = SqliteStore::open(config.resources_db_path())
+                .map_err(|e| format!("Failed to open resource store: {e}"))?;
+            // With `storage.single_database` the index lives in the resources file,
+            // so each write commits with its index entries.
+            let index = if config.storage.single_database {
+                SearchIndex::open_shared(config.resources_db_path())
+            } else {
+                SearchIndex::open(config.search_index_db_path())
+            };
+            let index = index.map_err(|e| format!("Failed to open search index: {e}"))?;
+            Ok((Box::new(store), Box::new(index)))
+        }
+        StorageBackend::Postgres => open_postgres(config),
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres(config: &ServerConfig) -> Result<Storage, String> {
+    let url = config
+        .storage
+        .postgres_url
+        .as_deref()
+        .ok_or("storage.backend is postgres but storage.postgres_url is not set")?;
+    let store = sazare_store::PgStore::open(url).map_err(|e| format!("Failed to open resource store: {e}"))?;
+    let index = sazare_store::PgSearchIndex::open(url).map_err(|e| format!("Failed to open search index: {e}"))?;
+    tracing::info!("Using PostgreSQL storage");
+    Ok((Box::new(store), Box::new(index)))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres(_config: &ServerConfig) -> Result<Storage, String> {
+    Err("storage.backend is postgres, but this server was built without the `postgres` feature".to_string())
+}
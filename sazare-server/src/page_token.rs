@@ -0,0 +1,123 @@
+//! Signed `_page_token` cursors for searchset paging links.
+//!
+//! A `next`/`previous` link carries the keyset position of the page boundary
+//! (the row's `_sort` values and id, see [`PageCursor`]), the direction, and the
+//! snapshot time of the first page. The token is
+//! `base64url(payload) "." base64url(HMAC-SHA256(payload, query))`: the MAC
+//! also covers the search it was issued for, so a token can be neither edited
+//! nor replayed against a different query. The key is
+//! `server.page_token_secret`, or a per-process random key when unset (links
+//! then stop working across restarts and between replicas).
+
+use std::sync::LazyLock;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sazare_store::sqlite_index::{PageCursor, PageStart};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    /// Boundary row.
+    c: PageCursor,
+    /// `true` for rows before the cursor (a `previous` link).
+    b: bool,
+    /// Snapshot time, epoch microseconds.
+    t: i64,
+}
+
+fn signing_key(secret: Option<&str>) -> hmac::Key {
+    static PROCESS_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("system random source unavailable");
+        hmac::Key::new(hmac::HMAC_SHA256, &bytes)
+    });
+    match secret {
+        Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        None => PROCESS_KEY.clone(),
+    }
+}
+
+fn signed_message(payload: &str, query: &str) -> Vec<u8> {
+    format!("{payload}\n{query}").into_bytes()
+}
+
+/// Encode a token resuming `query` after the `cursor` row, or before it when
+/// `backward`.
+pub fn encode(
+    secret: Option<&str>,
+    query: &str,
+    cursor: &PageCursor,
+    backward: bool,
+    snapshot: DateTime<Utc>,
+) -> String {
+    let payload = Payload { c: cursor.clone(), b: backward, t: snapshot.timestamp_micros() };
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&payload).unwrap_or_default());
+    let tag = hmac::sign(&signing_key(secret), &signed_message(&payload, query));
+    format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+/// Verify and decode a token issued for `query`, returning where the page
+/// starts and the snapshot time. Fails on any malformed or tampered token.
+pub fn decode(
+    secret: Option<&str>,
+    query: &str,
+    token: &str,
+) -> Result<(PageStart, DateTime<Utc>), String> {
+    let invalid = || "Invalid _page_token: it was altered or belongs to a different search".to_string();
+    let (payload, tag) = token.split_once('.').ok_or_else(invalid)?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid())?;
+    hmac::verify(&signing_key(secret), &signed_message(payload, query), &tag)
+        .map_err(|_| invalid())?;
+
+    let bytes = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+    let payload: Payload = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+    let snapshot = DateTime::from_timestamp_micros(payload.t).ok_or_else(invalid)?;
+    let start = if payload.b {
+        PageStart::Before(payload.c)
+    } else {
+        PageStart::After(payload.c)
+    };
+    Ok((start, snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor() -> PageCursor {
+        PageCursor { keys: vec![serde_json::json!(1_700_000_000_000_000i64), serde_json::Value::Null], id: "o1".into() }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let now = DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+        let token = encode(Some("s3cret"), "Observation?status=final", &cursor(), true, now);
+        let (start, snapshot) = decode(Some("s3cret"), "Observation?status=final", &token).unwrap();
+        assert_eq!(start, PageStart::Before(cursor()));
+        assert_eq!(snapshot, now);
+    }
+
+    #[test]
+    fn test_rejects_tampering_and_other_queries() {
+        let now = Utc::now();
+        let token = encode(None, "Observation?status=final", &cursor(), false, now);
+        assert!(decode(None, "Observation?status=amended", &token).is_err());
+        assert!(decode(Some("other"), "Observation?status=final", &token).is_err());
+
+        // Re-encode the payload with a different cursor id under the old tag.
+        let (_, tag) = token.split_once('.').unwrap();
+        let forged = Payload {
+            c: PageCursor { id: "o9".into(), ..cursor() },
+            b: false,
+            t: now.timestamp_micros(),
+        };
+        let forged = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert!(decode(None, "Observation?status=final", &format!("{forged}.{tag}")).is_err());
+        assert!(decode(None, "Observation?status=final", "garbage").is_err());
+    }
+}
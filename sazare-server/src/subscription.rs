@@ -203,7 +203,7 @@ impl SubscriptionManager {
             let query = SearchQuery::parse(criteria_query).map_err(|e| e.to_string())?;
 
             let index = state.index.lock().await;
-            let executor = SearchExecutor::new(&state.store, &index);
+            let executor = SearchExecutor::new(&*state.store, &**index);
             let ids = executor.search(resource_type, &query)?;
 
             if !ids.contains(&resource_id.to_string()) {
@@ -357,21 +357,17 @@ impl SubscriptionManager {
             if let Ok(bytes) = serde_json::to_vec(&sub) {
                 // Compare-and-swap so a concurrent user edit of the Subscription
                 // isn't silently clobbered by this background status flip.
-                let wrote = state
-                    .store
-                    .put_with_version_cas("Subscription", id, Some(&current), &new_ver, &bytes)
-                    .unwrap_or(false);
-                // Keep the search index in step (status is an indexed param).
-                if wrote {
-                    let index = state.index.lock().await;
-                    crate::handlers::update_search_index(
-                        &index,
-                        &state.search_param_registry,
-                        "Subscription",
-                        id,
-                        &sub,
-                    );
-                }
+                // The search index is kept in step (status is an indexed param).
+                let _ = crate::handlers::store_resource_cas(
+                    state,
+                    "Subscription",
+                    id,
+                    Some(&current),
+                    &new_ver,
+                    &bytes,
+                    &sub,
+                )
+                .await;
             }
         }
     }
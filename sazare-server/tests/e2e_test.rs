@@ -5,8 +5,8 @@
 
 use sazare_core::validation::{ProfileRegistry, TerminologyRegistry};
 use sazare_core::{CompartmentDef, SearchParamRegistry};
-use sazare_server::{build_router, config::ServerConfig, AppState};
-use sazare_store::{AuditLog, SearchIndex, SqliteStore};
+use sazare_server::{build_router, config::{ServerConfig, StorageBackend}, AppState};
+use sazare_store::{AuditLog, ResourceStore, SearchBackend, SearchIndex, SqliteStore};
 use serde_json::{json, Value};
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -15,20 +15,44 @@ use tokio::sync::Mutex;
 
 /// Start a test server on a random port, returns (base_url, _temp_dir)
 async fn start_test_server() -> (String, TempDir) {
+    start_test_server_with(ServerConfig::default()).await
+}
+
+/// Start a test server with the given configuration.
+async fn start_test_server_with(config: ServerConfig) -> (String, TempDir) {
     let temp_dir = TempDir::new().unwrap();
 
-    let store = SqliteStore::open(temp_dir.path().join("resources.sqlite")).unwrap();
-    let index = SearchIndex::open(temp_dir.path().join("search_index.sqlite")).unwrap();
+    let (store, index): (Box<dyn ResourceStore>, Box<dyn SearchBackend>) = match config.storage.backend {
+        StorageBackend::Sqlite => {
+            let store = SqliteStore::open(temp_dir.path().join("resources.sqlite")).unwrap();
+            let index = if config.storage.single_database {
+                SearchIndex::open_shared(temp_dir.path().join("resources.sqlite")).unwrap()
+            } else {
+                SearchIndex::open(temp_dir.path().join("search_index.sqlite")).unwrap()
+            };
+            (Box::new(store), Box::new(index))
+        }
+        #[cfg(feature = "postgres")]
+        StorageBackend::Postgres => {
+            let url = config.storage.postgres_url.as_deref().unwrap();
+            (
+                Box::new(sazare_store::PgStore::open(url).unwrap()),
+                Box::new(sazare_store::PgSearchIndex::open(url).unwrap()),
+            )
+        }
+        #[cfg(not(feature = "postgres"))]
+        StorageBackend::Postgres => panic!("built without the postgres feature"),
+    };
     let audit = AuditLog::open(temp_dir.path().join("audit.sqlite")).unwrap();
 
     let state = Arc::new(AppState {
         store,
         index: Mutex::new(index),
         audit: Arc::new(Mutex::new(audit)),
-        config: ServerConfig::default(),
+        config,
         profile_registry: ProfileRegistry::new(),
         terminology_registry: TerminologyRegistry::new(),
-        search_param_registry: SearchParamRegistry::new(),
+        search_param_registry: std::sync::RwLock::new(SearchParamRegistry::new()),
         compartment_def: CompartmentDef::patient_compartment(),
         jwk_cache: tokio::sync::RwLock::new(sazare_server::auth::JwkCache::new()),
         plugin_names: Vec::new(),
@@ -81,24 +105,26 @@ async fn test_webhook_task_completed_fires() {
 
     // A sazare server with webhooks enabled, pointing at the sink.
     let temp_dir = TempDir::new().unwrap();
-    let mut config = ServerConfig::default();
-    config.webhook = WebhookSettings {
-        enabled: true,
-        endpoints: vec![WebhookEndpoint {
-            url: sink_url,
-            events: vec!["TaskCompleted".to_string()],
-            headers: Default::default(),
-        }],
+    let config = ServerConfig {
+        webhook: WebhookSettings {
+            enabled: true,
+            endpoints: vec![WebhookEndpoint {
+                url: sink_url,
+                events: vec!["TaskCompleted".to_string()],
+                headers: Default::default(),
+            }],
+        },
+        ..Default::default()
     };
     let webhook = Arc::new(sazare_server::webhook::WebhookManager::new(config.webhook.clone()));
     let state = Arc::new(AppState {
-        store: SqliteStore::open(temp_dir.path().join("r.sqlite")).unwrap(),
-        index: Mutex::new(SearchIndex::open(temp_dir.path().join("i.sqlite")).unwrap()),
+        store: Box::new(SqliteStore::open(temp_dir.path().join("r.sqlite")).unwrap()),
+        index: Mutex::new(Box::new(SearchIndex::open(temp_dir.path().join("i.sqlite")).unwrap())),
         audit: Arc::new(Mutex::new(AuditLog::open(temp_dir.path().join("a.sqlite")).unwrap())),
         config,
         profile_registry: ProfileRegistry::new(),
         terminology_registry: TerminologyRegistry::new(),
-        search_param_registry: SearchParamRegistry::new(),
+        search_param_registry: std::sync::RwLock::new(SearchParamRegistry::new()),
         compartment_def: CompartmentDef::patient_compartment(),
         jwk_cache: tokio::sync::RwLock::new(sazare_server::auth::JwkCache::new()),
         plugin_names: Vec::new(),
@@ -402,13 +428,13 @@ async fn test_smart_backend_services_token_flow() {
     });
 
     let state = Arc::new(AppState {
-        store: SqliteStore::open(temp_dir.path().join("r.sqlite")).unwrap(),
-        index: Mutex::new(SearchIndex::open(temp_dir.path().join("i.sqlite")).unwrap()),
+        store: Box::new(SqliteStore::open(temp_dir.path().join("r.sqlite")).unwrap()),
+        index: Mutex::new(Box::new(SearchIndex::open(temp_dir.path().join("i.sqlite")).unwrap())),
         audit: Arc::new(Mutex::new(AuditLog::open(temp_dir.path().join("a.sqlite")).unwrap())),
         config,
         profile_registry: ProfileRegistry::new(),
         terminology_registry: TerminologyRegistry::new(),
-        search_param_registry: SearchParamRegistry::new(),
+        search_param_registry: std::sync::RwLock::new(SearchParamRegistry::new()),
         compartment_def: CompartmentDef::patient_compartment(),
         jwk_cache: tokio::sync::RwLock::new(sazare_server::auth::JwkCache::new()),
         plugin_names: Vec::new(),
@@ -1112,6 +1138,30 @@ async fn test_search_include_and_revinclude() {
         .collect();
     assert!(types.contains(&"Patient"), "_revinclude result has the Patient");
     assert!(types.contains(&"Observation"), "_revinclude should pull in the referencing Observation");
+
+    // `*:subject`: every type whose `subject` references the Patient.
+    create(
+        &client,
+        &base_url,
+        "Condition",
+        &json!({"resourceType": "Condition", "subject": {"reference": format!("Patient/{}", pid)}}),
+    )
+    .await;
+    let resp = client
+        .get(format!("{}/Patient?family=Incl&_revinclude=*:subject", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let bundle: Value = resp.json().await.unwrap();
+    let mut types: Vec<&str> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["resource"]["resourceType"].as_str().unwrap())
+        .collect();
+    types.sort();
+    assert_eq!(types, vec!["Condition", "Observation", "Patient"]);
 }
 
 #[tokio::test]
@@ -1492,30 +1542,1684 @@ async fn test_search_by_profile() {
 }
 
 #[tokio::test]
-async fn test_metadata_mode_terminology() {
+async fn test_search_sort_and_unsupported_key_warning() {
     let (base_url, _dir) = start_test_server().await;
     let client = reqwest::Client::new();
 
-    // Default and ?mode=full return a CapabilityStatement.
-    for url in [format!("{base_url}/metadata"), format!("{base_url}/metadata?mode=full")] {
-        let body: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
-        assert_eq!(body["resourceType"], "CapabilityStatement", "{url}");
+    let mut ids = Vec::new();
+    for family in ["Suzuki", "Abe", "Tanaka"] {
+        ids.push(create(&client, &base_url, "Patient", &json!({
+            "resourceType": "Patient",
+            "name": [{"family": family}]
+        })).await);
     }
 
-    // ?mode=terminology returns a *different* resource type.
-    let term: Value = client
-        .get(format!("{base_url}/metadata?mode=terminology"))
+    let bundle: Value = client
+        .get(format!("{base_url}/Patient?_sort=-family"))
         .send()
         .await
         .unwrap()
         .json()
         .await
         .unwrap();
-    assert_eq!(term["resourceType"], "TerminologyCapabilities");
-    assert_eq!(term["kind"], "instance");
-    assert_eq!(term["status"], "active");
-    assert!(term["date"].is_string());
-    // Not a terminology server: no operations are declared.
-    assert!(term.get("validateCode").is_none());
-    assert!(term.get("expansion").is_none());
+    let order: Vec<&str> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["resource"]["name"][0]["family"].as_str().unwrap())
+        .collect();
+    assert_eq!(order, vec!["Tanaka", "Suzuki", "Abe"]);
+
+    // A key the server can't sort on (not a Patient param here) doesn't fail
+    // the search: the Bundle carries a warning outcome entry naming it.
+    let resp = client
+        .get(format!("{base_url}/Patient?_sort=organization,family"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let bundle: Value = resp.json().await.unwrap();
+    let entries = bundle["entry"].as_array().unwrap();
+    let families: Vec<&str> = entries
+        .iter()
+        .filter(|e| e["search"]["mode"] == "match")
+        .map(|e| e["resource"]["name"][0]["family"].as_str().unwrap())
+        .collect();
+    assert_eq!(families, vec!["Abe", "Suzuki", "Tanaka"]);
+    let outcome = entries
+        .iter()
+        .find(|e| e["search"]["mode"] == "outcome")
+        .expect("warning outcome entry");
+    assert_eq!(outcome["resource"]["issue"][0]["severity"], "warning");
+    assert!(outcome["resource"]["issue"][0]["diagnostics"]
+        .as_str()
+        .unwrap()
+        .contains("organization"));
+}
+
+#[tokio::test]
+async fn test_search_value_quantity() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let mut ids = Vec::new();
+    for value in [4.9, 7.2] {
+        ids.push(create(&client, &base_url, "Observation", &json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "15074-8"}]},
+            "valueQuantity": {
+                "value": value,
+                "unit": "mmol/l",
+                "system": "http://unitsofmeasure.org",
+                "code": "mmol/L"
+            }
+        })).await);
+    }
+
+    let resp = client
+        .get(format!("{base_url}/Observation"))
+        .query(&[("value-quantity", "gt5.4|http://unitsofmeasure.org|mmol/L")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let bundle: Value = resp.json().await.unwrap();
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["id"], ids[1]);
+
+    // Without a system the unit is matched exactly: a different code doesn't
+    // match, even though the number does.
+    let bundle: Value = client
+        .get(format!("{base_url}/Observation"))
+        .query(&[("value-quantity", "gt5.4||mg/dL")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(bundle["total"], 0);
+
+    // A UCUM query in mass units is converted through glucose's molar mass:
+    // 7.2 mmol/L is ~130 mg/dL, 4.9 mmol/L ~88 mg/dL.
+    let bundle: Value = client
+        .get(format!("{base_url}/Observation"))
+        .query(&[("value-quantity", "gt100|http://unitsofmeasure.org|mg/dL")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["id"], ids[1]);
+}
+
+#[tokio::test]
+async fn test_search_value_set_by_url() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let url = "http://jpfhir.jp/fhir/core/ValueSet/JP_Gender_VS";
+    let vs = create(&client, &base_url, "ValueSet", &json!({
+        "resourceType": "ValueSet", "url": url, "status": "active"
+    })).await;
+    create(&client, &base_url, "ValueSet", &json!({
+        "resourceType": "ValueSet", "url": "http://example.org/ValueSet/other", "status": "active"
+    })).await;
+
+    let search = |query: Vec<(&'static str, String)>| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let bundle: Value = client
+                .get(format!("{base_url}/ValueSet"))
+                .query(&query)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            bundle
+        }
+    };
+
+    let bundle = search(vec![("url", url.to_string())]).await;
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["id"], vs);
+
+    // Uri matching is exact: a prefix alone doesn't match without `:below`.
+    let bundle = search(vec![("url", "http://jpfhir.jp/fhir/core".to_string())]).await;
+    assert_eq!(bundle["total"], 0);
+    let bundle = search(vec![("url:below", "http://jpfhir.jp/fhir/core".to_string())]).await;
+    assert_eq!(bundle["total"], 1);
+}
+
+#[tokio::test]
+async fn test_search_page_token_links() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let mut ids = Vec::new();
+    for family in ["Abe", "Baba", "Chiba"] {
+        ids.push(create(&client, &base_url, "Patient", &json!({
+            "resourceType": "Patient",
+            "name": [{"family": family}]
+        })).await);
+    }
+    let get = |url: String| {
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap() }
+    };
+    let link = |bundle: &Value, relation: &str| {
+        bundle["link"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["relation"] == relation)
+            .map(|l| l["url"].as_str().unwrap().to_string())
+    };
+    let families = |bundle: &Value| -> Vec<String> {
+        bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["resource"]["name"][0]["family"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let first: Value = get(format!("{base_url}/Patient?_sort=family&_count=2"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(families(&first), vec!["Abe", "Baba"]);
+    let next = link(&first, "next").unwrap();
+    assert!(next.contains("_page_token="));
+    assert!(link(&first, "previous").is_none());
+
+    // A patient sorting before the cursor, written between fetches, does not
+    // shift the next page, and resources created after the first page's
+    // snapshot stay out of it. One updated since, not yet returned, stays in.
+    for family in ["Aoki", "Chikura"] {
+        create(&client, &base_url, "Patient", &json!({
+            "resourceType": "Patient",
+            "name": [{"family": family}]
+        })).await;
+    }
+    let resp = client
+        .put(format!("{base_url}/Patient/{}", ids[2]))
+        .json(&json!({"resourceType": "Patient", "id": ids[2], "name": [{"family": "Chiba", "given": ["Chie"]}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let second: Value = get(next.clone()).await.json().await.unwrap();
+    assert_eq!(families(&second), vec!["Chiba"]);
+    assert_eq!(second["entry"][0]["resource"]["name"][0]["given"][0], "Chie");
+    assert_eq!(second["total"], 3);
+    assert!(link(&second, "next").is_none());
+
+    let previous: Value = get(link(&second, "previous").unwrap()).await.json().await.unwrap();
+    assert_eq!(families(&previous), vec!["Abe", "Baba"]);
+
+    // Tampered tokens, or tokens replayed against another search, are rejected.
+    let tampered = next.replace("_page_token=", "_page_token=x");
+    assert_eq!(get(tampered).await.status(), 400);
+    let token = next.split("_page_token=").nth(1).unwrap();
+    let other = get(format!("{base_url}/Patient?_sort=-family&_count=2&_page_token={token}")).await;
+    assert_eq!(other.status(), 400);
+    let outcome: Value = other.json().await.unwrap();
+    assert_eq!(outcome["resourceType"], "OperationOutcome");
+}
+
+#[tokio::test]
+async fn test_type_and_system_history() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let a = create(&client, &base_url, "Patient", &json!({"resourceType": "Patient", "name": [{"family": "Abe"}]})).await;
+    let obs = create(&client, &base_url, "Observation", &json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": {"text": "x"}
+    })).await;
+    let b = create(&client, &base_url, "Patient", &json!({"resourceType": "Patient", "name": [{"family": "Baba"}]})).await;
+    let resp = client
+        .put(format!("{base_url}/Patient/{a}"))
+        .json(&json!({"resourceType": "Patient", "id": a, "name": [{"family": "Abe", "given": ["Aya"]}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let b_read: Value = client.get(format!("{base_url}/Patient/{b}")).send().await.unwrap().json().await.unwrap();
+
+    let get = |url: String| {
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap() }
+    };
+    let entries = |bundle: &Value| -> Vec<String> {
+        bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| format!("{}/{}", e["request"]["url"].as_str().unwrap(), e["response"]["etag"].as_str().unwrap()))
+            .collect()
+    };
+    let next = |bundle: &Value| {
+        bundle["link"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["relation"] == "next")
+            .map(|l| l["url"].as_str().unwrap().to_string())
+    };
+
+    // Newest first, across types or within one.
+    let all: Value = get(format!("{base_url}/_history")).await.json().await.unwrap();
+    assert_eq!(all["type"], "history");
+    assert_eq!(all["total"], 4);
+    assert_eq!(entries(&all), vec![
+        format!("Patient/{a}/W/\"2\""),
+        format!("Patient/{b}/W/\"1\""),
+        format!("Observation/{obs}/W/\"1\""),
+        format!("Patient/{a}/W/\"1\""),
+    ]);
+    assert_eq!(all["entry"][0]["request"]["method"], "PUT");
+    let patients: Value = get(format!("{base_url}/Patient/_history")).await.json().await.unwrap();
+    assert_eq!(entries(&patients).len(), 3);
+    assert!(entries(&patients).iter().all(|e| e.starts_with("Patient/")));
+
+    // `_count` pages through it with `next` links.
+    let first: Value = get(format!("{base_url}/_history?_count=3")).await.json().await.unwrap();
+    assert_eq!(entries(&first), entries(&all)[..3]);
+    assert!(first.get("total").is_none());
+    let second: Value = get(next(&first).unwrap()).await.json().await.unwrap();
+    assert_eq!(entries(&second), entries(&all)[3..]);
+    assert!(next(&second).is_none());
+    let instance: Value = get(format!("{base_url}/Patient/{a}/_history?_count=1")).await.json().await.unwrap();
+    assert_eq!(entries(&instance), vec![format!("Patient/{a}/W/\"2\"")]);
+    assert!(next(&instance).is_some());
+    // `_count=0` asks for no entries, and a huge one for at most a full page.
+    let empty: Value = get(format!("{base_url}/_history?_count=0")).await.json().await.unwrap();
+    assert_eq!(empty["type"], "history");
+    assert!(empty.get("entry").is_none());
+    assert!(next(&empty).is_none());
+    let huge: Value = get(format!("{base_url}/_history?_count={}", usize::MAX)).await.json().await.unwrap();
+    assert_eq!(entries(&huge), entries(&all));
+    assert!(huge["link"][0]["url"].as_str().unwrap().ends_with("_count=1000"));
+
+    // `_since` keeps what changed from then on; `_at` what was current then.
+    let since = b_read["meta"]["lastUpdated"].as_str().unwrap();
+    let changed: Value = get(format!("{base_url}/Patient/_history?_since={}", since.replace('+', "%2B"))).await.json().await.unwrap();
+    assert_eq!(entries(&changed), vec![format!("Patient/{a}/W/\"2\""), format!("Patient/{b}/W/\"1\"")]);
+    let then: Value = get(format!("{base_url}/Patient/_history?_at={}", since.replace('+', "%2B"))).await.json().await.unwrap();
+    assert_eq!(entries(&then), vec![format!("Patient/{b}/W/\"1\""), format!("Patient/{a}/W/\"1\"")]);
+    let none: Value = get(format!("{base_url}/_history?_since=2999-01-01")).await.json().await.unwrap();
+    assert_eq!(none["total"], 0);
+
+    // A token is bound to the history and filters it was issued for.
+    let token = next(&first).unwrap().split("_page_token=").nth(1).unwrap().to_string();
+    assert_eq!(get(format!("{base_url}/Patient/_history?_count=3&_page_token={token}")).await.status(), 400);
+    assert_eq!(get(format!("{base_url}/_history?_since=yesterday")).await.status(), 400);
+}
+
+#[tokio::test]
+async fn test_delete_leaves_tombstone_in_history() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let patient = json!({"resourceType": "Patient", "name": [{"family": "Abe"}]});
+    let id = create(&client, &base_url, "Patient", &patient).await;
+    let resp = client.put(format!("{base_url}/Patient/{id}")).json(&patient).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = client.delete(format!("{base_url}/Patient/{id}")).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+
+    let get = |url: String| {
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap() }
+    };
+    let history: Value = get(format!("{base_url}/Patient/{id}/_history")).await.json().await.unwrap();
+    let methods: Vec<_> = history["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["request"]["method"].as_str().unwrap(), e["response"]["etag"].as_str().unwrap()))
+        .collect();
+    assert_eq!(methods, vec![("DELETE", "W/\"3\""), ("PUT", "W/\"2\""), ("POST", "W/\"1\"")]);
+    assert!(history["entry"][0].get("resource").is_none());
+    assert!(history["entry"][0]["response"]["lastModified"].as_str().is_some_and(|t| !t.is_empty()));
+
+    // The tombstone is Gone; versions before it still read.
+    assert_eq!(get(format!("{base_url}/Patient/{id}/_history/3")).await.status(), 410);
+    assert_eq!(get(format!("{base_url}/Patient/{id}/_history/2")).await.status(), 200);
+    assert_eq!(get(format!("{base_url}/Patient/{id}/_history/9")).await.status(), 404);
+
+    // Recreating the id carries on past the tombstone, keeping the old versions.
+    let resp = client.put(format!("{base_url}/Patient/{id}")).json(&patient).send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    let recreated: Value = resp.json().await.unwrap();
+    assert_eq!(recreated["meta"]["versionId"], "4");
+    assert_eq!(get(format!("{base_url}/Patient/{id}/_history/1")).await.status(), 200);
+
+    // A transaction's DELETE leaves one too, and it shows in system history.
+    let bundle = json!({
+        "resourceType": "Bundle",
+        "type": "transaction",
+        "entry": [{"request": {"method": "DELETE", "url": format!("Patient/{id}")}}]
+    });
+    let resp = client.post(&base_url).json(&bundle).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let all: Value = get(format!("{base_url}/_history?_count=1")).await.json().await.unwrap();
+    assert_eq!(all["entry"][0]["request"]["method"], "DELETE");
+    assert_eq!(all["entry"][0]["response"]["etag"], "W/\"5\"");
+}
+
+#[tokio::test]
+async fn test_expunge() {
+    let (base_url, dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let patient = json!({"resourceType": "Patient", "name": [{"family": "Abe"}]});
+    let kept = create(&client, &base_url, "Patient", &patient).await;
+    let resp = client.put(format!("{base_url}/Patient/{kept}")).json(&patient).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let deleted = create(&client, &base_url, "Patient", &patient).await;
+    let resp = client.delete(format!("{base_url}/Patient/{deleted}")).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let observation = create(&client, &base_url, "Observation", &json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": {"text": "x"}
+    })).await;
+
+    let expunge = |path: String, body: Option<Value>| {
+        let client = client.clone();
+        async move {
+            let request = client.post(path);
+            let request = match body {
+                Some(body) => request.json(&body),
+                None => request,
+            };
+            request.send().await.unwrap()
+        }
+    };
+    let option = |name: &str| json!({"resourceType": "Parameters", "parameter": [{"name": name, "valueBoolean": true}]});
+    let count = |parameters: &Value| parameters["parameter"][0]["valueInteger"].as_u64().unwrap();
+
+    // Previous versions of one resource: its current one stays.
+    let resp = expunge(format!("{base_url}/Patient/{kept}/$expunge"), Some(option("expungePreviousVersions"))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(count(&resp.json().await.unwrap()), 1);
+    let history: Value = client.get(format!("{base_url}/Patient/{kept}/_history")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(history["entry"].as_array().unwrap().len(), 1);
+    assert_eq!(client.get(format!("{base_url}/Patient/{kept}")).send().await.unwrap().status(), 200);
+
+    // Deleted resources of a type, from the query string: no trace left.
+    let resp = expunge(format!("{base_url}/Patient/$expunge?expungeDeletedResources=true"), None).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(count(&resp.json().await.unwrap()), 2);
+    assert_eq!(client.get(format!("{base_url}/Patient/{deleted}")).send().await.unwrap().status(), 404);
+    assert_eq!(client.get(format!("{base_url}/Patient/{deleted}/_history/1")).send().await.unwrap().status(), 404);
+
+    // Everything: the current resource and its index entries go too.
+    let resp = expunge(format!("{base_url}/Observation/{observation}/$expunge"), Some(option("expungeEverything"))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(client.get(format!("{base_url}/Observation/{observation}")).send().await.unwrap().status(), 404);
+    let search: Value = client.get(format!("{base_url}/Observation?status=final")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(search["total"], 0);
+
+    // At type level, every resource's index entries go.
+    let mut observations = Vec::new();
+    for _ in 0..2 {
+        observations.push(create(&client, &base_url, "Observation", &json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"text": "x"}
+        })).await);
+    }
+    let resp = expunge(format!("{base_url}/Observation/$expunge"), Some(option("expungeEverything"))).await;
+    assert_eq!(resp.status(), 200);
+    let index = SearchIndex::open(dir.path().join("search_index.sqlite")).unwrap();
+    for id in &observations {
+        assert_eq!(index.indexed_last_updated("Observation", id).unwrap(), None);
+    }
+
+    assert_eq!(expunge(format!("{base_url}/$expunge"), None).await.status(), 400);
+    assert_eq!(expunge(format!("{base_url}/$expunge"), Some(option("expungeSomething"))).await.status(), 400);
+
+    // Each run is audited.
+    let audit = AuditLog::open(dir.path().join("audit.sqlite")).unwrap();
+    let mut expunges = 0;
+    for _ in 0..50 {
+        expunges = audit.recent_entries(100).unwrap().iter().filter(|e| e.1 == "expunge").count();
+        if expunges == 4 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(expunges, 4);
+}
+
+#[tokio::test]
+async fn test_expunge_is_admin_only() {
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use sazare_server::config::{ApiKey, JwtSettings};
+
+    let mut config = ServerConfig::default();
+    config.auth.enabled = true;
+    config.auth.api_keys = vec![ApiKey { name: "ops".into(), key: "ops-key".into() }];
+    config.auth.jwt = Some(JwtSettings {
+        issuer: None,
+        audience: None,
+        secret: Some("expunge-test-secret".into()),
+        public_key_file: None,
+        jwk_url: None,
+    });
+    let (base_url, _dir) = start_test_server_with(config).await;
+    let client = reqwest::Client::new();
+    let token = |scope: &str| {
+        let claims = json!({"sub": "someone", "scope": scope, "exp": chrono::Utc::now().timestamp() + 300});
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(b"expunge-test-secret")).unwrap()
+    };
+    let url = format!("{base_url}/Patient/$expunge?expungeDeletedResources=true");
+
+    for scope in ["system/*.*", "user/Patient.write", "patient/*.write"] {
+        let resp = client.post(&url).bearer_auth(token(scope)).send().await.unwrap();
+        assert_eq!(resp.status(), 403, "{scope}");
+    }
+    let resp = client.post(&url).bearer_auth(token("system/$expunge.write")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = client.post(&url).bearer_auth("ops-key").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_conditional_read_and_delete() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let patient = json!({"resourceType": "Patient", "name": [{"family": "Ueda"}]});
+    let id = create(&client, &base_url, "Patient", &patient).await;
+    let url = format!("{base_url}/Patient/{id}");
+    let get = |url: String, header: &'static str, value: String| {
+        let client = client.clone();
+        async move { client.get(url).header(header, value).send().await.unwrap() }
+    };
+
+    // If-None-Match, weak or strong, on read and vread.
+    let resp = get(url.clone(), "If-None-Match", "W/\"1\"".to_string()).await;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers()["etag"], "W/\"1\"");
+    assert!(resp.text().await.unwrap().is_empty());
+    assert_eq!(get(url.clone(), "If-None-Match", "W/\"2\"".to_string()).await.status(), 200);
+    assert_eq!(get(format!("{url}/_history/1"), "If-None-Match", "\"1\"".to_string()).await.status(), 304);
+
+    // If-Modified-Since: unchanged since Last-Modified, but not since an hour before.
+    let last_modified = client.get(&url).send().await.unwrap().headers()["last-modified"].to_str().unwrap().to_string();
+    assert_eq!(get(url.clone(), "If-Modified-Since", last_modified.clone()).await.status(), 304);
+    let earlier = (chrono::Utc::now() - chrono::Duration::hours(1)).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    assert_eq!(get(url.clone(), "If-Modified-Since", earlier).await.status(), 200);
+
+    // A search page has an ETag of its own, which an update changes.
+    let search = format!("{base_url}/Patient?family=Ueda");
+    let etag = client.get(&search).send().await.unwrap().headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(get(search.clone(), "If-None-Match", etag.clone()).await.status(), 304);
+    let resp = client.put(&url).json(&patient).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = get(search.clone(), "If-None-Match", etag.clone()).await;
+    assert_eq!(resp.status(), 200);
+    assert_ne!(resp.headers()["etag"].to_str().unwrap(), etag);
+
+    // If-Match on DELETE: only the current version goes.
+    let resp = client.delete(&url).header("If-Match", "W/\"1\"").send().await.unwrap();
+    assert_eq!(resp.status(), 412);
+    assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+    let resp = client.delete(&url).header("If-Match", "W/\"2\"").send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = client.delete(&url).header("If-Match", "W/\"3\"").send().await.unwrap();
+    assert_eq!(resp.status(), 412);
+}
+
+#[tokio::test]
+async fn test_search_if_modified_since() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let patient = json!({"resourceType": "Patient", "name": [{"family": "Kato"}]});
+    create(&client, &base_url, "Patient", &patient).await;
+    let deleted = create(&client, &base_url, "Patient", &patient).await;
+    let search = format!("{base_url}/Patient?family=Kato");
+
+    let resp = client.get(&search).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let since = resp.headers()["last-modified"].to_str().unwrap().to_string();
+    let resp = client.get(&search).header("If-Modified-Since", &since).send().await.unwrap();
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers()["last-modified"].to_str().unwrap(), since);
+
+    // Deleting a match leaves the remaining entries' times as they were, but
+    // still moves the search's. An HTTP-date has whole seconds.
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let resp = client.delete(format!("{base_url}/Patient/{deleted}")).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = client.get(&search).header("If-Modified-Since", &since).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_ne!(resp.headers()["last-modified"].to_str().unwrap(), since);
+    let bundle: Value = resp.json().await.unwrap();
+    assert_eq!(bundle["total"], 1);
+}
+
+#[tokio::test]
+async fn test_search_total_modes() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    for status in ["final", "final", "amended"] {
+        create(&client, &base_url, "Observation", &json!({
+            "resourceType": "Observation",
+            "status": status,
+            "code": {"text": "x"}
+        })).await;
+    }
+    let search = |query: &'static str| {
+        let client = client.clone();
+        let url = format!("{base_url}/Observation?{query}");
+        async move { client.get(url).send().await.unwrap() }
+    };
+
+    let bundle: Value = search("status=final").await.json().await.unwrap();
+    assert_eq!(bundle["total"], 2);
+    let bundle: Value = search("status=final&_total=accurate").await.json().await.unwrap();
+    assert_eq!(bundle["total"], 2);
+    // No statistics have been gathered on this fresh index, so the estimate
+    // is the exact count.
+    let bundle: Value = search("status=final&_total=estimate").await.json().await.unwrap();
+    assert_eq!(bundle["total"], 2);
+    let bundle: Value = search("status=final&_total=none").await.json().await.unwrap();
+    assert!(bundle.get("total").is_none());
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 2);
+    assert_eq!(search("_total=sometimes").await.status(), 400);
+
+    // The server default applies when the client doesn't ask.
+    let mut config = ServerConfig::default();
+    config.search.default_total = sazare_core::TotalMode::None;
+    let (base_url, _dir) = start_test_server_with(config).await;
+    create(&client, &base_url, "Patient", &json!({"resourceType": "Patient"})).await;
+    let bundle: Value = client
+        .get(format!("{base_url}/Patient"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(bundle.get("total").is_none());
+    let bundle: Value = client
+        .get(format!("{base_url}/Patient?_total=accurate"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(bundle["total"], 1);
+}
+
+#[tokio::test]
+async fn test_search_contained_resources() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let rxnorm = |code: &str| json!({"coding": [{"system": "http://www.nlm.nih.gov/research/umls/rxnorm", "code": code}]});
+    let mr_id = create(&client, &base_url, "MedicationRequest", &json!({
+        "resourceType": "MedicationRequest",
+        "status": "active",
+        "intent": "order",
+        "contained": [{"resourceType": "Medication", "id": "med", "code": rxnorm("1049502")}],
+        "medicationReference": {"reference": "#med"},
+        "subject": {"reference": "Patient/p1"}
+    })).await;
+    create(&client, &base_url, "Medication", &json!({
+        "resourceType": "Medication",
+        "code": rxnorm("1049502")
+    })).await;
+    let search = |query: &str| {
+        let url = format!("{base_url}/Medication?{query}");
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap().json::<Value>().await.unwrap() }
+    };
+
+    let bundle = search("code=1049502").await;
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["resourceType"], "Medication");
+
+    // By default a contained match is returned inside its container.
+    let bundle = search("code=1049502&_contained=true").await;
+    assert_eq!(bundle["total"], 1);
+    let entry = &bundle["entry"][0];
+    assert_eq!(entry["resource"]["resourceType"], "MedicationRequest");
+    assert_eq!(entry["fullUrl"], format!("{base_url}/MedicationRequest/{mr_id}"));
+
+    let bundle = search("code=1049502&_contained=true&_containedType=contained").await;
+    let entry = &bundle["entry"][0];
+    assert_eq!(entry["resource"]["resourceType"], "Medication");
+    assert_eq!(entry["resource"]["id"], "med");
+    assert_eq!(entry["fullUrl"], format!("{base_url}/MedicationRequest/{mr_id}#med"));
+
+    let bundle = search("code=1049502&_contained=both").await;
+    assert_eq!(bundle["total"], 2);
+
+    // Updating the container re-indexes what it contains.
+    client
+        .put(format!("{base_url}/MedicationRequest/{mr_id}"))
+        .json(&json!({
+            "resourceType": "MedicationRequest",
+            "id": mr_id,
+            "status": "active",
+            "intent": "order",
+            "contained": [{"resourceType": "Medication", "id": "med", "code": rxnorm("197361")}],
+            "medicationReference": {"reference": "#med"},
+            "subject": {"reference": "Patient/p1"}
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(search("code=1049502&_contained=true").await["total"], 0);
+    assert_eq!(search("code=197361&_contained=true").await["total"], 1);
+
+    let resp = client
+        .get(format!("{base_url}/Medication?_contained=sometimes"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_text_and_content() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let condition = |narrative: &str, note: &str| {
+        json!({
+            "resourceType": "Condition",
+            "text": {"status": "generated", "div": format!("<div xmlns=\"http://www.w3.org/1999/xhtml\">{narrative}</div>")},
+            "subject": {"reference": "Patient/p1"},
+            "note": [{"text": note}]
+        })
+    };
+    let foot = create(&client, &base_url, "Condition", &condition("Diabetic foot ulcer", "Dressing changed daily")).await;
+    let chest = create(&client, &base_url, "Condition", &condition("Chest pain", "Sharp pain on exertion")).await;
+    let ids = |query: &str| {
+        let url = format!("{base_url}/Condition?{query}");
+        let client = client.clone();
+        async move {
+            let bundle: Value = client.get(url).send().await.unwrap().json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|entries| {
+                    entries.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect()
+                })
+                .unwrap_or_default();
+            ids.sort();
+            ids
+        }
+    };
+
+    assert_eq!(ids("_text=ulcer").await, vec![foot.clone()]);
+    assert!(ids("_text=exertion").await.is_empty());
+    assert_eq!(ids("_content=exertion").await, vec![chest.clone()]);
+    assert_eq!(ids("_content=%22sharp%20pain%22").await, vec![chest.clone()]);
+    assert_eq!(ids("_content=dress*").await, vec![foot.clone()]);
+    let mut both = vec![foot.clone(), chest.clone()];
+    both.sort();
+    assert_eq!(ids("_text=ulcer%20OR%20chest").await, both);
+
+    // Updates replace the indexed text.
+    let mut updated = condition("Healed ulcer", "Discharged");
+    updated["id"] = json!(foot);
+    client
+        .put(format!("{base_url}/Condition/{foot}"))
+        .json(&updated)
+        .send()
+        .await
+        .unwrap();
+    assert!(ids("_text=diabetic").await.is_empty());
+    assert_eq!(ids("_text=healed").await, vec![foot.clone()]);
+
+    let resp = client
+        .get(format!("{base_url}/Condition?_content=%22unterminated"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_token_modifiers() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let v2 = "http://terminology.hl7.org/CodeSystem/v2-0203";
+    let patient = create(&client, &base_url, "Patient", &json!({
+        "resourceType": "Patient",
+        "identifier": [{
+            "type": {"coding": [{"system": v2, "code": "MR"}]},
+            "system": "http://hospital.example/mrn", "value": "12345"
+        }]
+    }))
+    .await;
+    let observation = |code: &str, display: &str| {
+        json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": code, "display": display}]},
+            "subject": {"reference": format!("Patient/{patient}")}
+        })
+    };
+    let glucose = create(&client, &base_url, "Observation", &observation("2339-0", "Glucose [Mass/volume] in Blood")).await;
+    let weight = create(&client, &base_url, "Observation", &observation("29463-7", "Body weight")).await;
+    let search = |query: String| {
+        let client = client.clone();
+        let url = format!("{base_url}/{query}");
+        async move {
+            let resp = client.get(url).send().await.unwrap();
+            let status = resp.status().as_u16();
+            let bundle: Value = resp.json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|entries| {
+                    entries.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect()
+                })
+                .unwrap_or_default();
+            ids.sort();
+            (status, ids)
+        }
+    };
+    let mut both = vec![glucose.clone(), weight.clone()];
+    both.sort();
+
+    assert_eq!(search("Observation?code:text=glucose".into()).await, (200, vec![glucose.clone()]));
+    assert_eq!(search(format!("Patient?identifier:of-type={v2}|MR|12345")).await, (200, vec![patient.clone()]));
+    assert_eq!(search(format!("Patient?identifier:of-type={v2}|SS|12345")).await.1, Vec::<String>::new());
+    assert_eq!(search(format!("Observation?subject:Patient={patient}")).await.1, both);
+
+    let status_vs = "http://hl7.org/fhir/ValueSet/observation-status";
+    assert_eq!(search(format!("Observation?status:in={status_vs}")).await.1, both);
+    assert_eq!(search(format!("Observation?status:not-in={status_vs}")).await, (200, vec![]));
+
+    // Unknown terminology and unsupported modifiers are the client's error.
+    assert_eq!(search("Observation?status:in=http://example.org/no-such-vs".into()).await.0, 400);
+    assert_eq!(search("Observation?status:exact=final".into()).await.0, 400);
+    assert_eq!(search("Observation?date:above=2020".into()).await.0, 400);
+}
+
+#[tokio::test]
+async fn test_search_reference_identifier() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let id = create(&client, &base_url, "Observation", &json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": {"coding": [{"system": "http://loinc.org", "code": "8310-5"}]},
+        "subject": {"identifier": {"system": "http://hospital.org/mrn", "value": "12345"}}
+    }))
+    .await;
+
+    for query in ["subject:identifier=http://hospital.org/mrn|12345", "patient:identifier=12345"] {
+        let bundle: Value = client
+            .get(format!("{base_url}/Observation?{query}"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(bundle["total"], 1, "{query}");
+        assert_eq!(bundle["entry"][0]["resource"]["id"], id.as_str());
+    }
+    let bundle: Value = client
+        .get(format!("{base_url}/Observation?subject:identifier=http://hospital.org/mrn|99999"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(bundle["total"], 0);
+}
+
+#[tokio::test]
+async fn test_search_has_nested_and_prefixed() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mut patients = Vec::new();
+    let mut observations = Vec::new();
+    for date in ["2023-06-01", "2024-03-01"] {
+        let patient = create(&client, &base_url, "Patient", &json!({"resourceType": "Patient"})).await;
+        let observation = create(&client, &base_url, "Observation", &json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "4548-4"}]},
+            "subject": {"reference": format!("Patient/{patient}")},
+            "effectiveDateTime": date
+        }))
+        .await;
+        patients.push(patient);
+        observations.push(observation);
+    }
+    create(&client, &base_url, "Provenance", &json!({
+        "resourceType": "Provenance",
+        "target": [{"reference": format!("Observation/{}", observations[0])}],
+        "recorded": "2024-07-01T00:00:00Z",
+        "agent": [{"who": {"reference": "Practitioner/x"}}]
+    }))
+    .await;
+    let ids = |query: &str| {
+        let url = format!("{base_url}/Patient?{query}");
+        let client = client.clone();
+        async move {
+            let resp = client.get(url).send().await.unwrap();
+            assert_eq!(resp.status(), 200);
+            let bundle: Value = resp.json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|entries| {
+                    entries.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect()
+                })
+                .unwrap_or_default();
+            ids.sort();
+            ids
+        }
+    };
+
+    assert_eq!(ids("_has:Observation:subject:date=ge2024-01-01").await, vec![patients[1].clone()]);
+    assert_eq!(ids("_has:Observation:subject:date=lt2024-01-01").await, vec![patients[0].clone()]);
+    let mut both = patients.clone();
+    both.sort();
+    assert_eq!(ids("_has:Observation:subject:code=4548-4,29463-7").await, both);
+    assert_eq!(
+        ids("_has:Observation:subject:_has:Provenance:target:recorded=ge2024-01-01").await,
+        vec![patients[0].clone()]
+    );
+
+    let resp = client
+        .get(format!("{base_url}/Patient?_has:Observation:subject:code:exact=4548-4"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_chain_prefix_modifier_and_typeless() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mut observations = Vec::new();
+    for (family, birth_date) in [("Doe", "1940-02-01"), ("Doelle", "1980-07-04")] {
+        let patient = create(&client, &base_url, "Patient", &json!({
+            "resourceType": "Patient",
+            "name": [{"family": family}],
+            "birthDate": birth_date
+        }))
+        .await;
+        observations.push(
+            create(&client, &base_url, "Observation", &json!({
+                "resourceType": "Observation",
+                "status": "final",
+                "code": {"coding": [{"system": "http://loinc.org", "code": "8310-5"}]},
+                "subject": {"reference": format!("Patient/{patient}")}
+            }))
+            .await,
+        );
+    }
+    let search = |query: &str| {
+        let url = format!("{base_url}/Observation?{query}");
+        let client = client.clone();
+        async move {
+            let resp = client.get(url).send().await.unwrap();
+            let status = resp.status().as_u16();
+            let bundle: Value = resp.json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|entries| {
+                    entries.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect()
+                })
+                .unwrap_or_default();
+            ids.sort();
+            (status, ids)
+        }
+    };
+    let mut both = observations.clone();
+    both.sort();
+
+    assert_eq!(search("subject:Patient.birthdate=lt1950").await, (200, vec![observations[0].clone()]));
+    assert_eq!(search("subject.name=Doe").await, (200, both));
+    assert_eq!(search("subject.name:exact=Doe").await, (200, vec![observations[0].clone()]));
+    assert_eq!(search("patient.birthdate=gt1950").await, (200, vec![observations[1].clone()]));
+    // `code` is no reference: there is nothing to chain through.
+    assert_eq!(search("code.name=x").await.0, 400);
+    assert_eq!(search("subject.name:above=Doe").await.0, 400);
+}
+
+#[tokio::test]
+async fn test_search_typeless_chain_through_declared_targets() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let loinc = |code: &str| json!({"coding": [{"system": "http://loinc.org", "code": code}]});
+    let observation = create(&client, &base_url, "Observation", &json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": loinc("2339-0")
+    }))
+    .await;
+    let report = create(&client, &base_url, "DiagnosticReport", &json!({
+        "resourceType": "DiagnosticReport",
+        "status": "final",
+        "code": loinc("24323-8"),
+        "result": [{"reference": format!("Observation/{observation}")}]
+    }))
+    .await;
+    let medication = create(&client, &base_url, "Medication", &json!({
+        "resourceType": "Medication",
+        "code": {"coding": [{"system": "http://www.nlm.nih.gov/research/umls/rxnorm", "code": "1049502"}]}
+    }))
+    .await;
+    let request = create(&client, &base_url, "MedicationRequest", &json!({
+        "resourceType": "MedicationRequest",
+        "status": "active",
+        "intent": "order",
+        "medicationReference": {"reference": format!("Medication/{medication}")},
+        "subject": {"reference": "Patient/p1"}
+    }))
+    .await;
+    let location = create(&client, &base_url, "Location", &json!({"resourceType": "Location", "name": "Ward 3"})).await;
+    let environmental = create(&client, &base_url, "Observation", &json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": loinc("8310-5"),
+        "subject": {"reference": format!("Location/{location}")}
+    }))
+    .await;
+    let search = |query: &str| {
+        let url = format!("{base_url}/{query}");
+        let client = client.clone();
+        async move {
+            let resp = client.get(url).send().await.unwrap();
+            let status = resp.status().as_u16();
+            let bundle: Value = resp.json().await.unwrap();
+            let ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|entries| {
+                    entries.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect()
+                })
+                .unwrap_or_default();
+            (status, ids)
+        }
+    };
+
+    assert_eq!(search("DiagnosticReport?result.code=2339-0").await, (200, vec![report]));
+    assert_eq!(search("MedicationRequest?medication.code=1049502").await, (200, vec![request]));
+    // An Observation's subject may be a Location.
+    assert_eq!(search("Observation?subject.name=Ward").await, (200, vec![environmental]));
+}
+
+#[tokio::test]
+async fn test_search_include_iterate() {
+    let mut config = ServerConfig::default();
+    config.search.max_included = 3;
+    let (base_url, _dir) = start_test_server_with(config).await;
+    let client = reqwest::Client::new();
+    let encounter = create(&client, &base_url, "Encounter", &json!({
+        "resourceType": "Encounter",
+        "status": "finished",
+        "class": {"system": "http://terminology.hl7.org/CodeSystem/v3-ActCode", "code": "AMB"}
+    }))
+    .await;
+    let mut expected = Vec::new();
+    for code in ["8310-5", "8867-4"] {
+        let practitioner = create(&client, &base_url, "Practitioner", &json!({"resourceType": "Practitioner"})).await;
+        let observation = create(&client, &base_url, "Observation", &json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": code}]},
+            "encounter": {"reference": format!("Encounter/{encounter}")},
+            "performer": [{"reference": format!("Practitioner/{practitioner}")}]
+        }))
+        .await;
+        expected.push(format!("Observation/{observation}"));
+        expected.push(format!("Practitioner/{practitioner}"));
+    }
+    expected.sort();
+    let search = |query: String| {
+        let client = client.clone();
+        async move {
+            let bundle: Value = client.get(query).send().await.unwrap().json().await.unwrap();
+            let entries = bundle["entry"].as_array().cloned().unwrap_or_default();
+            let mut included: Vec<String> = entries
+                .iter()
+                .filter(|e| e["search"]["mode"] == "include")
+                .map(|e| format!("{}/{}", e["resource"]["resourceType"].as_str().unwrap(), e["resource"]["id"].as_str().unwrap()))
+                .collect();
+            included.sort();
+            let warned = entries.iter().any(|e| e["search"]["mode"] == "outcome");
+            (included, warned)
+        }
+    };
+
+    // The encounter, its observations, and the practitioners they reference
+    // — past the cap of three, so one is left out with a warning.
+    let (included, warned) = search(format!(
+        "{base_url}/Encounter?_id={encounter}&_revinclude=Observation:encounter&_include:iterate=Observation:performer"
+    ))
+    .await;
+    assert_eq!(included.len(), 3);
+    assert!(included.iter().all(|r| expected.contains(r)));
+    assert!(warned);
+
+    // Without iterate only the observations come along.
+    let (included, warned) =
+        search(format!("{base_url}/Encounter?_id={encounter}&_revinclude=Observation:encounter")).await;
+    assert_eq!(included.iter().filter(|r| r.starts_with("Observation/")).count(), 2);
+    assert_eq!(included.len(), 2);
+    assert!(!warned);
+
+    // `*` follows every reference of the match.
+    let observation = expected[0].clone();
+    let id = observation.trim_start_matches("Observation/");
+    let (included, _) = search(format!("{base_url}/Observation?_id={id}&_include=*")).await;
+    assert_eq!(included.len(), 2);
+    assert!(included.contains(&format!("Encounter/{encounter}")));
+}
+
+#[tokio::test]
+async fn test_metadata_mode_terminology() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    // Default and ?mode=full return a CapabilityStatement.
+    for url in [format!("{base_url}/metadata"), format!("{base_url}/metadata?mode=full")] {
+        let body: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+        assert_eq!(body["resourceType"], "CapabilityStatement", "{url}");
+    }
+
+    // ?mode=terminology returns a *different* resource type.
+    let term: Value = client
+        .get(format!("{base_url}/metadata?mode=terminology"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(term["resourceType"], "TerminologyCapabilities");
+    assert_eq!(term["kind"], "instance");
+    assert_eq!(term["status"], "active");
+    assert!(term["date"].is_string());
+    // Not a terminology server: no operations are declared.
+    assert!(term.get("validateCode").is_none());
+    assert!(term.get("expansion").is_none());
+}
+
+#[tokio::test]
+async fn test_fhirpath_operation() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let patient = json!({
+        "resourceType": "Patient",
+        "active": true,
+        "name": [
+            {"use": "official", "family": "Chalmers", "given": ["Peter", "James"]},
+            {"use": "usual", "given": ["Jim"]}
+        ],
+        "birthDate": "1974-12-25"
+    });
+    let id = create(&client, &base_url, "Patient", &patient).await;
+    let result_parts = |body: &Value| -> Vec<Value> {
+        let result = body["parameter"].as_array().unwrap().iter().find(|p| p["name"] == "result").unwrap();
+        result["part"].as_array().cloned().unwrap_or_default()
+    };
+
+    // Against a stored resource, expression in the query.
+    let url = format!("{base_url}/Patient/{id}/$fhirpath");
+    let resp = client
+        .get(&url)
+        .query(&[("expression", "name.where(use = 'official').given")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["resourceType"], "Parameters");
+    assert_eq!(
+        result_parts(&body),
+        vec![json!({"name": "string", "valueString": "Peter"}), json!({"name": "string", "valueString": "James"})]
+    );
+
+    // Typed System results.
+    let body: Value = client
+        .get(&url)
+        .query(&[("expression", "birthDate + 1 year = @1975-12-25")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result_parts(&body), vec![json!({"name": "boolean", "valueBoolean": true})]);
+
+    // Against a posted resource, in a Parameters body.
+    let resp = client
+        .post(format!("{base_url}/$fhirpath"))
+        .json(&json!({
+            "resourceType": "Parameters",
+            "parameter": [
+                {"name": "expression", "valueString": "name.given.count() + 0.5"},
+                {"name": "resource", "resource": patient}
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(result_parts(&body), vec![json!({"name": "decimal", "valueDecimal": 3.5})]);
+
+    // A bare resource with the expression in the query; a resource result.
+    let body: Value = client
+        .post(format!("{base_url}/$fhirpath?expression=%25resource"))
+        .json(&patient)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result_parts(&body)[0]["name"], "Patient");
+    assert_eq!(result_parts(&body)[0]["resource"]["birthDate"], "1974-12-25");
+
+    // Invalid expressions, evaluation errors and unknown resources.
+    let resp = client.get(&url).query(&[("expression", "name.frobnicate()")]).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client.get(&url).query(&[("expression", "name.single()")]).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client
+        .get(format!("{base_url}/Patient/nope/$fhirpath?expression=id"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_search_parameter_runtime_management() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let ann = create(
+        &client,
+        &base_url,
+        "Patient",
+        &json!({"resourceType": "Patient", "name": [
+            {"use": "official", "family": "Davis", "given": ["Ann"]},
+            {"use": "nickname", "given": ["Annie"]}
+        ]}),
+    )
+    .await;
+    // Search ids for `query`, once the background reindex has caught up with
+    // `expected`.
+    let search_until = |query: &'static str, expected: Vec<String>| {
+        let client = client.clone();
+        let url = format!("{base_url}/Patient?{query}");
+        async move {
+            for _ in 0..100 {
+                let resp = client.get(&url).send().await.unwrap();
+                assert_eq!(resp.status(), 200, "{query}");
+                let bundle: Value = resp.json().await.unwrap();
+                let ids: Vec<String> = bundle["entry"]
+                    .as_array()
+                    .map(|e| e.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect())
+                    .unwrap_or_default();
+                if ids == expected {
+                    return;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            panic!("{query} never returned {expected:?}");
+        }
+    };
+
+    let resp = client.get(format!("{base_url}/Patient?nickname=annie")).send().await.unwrap();
+    assert_eq!(resp.status(), 400, "unknown parameter before it is registered");
+
+    // An expression that doesn't compile is refused and nothing is stored.
+    let resp = client
+        .post(format!("{base_url}/SearchParameter"))
+        .json(&json!({"resourceType": "SearchParameter", "status": "active", "code": "nickname",
+            "base": ["Patient"], "type": "string", "expression": "Patient.name.where("}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    // Create: the existing Patient is reindexed for the new parameter.
+    let mut sp = json!({
+        "resourceType": "SearchParameter",
+        "url": "http://example.org/SearchParameter/patient-nickname",
+        "name": "nickname",
+        "status": "active",
+        "code": "nickname",
+        "base": ["Patient"],
+        "type": "string",
+        "expression": "Patient.name.where(use = 'nickname').given"
+    });
+    let sp_id = create(&client, &base_url, "SearchParameter", &sp).await;
+    search_until("nickname=annie", vec![ann.clone()]).await;
+
+    // Update: the old values go, the new ones come in.
+    sp["id"] = json!(sp_id);
+    sp["expression"] = json!("Patient.name.where(use = 'official').given");
+    let resp = client
+        .put(format!("{base_url}/SearchParameter/{sp_id}"))
+        .json(&sp)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("nickname=ann", vec![ann.clone()]).await;
+    search_until("nickname=annie", vec![]).await;
+
+    // Delete: the parameter is unknown again.
+    let resp = client.delete(format!("{base_url}/SearchParameter/{sp_id}")).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = client.get(format!("{base_url}/Patient?nickname=ann")).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_search_parameter_conditional_and_bundle_writes() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let ann = create(
+        &client,
+        &base_url,
+        "Patient",
+        &json!({"resourceType": "Patient", "name": [
+            {"use": "official", "family": "Davis", "given": ["Ann"]},
+            {"use": "nickname", "given": ["Annie"]}
+        ]}),
+    )
+    .await;
+    // Status of `query` once the registry and background reindex have caught
+    // up: 200 with exactly `expected`, or 400 for an unknown parameter.
+    let search_until = |query: &'static str, expected: Option<Vec<String>>| {
+        let client = client.clone();
+        let url = format!("{base_url}/Patient?{query}");
+        async move {
+            for _ in 0..100 {
+                let resp = client.get(&url).send().await.unwrap();
+                let ids = if resp.status() == 200 {
+                    let bundle: Value = resp.json().await.unwrap();
+                    Some(
+                        bundle["entry"]
+                            .as_array()
+                            .map(|e| e.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect())
+                            .unwrap_or_default(),
+                    )
+                } else {
+                    assert_eq!(resp.status(), 400, "{query}");
+                    None
+                };
+                if ids == expected {
+                    return;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            panic!("{query} never returned {expected:?}");
+        }
+    };
+    let sp = |id: &str, code: &str, expression: &str| {
+        json!({"resourceType": "SearchParameter", "id": id, "status": "active", "code": code,
+            "base": ["Patient"], "type": "string", "expression": expression})
+    };
+    let nickname = "Patient.name.where(use = 'nickname').given";
+    let official = "Patient.name.where(use = 'official').given";
+
+    // Conditional update: refused when it won't register, else create, then update.
+    let url = format!("{base_url}/SearchParameter?_id=sp-nick");
+    let resp = client.put(&url).json(&sp("sp-nick", "nick", "Patient.name.where(")).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client.put(&url).json(&sp("sp-nick", "nick", nickname)).send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    search_until("nick=annie", Some(vec![ann.clone()])).await;
+    let resp = client.put(&url).json(&sp("sp-nick", "nick", official)).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("nick=annie", Some(vec![])).await;
+    search_until("nick=ann", Some(vec![ann.clone()])).await;
+
+    // Conditional delete unregisters it.
+    let resp = client.delete(&url).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    search_until("nick=ann", None).await;
+
+    // Transaction: an entry that won't register fails the whole bundle.
+    let transaction = |resource: Value| {
+        json!({"resourceType": "Bundle", "type": "transaction", "entry": [
+            {"resource": resource, "request": {"method": "PUT", "url": "SearchParameter/sp-tx"}}
+        ]})
+    };
+    let resp = client
+        .post(&base_url)
+        .json(&transaction(sp("sp-tx", "txnick", "Patient.name.where(")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client.get(format!("{base_url}/SearchParameter/sp-tx")).send().await.unwrap();
+    assert_eq!(resp.status(), 404);
+    let resp = client.post(&base_url).json(&transaction(sp("sp-tx", "txnick", nickname))).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("txnick=annie", Some(vec![ann.clone()])).await;
+    let resp = client
+        .post(&base_url)
+        .json(&json!({"resourceType": "Bundle", "type": "transaction", "entry": [
+            {"request": {"method": "DELETE", "url": "SearchParameter/sp-tx"}}
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("txnick=annie", None).await;
+
+    // Batch: each entry checked and applied on its own.
+    let resp = client
+        .post(&base_url)
+        .json(&json!({"resourceType": "Bundle", "type": "batch", "entry": [
+            {"resource": sp("sp-bad", "badnick", "Patient.name.where("),
+                "request": {"method": "PUT", "url": "SearchParameter/sp-bad"}},
+            {"resource": sp("sp-batch", "batchnick", nickname),
+                "request": {"method": "PUT", "url": "SearchParameter/sp-batch"}}
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let bundle: Value = resp.json().await.unwrap();
+    assert_eq!(bundle["entry"][0]["response"]["status"], "400 Bad Request");
+    assert_eq!(bundle["entry"][1]["response"]["status"], "201 Created");
+    search_until("batchnick=annie", Some(vec![ann.clone()])).await;
+    let resp = client
+        .post(&base_url)
+        .json(&json!({"resourceType": "Bundle", "type": "batch", "entry": [
+            {"resource": sp("sp-batch", "batchnick", official),
+                "request": {"method": "PUT", "url": "SearchParameter/sp-batch"}}
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("batchnick=annie", Some(vec![])).await;
+    search_until("batchnick=ann", Some(vec![ann.clone()])).await;
+    let resp = client
+        .post(&base_url)
+        .json(&json!({"resourceType": "Bundle", "type": "batch", "entry": [
+            {"request": {"method": "DELETE", "url": "SearchParameter/sp-batch"}}
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    search_until("batchnick=ann", None).await;
+}
+
+#[tokio::test]
+async fn test_reindex_job() {
+    let (base_url, _dir) = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mut patients = Vec::new();
+    for family in ["Davis", "Baker", "Chen"] {
+        let body = json!({"resourceType": "Patient", "name": [{"family": family}]});
+        patients.push(create(&client, &base_url, "Patient", &body).await);
+    }
+    create(&client, &base_url, "Observation", &json!({"resourceType": "Observation", "status": "final",
+        "code": {"coding": [{"system": "http://loinc.org", "code": "8867-4"}]}})).await;
+
+    // Kick off and poll until complete.
+    let run = |query: &'static str| {
+        let client = client.clone();
+        let url = format!("{base_url}/$reindex{query}");
+        async move {
+            let resp = client.post(&url).send().await.unwrap();
+            assert_eq!(resp.status(), 202, "{query}");
+            let status_url = resp.headers()["content-location"].to_str().unwrap().to_string();
+            assert!(status_url.contains("/$reindex-status/"));
+            loop {
+                let resp = client.get(&status_url).send().await.unwrap();
+                if resp.status() == 200 {
+                    break resp.json::<Value>().await.unwrap();
+                }
+                assert_eq!(resp.status(), 202, "in-progress status should be 202");
+                assert!(resp.headers().contains_key("x-progress"));
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        }
+    };
+    let value = |summary: &Value, name: &str| {
+        summary["parameter"].as_array().unwrap().iter().find(|p| p["name"] == name).cloned()
+    };
+
+    let summary = run("?type=Patient").await;
+    assert_eq!(value(&summary, "resourcesIndexed").unwrap()["valueInteger"], 3);
+    assert_eq!(value(&summary, "type").unwrap()["valueCode"], "Patient");
+    let bundle: Value = client.get(format!("{base_url}/Patient?family=baker")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["entry"][0]["resource"]["id"], patients[1].as_str());
+    // Observations were outside the scope and are still searchable.
+    let bundle: Value = client.get(format!("{base_url}/Observation?code=8867-4")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 1);
+
+    // One parameter of one type.
+    let summary = run("?type=Patient&parameter=family").await;
+    assert_eq!(value(&summary, "entriesWritten").unwrap()["valueInteger"], 3);
+    let bundle: Value = client.get(format!("{base_url}/Patient?family=chen")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["entry"][0]["resource"]["id"], patients[2].as_str());
+
+    // Everything.
+    let summary = run("").await;
+    assert_eq!(value(&summary, "resourcesIndexed").unwrap()["valueInteger"], 4);
+    assert!(value(&summary, "type").is_none());
+    let bundle: Value = client.get(format!("{base_url}/Patient?_count=10")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 3);
+
+    let resp = client.post(format!("{base_url}/$reindex?type=Patient&parameter=nope")).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = client.get(format!("{base_url}/$reindex-status/unknown")).send().await.unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_single_database_layout() {
+    let mut config = ServerConfig::default();
+    config.storage.single_database = true;
+    let (base_url, dir) = start_test_server_with(config).await;
+    let client = reqwest::Client::new();
+    let family_ids = |family: &'static str| {
+        let client = client.clone();
+        let url = format!("{base_url}/Patient?family={family}");
+        async move {
+            let bundle: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|e| e.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect())
+                .unwrap_or_default();
+            ids.sort();
+            ids
+        }
+    };
+
+    let id = create(&client, &base_url, "Patient", &json!({"resourceType": "Patient", "name": [{"family": "Davis"}]})).await;
+    assert_eq!(family_ids("Davis").await, vec![id.clone()]);
+
+    let resp = client
+        .put(format!("{base_url}/Patient/{id}"))
+        .json(&json!({"resourceType": "Patient", "id": id, "name": [{"family": "Evans"}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(family_ids("Davis").await.is_empty());
+    assert_eq!(family_ids("Evans").await, vec![id.clone()]);
+
+    // A transaction's entries are indexed with it.
+    let resp = client
+        .post(&base_url)
+        .json(&json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {"resource": {"resourceType": "Patient", "name": [{"family": "Evans"}]},
+                 "request": {"method": "POST", "url": "Patient"}},
+                {"request": {"method": "DELETE", "url": format!("Patient/{id}")}}
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let evans = family_ids("Evans").await;
+    assert_eq!(evans.len(), 1);
+    assert_ne!(evans[0], id);
+
+    let resp = client.delete(format!("{base_url}/Patient/{}", evans[0])).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    assert!(family_ids("Evans").await.is_empty());
+
+    // Everything lives in the resources database.
+    assert!(!dir.path().join("search_index.sqlite").exists());
+}
+
+/// Runs with `--features postgres` against the server named by
+/// `SAZARE_PG_TEST_URL` (a `key=value` connection string), in a schema of its
+/// own; skipped otherwise.
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn test_postgres_backend() {
+    let Ok(pg_url) = std::env::var("SAZARE_PG_TEST_URL") else {
+        return;
+    };
+    let (admin, connection) = tokio_postgres::connect(&pg_url, tokio_postgres::NoTls).await.unwrap();
+    tokio::spawn(connection);
+    let schema = format!("sazare_e2e_{}", std::process::id());
+    admin
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}"))
+        .await
+        .unwrap();
+
+    let mut config = ServerConfig::default();
+    config.storage.backend = StorageBackend::Postgres;
+    config.storage.postgres_url = Some(format!("{pg_url} options='-c search_path={schema}'"));
+    let (base_url, _dir) = start_test_server_with(config).await;
+    let client = reqwest::Client::new();
+    let search = |query: &'static str| {
+        let client = client.clone();
+        let url = format!("{base_url}/Patient?{query}");
+        async move {
+            let bundle: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+            let mut ids: Vec<String> = bundle["entry"]
+                .as_array()
+                .map(|e| e.iter().map(|e| e["resource"]["id"].as_str().unwrap().to_string()).collect())
+                .unwrap_or_default();
+            ids.sort();
+            (ids, bundle["total"].clone())
+        }
+    };
+
+    let id = create(&client, &base_url, "Patient", &json!({"resourceType": "Patient", "name": [{"family": "Davis"}], "birthDate": "1970-01-01"})).await;
+    assert_eq!(search("family=dav").await, (vec![id.clone()], json!(1)));
+    assert_eq!(search("birthdate=lt1980&_sort=-birthdate").await.0, vec![id.clone()]);
+
+    // Updates are compare-and-set on the stored version.
+    let update = |version: &'static str, family: &'static str| {
+        client
+            .put(format!("{base_url}/Patient/{id}"))
+            .header("If-Match", format!("W/\"{version}\""))
+            .json(&json!({"resourceType": "Patient", "id": id, "name": [{"family": family}]}))
+            .send()
+    };
+    assert_eq!(update("1", "Evans").await.unwrap().status(), 200);
+    assert_eq!(update("1", "Stale").await.unwrap().status(), 412);
+    assert!(search("family=davis").await.0.is_empty());
+    assert_eq!(search("family=evans").await.0, vec![id.clone()]);
+    let v1: Value = client.get(format!("{base_url}/Patient/{id}/_history/1")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(v1["name"][0]["family"], "Davis");
+
+    // A transaction's entries are indexed with it.
+    let resp = client
+        .post(&base_url)
+        .json(&json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {"resource": {"resourceType": "Patient", "name": [{"family": "Frost"}]},
+                 "request": {"method": "POST", "url": "Patient"}},
+                {"resource": {"resourceType": "Patient", "id": "p-frost", "name": [{"family": "Frost"}]},
+                 "request": {"method": "PUT", "url": "Patient/p-frost"}}
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let (frosts, total) = search("family=frost&_count=1").await;
+    assert_eq!((frosts.len(), total), (1, json!(2)));
+
+    let resp = client.delete(format!("{base_url}/Patient/{id}")).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    assert!(search("family=evans").await.0.is_empty());
+    let resp = client.get(format!("{base_url}/Patient/{id}")).send().await.unwrap();
+    assert_eq!(resp.status(), 410);
+
+    admin.batch_execute(&format!("DROP SCHEMA {schema} CASCADE")).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_startup_consistency_check() {
+    use sazare_server::handlers::reindex::check_consistency;
+
+    let dir = TempDir::new().unwrap();
+    let store = SqliteStore::open(dir.path().join("resources.sqlite")).unwrap();
+    let index = SearchIndex::open(dir.path().join("search_index.sqlite")).unwrap();
+    let registry = SearchParamRegistry::new();
+    let patient = |id: &str, family: &str, at: &str| {
+        json!({"resourceType": "Patient", "id": id, "meta": {"versionId": "1", "lastUpdated": at},
+            "name": [{"family": family}]})
+    };
+
+    // Indexed and current.
+    let p1 = patient("p1", "Davis", "2026-01-01T00:00:00Z");
+    store.put_with_version("Patient", "p1", "1", &serde_json::to_vec(&p1).unwrap()).unwrap();
+    sazare_server::handlers::update_search_index(&index, &store, &registry, "Patient", "p1", &p1);
+    // Stored, but the index never heard of it.
+    let p2 = patient("p2", "Evans", "2026-01-01T00:00:00Z");
+    store.put_with_version("Patient", "p2", "1", &serde_json::to_vec(&p2).unwrap()).unwrap();
+    // Indexed at an older version.
+    let p3_old = patient("p3", "Old", "2026-01-01T00:00:00Z");
+    sazare_server::handlers::update_search_index(&index, &store, &registry, "Patient", "p3", &p3_old);
+    let p3 = patient("p3", "New", "2026-02-01T00:00:00Z");
+    store.put_with_version("Patient", "p3", "2", &serde_json::to_vec(&p3).unwrap()).unwrap();
+    // Indexed, but never stored.
+    let ghost = patient("ghost", "Ghost", "2026-01-01T00:00:00Z");
+    sazare_server::handlers::update_search_index(&index, &store, &registry, "Patient", "ghost", &ghost);
+
+    let report = check_consistency(&store, &index, &registry).unwrap();
+    assert_eq!((report.checked, report.reindexed, report.removed), (3, 2, 1));
+
+    let executor = sazare_store::SearchExecutor::new(&store, &index);
+    let family = |v: &str| {
+        let query = sazare_core::SearchQuery::parse_for_resource(&format!("family:exact={v}"), Some("Patient")).unwrap();
+        executor.search("Patient", &query).unwrap()
+    };
+    assert_eq!(family("Evans"), vec!["p2".to_string()]);
+    assert_eq!(family("New"), vec!["p3".to_string()]);
+    assert!(family("Old").is_empty());
+    assert!(family("Ghost").is_empty());
+
+    let report = check_consistency(&store, &index, &registry).unwrap();
+    assert_eq!((report.reindexed, report.removed), (0, 0), "nothing left to repair");
 }
@@ -0,0 +1,529 @@
+//! Storage backend traits.
+//!
+//! The server reads and writes resources through a [`ResourceStore`] and
+//! searches through a [`SearchBackend`], never a particular database. SQLite
+//! ([`SqliteStore`](crate::SqliteStore), [`SearchIndex`](crate::SearchIndex))
+//! is the default; PostgreSQL (`crate::pg`) comes with the `postgres`
+//! feature. Both backends compile searches to the same SQL (see
+//! `crate::search_sql`), so the search behaviour is shared and each backend
+//! only has to run the statements.
+
+use crate::error::{Result, StoreError};
+use crate::search_sql::{
+    date_condition, numeric_condition, quantity_condition, string_condition, uri_condition, Dialect, IdSet,
+    Shape, SqlArgs,
+};
+use crate::sqlite_index::{number_range, Page, PageStart, ReindexJob, StringMatch};
+use crate::sqlite_store::{Expunged, ExpungeMode, HistoryEntry, HistoryQuery};
+use sazare_core::{SearchParamType, SortKey};
+
+/// Resource storage: the current version of each resource and its history.
+#[allow(clippy::result_large_err)]
+pub trait ResourceStore: Send + Sync {
+    /// Get a resource
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store a resource (current version)
+    fn put(&self, resource_type: &str, id: &str, data: &[u8]) -> Result<()>;
+
+    /// Store a resource with version history
+    fn put_with_version(&self, resource_type: &str, id: &str, version_id: &str, data: &[u8]) -> Result<()>;
+
+    /// Compare-and-swap write: persist `data` as `new_version` only if the
+    /// resource's current stored version still matches `expected_current`
+    /// (`Some(v)` for an update of a resource last seen at version `v`, `None`
+    /// for a create that requires the resource to be absent). Returns `false`
+    /// without writing when the precondition fails — the caller maps that to a
+    /// 409/412. The read-compare-write is atomic, so two concurrent updates
+    /// can't both bump to the same version and clobber each other's history
+    /// (a lost update).
+    fn put_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: Option<&str>,
+        new_version: &str,
+        data: &[u8],
+    ) -> Result<bool>;
+
+    /// Get a specific version. `None` for a deletion's tombstone, as for a
+    /// version that was never written.
+    fn get_version(&self, resource_type: &str, id: &str, version_id: &str) -> Result<Option<Vec<u8>>>;
+
+    /// True if the resource has no current row but does have history — i.e. it
+    /// was deleted (a tombstone). Used to answer reads of deleted resources with
+    /// 410 Gone rather than 404 Not Found.
+    fn is_deleted(&self, resource_type: &str, id: &str) -> Result<bool>;
+
+    /// Delete a resource: remove its current row and record `tombstone` in
+    /// its history as version `new_version`, provided the current version is
+    /// still `expected_current` (a compare-and-swap, as in
+    /// [`Self::put_with_version_cas`]). Returns `false`, deleting nothing,
+    /// when the resource is absent or has moved on to another version.
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool>;
+
+    /// List version history (list of version_ids), newest first, tombstones
+    /// included
+    fn list_versions(&self, resource_type: &str, id: &str) -> Result<Vec<String>>;
+
+    /// Get resource counts by type
+    fn count_by_type(&self) -> Result<Vec<(String, i64)>>;
+
+    /// List all resources (optionally filtered by resource type)
+    fn list_all(&self, resource_type: Option<&str>) -> Result<Vec<(String, String, Vec<u8>)>>;
+
+    /// Up to `limit` resources (optionally of one type) in `(resource_type, id)`
+    /// order, starting after the `after` key. Lets a long pass over the store
+    /// (a reindex) stream it a page at a time and pick up where it left off.
+    fn list_page(
+        &self,
+        resource_type: Option<&str>,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Vec<u8>)>>;
+
+    /// Like [`Self::list_page`] over the whole store, but with each
+    /// resource's `meta.lastUpdated` instead of its body, for checking the
+    /// search index against the store without parsing every resource.
+    fn list_last_updated_page(
+        &self,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Option<String>)>>;
+
+    /// Whether a resource is stored (current version).
+    fn contains(&self, resource_type: &str, id: &str) -> Result<bool>;
+
+    /// List resource IDs of a type (id column only), in id order.
+    fn list_ids(&self, resource_type: &str) -> Result<Vec<String>>;
+
+    /// List resources sorted by meta.lastUpdated descending with pagination.
+    /// Returns (entries as (id, value), total_count).
+    #[allow(clippy::type_complexity)]
+    fn list_by_last_updated(
+        &self,
+        resource_type: &str,
+        count: usize,
+        offset: usize,
+    ) -> Result<(Vec<(String, Vec<u8>)>, usize)>;
+
+    /// Up to `query.limit` versions from the history of the whole store, a
+    /// resource type, or one resource, newest first (see [`HistoryQuery`]).
+    fn history(&self, query: &HistoryQuery<'_>) -> Result<Vec<HistoryEntry>>;
+
+    /// IDs of the resources of a type whose earliest version was last updated
+    /// after `time`: those created since then, as opposed to updated.
+    fn created_after(&self, resource_type: &str, time: &str) -> Result<Vec<String>>;
+
+    /// The newest `meta.lastUpdated` in a type's history, deletions'
+    /// tombstones included: when anything of the type last changed. `None`
+    /// when the type has no history.
+    fn type_last_updated(&self, resource_type: &str) -> Result<Option<String>>;
+
+    /// Physically remove what `mode` names from the whole store, a resource
+    /// type, or one resource (`$expunge`), in one transaction. Unlike a
+    /// delete, this leaves nothing behind; the caller updates the index.
+    fn expunge(&self, resource_type: Option<&str>, id: Option<&str>, mode: ExpungeMode) -> Result<Expunged>;
+
+    /// Remove every version last updated before `before` (written like
+    /// [`HistoryQuery`]'s times) except the current version of a stored
+    /// resource, for a history retention policy. Returns how many went.
+    fn prune_history(&self, before: &str) -> Result<usize>;
+
+    /// Run `f` in a transaction: all of its writes land, or none do. Call it
+    /// through [`in_transaction`](trait.ResourceStore.html#method.in_transaction),
+    /// which also hands back a result.
+    fn transaction(&self, f: &mut dyn FnMut(&dyn ResourceTransaction) -> Result<()>) -> Result<()>;
+}
+
+#[allow(clippy::result_large_err)]
+impl dyn ResourceStore + '_ {
+    /// Execute multiple operations atomically within a transaction.
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn ResourceTransaction) -> Result<T>,
+    {
+        let mut f = Some(f);
+        let mut result = None;
+        self.transaction(&mut |ops| {
+            if let Some(f) = f.take() {
+                result = Some(f(ops)?);
+            }
+            Ok(())
+        })?;
+        result.ok_or_else(|| StoreError::Other("transaction body did not run".to_string()))
+    }
+}
+
+/// Operations available within a [`ResourceStore`] transaction
+#[allow(clippy::result_large_err)]
+pub trait ResourceTransaction {
+    /// Get a resource
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store a resource with version history
+    fn put_with_version(&self, resource_type: &str, id: &str, version_id: &str, data: &[u8]) -> Result<()>;
+
+    /// Compare-and-swap write (see [`ResourceStore::put_with_version_cas`]).
+    fn put_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: Option<&str>,
+        new_version: &str,
+        data: &[u8],
+    ) -> Result<bool>;
+
+    /// Delete with a tombstone (see [`ResourceStore::delete_with_version_cas`]).
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool>;
+
+    /// The newest version in a resource's history, a tombstone or not; a
+    /// resource recreated after a delete carries on from it.
+    fn latest_version(&self, resource_type: &str, id: &str) -> Result<Option<String>>;
+
+    /// Write search index entries in this transaction. Only for a store whose
+    /// database the search index shares (a SQLite index opened with
+    /// `SearchIndex::open_shared`, or PostgreSQL): anywhere else the index
+    /// tables don't exist and every write fails.
+    fn index(&self) -> Box<dyn IndexWrite + '_>;
+}
+
+/// Writes to the search index.
+#[allow(clippy::result_large_err)]
+pub trait IndexWrite {
+    /// Add an index entry
+    fn add_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()>;
+
+    /// Replace a resource's full-text entry: its narrative (`_text`) and all
+    /// of its text (`_content`), as extracted by `IndexBuilder::extract_text`.
+    fn index_text(&self, resource_type: &str, resource_id: &str, narrative: &str, content: &str) -> Result<()>;
+
+    /// Remove all index entries for a resource, including those of the
+    /// resources it contains and its full-text entry
+    fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()>;
+
+    /// Remove a resource's entries for one search parameter, including the
+    /// part rows of a composite one (`<name>$<part>`); its other entries and
+    /// those of the resources it contains are kept.
+    fn remove_param_index(&self, resource_type: &str, resource_id: &str, param_name: &str) -> Result<()>;
+}
+
+/// The search index: entries extracted from resources by `IndexBuilder`, and
+/// searches over them.
+///
+/// A backend runs the id sets `crate::search_sql` compiles ([`Self::ids`],
+/// [`Self::count`], [`Self::page`]); the `search_*` lookups are built on
+/// those. Not `Sync`: the server keeps it behind a lock.
+#[allow(clippy::result_large_err)]
+pub trait SearchBackend: IndexWrite + Send {
+    /// The SQL dialect the backend's statements are compiled for.
+    fn dialect(&self) -> Dialect;
+
+    /// Add an entry to the reindex shadow table, to be swapped in by
+    /// [`Self::finish_reindex`]. Takes the same arguments as
+    /// [`IndexWrite::add_index`].
+    fn add_shadow_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()>;
+
+    /// Count total entries in the search index (for reindex decisions)
+    fn row_count(&self) -> Result<usize>;
+
+    /// Drop all entries from the search index
+    fn clear_all(&self) -> Result<()>;
+
+    /// What the index holds for a resource, to check it against the store:
+    /// `None` when the resource has no entries at all, otherwise the
+    /// `_lastUpdated` it was indexed at (`Some(None)` if it had none).
+    fn indexed_last_updated(&self, resource_type: &str, resource_id: &str) -> Result<Option<Option<String>>>;
+
+    /// Up to `limit` resources with index or full-text entries, in
+    /// `(resource_type, id)` order after the `after` key; contained resources
+    /// are left out (they go with their container).
+    fn resource_keys_page(&self, after: Option<(&str, &str)>, limit: usize) -> Result<Vec<(String, String)>>;
+
+    /// Recompute the per-parameter statistics `_total=estimate` works from:
+    /// for every resource type and parameter, the number of resources with a
+    /// value and the number of distinct values. One pass over the index; run at
+    /// startup and after a reindex, so estimates lag writes in between.
+    fn refresh_statistics(&self) -> Result<()>;
+
+    /// Run `f` in a transaction: all of its writes land, or none do. Call it
+    /// through [`in_transaction`](trait.SearchBackend.html#method.in_transaction),
+    /// which also hands back a result.
+    fn transaction(&self, f: &mut dyn FnMut(&dyn SearchBackend) -> Result<()>) -> Result<()>;
+
+    /// Start a reindex of everything, or of one resource type and/or one
+    /// search parameter: clears the shadow table and records the run. Returns
+    /// `false`, starting nothing, while another run is in progress.
+    fn begin_reindex(
+        &self,
+        id: &str,
+        resource_type: Option<&str>,
+        param_name: Option<&str>,
+        started: &str,
+    ) -> Result<bool>;
+
+    /// The reindex run with this id, finished or not.
+    fn reindex_job(&self, id: &str) -> Result<Option<ReindexJob>>;
+
+    /// The reindex run in progress, if any — after a restart, the one to resume.
+    fn active_reindex(&self) -> Result<Option<ReindexJob>>;
+
+    /// Take the run in progress for `owner`, so one worker runs it across
+    /// every process sharing the database. Succeeds when the run is unowned,
+    /// already `owner`'s, or its owner's last heartbeat (claim or checkpoint)
+    /// is older than `stale_before` — that worker having stopped.
+    fn claim_reindex(&self, id: &str, owner: &str, stale_before: &str) -> Result<bool>;
+
+    /// Record progress after a chunk of resources has been written to the
+    /// shadow table, up to and including `cursor`, and renew `owner`'s
+    /// heartbeat. Run it in the same transaction as those writes so a crash
+    /// loses neither half. Returns `false`, recording nothing, unless the run
+    /// is still in progress and `owner`'s; the caller then rolls back.
+    fn checkpoint_reindex(
+        &self,
+        id: &str,
+        owner: &str,
+        cursor: (&str, &str),
+        resources: usize,
+        entries: usize,
+    ) -> Result<bool>;
+
+    /// Swap the shadow table in: in one transaction, the run's scope (see
+    /// [`ReindexJob`]) is replaced by the shadow rows, except for resources
+    /// written while it ran, whose current rows stay. Returns `false`,
+    /// touching nothing, unless the run is still in progress and `owner`'s.
+    fn finish_reindex(&self, id: &str, owner: &str) -> Result<bool>;
+
+    /// Give up on a reindex run: the live index is left as it was. Returns
+    /// `false`, touching nothing, unless the run is still in progress and
+    /// `owner`'s.
+    fn fail_reindex(&self, id: &str, owner: &str, error: &str) -> Result<bool>;
+
+    /// Every top-level resource with any reference parameter pointing at
+    /// `reference`, as `(type, id)` — for `_revinclude=*`.
+    fn search_referencing(&self, reference: &str) -> Result<Vec<(String, String)>>;
+
+    /// Every id in `set`, unordered.
+    fn ids(&self, set: &IdSet) -> Result<Vec<String>>;
+
+    /// Number of ids in `set`.
+    fn count(&self, set: &IdSet) -> Result<usize>;
+
+    /// Estimated number of ids in a set of this shape, from the statistics of
+    /// [`refresh_statistics`](Self::refresh_statistics) rather than by running
+    /// it. `None` when there are no statistics for the type yet.
+    fn estimate(&self, resource_type: &str, shape: &Shape) -> Result<Option<usize>>;
+
+    /// One page of `set`, ordered by the `sort` keys and then by id (see
+    /// `search_sql::page_query`). Ordering and paging run inside the
+    /// database, so only the requested page is ever read back.
+    fn page(
+        &self,
+        resource_type: &str,
+        set: &IdSet,
+        sort: &[SortKey],
+        start: &PageStart,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Result<Page>;
+
+    /// Token search (code, identifier, etc.)
+    fn search_token(&self, resource_type: &str, param_name: &str, system: Option<&str>, code: &str) -> Result<Vec<String>> {
+        let (cond, args): (&str, SqlArgs) = match system {
+            Some(system) => (
+                "s.value_system = ? AND s.value_string = ?",
+                vec![system.to_string().into(), code.to_string().into()],
+            ),
+            None => ("s.value_string = ?", vec![code.to_string().into()]),
+        };
+        self.ids(&IdSet::matching(resource_type, param_name, cond, args))
+    }
+
+    /// String search (name, etc.) honoring the `:exact` / `:contains` modifiers.
+    fn search_string(&self, resource_type: &str, param_name: &str, value: &str, mode: StringMatch) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let cond = string_condition("s", value, mode, &mut args);
+        self.ids(&IdSet::matching(resource_type, param_name, &cond, args))
+    }
+
+    /// Token search with NO system (`|code`): the resource's value must carry
+    /// the code and have been indexed without a system.
+    fn search_token_no_system(&self, resource_type: &str, param_name: &str, code: &str) -> Result<Vec<String>> {
+        let cond = "s.value_string = ? AND s.value_system IS NULL";
+        self.ids(&IdSet::matching(resource_type, param_name, cond, vec![code.to_string().into()]))
+    }
+
+    /// Token search by system only (`system|`): any code within the system.
+    fn search_token_system_only(&self, resource_type: &str, param_name: &str, system: &str) -> Result<Vec<String>> {
+        let cond = "s.value_system = ?";
+        self.ids(&IdSet::matching(resource_type, param_name, cond, vec![system.to_string().into()]))
+    }
+
+    /// Distinct resource ids that have at least one index entry for `param_name`
+    /// (used to implement the `:missing` and `:not` modifiers).
+    fn ids_with_param(&self, resource_type: &str, param_name: &str) -> Result<Vec<String>> {
+        self.ids(&IdSet::with_param(resource_type, param_name))
+    }
+
+    /// Reference search (subject, patient, etc.)
+    fn search_reference(&self, resource_type: &str, param_name: &str, reference: &str) -> Result<Vec<String>> {
+        let args = vec![reference.to_string().into()];
+        let cond = "s.value_string = ? AND s.param_type = 'reference'";
+        self.ids(&IdSet::matching(resource_type, param_name, cond, args))
+    }
+
+    /// Resolve the references held by a set of source resources — the inverse of
+    /// [`search_reference`](Self::search_reference). Given source resources of
+    /// `source_type` and their `param_name` reference parameter, return the ids
+    /// of every referenced resource of `target_type`.
+    fn referenced_targets(
+        &self,
+        source_type: &str,
+        param_name: &str,
+        source_ids: &[String],
+        target_type: &str,
+    ) -> Result<Vec<String>> {
+        let mut out: Vec<String> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        // Chunk the id list to stay clear of SQLite's bound-variable limit.
+        for chunk in source_ids.chunks(500) {
+            let set = IdSet::referenced_by(source_type, param_name, IdSet::of(chunk), target_type);
+            for id in self.ids(&set)? {
+                if seen.insert(id.clone()) {
+                    out.push(id);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Date search (with prefix: eq, ne, ge, le, gt, lt).
+    ///
+    /// Indexed values are half-open epoch ranges `[start, end)` reflecting their
+    /// precision (and the full span of a Period). The query value is likewise
+    /// expanded to a range `[qs, qe)`, then compared per FHIR date semantics so
+    /// that, e.g., an instant `eq` search does not match a wider Period.
+    fn search_date_with_prefix(&self, resource_type: &str, param_name: &str, prefix: &str, value: &str) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let Some(cond) = date_condition("s", prefix, value, &mut args) else {
+            return Ok(Vec::new());
+        };
+        self.ids(&IdSet::matching(resource_type, param_name, &cond, args))
+    }
+
+    /// Number search with a comparator prefix (eq, ne, gt, lt, ge, le, sa, eb, ap).
+    ///
+    /// `eq`/`ne` honour the query value's implicit precision (`100` means
+    /// `[99.5, 100.5)`); the ordering prefixes compare against the value
+    /// itself. `ap` matches within 10% of the value, or the precision range if
+    /// that is wider. Returns an empty set for a value that isn't a number.
+    fn search_number(&self, resource_type: &str, param_name: &str, prefix: &str, value: &str) -> Result<Vec<String>> {
+        let Some(range) = number_range(value) else {
+            return Ok(Vec::new());
+        };
+        let mut args: SqlArgs = Vec::new();
+        let cond = numeric_condition("s.value_number", prefix, range, &mut args);
+        self.ids(&IdSet::matching(resource_type, param_name, &cond, args))
+    }
+
+    /// Quantity search: a number comparison (as
+    /// [`search_number`](Self::search_number)) plus unit matching.
+    ///
+    /// A UCUM-coded query (`5.4|http://unitsofmeasure.org|mmol/L`) compares
+    /// canonical values, so it matches any commensurable stored unit
+    /// (`0.0054 mol/L`), and also mass-unit values of an analyte with a known
+    /// molar mass (`97.3 mg/dL` glucose). Otherwise units match exactly: with a
+    /// system, both system and code must match; with only a code
+    /// (`5.4||mmol/L`) the code matches regardless of system and with no
+    /// conversion; with neither, any unit matches.
+    #[allow(clippy::too_many_arguments)]
+    fn search_quantity(
+        &self,
+        resource_type: &str,
+        param_name: &str,
+        prefix: &str,
+        value: &str,
+        system: Option<&str>,
+        code: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let Some(cond) = quantity_condition("s", prefix, value, system, code, &mut args) else {
+            return Ok(Vec::new());
+        };
+        self.ids(&IdSet::matching(resource_type, param_name, &cond, args))
+    }
+
+    /// Uri search: exact match, or with `:below` any indexed uri the value is a
+    /// prefix of, or with `:above` any indexed uri that is a prefix of the value
+    /// (e.g. a ValueSet url above a versioned canonical).
+    fn search_uri(&self, resource_type: &str, param_name: &str, value: &str, modifier: Option<&str>) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let cond = uri_condition("s", value, modifier, &mut args);
+        self.ids(&IdSet::matching(resource_type, param_name, &cond, args))
+    }
+
+    /// Composite search. Each part is `(type, prefix, value)` and is matched
+    /// against the `<param_name>$<i>` rows; a resource matches only if one
+    /// group (one repeating element, see `ExtractionMode::Composite`) matches
+    /// every part. Returns an empty set if any part value is malformed.
+    fn search_composite(
+        &self,
+        resource_type: &str,
+        param_name: &str,
+        parts: &[(SearchParamType, Option<&str>, &str)],
+    ) -> Result<Vec<String>> {
+        match IdSet::composite(resource_type, param_name, parts) {
+            Some(set) => self.ids(&set),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl dyn SearchBackend + '_ {
+    /// Run `f` in a transaction: all of its writes land, or none do.
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn SearchBackend) -> Result<T>,
+    {
+        let mut f = Some(f);
+        let mut result = None;
+        self.transaction(&mut |index| {
+            if let Some(f) = f.take() {
+                result = Some(f(index)?);
+            }
+            Ok(())
+        })?;
+        result.ok_or_else(|| StoreError::Other("transaction body did not run".to_string()))
+    }
+}
@@ -5,6 +5,10 @@ pub enum StoreError {
     #[error("SQLite error: {0}")]
     Sqlite(#[from] rusqlite::Error),
 
+    #[cfg(feature = "postgres")]
+    #[error("PostgreSQL error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+
     #[error("Resource not found: {resource_type}/{id}")]
     NotFound {
         resource_type: String,
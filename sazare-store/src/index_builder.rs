@@ -1,3 +1,4 @@
+use sazare_core::fhirpath::{Context, Resolver};
 use sazare_core::search_param_registry::{ExtractionMode, SearchParamDef, SearchParamRegistry};
 use serde_json::Value;
 
@@ -11,15 +12,77 @@ impl IndexBuilder {
         registry: &SearchParamRegistry,
         resource_type: &str,
         resource: &Value,
+    ) -> Vec<(String, String, String, Option<String>)> {
+        Self::extract_indices_with_resolver(registry, resource_type, resource, None)
+    }
+
+    /// Like [`Self::extract_indices_with_registry`], with `resolver` looking
+    /// up reference targets for `resolve()` in FHIRPath search parameters.
+    /// Without one, `resolve()` still knows a literal reference's type.
+    pub fn extract_indices_with_resolver(
+        registry: &SearchParamRegistry,
+        resource_type: &str,
+        resource: &Value,
+        resolver: Option<&Resolver>,
     ) -> Vec<(String, String, String, Option<String>)> {
         let mut indices = Vec::new();
         let defs = registry.get_definitions(resource_type);
         for def in defs {
-            Self::extract_by_definition(resource, def, &mut indices);
+            Self::extract_by_definition(resource, def, resolver, &mut indices);
         }
+        Self::extract_contained(registry, resource, resolver, &mut indices);
         indices
     }
 
+    /// Index each resource in `contained` by its own type's parameters, so
+    /// `_contained` searches can find it. Its rows are tagged by prefixing the
+    /// parameter name with `#<type>/<local id>/`, which `SearchIndex::add_index`
+    /// files under the contained type. Contained resources cannot themselves
+    /// contain others, so this does not recurse.
+    fn extract_contained(
+        registry: &SearchParamRegistry,
+        resource: &Value,
+        resolver: Option<&Resolver>,
+        indices: &mut Vec<(String, String, String, Option<String>)>,
+    ) {
+        let Some(contained) = resource.get("contained").and_then(|c| c.as_array()) else {
+            return;
+        };
+        for inner in contained {
+            let (Some(inner_type), Some(local_id)) = (
+                inner.get("resourceType").and_then(|t| t.as_str()),
+                inner.get("id").and_then(|i| i.as_str()),
+            ) else {
+                continue;
+            };
+            let mut inner_indices = Vec::new();
+            for def in registry.get_definitions(inner_type) {
+                Self::extract_by_definition(inner, def, resolver, &mut inner_indices);
+            }
+            for (name, param_type, value, system) in inner_indices {
+                indices.push((format!("#{inner_type}/{local_id}/{name}"), param_type, value, system));
+            }
+        }
+    }
+
+    /// Text for full-text search: the narrative (`text.div` without its
+    /// markup), for `_text`, and every string in the resource with the
+    /// narrative in place of its XHTML, for `_content`.
+    pub fn extract_text(resource: &Value) -> (String, String) {
+        let narrative = resource
+            .pointer("/text/div")
+            .and_then(|d| d.as_str())
+            .map(strip_markup)
+            .unwrap_or_default();
+        let mut content = Vec::new();
+        collect_strings(resource, &mut content);
+        if !narrative.is_empty() {
+            content.push(narrative.as_str());
+        }
+        let content = content.join(" ");
+        (narrative, content)
+    }
+
     /// Extract all searchable indices using a default registry (backward compatible).
     /// Returns Vec<(param_name, param_type, value, system)>
     pub fn extract_indices(
@@ -35,6 +98,7 @@ impl IndexBuilder {
     fn extract_by_definition(
         resource: &Value,
         def: &SearchParamDef,
+        resolver: Option<&Resolver>,
         indices: &mut Vec<(String, String, String, Option<String>)>,
     ) {
         let param_type_str = match def.param_type {
@@ -43,6 +107,9 @@ impl IndexBuilder {
             sazare_core::SearchParamType::Date => "date",
             sazare_core::SearchParamType::Reference => "reference",
             sazare_core::SearchParamType::Number => "number",
+            sazare_core::SearchParamType::Quantity => "quantity",
+            sazare_core::SearchParamType::Uri => "uri",
+            sazare_core::SearchParamType::Composite(_) => "composite",
         };
 
         match &def.extraction {
@@ -94,8 +161,14 @@ impl IndexBuilder {
             ExtractionMode::ExtensionDate => {
                 Self::extract_extension_date(resource, &def.path, &def.name, param_type_str, indices);
             }
+            ExtractionMode::Quantity => {
+                Self::extract_quantity(resource, &def.path, &def.name, param_type_str, indices);
+            }
+            ExtractionMode::Composite { elements, parts } => {
+                Self::extract_composite(resource, def, elements.as_ref(), parts, resolver, indices);
+            }
             ExtractionMode::FhirPath(expr) => {
-                Self::extract_fhirpath(resource, expr, def, param_type_str, indices);
+                Self::extract_fhirpath(resource, expr, def, param_type_str, resolver, indices);
             }
         }
     }
@@ -108,23 +181,25 @@ impl IndexBuilder {
         expr: &sazare_core::fhirpath::Expr,
         def: &SearchParamDef,
         param_type: &str,
+        resolver: Option<&Resolver>,
         indices: &mut Vec<(String, String, String, Option<String>)>,
     ) {
         use sazare_core::SearchParamType;
         let name = &def.name;
-        for node in sazare_core::fhirpath::evaluate(expr, resource) {
+        for node in sazare_core::fhirpath::select(expr, &fhirpath_context(resource, resolver)) {
             match def.param_type {
                 SearchParamType::String => {
                     if let Some(s) = node.as_str() {
                         indices.push((name.clone(), param_type.to_string(), s.to_lowercase(), None));
                     }
                 }
-                SearchParamType::Date => {
+                SearchParamType::Date | SearchParamType::Uri => {
                     if let Some(s) = node.as_str() {
                         indices.push((name.clone(), param_type.to_string(), s.to_string(), None));
                     }
                 }
                 SearchParamType::Reference => {
+                    push_reference_identifier(node, name, &[], indices);
                     let r = node
                         .get("reference")
                         .and_then(|v| v.as_str())
@@ -134,11 +209,122 @@ impl IndexBuilder {
                     }
                 }
                 SearchParamType::Token => Self::push_token_node(node, name, param_type, indices),
-                SearchParamType::Number => {}
+                SearchParamType::Number => {
+                    if let Some(n) = number_text(node) {
+                        indices.push((name.clone(), param_type.to_string(), n, None));
+                    }
+                }
+                SearchParamType::Quantity => Self::push_quantity_node(
+                    node,
+                    analyte_molar_mass(resource),
+                    name,
+                    param_type,
+                    indices,
+                ),
+                // Composite parameters are extracted part by part.
+                SearchParamType::Composite(_) => {}
             }
         }
     }
 
+    /// Composite: extract each part relative to each group element and emit the
+    /// part rows as `"<name>$<part>#<group>"`. The store files them under
+    /// `<name>$<part>` with the group number alongside, so a search can require
+    /// all parts to match within one element.
+    fn extract_composite(
+        resource: &Value,
+        def: &SearchParamDef,
+        elements: Option<&sazare_core::fhirpath::Expr>,
+        parts: &[SearchParamDef],
+        resolver: Option<&Resolver>,
+        indices: &mut Vec<(String, String, String, Option<String>)>,
+    ) {
+        let groups: Vec<&Value> = match elements {
+            Some(expr) => sazare_core::fhirpath::select(expr, &fhirpath_context(resource, resolver)),
+            None => {
+                let mut nodes = vec![resource];
+                for segment in &def.path {
+                    nodes = nodes
+                        .into_iter()
+                        .filter_map(|n| n.get(segment.as_str()))
+                        .flat_map(|v| match v.as_array() {
+                            Some(items) => items.iter().collect(),
+                            None => vec![v],
+                        })
+                        .collect();
+                }
+                nodes
+            }
+        };
+        for (group, element) in groups.into_iter().enumerate() {
+            for (i, part) in parts.iter().enumerate() {
+                let mut rows = Vec::new();
+                Self::extract_by_definition(element, part, resolver, &mut rows);
+                // Only the part's own rows; modifier companions (`code:text`)
+                // have no place in a composite.
+                for (_, t, v, s) in rows.into_iter().filter(|(n, ..)| *n == part.name) {
+                    indices.push((format!("{}${i}#{group}", def.name), t, v, s));
+                }
+            }
+        }
+    }
+
+    /// Quantity: walk `path`, fanning out over arrays, and index every Quantity
+    /// found at the end (e.g. `Observation.component.valueQuantity`). The
+    /// element holding the Quantity (the Observation, or the component) supplies
+    /// the analyte code.
+    fn extract_quantity(
+        resource: &Value,
+        path: &[String],
+        name: &str,
+        param_type: &str,
+        indices: &mut Vec<(String, String, String, Option<String>)>,
+    ) {
+        let mut nodes = vec![(resource, resource)];
+        for segment in path {
+            nodes = nodes
+                .into_iter()
+                .filter_map(|(_, n)| n.get(segment.as_str()).map(|v| (n, v)))
+                .flat_map(|(parent, v)| match v.as_array() {
+                    Some(items) => items.iter().map(|i| (parent, i)).collect(),
+                    None => vec![(parent, v)],
+                })
+                .collect();
+        }
+        for (parent, node) in nodes {
+            Self::push_quantity_node(node, analyte_molar_mass(parent), name, param_type, indices);
+        }
+    }
+
+    /// Shape a Quantity into an index row. The value is encoded as
+    /// `"<number>|<unit code>"`, with `"|<molar mass>"` appended when the
+    /// analyte's molar mass is known (the store splits it back apart), and the
+    /// unit system rides in the system slot. The code falls back to the
+    /// free-text `unit` so `value-quantity=5||mg` still matches a code-less
+    /// Quantity.
+    fn push_quantity_node(
+        node: &Value,
+        molar_mass: Option<f64>,
+        name: &str,
+        param_type: &str,
+        indices: &mut Vec<(String, String, String, Option<String>)>,
+    ) {
+        let Some(number) = node.get("value").and_then(number_text) else {
+            return;
+        };
+        let code = node
+            .get("code")
+            .or_else(|| node.get("unit"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        let system = node.get("system").and_then(|v| v.as_str()).map(str::to_string);
+        let value = match molar_mass {
+            Some(m) => format!("{number}|{code}|{m}"),
+            None => format!("{number}|{code}"),
+        };
+        indices.push((name.to_string(), param_type.to_string(), value, system));
+    }
+
     /// Shape a FHIRPath result node into token index rows: CodeableConcept,
     /// Coding, Identifier, or a bare code string.
     fn push_token_node(
@@ -154,12 +340,15 @@ impl IndexBuilder {
                     indices.push((name.to_string(), param_type.to_string(), code.to_string(), system));
                 }
             }
+            push_token_text(node, name, &[], indices);
         } else if let Some(code) = node.get("code").and_then(|v| v.as_str()) {
             let system = node.get("system").and_then(|v| v.as_str()).map(str::to_string);
             indices.push((name.to_string(), param_type.to_string(), code.to_string(), system));
+            push_token_text(node, name, &[], indices);
         } else if let Some(value) = node.get("value").and_then(|v| v.as_str()) {
             let system = node.get("system").and_then(|v| v.as_str()).map(str::to_string);
             indices.push((name.to_string(), param_type.to_string(), value.to_string(), system));
+            push_identifier_type(node, name, indices);
         } else if let Some(s) = node.as_str() {
             indices.push((name.to_string(), param_type.to_string(), s.to_string(), None));
         }
@@ -258,11 +447,11 @@ impl IndexBuilder {
             return;
         };
         for element in elements {
-            let Some(reference) = element
-                .get(path[1].as_str())
-                .and_then(|r| r.get("reference"))
-                .and_then(|v| v.as_str())
-            else {
+            let Some(ref_obj) = element.get(path[1].as_str()) else {
+                continue;
+            };
+            push_reference_identifier(ref_obj, name, aliases, indices);
+            let Some(reference) = ref_obj.get("reference").and_then(|v| v.as_str()) else {
                 continue;
             };
             indices.push((name.to_string(), param_type.to_string(), reference.to_string(), None));
@@ -376,6 +565,7 @@ impl IndexBuilder {
                         }
                     }
                 }
+                push_token_text(concept, name, aliases, indices);
             }
         }
     }
@@ -407,6 +597,7 @@ impl IndexBuilder {
                 indices.push((alias.to_string(), param_type.to_string(), code.to_string(), system.clone()));
             }
         }
+        push_token_text(current, name, aliases, indices);
     }
 
     /// CodingArray: navigate path to an array of Coding objects (e.g. meta.tag).
@@ -430,6 +621,7 @@ impl IndexBuilder {
                     let system = coding.get("system").and_then(|v| v.as_str()).map(|s| s.to_string());
                     indices.push((name.to_string(), param_type.to_string(), code.to_string(), system));
                 }
+                push_token_text(coding, name, &[], indices);
             }
         }
     }
@@ -474,12 +666,18 @@ impl IndexBuilder {
                 None => return,
             }
         }
-        if let Some(s) = current.as_str() {
-            let value = if param_type == "string" {
-                s.to_lowercase()
-            } else {
-                s.to_string()
-            };
+        let value = if param_type == "number" {
+            number_text(current)
+        } else {
+            current.as_str().map(|s| {
+                if param_type == "string" {
+                    s.to_lowercase()
+                } else {
+                    s.to_string()
+                }
+            })
+        };
+        if let Some(value) = value {
             indices.push((name.to_string(), param_type.to_string(), value.clone(), None));
             for alias in aliases {
                 indices.push((alias.to_string(), param_type.to_string(), value.clone(), None));
@@ -578,6 +776,7 @@ impl IndexBuilder {
                     }
                 }
             }
+            push_token_text(concept, name, aliases, indices);
         }
     }
 
@@ -605,6 +804,7 @@ impl IndexBuilder {
                     let system = identifier.get("system").and_then(|v| v.as_str()).map(|s| s.to_string());
                     indices.push((name.to_string(), "token".to_string(), value.to_string(), system));
                 }
+                push_identifier_type(identifier, name, indices);
             }
         } else if current.is_object() {
             // Single Identifier object (e.g. ServiceRequest.requisition)
@@ -612,6 +812,7 @@ impl IndexBuilder {
                 let system = current.get("system").and_then(|v| v.as_str()).map(|s| s.to_string());
                 indices.push((name.to_string(), "token".to_string(), value.to_string(), system));
             }
+            push_identifier_type(current, name, indices);
         }
     }
 
@@ -619,7 +820,8 @@ impl IndexBuilder {
     /// Handles both single Reference objects (e.g. Observation.subject) and
     /// arrays of References (e.g. Provenance.target).
     /// Indexes both the full reference and the bare resource id so FHIR clients
-    /// can search using either form (`?patient=Patient/123` or `?patient=123`).
+    /// can search using either form (`?patient=Patient/123` or `?patient=123`),
+    /// and a logical `identifier` for `:identifier` searches.
     fn extract_reference(
         resource: &Value,
         path: &[String],
@@ -645,6 +847,7 @@ impl IndexBuilder {
             vec![current]
         };
         for ref_obj in refs {
+            push_reference_identifier(ref_obj, name, aliases, indices);
             let Some(reference) = ref_obj.get("reference").and_then(|v| v.as_str()) else {
                 continue;
             };
@@ -689,6 +892,148 @@ impl IndexBuilder {
     }
 }
 
+/// Molar mass of the analyte named by an element's LOINC `code`, if it is one
+/// of the analytes [`sazare_core::ucum::molar_mass_for_loinc`] knows.
+/// Companion rows for the `:text` token modifier: a `<name>:text` string row
+/// for a CodeableConcept's `text` and for the `display` of each of its codings
+/// (or of a lone Coding).
+fn fhirpath_context<'a>(resource: &'a Value, resolver: Option<&'a Resolver<'a>>) -> Context<'a> {
+    match resolver {
+        Some(resolver) => Context::new(resource).with_resolver(resolver),
+        None => Context::new(resource),
+    }
+}
+
+fn push_token_text(
+    node: &Value,
+    name: &str,
+    aliases: &[String],
+    indices: &mut Vec<(String, String, String, Option<String>)>,
+) {
+    let codings: Vec<&Value> = match node.get("coding").and_then(|v| v.as_array()) {
+        Some(codings) => codings.iter().collect(),
+        None => vec![node],
+    };
+    let texts = node
+        .get("text")
+        .into_iter()
+        .chain(codings.into_iter().filter_map(|c| c.get("display")))
+        .filter_map(|t| t.as_str());
+    for text in texts {
+        for n in std::iter::once(name).chain(aliases.iter().map(String::as_str)) {
+            indices.push((format!("{n}:text"), "string".to_string(), text.to_string(), None));
+        }
+    }
+}
+
+/// Companion row for the `:of-type` token modifier: an Identifier's type
+/// coding with its value, as `<type code>|<value>` under the type's system.
+fn push_identifier_type(
+    identifier: &Value,
+    name: &str,
+    indices: &mut Vec<(String, String, String, Option<String>)>,
+) {
+    let Some(value) = identifier.get("value").and_then(|v| v.as_str()) else {
+        return;
+    };
+    let codings = identifier
+        .pointer("/type/coding")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten();
+    for coding in codings {
+        if let Some(code) = coding.get("code").and_then(|c| c.as_str()) {
+            let system = coding.get("system").and_then(|s| s.as_str()).map(str::to_string);
+            indices.push((format!("{name}:of-type"), "token".to_string(), format!("{code}|{value}"), system));
+        }
+    }
+}
+
+/// Companion row for the `:identifier` reference modifier: a Reference's
+/// logical `identifier`, as a token under `<name>:identifier`. Written whether
+/// or not the Reference also has a literal `reference`.
+fn push_reference_identifier(
+    reference: &Value,
+    name: &str,
+    aliases: &[String],
+    indices: &mut Vec<(String, String, String, Option<String>)>,
+) {
+    let Some(identifier) = reference.get("identifier") else {
+        return;
+    };
+    let Some(value) = identifier.get("value").and_then(|v| v.as_str()) else {
+        return;
+    };
+    let system = identifier.get("system").and_then(|s| s.as_str()).map(str::to_string);
+    for n in std::iter::once(name).chain(aliases.iter().map(String::as_str)) {
+        indices.push((format!("{n}:identifier"), "token".to_string(), value.to_string(), system.clone()));
+    }
+}
+
+/// Every string value under `value`, except XHTML narrative (`div`), base64
+/// attachment payloads (`data`) and the `resourceType` discriminator.
+fn collect_strings<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+    match value {
+        Value::String(s) => out.push(s),
+        Value::Array(items) => items.iter().for_each(|v| collect_strings(v, out)),
+        Value::Object(fields) => {
+            for (key, v) in fields {
+                if !matches!(key.as_str(), "div" | "data" | "resourceType") {
+                    collect_strings(v, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The text of an XHTML fragment: tags dropped (each as a space, so words in
+/// adjacent elements stay apart) and the common entities decoded.
+fn strip_markup(xhtml: &str) -> String {
+    let mut text = String::with_capacity(xhtml.len());
+    let mut in_tag = false;
+    for c in xhtml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn analyte_molar_mass(element: &Value) -> Option<f64> {
+    element
+        .get("code")?
+        .get("coding")?
+        .as_array()?
+        .iter()
+        .filter(|c| c.get("system").and_then(|v| v.as_str()) == Some("http://loinc.org"))
+        .filter_map(|c| c.get("code").and_then(|v| v.as_str()))
+        .find_map(sazare_core::ucum::molar_mass_for_loinc)
+}
+
+/// The textual form of a JSON number (or a numeric string, as some senders
+/// quote decimals), for number and quantity index rows.
+fn number_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) if s.trim().parse::<f64>().is_ok() => Some(s.trim().to_string()),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -729,16 +1074,123 @@ mod tests {
     }
 
     #[test]
-    fn test_register_rejects_out_of_subset_expression() {
+    fn test_custom_composite_search_parameter() {
+        // The base spec's Observation-component-code-value-quantity, loaded at
+        // runtime: components resolve by the `<Resource>-<code>` URL convention.
+        let mut reg = SearchParamRegistry::new();
+        let sp = json!({
+            "resourceType": "SearchParameter",
+            "url": "http://example.org/SearchParameter/component-code-value",
+            "code": "component-code-value",
+            "base": ["Observation"],
+            "type": "composite",
+            "expression": "Observation.component",
+            "component": [
+                {"definition": "http://hl7.org/fhir/SearchParameter/Observation-code", "expression": "code"},
+                {"definition": "http://hl7.org/fhir/SearchParameter/Observation-value-quantity", "expression": "value.ofType(Quantity)"}
+            ]
+        });
+        reg.register_search_parameter(&sp).unwrap();
+        assert_eq!(
+            reg.lookup_param_type("Observation", "component-code-value"),
+            Some(sazare_core::SearchParamType::Composite(vec![
+                sazare_core::SearchParamType::Token,
+                sazare_core::SearchParamType::Quantity,
+            ]))
+        );
+
+        let obs = json!({
+            "resourceType": "Observation",
+            "component": [
+                {"code": {"coding": [{"system": "http://loinc.org", "code": "8480-6"}]},
+                 "valueQuantity": {"value": 120, "code": "mm[Hg]"}},
+                {"code": {"coding": [{"system": "http://loinc.org", "code": "8462-4"}]},
+                 "valueQuantity": {"value": 80, "code": "mm[Hg]"}}
+            ]
+        });
+        let idx = IndexBuilder::extract_indices_with_registry(&reg, "Observation", &obs);
+        let rows: Vec<(&str, &str)> = idx
+            .iter()
+            .filter(|(n, _, _, _)| n.starts_with("component-code-value$"))
+            .map(|(n, _, v, _)| (n.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("component-code-value$0#0", "8480-6"),
+                ("component-code-value$1#0", "120|mm[Hg]"),
+                ("component-code-value$0#1", "8462-4"),
+                ("component-code-value$1#1", "80|mm[Hg]"),
+            ]
+        );
+
+        // A component that can't be resolved is rejected at load time.
+        let mut bad = sp.clone();
+        bad["component"][0]["definition"] = json!("http://example.org/SearchParameter/unknown");
+        assert!(reg.register_search_parameter(&bad).is_err());
+    }
+
+    #[test]
+    fn test_register_rejects_invalid_expression() {
         let mut reg = SearchParamRegistry::new();
         let sp = json!({
             "resourceType": "SearchParameter",
             "code": "bad", "base": ["Patient"], "type": "reference",
-            "expression": "Patient.link.other.where(resolve() is Patient)"
+            "expression": "Patient.link.other.where(resolve() is)"
         });
         assert!(reg.register_search_parameter(&sp).is_err());
     }
 
+    #[test]
+    fn test_resolve_type_filter_search_parameter() {
+        // The usual IG shape: a reference parameter narrowed to one target type.
+        let mut reg = SearchParamRegistry::new();
+        let sp = json!({
+            "resourceType": "SearchParameter",
+            "code": "subject-patient", "base": ["Observation"], "type": "reference",
+            "expression": "Observation.subject.where(resolve() is Patient)"
+        });
+        reg.register_search_parameter(&sp).unwrap();
+        let rows = |obs: &Value, resolver: Option<&Resolver>| -> Vec<String> {
+            IndexBuilder::extract_indices_with_resolver(&reg, "Observation", obs, resolver)
+                .into_iter()
+                .filter(|(n, ..)| n == "subject-patient")
+                .map(|(_, _, v, _)| v)
+                .collect()
+        };
+
+        let patient = json!({"resourceType": "Observation", "subject": {"reference": "Patient/p1"}});
+        assert_eq!(rows(&patient, None), ["Patient/p1"]);
+        let group = json!({"resourceType": "Observation", "subject": {"reference": "Group/g1"}});
+        assert!(rows(&group, None).is_empty());
+
+        // A reference whose literal names no type needs the resolver.
+        let urn = json!({"resourceType": "Observation", "subject": {"reference": "urn:uuid:7f1c"}});
+        assert!(rows(&urn, None).is_empty());
+        let resolver = |r: &str| (r == "urn:uuid:7f1c").then(|| json!({"resourceType": "Patient"}));
+        assert_eq!(rows(&urn, Some(&resolver)), ["urn:uuid:7f1c"]);
+    }
+
+    #[test]
+    fn test_extract_text() {
+        let resource = json!({
+            "resourceType": "Condition",
+            "text": {
+                "status": "generated",
+                "div": "<div xmlns=\"http://www.w3.org/1999/xhtml\"><p>Diabetic<b>foot</b> ulcer &amp; neuropathy</p></div>"
+            },
+            "code": {"text": "Ulcer of foot"},
+            "note": [{"text": "Dressing changed daily"}]
+        });
+        let (narrative, content) = IndexBuilder::extract_text(&resource);
+        assert_eq!(narrative, "Diabetic foot ulcer & neuropathy");
+        assert!(content.contains("Ulcer of foot"));
+        assert!(content.contains("Dressing changed daily"));
+        assert!(content.contains("Diabetic foot ulcer"));
+        assert!(!content.contains("<p>"));
+        assert!(!content.contains("Condition"));
+    }
+
     #[test]
     fn test_extract_patient_indices() {
         let patient = json!({
@@ -900,6 +1352,28 @@ mod tests {
         assert!(indices.iter().any(|(n, _, v, _)| n == "patient" && v == "abc-123"));
     }
 
+    #[test]
+    fn test_reference_identifier_indexed() {
+        // Identifier-only references have no literal form but are still
+        // searchable with `:identifier`.
+        let observation = json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "code": {"coding": [{"code": "8310-5"}]},
+            "subject": {"identifier": {"system": "http://hospital.org/mrn", "value": "12345"}}
+        });
+
+        let indices = IndexBuilder::extract_indices("Observation", &observation);
+
+        assert!(!indices.iter().any(|(n, _, _, _)| n == "subject"));
+        for name in ["subject:identifier", "patient:identifier"] {
+            assert!(indices.iter().any(|(n, t, v, s)| n == name
+                && t == "token"
+                && v == "12345"
+                && s.as_deref() == Some("http://hospital.org/mrn")));
+        }
+    }
+
     #[test]
     fn test_reference_with_history_strips_version() {
         // `Patient/123/_history/4` should still yield bare id "123", not "4".
@@ -1359,4 +1833,28 @@ mod tests {
         let indices = IndexBuilder::extract_indices("Condition", &cond);
         assert!(indices.iter().any(|(n, t, v, _)| n == "asserted-date" && t == "date" && v == "2020-05-15"));
     }
+
+    #[test]
+    fn test_extract_observation_value_quantity() {
+        let obs = json!({
+            "resourceType": "Observation",
+            "valueQuantity": {"value": 5.4, "unit": "mmol/l", "system": "http://unitsofmeasure.org", "code": "mmol/L"},
+            "component": [
+                {"valueQuantity": {"value": 120, "unit": "mmHg"}},
+                {"valueQuantity": {"value": 80, "unit": "mmHg"}}
+            ]
+        });
+
+        let indices = IndexBuilder::extract_indices("Observation", &obs);
+        assert!(indices.iter().any(|(n, t, v, s)| n == "value-quantity"
+            && t == "quantity"
+            && v == "5.4|mmol/L"
+            && s.as_deref() == Some("http://unitsofmeasure.org")));
+        let components: Vec<&str> = indices
+            .iter()
+            .filter(|(n, _, _, _)| n == "component-value-quantity")
+            .map(|(_, _, v, _)| v.as_str())
+            .collect();
+        assert_eq!(components, vec!["120|mmHg", "80|mmHg"]);
+    }
 }
@@ -1,14 +1,21 @@
+pub mod backend;
 pub mod error;
 mod migrate;
+mod search_sql;
 pub mod sqlite_store;
 pub mod sqlite_index;
 pub mod sqlite_audit;
 pub mod search_executor;
 pub mod index_builder;
+#[cfg(feature = "postgres")]
+pub mod pg;
 
+pub use backend::{IndexWrite, ResourceStore, ResourceTransaction, SearchBackend};
 pub use error::{Result, StoreError};
-pub use sqlite_store::SqliteStore;
-pub use sqlite_index::SearchIndex;
+pub use sqlite_store::{Expunged, ExpungeMode, HistoryEntry, HistoryKey, HistoryQuery, SqliteStore};
+pub use sqlite_index::{IndexWriter, ReindexJob, ReindexStatus, SearchIndex};
 pub use sqlite_audit::{AuditLog, Operation};
 pub use search_executor::SearchExecutor;
 pub use index_builder::IndexBuilder;
+#[cfg(feature = "postgres")]
+pub use pg::{PgSearchIndex, PgStore};
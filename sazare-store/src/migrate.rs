@@ -7,6 +7,11 @@
 //! ones are skipped. Existing pre-versioning databases sit at version 0 and are
 //! brought forward by the (idempotent, `IF NOT EXISTS`) initial migration
 //! without data loss. New schema changes are made by appending a script.
+//!
+//! When two schemas share one database file (the search index inside the
+//! resource store's, see `SearchIndex::open_shared`), only one of them can own
+//! `user_version`; the other tracks its version by name in a `schema_version`
+//! table instead (see [`run_named_migrations`]).
 
 use crate::error::Result;
 use rusqlite::Connection;
@@ -24,6 +29,34 @@ pub(crate) fn run_migrations(conn: &mut Connection, migrations: &[&str]) -> Resu
     Ok(())
 }
 
+/// Like [`run_migrations`], but with the version of the `name` schema kept in
+/// the `schema_version` table, so it can live alongside another schema.
+pub(crate) fn run_named_migrations(conn: &mut Connection, name: &str, migrations: &[&str]) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            name TEXT PRIMARY KEY,
+            version INTEGER NOT NULL
+        );",
+    )?;
+    let current: i64 = conn
+        .query_row("SELECT version FROM schema_version WHERE name = ?1", [name], |row| row.get(0))
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(0),
+            other => Err(other),
+        })?;
+    let target = migrations.len() as i64;
+    for version in current..target {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migrations[version as usize])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_version (name, version) VALUES (?1, ?2)",
+            rusqlite::params![name, version + 1],
+        )?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -68,4 +101,22 @@ mod tests {
             assert_eq!(n, 1, "table {} should exist", t);
         }
     }
+
+    #[test]
+    fn test_named_migrations_leave_user_version_alone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn, &["CREATE TABLE a (id INTEGER);"]).unwrap();
+        let named = ["CREATE TABLE b (id INTEGER);", "CREATE TABLE c (id INTEGER);"];
+        run_named_migrations(&mut conn, "other", &named[..1]).unwrap();
+        run_named_migrations(&mut conn, "other", &named).unwrap();
+
+        let v: i64 = conn.query_row("PRAGMA user_version", [], |r| r.get(0)).unwrap();
+        assert_eq!(v, 1);
+        let named_v: i64 = conn
+            .query_row("SELECT version FROM schema_version WHERE name = 'other'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(named_v, 2);
+        // Re-running applies nothing: `b` would fail to be created twice.
+        run_named_migrations(&mut conn, "other", &named).unwrap();
+    }
 }
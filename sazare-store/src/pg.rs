@@ -0,0 +1,1590 @@
+//! PostgreSQL storage backend (`postgres` feature).
+//!
+//! Resources and the search index live in one database, so a resource and
+//! its index entries always commit together, and several server processes
+//! can share the data. The tables mirror the SQLite ones, and searches run
+//! the same statements (see `crate::search_sql`): their `?` placeholders are
+//! numbered and typed for PostgreSQL, and `_text`/`_content` match generated
+//! `tsvector` columns.
+//!
+//! Queries go through `tokio-postgres`, blocking on each result as the
+//! SQLite backend blocks on its file. Every connection is driven by a thread
+//! of its own, so blocking never stalls the server's runtime.
+
+use crate::backend::{IndexWrite, ResourceStore, ResourceTransaction, SearchBackend};
+use crate::error::{Result, StoreError};
+use crate::search_sql::{count_query, estimate, page_query, Dialect, IdSet, ParamStats, Shape};
+use crate::sqlite_index::{
+    IndexRow, Page, PageCursor, PageStart, ReindexJob, ReindexStatus, INDEX_COLUMNS, REINDEX_DIRTY, REINDEX_SCOPE,
+};
+use crate::sqlite_store::{
+    expunge_statements, history_query, prune_history_statement, Expunged, ExpungeMode, HistoryEntry, HistoryKey, HistoryQuery,
+};
+use futures_executor::block_on;
+use rusqlite::types::Value as SqlValue;
+use sazare_core::SortKey;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{Client, NoTls, Row};
+
+/// Connections a [`PgStore`] opens. Each request holds one for the length of
+/// a read or a transaction.
+const POOL_SIZE: usize = 4;
+
+/// Advisory lock taken while migrating, so processes starting together
+/// against a fresh database don't race to create the tables.
+const MIGRATION_LOCK: i64 = 0x7361_7a61_7265;
+
+type Params<'a> = [&'a (dyn ToSql + Sync)];
+
+/// Connect to `url` (a `key=value` connection string or a `postgres://`
+/// URL), driving the connection from a thread of its own.
+fn connect(url: &str) -> Result<Client> {
+    let url = url.to_string();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("sazare-postgres".to_string())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = tx.send(Err(StoreError::Other(format!("PostgreSQL connection runtime: {e}"))));
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                match tokio_postgres::connect(&url, NoTls).await {
+                    Ok((client, connection)) => {
+                        let _ = tx.send(Ok(client));
+                        // Runs until the client is dropped or the server goes away;
+                        // either way the client's calls report the error.
+                        let _ = connection.await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e.into()));
+                    }
+                }
+            });
+        })
+        .map_err(|e| StoreError::Other(format!("PostgreSQL connection thread: {e}")))?;
+    rx.recv()
+        .map_err(|_| StoreError::Other("PostgreSQL connection thread exited".to_string()))?
+}
+
+fn execute(client: &Client, sql: &str, params: &Params) -> Result<u64> {
+    Ok(block_on(client.execute(sql, params))?)
+}
+
+fn query(client: &Client, sql: &str, params: &Params) -> Result<Vec<Row>> {
+    Ok(block_on(client.query(sql, params))?)
+}
+
+fn query_opt(client: &Client, sql: &str, params: &Params) -> Result<Option<Row>> {
+    Ok(block_on(client.query_opt(sql, params))?)
+}
+
+fn batch(client: &Client, sql: &str) -> Result<()> {
+    Ok(block_on(client.batch_execute(sql))?)
+}
+
+/// Run a statement compiled by `crate::search_sql`, whose placeholders are
+/// SQLite's anonymous `?`. Each becomes `$n`, cast to its argument's type:
+/// PostgreSQL can't infer a type for a parameter that is only compared with
+/// another (`? IS NULL`, `SELECT ? AS resource_id`).
+fn query_compiled(client: &Client, sql: &str, args: &[SqlValue]) -> Result<Vec<Row>> {
+    let (sql, values) = compile(sql, args);
+    let params: Vec<&(dyn ToSql + Sync)> = values.iter().map(|v| v.as_ref()).collect();
+    query(client, &sql, &params)
+}
+
+/// Like [`query_compiled`], for a statement returning no rows: the number of
+/// rows it changed.
+fn execute_compiled(client: &Client, sql: &str, args: &[SqlValue]) -> Result<u64> {
+    let (sql, values) = compile(sql, args);
+    let params: Vec<&(dyn ToSql + Sync)> = values.iter().map(|v| v.as_ref()).collect();
+    execute(client, &sql, &params)
+}
+
+/// `sql` with numbered, typed placeholders, and `args` as parameters.
+fn compile(sql: &str, args: &[SqlValue]) -> (String, Vec<Box<dyn ToSql + Sync>>) {
+    let mut numbered = String::with_capacity(sql.len() + 8 * args.len());
+    let mut quoted = false;
+    let mut n = 0;
+    for c in sql.chars() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                numbered.push(c);
+            }
+            '?' if !quoted => {
+                let ty = match args.get(n) {
+                    Some(SqlValue::Integer(_)) => "int8",
+                    Some(SqlValue::Real(_)) => "float8",
+                    Some(SqlValue::Blob(_)) => "bytea",
+                    _ => "text",
+                };
+                n += 1;
+                numbered.push_str(&format!("${n}::{ty}"));
+            }
+            _ => numbered.push(c),
+        }
+    }
+    let values: Vec<Box<dyn ToSql + Sync>> = args
+        .iter()
+        .map(|arg| -> Box<dyn ToSql + Sync> {
+            match arg {
+                SqlValue::Null => Box::new(None::<String>),
+                SqlValue::Integer(i) => Box::new(*i),
+                SqlValue::Real(f) => Box::new(*f),
+                SqlValue::Text(s) => Box::new(s.clone()),
+                SqlValue::Blob(b) => Box::new(b.clone()),
+            }
+        })
+        .collect();
+    (numbered, values)
+}
+
+/// An open transaction on a client, rolled back unless committed.
+struct Transaction<'c> {
+    client: &'c Client,
+    open: bool,
+}
+
+#[allow(clippy::result_large_err)]
+impl<'c> Transaction<'c> {
+    fn begin(client: &'c Client) -> Result<Self> {
+        batch(client, "BEGIN")?;
+        Ok(Self { client, open: true })
+    }
+
+    fn commit(mut self) -> Result<()> {
+        self.open = false;
+        batch(self.client, "COMMIT")
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = batch(self.client, "ROLLBACK");
+        }
+    }
+}
+
+/// Apply the migrations of schema `name` past its recorded version, like
+/// `crate::migrate::run_named_migrations`.
+fn migrate(client: &Client, name: &str, migrations: &[&str]) -> Result<()> {
+    let tx = Transaction::begin(client)?;
+    execute(client, "SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+    batch(
+        client,
+        "SET LOCAL client_min_messages = warning;
+         CREATE TABLE IF NOT EXISTS schema_version (name TEXT PRIMARY KEY, version INTEGER NOT NULL);",
+    )?;
+    let current = query_opt(client, "SELECT version FROM schema_version WHERE name = $1", &[&name])?
+        .map_or(0, |row| row.get::<_, i32>(0));
+    if (current as usize) < migrations.len() {
+        for migration in &migrations[current as usize..] {
+            batch(client, migration)?;
+        }
+        execute(
+            client,
+            "INSERT INTO schema_version (name, version) VALUES ($1, $2) \
+             ON CONFLICT (name) DO UPDATE SET version = EXCLUDED.version",
+            &[&name, &(migrations.len() as i32)],
+        )?;
+    }
+    tx.commit()
+}
+
+fn resource_rows(rows: Vec<Row>) -> Vec<(String, String, Vec<u8>)> {
+    rows.into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get::<_, String>(2).into_bytes()))
+        .collect()
+}
+
+fn utf8(data: &[u8]) -> Result<&str> {
+    std::str::from_utf8(data).map_err(|e| StoreError::Other(format!("Invalid UTF-8: {}", e)))
+}
+
+/// PostgreSQL-backed resource store.
+pub struct PgStore {
+    pool: Vec<Mutex<Client>>,
+    next: AtomicUsize,
+}
+
+#[allow(clippy::result_large_err)]
+impl PgStore {
+    /// Ordered schema migrations. Append-only.
+    const MIGRATIONS: &'static [&'static str] = &[
+        // v1 — initial schema. The version and last-updated time are read
+        // out of the resource for CAS writes and `_history` ordering.
+        r#"
+        CREATE TABLE resources (
+            resource_type TEXT COLLATE "C" NOT NULL,
+            id TEXT COLLATE "C" NOT NULL,
+            value TEXT NOT NULL,
+            version_id TEXT GENERATED ALWAYS AS (value::jsonb #>> '{meta,versionId}') STORED,
+            last_updated TEXT GENERATED ALWAYS AS (value::jsonb #>> '{meta,lastUpdated}') STORED,
+            PRIMARY KEY (resource_type, id)
+        );
+        CREATE TABLE resource_history (
+            resource_type TEXT COLLATE "C" NOT NULL,
+            id TEXT COLLATE "C" NOT NULL,
+            version_id TEXT COLLATE "C" NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (resource_type, id, version_id)
+        );
+        CREATE INDEX idx_resources_last_updated ON resources (resource_type, last_updated);
+        "#,
+        // v2 — each version's last-updated time, for type- and system-level
+        // `_history`.
+        r#"
+        ALTER TABLE resource_history ADD COLUMN last_updated TEXT COLLATE "C"
+            GENERATED ALWAYS AS (COALESCE(value::jsonb #>> '{meta,lastUpdated}', '')) STORED;
+        CREATE INDEX idx_history_last_updated ON resource_history (last_updated);
+        CREATE INDEX idx_history_type_last_updated ON resource_history (resource_type, last_updated);
+        "#,
+        // v3 — deletions recorded as tombstone versions.
+        r#"
+        ALTER TABLE resource_history ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT false;
+        "#,
+    ];
+
+    /// Connect to the database at `url`, creating the tables if needed.
+    pub fn open(url: &str) -> Result<Self> {
+        let pool = (0..POOL_SIZE).map(|_| connect(url)).collect::<Result<Vec<_>>>()?;
+        migrate(&pool[0], "resources", Self::MIGRATIONS)?;
+        Ok(Self {
+            pool: pool.into_iter().map(Mutex::new).collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Pick a connection round-robin. A poisoned lock is recovered: the
+    /// client holds no state a panic could have left half-changed, and an
+    /// open transaction was rolled back by [`Transaction`]'s drop.
+    fn client(&self) -> MutexGuard<'_, Client> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        self.pool[i].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run `f` in a transaction on one connection: all of its writes land,
+    /// or none do.
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&PgTransaction<'_>) -> Result<T>,
+    {
+        let client = self.client();
+        let tx = Transaction::begin(&client)?;
+        let result = f(&PgTransaction { client: &client })?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl ResourceStore for PgStore {
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        PgTransaction { client: &self.client() }.get(resource_type, id)
+    }
+
+    fn put(&self, resource_type: &str, id: &str, data: &[u8]) -> Result<()> {
+        execute(
+            &self.client(),
+            "INSERT INTO resources (resource_type, id, value) VALUES ($1, $2, $3) \
+             ON CONFLICT (resource_type, id) DO UPDATE SET value = EXCLUDED.value",
+            &[&resource_type, &id, &utf8(data)?],
+        )?;
+        Ok(())
+    }
+
+    fn put_with_version(&self, resource_type: &str, id: &str, version_id: &str, data: &[u8]) -> Result<()> {
+        self.in_transaction(|tx| tx.put_with_version(resource_type, id, version_id, data))
+    }
+
+    fn put_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: Option<&str>,
+        new_version: &str,
+        data: &[u8],
+    ) -> Result<bool> {
+        self.in_transaction(|tx| tx.put_with_version_cas(resource_type, id, expected_current, new_version, data))
+    }
+
+    fn get_version(&self, resource_type: &str, id: &str, version_id: &str) -> Result<Option<Vec<u8>>> {
+        let row = query_opt(
+            &self.client(),
+            "SELECT value FROM resource_history \
+             WHERE resource_type = $1 AND id = $2 AND version_id = $3 AND NOT deleted",
+            &[&resource_type, &id, &version_id],
+        )?;
+        Ok(row.map(|row| row.get::<_, String>(0).into_bytes()))
+    }
+
+    fn is_deleted(&self, resource_type: &str, id: &str) -> Result<bool> {
+        let row = query_opt(
+            &self.client(),
+            "SELECT 1 WHERE NOT EXISTS (SELECT 1 FROM resources WHERE resource_type = $1 AND id = $2) \
+             AND EXISTS (SELECT 1 FROM resource_history WHERE resource_type = $1 AND id = $2)",
+            &[&resource_type, &id],
+        )?;
+        Ok(row.is_some())
+    }
+
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool> {
+        self.in_transaction(|tx| tx.delete_with_version_cas(resource_type, id, expected_current, new_version, tombstone))
+    }
+
+    fn list_versions(&self, resource_type: &str, id: &str) -> Result<Vec<String>> {
+        // Newest first, numerically; a non-numeric version sorts last, as
+        // SQLite's CAST makes it 0.
+        let rows = query(
+            &self.client(),
+            "SELECT version_id FROM resource_history WHERE resource_type = $1 AND id = $2 \
+             ORDER BY CASE WHEN version_id ~ '^[0-9]{1,18}$' THEN version_id::int8 END DESC NULLS LAST",
+            &[&resource_type, &id],
+        )?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    fn count_by_type(&self) -> Result<Vec<(String, i64)>> {
+        let rows = query(
+            &self.client(),
+            "SELECT resource_type, COUNT(*) FROM resources GROUP BY resource_type ORDER BY resource_type",
+            &[],
+        )?;
+        Ok(rows.into_iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn list_all(&self, resource_type: Option<&str>) -> Result<Vec<(String, String, Vec<u8>)>> {
+        let rows = query(
+            &self.client(),
+            "SELECT resource_type, id, value FROM resources \
+             WHERE $1::text IS NULL OR resource_type = $1 ORDER BY resource_type, id",
+            &[&resource_type],
+        )?;
+        Ok(resource_rows(rows))
+    }
+
+    fn list_page(
+        &self,
+        resource_type: Option<&str>,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Vec<u8>)>> {
+        let (after_type, after_id) = after.unzip();
+        let rows = query(
+            &self.client(),
+            "SELECT resource_type, id, value FROM resources \
+             WHERE ($1::text IS NULL OR resource_type = $1) \
+             AND ($2::text IS NULL OR (resource_type, id) > ($2, $3::text)) \
+             ORDER BY resource_type, id LIMIT $4",
+            &[&resource_type, &after_type, &after_id, &(limit as i64)],
+        )?;
+        Ok(resource_rows(rows))
+    }
+
+    fn list_last_updated_page(
+        &self,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Option<String>)>> {
+        let (after_type, after_id) = after.unzip();
+        let rows = query(
+            &self.client(),
+            "SELECT resource_type, id, last_updated FROM resources \
+             WHERE $1::text IS NULL OR (resource_type, id) > ($1, $2::text) \
+             ORDER BY resource_type, id LIMIT $3",
+            &[&after_type, &after_id, &(limit as i64)],
+        )?;
+        Ok(rows.into_iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect())
+    }
+
+    fn contains(&self, resource_type: &str, id: &str) -> Result<bool> {
+        let row = query_opt(
+            &self.client(),
+            "SELECT 1 FROM resources WHERE resource_type = $1 AND id = $2",
+            &[&resource_type, &id],
+        )?;
+        Ok(row.is_some())
+    }
+
+    fn list_ids(&self, resource_type: &str) -> Result<Vec<String>> {
+        let rows = query(
+            &self.client(),
+            "SELECT id FROM resources WHERE resource_type = $1 ORDER BY id",
+            &[&resource_type],
+        )?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn list_by_last_updated(
+        &self,
+        resource_type: &str,
+        count: usize,
+        offset: usize,
+    ) -> Result<(Vec<(String, Vec<u8>)>, usize)> {
+        let client = self.client();
+        let total: i64 = query(
+            &client,
+            "SELECT COUNT(*) FROM resources WHERE resource_type = $1",
+            &[&resource_type],
+        )?[0]
+            .get(0);
+        let rows = query(
+            &client,
+            "SELECT id, value FROM resources WHERE resource_type = $1 \
+             ORDER BY last_updated DESC NULLS LAST LIMIT $2 OFFSET $3",
+            &[&resource_type, &(count as i64), &(offset as i64)],
+        )?;
+        let entries = rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get::<_, String>(1).into_bytes()))
+            .collect();
+        Ok((entries, total as usize))
+    }
+
+    fn history(&self, query: &HistoryQuery<'_>) -> Result<Vec<HistoryEntry>> {
+        let (sql, args) = history_query(query);
+        let rows = query_compiled(&self.client(), &sql, &args)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| HistoryEntry {
+                key: HistoryKey {
+                    resource_type: row.get(0),
+                    id: row.get(1),
+                    version_id: row.get(2),
+                    last_updated: row.get(3),
+                },
+                value: row.get::<_, String>(4).into_bytes(),
+                deleted: row.get(5),
+            })
+            .collect())
+    }
+
+    fn created_after(&self, resource_type: &str, time: &str) -> Result<Vec<String>> {
+        let rows = query(
+            &self.client(),
+            "SELECT DISTINCT h.id FROM resource_history h \
+             WHERE h.resource_type = $1 AND h.last_updated > $2 AND NOT EXISTS (SELECT 1 FROM resource_history o \
+             WHERE o.resource_type = h.resource_type AND o.id = h.id AND o.last_updated <= $2)",
+            &[&resource_type, &time],
+        )?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    fn type_last_updated(&self, resource_type: &str) -> Result<Option<String>> {
+        let rows = query(
+            &self.client(),
+            "SELECT MAX(last_updated) FROM resource_history WHERE resource_type = $1",
+            &[&resource_type],
+        )?;
+        Ok(rows[0].get(0))
+    }
+
+    fn expunge(&self, resource_type: Option<&str>, id: Option<&str>, mode: ExpungeMode) -> Result<Expunged> {
+        let statements = expunge_statements(resource_type, id, mode, "r.version_id");
+        self.in_transaction(|tx| {
+            let resources: i64 = query_compiled(tx.client, &statements.count, &statements.args)?[0].get(0);
+            let versions = execute_compiled(tx.client, &statements.history, &statements.args)?;
+            if let Some(current) = &statements.current {
+                execute_compiled(tx.client, current, &statements.args)?;
+            }
+            Ok(Expunged {
+                resources: resources as usize,
+                versions: versions as usize,
+            })
+        })
+    }
+
+    fn prune_history(&self, before: &str) -> Result<usize> {
+        let (sql, args) = prune_history_statement(before, "r.version_id");
+        Ok(execute_compiled(&self.client(), &sql, &args)? as usize)
+    }
+
+    fn transaction(&self, f: &mut dyn FnMut(&dyn ResourceTransaction) -> Result<()>) -> Result<()> {
+        self.in_transaction(|tx| f(tx))
+    }
+}
+
+/// Operations within a [`PgStore`] transaction.
+pub struct PgTransaction<'c> {
+    client: &'c Client,
+}
+
+#[allow(clippy::result_large_err)]
+impl ResourceTransaction for PgTransaction<'_> {
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        let row = query_opt(
+            self.client,
+            "SELECT value FROM resources WHERE resource_type = $1 AND id = $2",
+            &[&resource_type, &id],
+        )?;
+        Ok(row.map(|row| row.get::<_, String>(0).into_bytes()))
+    }
+
+    fn put_with_version(&self, resource_type: &str, id: &str, version_id: &str, data: &[u8]) -> Result<()> {
+        let value = utf8(data)?;
+        execute(
+            self.client,
+            "INSERT INTO resources (resource_type, id, value) VALUES ($1, $2, $3) \
+             ON CONFLICT (resource_type, id) DO UPDATE SET value = EXCLUDED.value",
+            &[&resource_type, &id, &value],
+        )?;
+        self.put_history(resource_type, id, version_id, value, false)
+    }
+
+    fn put_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: Option<&str>,
+        new_version: &str,
+        data: &[u8],
+    ) -> Result<bool> {
+        let value = utf8(data)?;
+        // Lock the current row, so a concurrent write waits for this one.
+        let row = query_opt(
+            self.client,
+            "SELECT version_id FROM resources WHERE resource_type = $1 AND id = $2 FOR UPDATE",
+            &[&resource_type, &id],
+        )?;
+        let exists = row.is_some();
+        let current: Option<String> = row.and_then(|row| row.get(0));
+
+        let ok = match (expected_current, current.as_deref()) {
+            (None, None) => true,                       // create, still absent
+            (None, Some(_)) => false,                   // create, but now exists
+            (Some(exp), Some(cur)) => exp == cur,       // update, version unchanged
+            (Some(_), None) => false,                   // update, but vanished
+        };
+        if !ok {
+            return Ok(false);
+        }
+
+        if exists {
+            execute(
+                self.client,
+                "UPDATE resources SET value = $3 WHERE resource_type = $1 AND id = $2",
+                &[&resource_type, &id, &value],
+            )?;
+        } else {
+            // There was no row to lock: a create racing this one may have
+            // inserted it since.
+            let inserted = execute(
+                self.client,
+                "INSERT INTO resources (resource_type, id, value) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&resource_type, &id, &value],
+            )?;
+            if inserted == 0 {
+                return Ok(false);
+            }
+        }
+        self.put_history(resource_type, id, new_version, value, false)?;
+        Ok(true)
+    }
+
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool> {
+        let value = utf8(tombstone)?;
+        let row = query_opt(
+            self.client,
+            "SELECT version_id FROM resources WHERE resource_type = $1 AND id = $2 FOR UPDATE",
+            &[&resource_type, &id],
+        )?;
+        if row.and_then(|row| row.get::<_, Option<String>>(0)).as_deref() != Some(expected_current) {
+            return Ok(false);
+        }
+        execute(
+            self.client,
+            "DELETE FROM resources WHERE resource_type = $1 AND id = $2",
+            &[&resource_type, &id],
+        )?;
+        self.put_history(resource_type, id, new_version, value, true)?;
+        Ok(true)
+    }
+
+    fn latest_version(&self, resource_type: &str, id: &str) -> Result<Option<String>> {
+        let row = query_opt(
+            self.client,
+            "SELECT version_id FROM resource_history WHERE resource_type = $1 AND id = $2 \
+             ORDER BY CASE WHEN version_id ~ '^[0-9]{1,18}$' THEN version_id::int8 END DESC NULLS LAST LIMIT 1",
+            &[&resource_type, &id],
+        )?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn index(&self) -> Box<dyn IndexWrite + '_> {
+        Box::new(PgIndexWriter { client: self.client })
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl PgTransaction<'_> {
+    fn put_history(&self, resource_type: &str, id: &str, version_id: &str, value: &str, deleted: bool) -> Result<()> {
+        execute(
+            self.client,
+            "INSERT INTO resource_history (resource_type, id, version_id, value, deleted) VALUES ($1, $2, $3, $4, $5) \
+             ON CONFLICT (resource_type, id, version_id) DO UPDATE SET value = EXCLUDED.value, deleted = EXCLUDED.deleted",
+            &[&resource_type, &id, &version_id, &value, &deleted],
+        )?;
+        Ok(())
+    }
+}
+
+/// Writes to the search index tables through a borrowed client: the
+/// index's own, or a [`PgStore`] transaction's (see [`PgTransaction::index`]).
+struct PgIndexWriter<'c> {
+    client: &'c Client,
+}
+
+#[allow(clippy::result_large_err)]
+impl PgIndexWriter<'_> {
+    #[allow(clippy::too_many_arguments)]
+    fn insert_row(
+        &self,
+        table: &str,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        let row = IndexRow::new(resource_type, resource_id, param_name, param_type, value_string, value_system);
+        execute(
+            self.client,
+            &format!(
+                "INSERT INTO {table} ({INDEX_COLUMNS}) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) \
+                 ON CONFLICT DO NOTHING"
+            ),
+            &[
+                &row.resource_type.as_ref(),
+                &row.resource_id.as_ref(),
+                &row.param_name,
+                &row.param_type,
+                &row.value_string,
+                &row.value_string_lower,
+                &row.value_system,
+                &row.date_start,
+                &row.date_end,
+                &row.number,
+                &row.code,
+                &row.canonical_value,
+                &row.canonical_unit,
+                &row.molar_mass,
+                &(row.group as i32),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// While a reindex runs, note a resource whose rows are being rewritten
+    /// (see `IndexWriter::mark_dirty`).
+    fn mark_dirty(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        execute(
+            self.client,
+            "INSERT INTO reindex_dirty (resource_type, resource_id) \
+             SELECT $1::text, $2::text WHERE EXISTS (SELECT 1 FROM reindex_job WHERE status = 'in-progress') \
+             ON CONFLICT DO NOTHING",
+            &[&resource_type, &resource_id],
+        )?;
+        Ok(())
+    }
+
+    fn remove_text(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        execute(
+            self.client,
+            "DELETE FROM search_text WHERE resource_type = $1 AND resource_id = $2",
+            &[&resource_type, &resource_id],
+        )?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl IndexWrite for PgIndexWriter<'_> {
+    fn add_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        self.insert_row("search_index", resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
+
+    fn index_text(&self, resource_type: &str, resource_id: &str, narrative: &str, content: &str) -> Result<()> {
+        execute(
+            self.client,
+            "INSERT INTO search_text (resource_type, resource_id, narrative, content) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (resource_type, resource_id) \
+             DO UPDATE SET narrative = EXCLUDED.narrative, content = EXCLUDED.content",
+            &[&resource_type, &resource_id, &narrative, &content],
+        )?;
+        Ok(())
+    }
+
+    fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.mark_dirty(resource_type, resource_id)?;
+        execute(
+            self.client,
+            "DELETE FROM search_index WHERE resource_type = $1 AND resource_id = $2",
+            &[&resource_type, &resource_id],
+        )?;
+        // Contained ids are "<type>/<id>#<local id>": the range ['…#', '…$')
+        // holds exactly this container's.
+        execute(
+            self.client,
+            "DELETE FROM search_index WHERE resource_type LIKE '#%' \
+             AND resource_id >= $1 AND resource_id < $2",
+            &[
+                &format!("{resource_type}/{resource_id}#"),
+                &format!("{resource_type}/{resource_id}$"),
+            ],
+        )?;
+        self.remove_text(resource_type, resource_id)
+    }
+
+    fn remove_param_index(&self, resource_type: &str, resource_id: &str, param_name: &str) -> Result<()> {
+        self.mark_dirty(resource_type, resource_id)?;
+        execute(
+            self.client,
+            "DELETE FROM search_index WHERE resource_type = $1 AND resource_id = $2 \
+             AND (param_name = $3::text OR substr(param_name, 1, length($3) + 1) = $3 || '$')",
+            &[&resource_type, &resource_id, &param_name],
+        )?;
+        Ok(())
+    }
+}
+
+/// PostgreSQL-backed search index, on a connection of its own.
+pub struct PgSearchIndex {
+    client: Client,
+    /// Whether a transaction is open, so a nested one joins it.
+    in_transaction: Cell<bool>,
+}
+
+#[allow(clippy::result_large_err)]
+impl PgSearchIndex {
+    /// Ordered schema migrations. Append-only.
+    const MIGRATIONS: &'static [&'static str] = &[
+        // v1 — the tables of the SQLite index as of its v10. Full text is
+        // matched through generated `tsvector` columns.
+        r#"
+        CREATE TABLE search_index (
+            id BIGSERIAL PRIMARY KEY,
+            resource_type TEXT COLLATE "C" NOT NULL,
+            resource_id TEXT COLLATE "C" NOT NULL,
+            param_name TEXT COLLATE "C" NOT NULL,
+            param_type TEXT NOT NULL,
+            value_string TEXT COLLATE "C",
+            value_string_lower TEXT COLLATE "C",
+            value_system TEXT COLLATE "C",
+            value_date_start BIGINT,
+            value_date_end BIGINT,
+            value_number DOUBLE PRECISION,
+            value_code TEXT COLLATE "C",
+            value_canonical DOUBLE PRECISION,
+            value_canonical_unit TEXT COLLATE "C",
+            value_molar_mass DOUBLE PRECISION,
+            value_group INTEGER NOT NULL DEFAULT -1,
+            UNIQUE (resource_type, resource_id, param_name, value_string, value_system, value_group)
+        );
+        CREATE INDEX idx_type_param_string
+            ON search_index (resource_type, param_name, value_string);
+        CREATE INDEX idx_type_param_string_lower
+            ON search_index (resource_type, param_name, value_string_lower);
+        CREATE INDEX idx_type_param_token
+            ON search_index (resource_type, param_name, value_system, value_string);
+        CREATE INDEX idx_type_param_date
+            ON search_index (resource_type, param_name, value_date_start, value_date_end);
+        CREATE INDEX idx_resource
+            ON search_index (resource_type, resource_id);
+        CREATE INDEX idx_type_param_number
+            ON search_index (resource_type, param_name, value_number);
+        CREATE INDEX idx_type_param_canonical
+            ON search_index (resource_type, param_name, value_canonical_unit, value_canonical);
+        CREATE INDEX idx_contained
+            ON search_index (resource_id) WHERE resource_type LIKE '#%';
+        CREATE INDEX idx_reference
+            ON search_index (value_string) WHERE param_type = 'reference';
+        CREATE TABLE search_index_shadow (LIKE search_index INCLUDING DEFAULTS INCLUDING CONSTRAINTS INCLUDING INDEXES);
+        CREATE TABLE search_index_stats (
+            resource_type TEXT COLLATE "C" NOT NULL,
+            param_name TEXT COLLATE "C" NOT NULL,
+            resources BIGINT NOT NULL,
+            distinct_values BIGINT NOT NULL,
+            PRIMARY KEY (resource_type, param_name)
+        );
+        CREATE TABLE search_text (
+            id BIGSERIAL PRIMARY KEY,
+            resource_type TEXT COLLATE "C" NOT NULL,
+            resource_id TEXT COLLATE "C" NOT NULL,
+            narrative TEXT NOT NULL,
+            content TEXT NOT NULL,
+            narrative_tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', narrative)) STORED,
+            content_tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED,
+            UNIQUE (resource_type, resource_id)
+        );
+        CREATE INDEX idx_text_narrative ON search_text USING GIN (narrative_tsv);
+        CREATE INDEX idx_text_content ON search_text USING GIN (content_tsv);
+        CREATE TABLE reindex_job (
+            id TEXT PRIMARY KEY,
+            resource_type TEXT,
+            param_name TEXT,
+            cursor_type TEXT,
+            cursor_id TEXT,
+            status TEXT NOT NULL,
+            error TEXT,
+            resources_indexed BIGINT NOT NULL DEFAULT 0,
+            entries_written BIGINT NOT NULL DEFAULT 0,
+            started TEXT NOT NULL
+        );
+        -- One run at a time, across every process sharing the database.
+        CREATE UNIQUE INDEX idx_reindex_active ON reindex_job (status) WHERE status = 'in-progress';
+        CREATE TABLE reindex_dirty (
+            resource_type TEXT COLLATE "C" NOT NULL,
+            resource_id TEXT COLLATE "C" NOT NULL,
+            PRIMARY KEY (resource_type, resource_id)
+        );
+        "#,
+        // v2 — one worker per `$reindex` run, however many processes resume
+        // it: the one that claimed it, and when it last showed signs of life.
+        r#"
+        ALTER TABLE reindex_job ADD COLUMN owner TEXT, ADD COLUMN heartbeat TEXT;
+        "#,
+    ];
+
+    /// Connect to the database at `url`, creating the tables if needed.
+    pub fn open(url: &str) -> Result<Self> {
+        let client = connect(url)?;
+        migrate(&client, "search_index", Self::MIGRATIONS)?;
+        Ok(Self {
+            client,
+            in_transaction: Cell::new(false),
+        })
+    }
+
+    fn writer(&self) -> PgIndexWriter<'_> {
+        PgIndexWriter { client: &self.client }
+    }
+
+    /// Run `f` in a transaction: all of its writes land, or none do. Called
+    /// within another, it joins that one.
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Self) -> Result<T>,
+    {
+        if self.in_transaction.replace(true) {
+            return f(self);
+        }
+        struct Close<'a>(&'a Cell<bool>);
+        impl Drop for Close<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+        let _close = Close(&self.in_transaction);
+        let tx = Transaction::begin(&self.client)?;
+        let result = f(self)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    fn query_reindex_job(&self, condition: &str, arg: &str) -> Result<Option<ReindexJob>> {
+        let sql = format!(
+            "SELECT id, resource_type, param_name, cursor_type, cursor_id, status, error, \
+             resources_indexed, entries_written, started FROM reindex_job WHERE {condition}"
+        );
+        let Some(row) = query_opt(&self.client, &sql, &[&arg])? else {
+            return Ok(None);
+        };
+        let cursor_type: Option<String> = row.get(3);
+        let cursor_id: Option<String> = row.get(4);
+        let status = match row.get::<_, &str>(5) {
+            "in-progress" => ReindexStatus::InProgress,
+            "complete" => ReindexStatus::Complete,
+            _ => ReindexStatus::Failed(row.get::<_, Option<String>>(6).unwrap_or_default()),
+        };
+        Ok(Some(ReindexJob {
+            id: row.get(0),
+            resource_type: row.get(1),
+            param_name: row.get(2),
+            cursor: cursor_type.zip(cursor_id),
+            status,
+            resources_indexed: row.get::<_, i64>(7) as usize,
+            entries_written: row.get::<_, i64>(8) as usize,
+            started: row.get(9),
+        }))
+    }
+
+    /// `REINDEX_SCOPE` with its parameters typed, since both may be NULL.
+    fn reindex_scope() -> String {
+        REINDEX_SCOPE.replace("?1", "$1::text").replace("?2", "$2::text")
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl IndexWrite for PgSearchIndex {
+    fn add_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        self.writer().add_index(resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
+
+    fn index_text(&self, resource_type: &str, resource_id: &str, narrative: &str, content: &str) -> Result<()> {
+        self.writer().index_text(resource_type, resource_id, narrative, content)
+    }
+
+    fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.writer().remove_index(resource_type, resource_id)
+    }
+
+    fn remove_param_index(&self, resource_type: &str, resource_id: &str, param_name: &str) -> Result<()> {
+        self.writer().remove_param_index(resource_type, resource_id, param_name)
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl SearchBackend for PgSearchIndex {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    fn add_shadow_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        self.writer()
+            .insert_row("search_index_shadow", resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
+
+    fn row_count(&self) -> Result<usize> {
+        let count: i64 = query(&self.client, "SELECT COUNT(*) FROM search_index", &[])?[0].get(0);
+        Ok(count as usize)
+    }
+
+    fn clear_all(&self) -> Result<()> {
+        batch(&self.client, "DELETE FROM search_index; DELETE FROM search_text;")
+    }
+
+    fn indexed_last_updated(&self, resource_type: &str, resource_id: &str) -> Result<Option<Option<String>>> {
+        let row = &query(
+            &self.client,
+            "SELECT COUNT(*), MAX(CASE WHEN param_name = '_lastUpdated' THEN value_string END) \
+             FROM search_index WHERE resource_type = $1 AND resource_id = $2",
+            &[&resource_type, &resource_id],
+        )?[0];
+        Ok((row.get::<_, i64>(0) > 0).then(|| row.get(1)))
+    }
+
+    fn resource_keys_page(&self, after: Option<(&str, &str)>, limit: usize) -> Result<Vec<(String, String)>> {
+        let (after_type, after_id) = after.unzip();
+        let rows = query(
+            &self.client,
+            "SELECT resource_type, resource_id FROM search_index \
+             WHERE resource_type NOT LIKE '#%' AND ($1::text IS NULL OR (resource_type, resource_id) > ($1, $2::text)) \
+             UNION \
+             SELECT resource_type, resource_id FROM search_text \
+             WHERE $1::text IS NULL OR (resource_type, resource_id) > ($1, $2::text) \
+             ORDER BY 1, 2 LIMIT $3",
+            &[&after_type, &after_id, &(limit as i64)],
+        )?;
+        Ok(rows.into_iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn refresh_statistics(&self) -> Result<()> {
+        self.in_transaction(|index| {
+            batch(
+                &index.client,
+                "DELETE FROM search_index_stats;
+                 INSERT INTO search_index_stats
+                     SELECT resource_type, param_name, COUNT(DISTINCT resource_id),
+                            COUNT(DISTINCT value_string)
+                     FROM search_index GROUP BY resource_type, param_name;",
+            )
+        })
+    }
+
+    fn transaction(&self, f: &mut dyn FnMut(&dyn SearchBackend) -> Result<()>) -> Result<()> {
+        self.in_transaction(|index| f(index))
+    }
+
+    fn begin_reindex(
+        &self,
+        id: &str,
+        resource_type: Option<&str>,
+        param_name: Option<&str>,
+        started: &str,
+    ) -> Result<bool> {
+        self.in_transaction(|index| {
+            // The unique index on the running job turns a concurrent start
+            // into a conflict rather than a second run.
+            let inserted = execute(
+                &index.client,
+                "INSERT INTO reindex_job (id, resource_type, param_name, status, started) \
+                 VALUES ($1, $2, $3, 'in-progress', $4) ON CONFLICT DO NOTHING",
+                &[&id, &resource_type, &param_name, &started],
+            )?;
+            if inserted == 0 {
+                return Ok(false);
+            }
+            batch(&index.client, "DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            Ok(true)
+        })
+    }
+
+    fn reindex_job(&self, id: &str) -> Result<Option<ReindexJob>> {
+        self.query_reindex_job("id = $1", id)
+    }
+
+    fn active_reindex(&self) -> Result<Option<ReindexJob>> {
+        self.query_reindex_job("status = $1", "in-progress")
+    }
+
+    fn claim_reindex(&self, id: &str, owner: &str, stale_before: &str) -> Result<bool> {
+        // The row lock makes concurrent claims take turns; the loser sees the
+        // winner's fresh heartbeat and matches nothing.
+        let claimed = execute(
+            &self.client,
+            "UPDATE reindex_job SET owner = $2, heartbeat = $3 \
+             WHERE id = $1 AND status = 'in-progress' AND (owner IS NULL OR owner = $2 OR heartbeat < $4)",
+            &[&id, &owner, &chrono::Utc::now().to_rfc3339(), &stale_before],
+        )?;
+        Ok(claimed == 1)
+    }
+
+    fn checkpoint_reindex(
+        &self,
+        id: &str,
+        owner: &str,
+        cursor: (&str, &str),
+        resources: usize,
+        entries: usize,
+    ) -> Result<bool> {
+        let updated = execute(
+            &self.client,
+            "UPDATE reindex_job SET cursor_type = $3, cursor_id = $4, \
+             resources_indexed = resources_indexed + $5, entries_written = entries_written + $6, heartbeat = $7 \
+             WHERE id = $1 AND owner = $2 AND status = 'in-progress'",
+            &[&id, &owner, &cursor.0, &cursor.1, &(resources as i64), &(entries as i64), &chrono::Utc::now().to_rfc3339()],
+        )?;
+        Ok(updated == 1)
+    }
+
+    fn finish_reindex(&self, id: &str, owner: &str) -> Result<bool> {
+        self.in_transaction(|index| {
+            // Completing the run first holds its row until the swap commits.
+            let owned = execute(
+                &index.client,
+                "UPDATE reindex_job SET status = 'complete' WHERE id = $1 AND owner = $2 AND status = 'in-progress'",
+                &[&id, &owner],
+            )?;
+            let Some(job) = index.reindex_job(id)?.filter(|_| owned == 1) else {
+                return Ok(false);
+            };
+            execute(
+                &index.client,
+                &format!(
+                    "DELETE FROM search_index AS x WHERE ({}) AND NOT {REINDEX_DIRTY}",
+                    Self::reindex_scope()
+                ),
+                &[&job.resource_type, &job.param_name],
+            )?;
+            execute(
+                &index.client,
+                &format!(
+                    "INSERT INTO search_index ({INDEX_COLUMNS}) \
+                     SELECT {INDEX_COLUMNS} FROM search_index_shadow AS x WHERE NOT {REINDEX_DIRTY} \
+                     ON CONFLICT DO NOTHING"
+                ),
+                &[],
+            )?;
+            batch(&index.client, "DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            Ok(true)
+        })
+    }
+
+    fn fail_reindex(&self, id: &str, owner: &str, error: &str) -> Result<bool> {
+        self.in_transaction(|index| {
+            let owned = execute(
+                &index.client,
+                "UPDATE reindex_job SET status = 'failed', error = $3 \
+                 WHERE id = $1 AND owner = $2 AND status = 'in-progress'",
+                &[&id, &owner, &error],
+            )?;
+            if owned == 0 {
+                return Ok(false);
+            }
+            batch(&index.client, "DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            Ok(true)
+        })
+    }
+
+    fn search_referencing(&self, reference: &str) -> Result<Vec<(String, String)>> {
+        let rows = query(
+            &self.client,
+            "SELECT DISTINCT resource_type, resource_id FROM search_index \
+             WHERE value_string = $1 AND param_type = 'reference' AND resource_type NOT LIKE '#%' \
+             ORDER BY resource_type, resource_id",
+            &[&reference],
+        )?;
+        Ok(rows.into_iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn ids(&self, set: &IdSet) -> Result<Vec<String>> {
+        let rows = query_compiled(&self.client, &set.sql, &set.args)?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    fn count(&self, set: &IdSet) -> Result<usize> {
+        let rows = query_compiled(&self.client, &count_query(set), &set.args)?;
+        Ok(rows[0].get::<_, i64>(0) as usize)
+    }
+
+    fn estimate(&self, resource_type: &str, shape: &Shape) -> Result<Option<usize>> {
+        let rows = query(
+            &self.client,
+            "SELECT param_name, resources, distinct_values FROM search_index_stats WHERE resource_type = $1",
+            &[&resource_type],
+        )?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let stats: ParamStats = rows
+            .into_iter()
+            .map(|row| (row.get(0), (row.get::<_, i64>(1) as f64, row.get::<_, i64>(2) as f64)))
+            .collect();
+        Ok(Some(estimate(shape, &stats).round() as usize))
+    }
+
+    fn page(
+        &self,
+        resource_type: &str,
+        set: &IdSet,
+        sort: &[SortKey],
+        start: &PageStart,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Result<Page> {
+        let query = page_query(Dialect::Postgres, resource_type, set, sort, start, offset, count)?;
+        let rows = query_compiled(&self.client, &query.sql, &query.args)?;
+        let mut cursors = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut keys = Vec::with_capacity(query.keys);
+            for i in 1..=query.keys {
+                keys.push(column_json(&row, i)?);
+            }
+            cursors.push(PageCursor { keys, id: row.get(0) });
+        }
+        Ok(query.finish(cursors))
+    }
+}
+
+/// A sort key column as JSON, for a page cursor.
+fn column_json(row: &Row, i: usize) -> Result<serde_json::Value> {
+    let ty = row.columns()[i].type_();
+    let value = if *ty == Type::INT8 {
+        row.try_get::<_, Option<i64>>(i)?.map(Into::into)
+    } else if *ty == Type::FLOAT8 {
+        row.try_get::<_, Option<f64>>(i)?.map(Into::into)
+    } else if *ty == Type::TEXT {
+        row.try_get::<_, Option<String>>(i)?.map(Into::into)
+    } else {
+        None
+    };
+    Ok(value.unwrap_or(serde_json::Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search_executor::SearchExecutor;
+    use sazare_core::{SearchQuery, TotalMode};
+
+    /// A schema of its own on the server named by `SAZARE_PG_TEST_URL` (a
+    /// `key=value` connection string), dropped afterwards. The tests are
+    /// skipped when it is unset.
+    struct TestDb {
+        admin: Client,
+        schema: String,
+        url: String,
+    }
+
+    impl TestDb {
+        fn new() -> Option<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let base = std::env::var("SAZARE_PG_TEST_URL").ok()?;
+            let schema = format!("sazare_test_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+            let admin = connect(&base).unwrap();
+            batch(&admin, &format!("CREATE SCHEMA {schema}")).unwrap();
+            let url = format!("{base} options='-c search_path={schema}'");
+            Some(Self { admin, schema, url })
+        }
+
+        fn open(&self) -> (PgStore, PgSearchIndex) {
+            (PgStore::open(&self.url).unwrap(), PgSearchIndex::open(&self.url).unwrap())
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = batch(&self.admin, &format!("DROP SCHEMA {} CASCADE", self.schema));
+        }
+    }
+
+    fn put(store: &PgStore, index: &PgSearchIndex, rt: &str, id: &str, body: serde_json::Value) {
+        store.put_with_version(rt, id, "1", &serde_json::to_vec(&body).unwrap()).unwrap();
+        index.add_index(rt, id, "_id", "token", Some(id), None).unwrap();
+    }
+
+    fn sorted(mut v: Vec<String>) -> Vec<String> {
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn test_resource_versions_and_cas() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, _) = db.open();
+        let version = |v: &str| serde_json::to_vec(&serde_json::json!({"resourceType": "Patient", "id": "p1", "meta": {"versionId": v}})).unwrap();
+
+        assert!(store.put_with_version_cas("Patient", "p1", None, "1", &version("1")).unwrap());
+        assert!(!store.put_with_version_cas("Patient", "p1", None, "1", &version("1")).unwrap());
+        assert!(!store.put_with_version_cas("Patient", "p1", Some("7"), "2", &version("2")).unwrap());
+        for v in 2..=10 {
+            let current = (v - 1).to_string();
+            assert!(store.put_with_version_cas("Patient", "p1", Some(&current), &v.to_string(), &version(&v.to_string())).unwrap());
+        }
+        assert_eq!(store.get("Patient", "p1").unwrap(), Some(version("10")));
+        assert_eq!(store.get_version("Patient", "p1", "2").unwrap(), Some(version("2")));
+        assert_eq!(store.list_versions("Patient", "p1").unwrap()[..3], ["10", "9", "8"]);
+
+        assert!(!store.delete_with_version_cas("Patient", "p1", "9", "11", &version("11")).unwrap());
+        assert!(store.delete_with_version_cas("Patient", "p1", "10", "11", &version("11")).unwrap());
+        assert!(store.is_deleted("Patient", "p1").unwrap());
+        assert!(!store.contains("Patient", "p1").unwrap());
+        assert!(!store.put_with_version_cas("Patient", "p1", Some("10"), "12", &version("12")).unwrap());
+        assert_eq!(store.get_version("Patient", "p1", "11").unwrap(), None);
+        assert_eq!(store.list_versions("Patient", "p1").unwrap()[0], "11");
+        let latest = store.in_transaction(|tx| tx.latest_version("Patient", "p1")).unwrap();
+        assert_eq!(latest.as_deref(), Some("11"));
+        let history = store.history(&HistoryQuery { id: Some("p1"), limit: 20, ..Default::default() }).unwrap();
+        let deleted: Vec<_> = history.iter().filter(|e| e.deleted).map(|e| e.key.version_id.as_str()).collect();
+        assert_eq!(deleted, ["11"]);
+    }
+
+    #[test]
+    fn test_listing_pages_in_byte_order() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, _) = db.open();
+        for (rt, id, updated) in [("Patient", "b", "2024-02-01"), ("Patient", "B", "2024-03-01"), ("Patient", "a", "2024-01-01"), ("Group", "g", "2024-04-01")] {
+            let body = serde_json::json!({"resourceType": rt, "id": id, "meta": {"lastUpdated": updated}});
+            store.put_with_version(rt, id, "1", &serde_json::to_vec(&body).unwrap()).unwrap();
+        }
+        let keys = |page: Vec<(String, String, Vec<u8>)>| page.into_iter().map(|(rt, id, _)| format!("{rt}/{id}")).collect::<Vec<_>>();
+
+        // Upper case sorts first, as in SQLite.
+        assert_eq!(store.list_ids("Patient").unwrap(), vec!["B", "a", "b"]);
+        assert_eq!(keys(store.list_page(None, None, 2).unwrap()), vec!["Group/g", "Patient/B"]);
+        assert_eq!(keys(store.list_page(None, Some(("Patient", "B")), 5).unwrap()), vec!["Patient/a", "Patient/b"]);
+        assert_eq!(keys(store.list_page(Some("Patient"), Some(("Patient", "a")), 5).unwrap()), vec!["Patient/b"]);
+        assert_eq!(store.count_by_type().unwrap(), vec![("Group".to_string(), 1), ("Patient".to_string(), 3)]);
+
+        let (entries, total) = store.list_by_last_updated("Patient", 2, 0).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(entries.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["B", "b"]);
+        let last = store.list_last_updated_page(Some(("Patient", "a")), 5).unwrap();
+        assert_eq!(last, vec![("Patient".to_string(), "b".to_string(), Some("2024-02-01".to_string()))]);
+    }
+
+    #[test]
+    fn test_history_since_at_and_paging() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, _) = db.open();
+        for (rt, id, v, updated) in [
+            ("Patient", "a", "1", "2024-01-01T00:00:00+00:00"),
+            ("Patient", "b", "1", "2024-02-01T00:00:00+00:00"),
+            ("Group", "g", "1", "2024-02-15T00:00:00+00:00"),
+            ("Patient", "a", "2", "2024-03-01T00:00:00.250+00:00"),
+        ] {
+            let body = serde_json::json!({"resourceType": rt, "id": id, "meta": {"versionId": v, "lastUpdated": updated}});
+            store.put_with_version(rt, id, v, &serde_json::to_vec(&body).unwrap()).unwrap();
+        }
+        let keys = |query: HistoryQuery| -> Vec<String> {
+            let entries = store.history(&HistoryQuery { limit: 10, ..query }).unwrap();
+            entries.iter().map(|e| format!("{}/{}/{}", e.key.resource_type, e.key.id, e.key.version_id)).collect()
+        };
+
+        assert_eq!(keys(HistoryQuery::default()), ["Patient/a/2", "Group/g/1", "Patient/b/1", "Patient/a/1"]);
+        assert_eq!(keys(HistoryQuery { resource_type: Some("Patient"), id: Some("a"), ..Default::default() }), ["Patient/a/2", "Patient/a/1"]);
+        assert_eq!(keys(HistoryQuery { since: Some("2024-02-01T00:00:00+00:00"), ..Default::default() }), ["Patient/a/2", "Group/g/1", "Patient/b/1"]);
+        assert_eq!(keys(HistoryQuery { at: Some("2024-02-20T00:00:00+00:00"), ..Default::default() }), ["Group/g/1", "Patient/b/1", "Patient/a/1"]);
+
+        let first = store.history(&HistoryQuery { resource_type: Some("Patient"), limit: 2, ..Default::default() }).unwrap();
+        let after = first.last().map(|e| e.key.clone());
+        assert_eq!(keys(HistoryQuery { resource_type: Some("Patient"), after, ..Default::default() }), ["Patient/a/1"]);
+
+        assert_eq!(store.type_last_updated("Patient").unwrap().as_deref(), Some("2024-03-01T00:00:00.250+00:00"));
+        assert_eq!(store.type_last_updated("Observation").unwrap(), None);
+    }
+
+    #[test]
+    fn test_expunge_and_prune_history() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, _) = db.open();
+        let version = |rt: &str, id: &str, v: &str, updated: &str| {
+            serde_json::to_vec(&serde_json::json!({"resourceType": rt, "id": id, "meta": {"versionId": v, "lastUpdated": updated}})).unwrap()
+        };
+        store.put_with_version("Patient", "a", "1", &version("Patient", "a", "1", "2024-01-01T00:00:00+00:00")).unwrap();
+        store.put_with_version("Patient", "b", "1", &version("Patient", "b", "1", "2024-02-01T00:00:00+00:00")).unwrap();
+        store.put_with_version("Group", "g", "1", &version("Group", "g", "1", "2024-02-15T00:00:00+00:00")).unwrap();
+        store.put_with_version("Patient", "a", "2", &version("Patient", "a", "2", "2024-03-01T00:00:00+00:00")).unwrap();
+        let tombstone = version("Patient", "b", "2", "2024-04-01T00:00:00+00:00");
+        assert!(store.delete_with_version_cas("Patient", "b", "1", "2", &tombstone).unwrap());
+        let keys = || -> Vec<String> {
+            let entries = store.history(&HistoryQuery { limit: 10, ..Default::default() }).unwrap();
+            entries.iter().map(|e| format!("{}/{}/{}", e.key.resource_type, e.key.id, e.key.version_id)).collect()
+        };
+        let expunged = |resources, versions| Expunged { resources, versions };
+
+        // The current version stays, a tombstone included.
+        assert_eq!(store.expunge(Some("Patient"), Some("a"), ExpungeMode::PreviousVersions).unwrap(), expunged(1, 1));
+        assert_eq!(store.expunge(None, None, ExpungeMode::PreviousVersions).unwrap(), expunged(1, 1));
+        assert_eq!(keys(), ["Patient/b/2", "Patient/a/2", "Group/g/1"]);
+
+        assert_eq!(store.expunge(Some("Patient"), None, ExpungeMode::DeletedResources).unwrap(), expunged(1, 1));
+        assert!(!store.is_deleted("Patient", "b").unwrap());
+        assert_eq!(store.expunge(Some("Group"), None, ExpungeMode::Everything).unwrap(), expunged(1, 1));
+        assert_eq!(store.get("Group", "g").unwrap(), None);
+        assert_eq!(keys(), ["Patient/a/2"]);
+
+        // Retention spares a stored resource's current version, however old.
+        store.put_with_version("Patient", "a", "3", &version("Patient", "a", "3", "2024-05-01T00:00:00+00:00")).unwrap();
+        assert_eq!(store.prune_history("2024-04-01T00:00:00+00:00").unwrap(), 1);
+        assert_eq!(store.prune_history("2025-01-01T00:00:00+00:00").unwrap(), 0);
+        assert_eq!(keys(), ["Patient/a/3"]);
+    }
+
+    #[test]
+    fn test_transaction_writes_resource_and_index_together() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, index) = db.open();
+        let store: &dyn ResourceStore = &store;
+        let body = br#"{"resourceType":"Patient","id":"p1"}"#;
+
+        let failed: Result<()> = store.in_transaction(|tx| {
+            tx.put_with_version("Patient", "p1", "1", body)?;
+            tx.index().add_index("Patient", "p1", "name", "string", Some("doe"), None)?;
+            Err(StoreError::Other("abort".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(store.get("Patient", "p1").unwrap(), None);
+        assert_eq!(index.row_count().unwrap(), 0);
+
+        store
+            .in_transaction(|tx| {
+                tx.put_with_version("Patient", "p1", "1", body)?;
+                tx.index().add_index("Patient", "p1", "name", "string", Some("doe"), None)
+            })
+            .unwrap();
+        assert!(store.contains("Patient", "p1").unwrap());
+        assert_eq!(index.search_string("Patient", "name", "do", crate::sqlite_index::StringMatch::Prefix).unwrap(), vec!["p1"]);
+    }
+
+    #[test]
+    fn test_search_values_chains_and_totals() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, index) = db.open();
+        for (id, name, birthdate) in [("p1", "doe", "1940-02-01"), ("p2", "roe", "1980-07-04")] {
+            put(&store, &index, "Patient", id, serde_json::json!({"resourceType":"Patient","id":id}));
+            index.add_index("Patient", id, "name", "string", Some(name), None).unwrap();
+            index.add_index("Patient", id, "birthdate", "date", Some(birthdate), None).unwrap();
+        }
+        for (id, subject, code, value) in [("o1", "Patient/p1", "a", "5.4"), ("o2", "Patient/p2", "b", "120"), ("o3", "Patient/p1", "c", "99")] {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            index.add_index("Observation", id, "subject", "reference", Some(subject), None).unwrap();
+            index.add_index("Observation", id, "code", "token", Some(code), Some("http://loinc.org")).unwrap();
+            index
+                .add_index("Observation", id, "value-quantity", "quantity", Some(&format!("{value}|mg")), Some("http://unitsofmeasure.org"))
+                .unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |rt: &str, q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some(rt)).unwrap();
+            sorted(exec.search(rt, &q).unwrap())
+        };
+
+        assert_eq!(search("Patient", "name=D"), vec!["p1"]);
+        assert_eq!(search("Patient", "name:contains=O"), vec!["p1", "p2"]);
+        assert_eq!(search("Patient", "birthdate=lt1950"), vec!["p1"]);
+        assert_eq!(search("Observation", "code=http://loinc.org|b"), vec!["o2"]);
+        assert_eq!(search("Observation", "value-quantity=gt100||mg"), vec!["o2"]);
+        assert_eq!(search("Observation", "value-quantity=5.4||mg"), vec!["o1"]);
+        assert_eq!(search("Observation", "subject:Patient.name=doe&code=b,c"), vec!["o3"]);
+        assert_eq!(search("Patient", "_has:Observation:subject:code=b"), vec!["p2"]);
+        assert_eq!(search("Patient", "name:missing=true"), Vec::<String>::new());
+        assert_eq!(search("Observation", "code:not=a"), vec!["o2", "o3"]);
+
+        let q = SearchQuery::parse_for_resource("code=a,b,c&_count=1&_offset=1&_sort=-code", Some("Observation")).unwrap();
+        assert_eq!(exec.search_with_total("Observation", &q).unwrap(), (vec!["o2".to_string()], 3));
+        let q = SearchQuery::parse_for_resource("code=a,b", Some("Observation")).unwrap();
+        assert_eq!(exec.count("Observation", &q, TotalMode::Accurate).unwrap(), Some(2));
+        index.refresh_statistics().unwrap();
+        assert_eq!(exec.count("Observation", &q, TotalMode::Estimate).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_keyset_pages_resume_from_cursor() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, index) = db.open();
+        for (id, date) in [("o1", Some("2024-03-01")), ("o2", Some("2024-01-01")), ("o3", None), ("o4", Some("2024-02-01")), ("o5", None)] {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            if let Some(date) = date {
+                index.add_index("Observation", id, "date", "date", Some(date), None).unwrap();
+            }
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let q = SearchQuery::parse_for_resource("_sort=-date&_count=2", Some("Observation")).unwrap();
+        let page = |start: &PageStart| exec.search_page("Observation", &q, start, None, TotalMode::Accurate).unwrap();
+
+        let (p1, total) = page(&PageStart::First);
+        assert_eq!((p1.ids.clone(), total, p1.more), (vec!["o1".to_string(), "o4".to_string()], Some(5), true));
+        let p2 = page(&PageStart::After(p1.last.clone().unwrap())).0;
+        assert_eq!(p2.ids, vec!["o2", "o3"]);
+        let p3 = page(&PageStart::After(p2.last.clone().unwrap())).0;
+        assert_eq!((p3.ids.clone(), p3.more), (vec!["o5".to_string()], false));
+        let back = page(&PageStart::Before(p3.first.unwrap())).0;
+        assert_eq!((back.ids, back.more), (vec!["o2".to_string(), "o3".to_string()], true));
+    }
+
+    #[test]
+    fn test_full_text_search() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, index) = db.open();
+        let notes = [
+            ("c1", "Diabetic foot ulcer", "Dressing changed daily"),
+            ("c2", "Chest pain, cardiology referral", "Sharp pain on exertion"),
+            ("c3", "Cardiac arrhythmia", "Follow-up with cardiology"),
+        ];
+        for (id, narrative, note) in notes {
+            let body = serde_json::json!({
+                "resourceType": "Condition", "id": id,
+                "text": {"status": "generated", "div": format!("<div>{narrative}</div>")},
+                "note": [{"text": note}]
+            });
+            put(&store, &index, "Condition", id, body.clone());
+            let (narrative, content) = crate::IndexBuilder::extract_text(&body);
+            index.index_text("Condition", id, &narrative, &content).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Condition")).unwrap();
+            sorted(exec.search("Condition", &q).unwrap())
+        };
+
+        assert_eq!(search("_text=ulcer"), vec!["c1"]);
+        assert!(search("_text=exertion").is_empty());
+        assert_eq!(search("_content=Exertion"), vec!["c2"]);
+        assert_eq!(search("_content=cardi*"), vec!["c2", "c3"]);
+        assert_eq!(search("_content=%22sharp%20pain%22"), vec!["c2"]);
+        assert!(search("_content=%22pain%20sharp%22").is_empty());
+        assert_eq!(search("_content=cardiology%20NOT%20pain"), vec!["c3"]);
+        assert_eq!(search("_text=ulcer%20OR%20arrhythmia"), vec!["c1", "c3"]);
+        assert_eq!(search("_content=(ulcer%20OR%20pain)%20daily"), vec!["c1"]);
+        // Query syntax inside a word is not interpreted.
+        assert_eq!(search("_content=foot:ulcer"), vec!["c1"]);
+        assert!(search("_content=it's").is_empty());
+
+        index.remove_index("Condition", "c1").unwrap();
+        assert!(search("_text=ulcer").is_empty());
+    }
+
+    #[test]
+    fn test_contained_resources_follow_their_container() {
+        let Some(db) = TestDb::new() else { return };
+        let (store, index) = db.open();
+        let body = serde_json::json!({
+            "resourceType": "MedicationRequest", "id": "mr1",
+            "contained": [{"resourceType": "Medication", "id": "a", "code": {"coding": [{"system": "http://rxnorm", "code": "111"}]}}],
+            "medicationReference": {"reference": "#a"}
+        });
+        store.put_with_version("MedicationRequest", "mr1", "1", &serde_json::to_vec(&body).unwrap()).unwrap();
+        for (name, t, v, sys) in crate::IndexBuilder::extract_indices("MedicationRequest", &body) {
+            index.add_index("MedicationRequest", "mr1", &name, &t, Some(&v), sys.as_deref()).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let q = SearchQuery::parse_for_resource("code=111&_contained=true", Some("Medication")).unwrap();
+        assert_eq!(exec.search("Medication", &q).unwrap(), vec!["MedicationRequest/mr1#a"]);
+
+        index.remove_index("MedicationRequest", "mr1").unwrap();
+        assert!(exec.search("Medication", &q).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reindex_swaps_scope_and_keeps_concurrent_writes() {
+        let Some(db) = TestDb::new() else { return };
+        let (_, index) = db.open();
+        for id in ["p1", "p2"] {
+            index.add_index("Patient", id, "name", "string", Some("old"), None).unwrap();
+            index.add_index("Patient", id, "gender", "token", Some("male"), None).unwrap();
+        }
+
+        assert!(index.begin_reindex("job1", Some("Patient"), Some("name"), "2024-01-01T00:00:00Z").unwrap());
+        assert!(!index.begin_reindex("job2", None, None, "2024-01-01T00:00:00Z").unwrap());
+        assert!(index.claim_reindex("job1", "w1", "2000-01-01T00:00:00Z").unwrap());
+        assert!(!index.claim_reindex("job1", "w2", "2000-01-01T00:00:00Z").unwrap());
+        for id in ["p1", "p2"] {
+            index.add_shadow_index("Patient", id, "name", "string", Some("new"), None).unwrap();
+        }
+        assert!(!index.checkpoint_reindex("job1", "w2", ("Patient", "p2"), 2, 2).unwrap());
+        assert!(index.checkpoint_reindex("job1", "w1", ("Patient", "p2"), 2, 2).unwrap());
+        // p2 is rewritten while the run is under way: its rows stay.
+        index.remove_param_index("Patient", "p2", "name").unwrap();
+        index.add_index("Patient", "p2", "name", "string", Some("newest"), None).unwrap();
+        assert!(!index.finish_reindex("job1", "w2").unwrap());
+        assert!(index.finish_reindex("job1", "w1").unwrap());
+        assert!(!index.finish_reindex("job1", "w1").unwrap());
+
+        let job = index.reindex_job("job1").unwrap().unwrap();
+        assert_eq!((job.status, job.cursor, job.resources_indexed), (ReindexStatus::Complete, Some(("Patient".to_string(), "p2".to_string())), 2));
+        assert_eq!(index.active_reindex().unwrap(), None);
+        let name = |value: &str| index.search_string("Patient", "name", value, crate::sqlite_index::StringMatch::Exact).unwrap();
+        assert_eq!((name("old"), name("new"), name("newest")), (vec![], vec!["p1".to_string()], vec!["p2".to_string()]));
+        assert_eq!(sorted(index.search_token("Patient", "gender", None, "male").unwrap()), vec!["p1", "p2"]);
+    }
+}
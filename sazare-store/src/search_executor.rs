@@ -1,16 +1,27 @@
-use crate::sqlite_index::StringMatch;
-use crate::{SearchIndex, SqliteStore};
-use sazare_core::{ChainParameter, HasParameter, SearchParameter, SearchParamType, SearchQuery};
+use crate::search_sql::{
+    date_condition, numeric_condition, quantity_condition, reference_condition,
+    string_condition, token_condition, uri_condition, IdSet, Shape,
+};
+use crate::sqlite_index::{contained_namespace, number_range, Page, PageStart, StringMatch};
+use rusqlite::types::Value as SqlValue;
+use crate::backend::{ResourceStore, SearchBackend};
+use crate::search_sql::Dialect;
+use sazare_core::{
+    ChainLink, ChainParameter, ContainedMode, ContainedType, HasParameter, SearchParameter, SearchParamType,
+    SearchQuery, TotalMode,
+};
+use sazare_core::search_param::{parse_prefix, parse_text_query};
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 
 /// Execute FHIR search queries
 pub struct SearchExecutor<'a> {
-    store: &'a SqliteStore,
-    index: &'a SearchIndex,
+    store: &'a dyn ResourceStore,
+    index: &'a dyn SearchBackend,
 }
 
 impl<'a> SearchExecutor<'a> {
-    pub fn new(store: &'a SqliteStore, index: &'a SearchIndex) -> Self {
+    pub fn new(store: &'a dyn ResourceStore, index: &'a dyn SearchBackend) -> Self {
         Self { store, index }
     }
 
@@ -20,94 +31,7 @@ impl<'a> SearchExecutor<'a> {
         resource_type: &str,
         query: &SearchQuery,
     ) -> Result<Vec<String>, String> {
-        let mut result_ids: Option<Vec<String>> = None;
-
-        // Process each search parameter
-        for param in &query.parameters {
-            let param_results = self.search_parameter(resource_type, param)?;
-
-            // Intersect with existing results (AND logic)
-            result_ids = match result_ids {
-                None => Some(param_results),
-                Some(existing) => {
-                    let intersection: Vec<String> = existing
-                        .into_iter()
-                        .filter(|id| param_results.contains(id))
-                        .collect();
-                    Some(intersection)
-                }
-            };
-
-            // Early exit if no results
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
-            }
-        }
-
-        // Process chain parameters (e.g. subject:Patient.name=Doe)
-        for chain in &query.chain_parameters {
-            let chain_results = self.search_chain(resource_type, chain)?;
-
-            result_ids = match result_ids {
-                None => Some(chain_results),
-                Some(existing) => {
-                    let intersection: Vec<String> = existing
-                        .into_iter()
-                        .filter(|id| chain_results.contains(id))
-                        .collect();
-                    Some(intersection)
-                }
-            };
-
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
-            }
-        }
-
-        // Process reverse-chain (_has) parameters.
-        for has in &query.has_parameters {
-            let has_results = self.search_has(resource_type, has)?;
-
-            result_ids = match result_ids {
-                None => Some(has_results),
-                Some(existing) => Some(
-                    existing
-                        .into_iter()
-                        .filter(|id| has_results.contains(id))
-                        .collect(),
-                ),
-            };
-
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
-            }
-        }
-
-        // If no search parameters were given, return all resources of this type
-        let mut ids = match result_ids {
-            Some(ids) => ids,
-            None => {
-                // No parameters: list all resource IDs (id column only — don't
-                // load every resource body just to drop it).
-                self.store.list_ids(resource_type).map_err(|e| e.to_string())?
-            }
-        };
-
-        // Apply pagination
-        if let Some(offset) = query.offset {
-            ids = ids.into_iter().skip(offset).collect();
-        }
-        if let Some(count) = query.count {
-            ids.truncate(count);
-        }
-
-        Ok(ids)
+        self.search_with_total(resource_type, query).map(|(ids, _)| ids)
     }
 
     /// Execute a search query and return matching resource IDs with total count.
@@ -117,436 +41,612 @@ impl<'a> SearchExecutor<'a> {
         resource_type: &str,
         query: &SearchQuery,
     ) -> Result<(Vec<String>, usize), String> {
-        let mut result_ids: Option<Vec<String>> = None;
-
-        for param in &query.parameters {
-            let param_results = self.search_parameter(resource_type, param)?;
-            result_ids = match result_ids {
-                None => Some(param_results),
-                Some(existing) => {
-                    let intersection: Vec<String> = existing
-                        .into_iter()
-                        .filter(|id| param_results.contains(id))
-                        .collect();
-                    Some(intersection)
-                }
-            };
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
-            }
-        }
+        let (page, total) =
+            self.search_page(resource_type, query, &PageStart::First, None, TotalMode::Accurate)?;
+        Ok((page.ids, total.unwrap_or(0)))
+    }
 
-        for chain in &query.chain_parameters {
-            let chain_results = self.search_chain(resource_type, chain)?;
-            result_ids = match result_ids {
-                None => Some(chain_results),
-                Some(existing) => {
-                    let intersection: Vec<String> = existing
-                        .into_iter()
-                        .filter(|id| chain_results.contains(id))
-                        .collect();
-                    Some(intersection)
-                }
-            };
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
+    /// Execute a search query and return one page of it plus the total count.
+    ///
+    /// The query compiles to a single SQL statement over the search index (see
+    /// [`IdSet`]); the database orders it by the `_sort` keys and then by resource
+    /// id, so the order is deterministic, and reads back only the page. The
+    /// page begins at `start` and then skips `_offset` rows. With a `snapshot`
+    /// time, resources created after it are left out, so a paged search (and
+    /// its total) doesn't grow after its first page. Resources updated since
+    /// stay in, matched and ordered by their current version.
+    /// The total is counted, estimated or skipped (`None`) per `total`.
+    ///
+    /// With `_contained`, resources contained in others match too, under ids
+    /// of the form `<container type>/<container id>#<local id>`
+    /// (see [`load_matches`](Self::load_matches)).
+    pub fn search_page(
+        &self,
+        resource_type: &str,
+        query: &SearchQuery,
+        start: &PageStart,
+        snapshot: Option<DateTime<Utc>>,
+        total: TotalMode,
+    ) -> Result<(Page, Option<usize>), String> {
+        let (mut set, stats_type) = compile_scoped(self.index.dialect(), resource_type, query)?;
+        // Estimates ignore the snapshot: it only holds back the few resources
+        // created since the first page.
+        let shape = set.shape.clone();
+        if let Some(snapshot) = snapshot {
+            let created = self
+                .store
+                .created_after(resource_type, &snapshot.to_rfc3339())
+                .map_err(|e| e.to_string())?;
+            if !created.is_empty() {
+                set = set.except(IdSet::of(&created));
             }
         }
+        let page = self
+            .index
+            .page(resource_type, &set, &query.sort, start, query.offset.unwrap_or(0), query.count)
+            .map_err(|e| e.to_string())?;
+        let total = self.total(&stats_type, &set, &shape, total)?;
+        Ok((page, total))
+    }
 
-        for has in &query.has_parameters {
-            let has_results = self.search_has(resource_type, has)?;
-            result_ids = match result_ids {
-                None => Some(has_results),
-                Some(existing) => Some(
-                    existing
-                        .into_iter()
-                        .filter(|id| has_results.contains(id))
-                        .collect(),
-                ),
-            };
-            if let Some(ref ids) = result_ids
-                && ids.is_empty()
-            {
-                break;
-            }
-        }
+    /// Number of resources matching the query's filters, ignoring paging
+    /// (`_summary=count`), counted or estimated per `mode` (`None` for
+    /// [`TotalMode::None`]).
+    pub fn count(
+        &self,
+        resource_type: &str,
+        query: &SearchQuery,
+        mode: TotalMode,
+    ) -> Result<Option<usize>, String> {
+        let (set, stats_type) = compile_scoped(self.index.dialect(), resource_type, query)?;
+        self.total(&stats_type, &set, &set.shape, mode)
+    }
 
-        let mut ids = match result_ids {
-            Some(ids) => ids,
-            None => self.store.list_ids(resource_type).map_err(|e| e.to_string())?,
+    /// `Bundle.total` for a compiled query. An estimate comes from the index
+    /// statistics without running the query, falling back to an exact count
+    /// when the type has no statistics yet.
+    fn total(
+        &self,
+        resource_type: &str,
+        set: &IdSet,
+        shape: &Shape,
+        mode: TotalMode,
+    ) -> Result<Option<usize>, String> {
+        let estimate = match mode {
+            TotalMode::None => return Ok(None),
+            TotalMode::Estimate => self
+                .index
+                .estimate(resource_type, shape)
+                .map_err(|e| e.to_string())?,
+            TotalMode::Accurate => None,
         };
-
-        let total = ids.len();
-
-        // Apply pagination
-        if let Some(offset) = query.offset {
-            ids = ids.into_iter().skip(offset).collect();
-        }
-        if let Some(count) = query.count {
-            ids.truncate(count);
+        match estimate {
+            Some(n) => Ok(Some(n)),
+            None => self.index.count(set).map(Some).map_err(|e| e.to_string()),
         }
+    }
 
-        Ok((ids, total))
+    /// Whether `_sort` can order by a parameter of this type. Token, string and
+    /// date parameters have a comparable index column; references and numbers
+    /// do not.
+    pub fn is_sortable(param_type: &SearchParamType) -> bool {
+        matches!(
+            param_type,
+            SearchParamType::Token | SearchParamType::String | SearchParamType::Date
+        )
     }
 
-    /// Search for a single parameter
-    ///
-    /// FHIR spec: comma-separated values in a single param mean OR.
-    /// e.g. `intent=order,plan` → resources matching `order` OR `plan`.
-    fn search_parameter(
+    /// Load full resources for the given IDs
+    pub fn load_resources(
         &self,
         resource_type: &str,
-        param: &SearchParameter,
-    ) -> Result<Vec<String>, String> {
-        // `:missing` — presence/absence of the parameter, independent of value.
-        if param.modifier.as_deref() == Some("missing") {
-            let want_missing = match param.value.trim() {
-                "true" => true,
-                "false" => false,
-                other => return Err(format!(":missing expects true|false, got '{other}'")),
-            };
-            let present = self
-                .index
-                .ids_with_param(resource_type, &param.name)
-                .map_err(|e| e.to_string())?;
-            if !want_missing {
-                return Ok(present);
+        ids: &[String],
+    ) -> Result<Vec<Value>, String> {
+        let mut resources = Vec::new();
+
+        for id in ids {
+            // A deleted resource or a stale index entry is skipped.
+            if let Some(resource) = self.load(resource_type, id)? {
+                resources.push(resource);
             }
-            let present: std::collections::HashSet<String> = present.into_iter().collect();
-            let all = self.store.list_ids(resource_type).map_err(|e| e.to_string())?;
-            return Ok(all.into_iter().filter(|id| !present.contains(id)).collect());
-        }
-
-        // `:not` — every resource of the type EXCEPT those matching the value(s).
-        // FHIR: a resource with no value for the param is included in `:not`.
-        if param.modifier.as_deref() == Some("not") {
-            let mut inner = param.clone();
-            inner.modifier = None;
-            let matched: std::collections::HashSet<String> = self
-                .search_parameter(resource_type, &inner)?
-                .into_iter()
-                .collect();
-            let all = self.store.list_ids(resource_type).map_err(|e| e.to_string())?;
-            return Ok(all.into_iter().filter(|id| !matched.contains(id)).collect());
         }
 
-        let values: Vec<&str> = param.value.split(',').collect();
-        if values.len() == 1 {
-            return self.search_parameter_single(resource_type, param, &param.value);
-        }
+        Ok(resources)
+    }
 
-        // Multi-value: union results across each value
-        let mut union: std::collections::HashSet<String> = std::collections::HashSet::new();
-        for v in values {
-            let v = v.trim();
-            if v.is_empty() {
-                continue;
-            }
-            let ids = self.search_parameter_single(resource_type, param, v)?;
-            union.extend(ids);
+    fn load(&self, resource_type: &str, id: &str) -> Result<Option<Value>, String> {
+        match self.store.get(resource_type, id) {
+            Ok(Some(data)) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| format!("Failed to parse resource: {}", e)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Failed to load resource {}/{}: {}", resource_type, id, e)),
         }
-        Ok(union.into_iter().collect())
     }
 
-    /// Search for a single parameter with a single value (no comma).
-    fn search_parameter_single(
+    /// Load the resources for the ids of a search page, each with its
+    /// reference. A contained match (`Type/id#local`) resolves per
+    /// `_containedType`: to its container, listed once however many of its
+    /// contained resources matched, or to the contained resource itself.
+    pub fn load_matches(
         &self,
         resource_type: &str,
-        param: &SearchParameter,
-        value: &str,
-    ) -> Result<Vec<String>, String> {
-        match param.param_type {
-            SearchParamType::Token => {
-                // FHIR token forms: `system|code`, `code` (any system),
-                // `|code` (no system), `system|` (any code in the system).
-                match value.split_once('|') {
-                    Some((system, "")) => self
-                        .index
-                        .search_token_system_only(resource_type, &param.name, system)
-                        .map_err(|e| e.to_string()),
-                    Some(("", code)) => self
-                        .index
-                        .search_token_no_system(resource_type, &param.name, code)
-                        .map_err(|e| e.to_string()),
-                    Some((system, code)) => self
-                        .index
-                        .search_token(resource_type, &param.name, Some(system), code)
-                        .map_err(|e| e.to_string()),
-                    None => self
-                        .index
-                        .search_token(resource_type, &param.name, None, value)
-                        .map_err(|e| e.to_string()),
+        ids: &[String],
+        contained_type: ContainedType,
+    ) -> Result<Vec<(String, Value)>, String> {
+        let mut matches = Vec::new();
+        let mut containers = std::collections::HashSet::new();
+        for id in ids {
+            let Some((container, local_id)) = id.split_once('#') else {
+                if let Some(resource) = self.load(resource_type, id)? {
+                    matches.push((format!("{resource_type}/{id}"), resource));
                 }
+                continue;
+            };
+            let Some((container_type, container_id)) = container.split_once('/') else {
+                continue;
+            };
+            if contained_type == ContainedType::Container && !containers.insert(container.to_string()) {
+                continue;
             }
-            SearchParamType::String => {
-                let mode = match param.modifier.as_deref() {
-                    Some("exact") => StringMatch::Exact,
-                    Some("contains") => StringMatch::Contains,
-                    _ => StringMatch::Prefix,
-                };
-                self.index.search_string(resource_type, &param.name, value, mode)
-                    .map_err(|e| e.to_string())
-            }
-            SearchParamType::Date => {
-                let prefix = param.prefix.as_deref().unwrap_or("eq");
-                self.index.search_date_with_prefix(resource_type, &param.name, prefix, value)
-                    .map_err(|e| e.to_string())
-            }
-            SearchParamType::Reference => {
-                self.index.search_reference(resource_type, &param.name, value)
-                    .map_err(|e| e.to_string())
-            }
-            SearchParamType::Number => {
-                // Number search isn't implemented. Return an explicit error
-                // rather than silently matching nothing (which looks like a
-                // valid "no results" to the client).
-                Err(format!(
-                    "Number search is not supported for parameter '{}'",
-                    param.name
-                ))
+            let Some(resource) = self.load(container_type, container_id)? else {
+                continue;
+            };
+            match contained_type {
+                ContainedType::Container => matches.push((container.to_string(), resource)),
+                ContainedType::Contained => {
+                    let inner = resource
+                        .get("contained")
+                        .and_then(|c| c.as_array())
+                        .and_then(|c| {
+                            c.iter().find(|r| r.get("id").and_then(|i| i.as_str()) == Some(local_id))
+                        });
+                    if let Some(inner) = inner {
+                        matches.push((id.clone(), inner.clone()));
+                    }
+                }
             }
         }
+        Ok(matches)
     }
 
-    /// Execute a chain search: search the target type first, then find
-    /// source resources that reference the matched targets.
+    /// Load the resources a page of matches pulls in through `_include`,
+    /// `_revinclude` and their `:iterate` forms.
     ///
-    /// Example: `subject:Patient.name=Doe` on Observation
-    /// 1. Search Patient where name=Doe → [Patient/p1, Patient/p2]
-    /// 2. Search Observation where subject = Patient/p1 OR Patient/p2
+    /// `_include` specs are `SourceType:search-param[:TargetType]`, following
+    /// the parameter's references out of each match; `_revinclude` specs are
+    /// `SourceType:search-param`, finding the resources whose parameter points
+    /// back at a top-level match of `resource_type`. Either parameter may be
+    /// `*` (any reference), and a bare `*` is short for `*:*`.
+    /// `:iterate` specs then run again over each round of newly included
+    /// resources until a round adds nothing.
     ///
-    /// Resolve a (possibly multi-level) chained search. The terminal parameter
-    /// is searched against the final target type, then each reference hop is
-    /// walked backward toward `resource_type`. For
-    /// `Observation?subject:Patient.organization:Organization.name=Acme`:
-    ///   1. `Organization?name=Acme` -> org ids
-    ///   2. `Patient` whose `organization` references those orgs -> patient ids
-    ///   3. `Observation` whose `subject` references those patients -> result
-    fn search_chain(
+    /// `registry` maps a search-parameter name to the JSON element it reads, so
+    /// `_include`s whose parameter name differs from the element resolve
+    /// correctly (`Observation:patient` → `subject`, hyphenated
+    /// `general-practitioner` → `generalPractitioner`). A resource appears
+    /// once, never both as a match and an include, which also ends reference
+    /// cycles. At most `limit` resources are included.
+    pub fn resolve_includes(
         &self,
         resource_type: &str,
-        chain: &ChainParameter,
-    ) -> Result<Vec<String>, String> {
-        let Some(last) = chain.links.last() else {
-            return Ok(Vec::new());
-        };
-
-        // Search the final target type by the terminal parameter.
-        let terminal = SearchParameter {
-            name: chain.target_param.clone(),
-            value: chain.value.clone(),
-            modifier: None,
-            prefix: if chain.target_param_type == SearchParamType::Date {
-                Some("eq".to_string())
-            } else {
-                None
-            },
-            param_type: chain.target_param_type.clone(),
-        };
-        let mut current_ids = self.search_parameter(&last.target_type, &terminal)?;
+        matches: &[(String, Value)],
+        query: &SearchQuery,
+        registry: &sazare_core::SearchParamRegistry,
+        limit: usize,
+    ) -> Result<Included, String> {
+        let mut included = Included::default();
+        let mut seen: std::collections::HashSet<String> =
+            matches.iter().map(|(reference, _)| reference.clone()).collect();
+        let (include_first, revinclude_first): (Vec<&String>, Vec<&String>) = (
+            query.include.iter().chain(&query.include_iterate).collect(),
+            query.revinclude.iter().chain(&query.revinclude_iterate).collect(),
+        );
 
-        // Walk hops backward. For hop i, the resources holding `reference_param`
-        // are of the previous hop's target type (or `resource_type` at i == 0).
-        for i in (0..chain.links.len()).rev() {
-            if current_ids.is_empty() {
-                return Ok(Vec::new());
-            }
-            let link = &chain.links[i];
-            let source_type: &str = if i == 0 {
-                resource_type
+        // Round one: the matches, by every spec. `_revinclude` follows only
+        // top-level matches of the searched type, not containers or contained
+        // resources, which aren't referenced as such.
+        let mut frontier: Vec<Value> = Vec::new();
+        for (reference, resource) in matches {
+            let revinclude: &[&String] = if reference.starts_with(&format!("{resource_type}/"))
+                && !reference.contains('#')
+            {
+                &revinclude_first
             } else {
-                &chain.links[i - 1].target_type
+                &[]
             };
+            let found = self.related(resource, &include_first, revinclude, registry)?;
+            if self.admit(found, &mut seen, &mut included, &mut frontier, limit)? {
+                return Ok(included);
+            }
+        }
 
-            let mut next_ids: Vec<String> = Vec::new();
-            for cid in &current_ids {
-                let reference = format!("{}/{}", link.target_type, cid);
-                let ids = self
-                    .index
-                    .search_reference(source_type, &link.reference_param, &reference)
-                    .map_err(|e| e.to_string())?;
-                for id in ids {
-                    if !next_ids.contains(&id) {
-                        next_ids.push(id);
-                    }
+        // Later rounds: what was just included, by the `:iterate` specs.
+        let include_iterate: Vec<&String> = query.include_iterate.iter().collect();
+        let revinclude_iterate: Vec<&String> = query.revinclude_iterate.iter().collect();
+        if include_iterate.is_empty() && revinclude_iterate.is_empty() {
+            return Ok(included);
+        }
+        while !frontier.is_empty() {
+            let mut next = Vec::new();
+            for resource in &frontier {
+                let found = self.related(resource, &include_iterate, &revinclude_iterate, registry)?;
+                if self.admit(found, &mut seen, &mut included, &mut next, limit)? {
+                    return Ok(included);
                 }
             }
-            current_ids = next_ids;
+            frontier = next;
         }
-
-        Ok(current_ids)
+        Ok(included)
     }
 
-    /// Execute a one-level `_has` reverse chain: find source resources matching
-    /// the inner search parameter, then return the searched-type resources they
-    /// reference back. The mirror of [`search_chain`].
-    ///
-    /// `Patient?_has:Observation:patient:code=1234-5`:
-    /// 1. `Observation?code=1234-5` -> matching observation ids
-    /// 2. follow each observation's `patient` reference -> the Patient ids
-    fn search_has(
+    /// The `(type, id)` of every resource `resource` includes by `includes`
+    /// and revincludes by `revincludes`.
+    fn related(
         &self,
-        resource_type: &str,
-        has: &HasParameter,
-    ) -> Result<Vec<String>, String> {
-        let inner = SearchParameter {
-            name: has.target_param.clone(),
-            value: has.value.clone(),
-            modifier: None,
-            prefix: if has.target_param_type == SearchParamType::Date {
-                Some("eq".to_string())
+        resource: &Value,
+        includes: &[&String],
+        revincludes: &[&String],
+        registry: &sazare_core::SearchParamRegistry,
+    ) -> Result<Vec<(String, String)>, String> {
+        let own_type = resource.get("resourceType").and_then(|v| v.as_str()).unwrap_or("");
+        let id = resource.get("id").and_then(|v| v.as_str()).unwrap_or("");
+        let mut found = Vec::new();
+
+        for spec in includes {
+            let parts: Vec<&str> = spec.split(':').collect();
+            // `*` alone: every reference of any match.
+            let (source_type, search_param, target_filter) = match parts.as_slice() {
+                ["*"] => ("*", "*", None),
+                [source, param] => (*source, *param, None),
+                [source, param, target] => (*source, *param, Some(*target)),
+                _ => continue,
+            };
+            if source_type != "*" && source_type != own_type {
+                continue;
+            }
+            let references = if search_param == "*" {
+                let mut all = Vec::new();
+                collect_references(resource, &mut all);
+                all
             } else {
-                None
-            },
-            param_type: has.target_param_type.clone(),
-        };
-        let source_ids = self.search_parameter(&has.source_type, &inner)?;
-        if source_ids.is_empty() {
-            return Ok(Vec::new());
+                // Resolve the JSON element the parameter reads (registry
+                // first, then the parameter name itself / its choice +
+                // camelCase fallbacks).
+                let element = registry
+                    .reference_element(own_type, search_param)
+                    .unwrap_or_else(|| search_param.to_string());
+                extract_references(resource, &element)
+            };
+            for reference in references {
+                if let Some((ref_type, ref_id)) = parse_reference(&reference)
+                    && target_filter.is_none_or(|t| t == ref_type)
+                {
+                    found.push((ref_type.to_string(), ref_id.to_string()));
+                }
+            }
         }
-        self.index
-            .referenced_targets(&has.source_type, &has.reference_param, &source_ids, resource_type)
-            .map_err(|e| e.to_string())
-    }
 
-    /// Load full resources for the given IDs
-    pub fn load_resources(
-        &self,
-        resource_type: &str,
-        ids: &[String],
-    ) -> Result<Vec<Value>, String> {
-        let mut resources = Vec::new();
-
-        for id in ids {
-            match self.store.get(resource_type, id) {
-                Ok(Some(data)) => {
-                    let resource: Value = serde_json::from_slice(&data)
-                        .map_err(|e| format!("Failed to parse resource: {}", e))?;
-                    resources.push(resource);
-                }
-                Ok(None) => {
-                    // Resource was deleted or index is stale, skip
-                }
-                Err(e) => {
-                    return Err(format!("Failed to load resource {}/{}: {}", resource_type, id, e));
+        if id.is_empty() {
+            return Ok(found);
+        }
+        let reference = format!("{own_type}/{id}");
+        for spec in revincludes {
+            let (source_type, search_param) = match spec.split_once(':') {
+                Some(parts) => parts,
+                None if spec.as_str() == "*" => ("*", "*"),
+                None => continue,
+            };
+            if search_param == "*" {
+                let sources = self.index.search_referencing(&reference).map_err(|e| e.to_string())?;
+                found.extend(sources.into_iter().filter(|(t, _)| source_type == "*" || t == source_type));
+            } else {
+                // `*:param`: every type with a reference parameter of that name.
+                let source_types = match source_type {
+                    "*" => registry.types_with_reference_param(search_param),
+                    _ => vec![source_type.to_string()],
+                };
+                for source_type in source_types {
+                    let ids = self
+                        .index
+                        .search_reference(&source_type, search_param, &reference)
+                        .map_err(|e| e.to_string())?;
+                    found.extend(ids.into_iter().map(|mid| (source_type.clone(), mid)));
                 }
             }
         }
-
-        Ok(resources)
+        Ok(found)
     }
 
-    /// Process _revinclude parameter to load resources that reference the search results.
-    ///
-    /// Each revinclude spec is `TargetType:search-param`, e.g. `Observation:subject`.
-    /// For each resource in the search results, find TargetType resources whose
-    /// search-param references `{resource_type}/{id}`.
-    pub fn process_revincludes(
+    /// Load each not-yet-seen resource into `included` and `frontier`.
+    /// Returns `true` once `limit` is reached, marking `included` truncated
+    /// if anything was left out.
+    fn admit(
         &self,
-        resources: &[Value],
-        resource_type: &str,
-        revincludes: &[String],
-    ) -> Result<Vec<Value>, String> {
-        let mut included = Vec::new();
-        let mut seen_ids: Vec<String> = Vec::new();
-
-        for revinclude_spec in revincludes {
-            let parts: Vec<&str> = revinclude_spec.split(':').collect();
-            if parts.len() != 2 {
+        found: Vec<(String, String)>,
+        seen: &mut std::collections::HashSet<String>,
+        included: &mut Included,
+        frontier: &mut Vec<Value>,
+        limit: usize,
+    ) -> Result<bool, String> {
+        for (ref_type, ref_id) in found {
+            let key = format!("{ref_type}/{ref_id}");
+            if seen.contains(&key) {
                 continue;
             }
-            let (target_type, search_param) = (parts[0], parts[1]);
+            if included.resources.len() >= limit {
+                included.truncated = true;
+                return Ok(true);
+            }
+            seen.insert(key);
+            if let Some(resource) = self.load(&ref_type, &ref_id)? {
+                included.resources.push(resource.clone());
+                frontier.push(resource);
+            }
+        }
+        Ok(false)
+    }
+}
 
-            for resource in resources {
-                let id = resource.get("id").and_then(|v| v.as_str()).unwrap_or("");
-                if id.is_empty() {
-                    continue;
-                }
-                let reference = format!("{}/{}", resource_type, id);
+/// Resources a searchset pulls in beside its matches.
+#[derive(Debug, Default)]
+pub struct Included {
+    /// Each included resource once, none of them also a match.
+    pub resources: Vec<Value>,
+    /// Whether the cap left some out.
+    pub truncated: bool,
+}
 
-                let matching_ids = self
-                    .index
-                    .search_reference(target_type, search_param, &reference)
-                    .map_err(|e| e.to_string())?;
+/// Compile the query over the resources `_contained` selects: top-level
+/// ones, those contained in another resource (kept in the index under
+/// [`contained_namespace`]), or both. Also returns the index type whose
+/// statistics estimate the result.
+fn compile_scoped(dialect: Dialect, resource_type: &str, query: &SearchQuery) -> Result<(IdSet, String), String> {
+    let contained = contained_namespace(resource_type);
+    Ok(match query.contained {
+        ContainedMode::False => (compile(dialect, resource_type, query)?, resource_type.to_string()),
+        ContainedMode::True => (compile(dialect, &contained, query)?, contained),
+        ContainedMode::Both => (
+            compile(dialect, resource_type, query)?.union(compile(dialect, &contained, query)?),
+            resource_type.to_string(),
+        ),
+    })
+}
 
-                for mid in &matching_ids {
-                    let key = format!("{}/{}", target_type, mid);
-                    if seen_ids.contains(&key) {
-                        continue;
-                    }
-                    seen_ids.push(key);
+/// Compile the query's filters (AND across parameters, chains and `_has`)
+/// into one id set. A query without filters matches every resource of the type.
+fn compile(dialect: Dialect, resource_type: &str, query: &SearchQuery) -> Result<IdSet, String> {
+    let mut sets = Vec::new();
+    for param in &query.parameters {
+        sets.push(parameter_set(dialect, resource_type, param)?);
+    }
+    // Chain parameters (e.g. subject:Patient.name=Doe)
+    for chain in &query.chain_parameters {
+        sets.push(chain_set(dialect, resource_type, chain)?);
+    }
+    // Reverse-chain (_has) parameters.
+    for has in &query.has_parameters {
+        sets.push(has_set(dialect, resource_type, has)?);
+    }
+    Ok(sets
+        .into_iter()
+        .reduce(IdSet::intersect)
+        .unwrap_or_else(|| IdSet::all(resource_type)))
+}
 
-                    if let Ok(Some(data)) = self.store.get(target_type, mid)
-                        && let Ok(val) = serde_json::from_slice::<Value>(&data)
-                    {
-                        included.push(val);
-                    }
-                }
-            }
+/// Ids matching a single parameter.
+///
+/// FHIR spec: comma-separated values in a single param mean OR.
+/// e.g. `intent=order,plan` → resources matching `order` OR `plan`.
+fn parameter_set(dialect: Dialect, resource_type: &str, param: &SearchParameter) -> Result<IdSet, String> {
+    // `:missing` — presence/absence of the parameter, independent of value.
+    if param.modifier.as_deref() == Some("missing") {
+        let mut present = IdSet::with_param(resource_type, &param.name);
+        if param.param_type == SearchParamType::Reference {
+            // An identifier-only reference is present too.
+            present = present.union(IdSet::with_param(resource_type, &format!("{}:identifier", param.name)));
         }
+        return match param.value.trim() {
+            "true" => Ok(IdSet::all(resource_type).except(present)),
+            "false" => Ok(present),
+            other => Err(format!(":missing expects true|false, got '{other}'")),
+        };
+    }
 
-        Ok(included)
+    // `:not` — every resource of the type EXCEPT those matching the value(s).
+    // FHIR: a resource with no value for the param is included in `:not`.
+    if param.modifier.as_deref() == Some("not") {
+        let mut inner = param.clone();
+        inner.modifier = None;
+        let matched = parameter_set(dialect, resource_type, &inner)?;
+        return Ok(IdSet::all(resource_type).except(matched));
     }
 
-    /// Process _include parameter to load related resources.
-    ///
-    /// `registry` maps a search-parameter name to the JSON element it reads, so
-    /// `_include`s whose parameter name differs from the element resolve
-    /// correctly (`Observation:patient` → `subject`, hyphenated
-    /// `general-practitioner` → `generalPractitioner`). Results are de-duplicated
-    /// (a resource SHALL appear once in a searchset Bundle).
-    pub fn process_includes(
-        &self,
-        resources: &[Value],
-        includes: &[String],
-        registry: &sazare_core::SearchParamRegistry,
-    ) -> Result<Vec<Value>, String> {
-        let mut included = Vec::new();
-        let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-        for include_spec in includes {
-            // Spec: `SourceType:search-param` or `SourceType:search-param:TargetType`.
-            // The optional target type is only a filter on the included results.
-            let parts: Vec<&str> = include_spec.split(':').collect();
-            if parts.len() < 2 || parts.len() > 3 {
-                continue;
+    let mut sets = Vec::new();
+    for v in param.value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        sets.push(value_set(dialect, resource_type, param, v)?);
+    }
+    Ok(sets.into_iter().reduce(IdSet::union).unwrap_or_else(IdSet::empty))
+}
+
+/// Ids matching a single parameter with a single value (no comma).
+fn value_set(dialect: Dialect, resource_type: &str, param: &SearchParameter, value: &str) -> Result<IdSet, String> {
+    let name = &param.name;
+    // Full-text search over the narrative or the whole resource.
+    if name == "_text" || name == "_content" {
+        let query = parse_text_query(value)?;
+        let column = if name == "_text" { "narrative" } else { "content" };
+        return Ok(IdSet::text(dialect, resource_type, column, &query));
+    }
+    // Token modifiers matched against the companion rows `IndexBuilder`
+    // writes alongside the token itself.
+    if param.param_type == SearchParamType::Token {
+        match param.modifier.as_deref() {
+            Some("text") => {
+                let mut args = Vec::new();
+                let cond = string_condition("s", value, StringMatch::Prefix, &mut args);
+                return Ok(IdSet::matching(resource_type, &format!("{name}:text"), &cond, args));
             }
-            let source_type = parts[0];
-            let search_param = parts[1];
-            let target_filter = parts.get(2).copied();
-
-            // Resolve the JSON element the parameter reads (registry first, then
-            // the parameter name itself / its choice + camelCase fallbacks).
-            let element = registry
-                .reference_element(source_type, search_param)
-                .unwrap_or_else(|| search_param.to_string());
-
-            for resource in resources {
-                for reference in extract_references(resource, &element) {
-                    if let Some((ref_type, ref_id)) = parse_reference(&reference) {
-                        if let Some(t) = target_filter
-                            && t != ref_type
-                        {
-                            continue;
-                        }
-                        let key = format!("{ref_type}/{ref_id}");
-                        if !seen.insert(key) {
-                            continue;
-                        }
-                        if let Ok(Some(data)) = self.store.get(ref_type, ref_id) {
-                            included.push(serde_json::from_slice(&data).unwrap_or_default());
-                        }
-                    }
-                }
+            Some("of-type") => {
+                // `system|code|value` -> type system, `code|value`.
+                let Some((system, typed_value)) = value.split_once('|') else {
+                    return Ok(IdSet::empty());
+                };
+                let cond = "s.value_system = ? AND s.value_string = ?";
+                let args = vec![SqlValue::from(system.to_string()), SqlValue::from(typed_value.to_string())];
+                return Ok(IdSet::matching(resource_type, &format!("{name}:of-type"), cond, args).exact());
+            }
+            Some(m @ ("in" | "not-in" | "above" | "below")) => {
+                return Err(format!(
+                    "'{name}:{m}' must be resolved against the terminology registry before searching"
+                ));
             }
+            _ => {}
+        }
+    }
+    let prefix = param.prefix.as_deref().unwrap_or("eq");
+    let mut args = Vec::new();
+    let cond = match &param.param_type {
+        SearchParamType::Token => Some(token_condition("s", value, &mut args)),
+        SearchParamType::String => {
+            let mode = match param.modifier.as_deref() {
+                Some("exact") => StringMatch::Exact,
+                Some("contains") => StringMatch::Contains,
+                _ => StringMatch::Prefix,
+            };
+            Some(string_condition("s", value, mode, &mut args))
+        }
+        SearchParamType::Date => date_condition("s", prefix, value, &mut args),
+        // `subject:identifier=system|value` matches a Reference's logical
+        // identifier rather than its literal `Type/id`.
+        SearchParamType::Reference if param.modifier.as_deref() == Some("identifier") => {
+            let cond = token_condition("s", value, &mut args);
+            return Ok(IdSet::matching(resource_type, &format!("{name}:identifier"), &cond, args));
         }
+        SearchParamType::Reference => {
+            // `subject:Patient=123` names the target type of a bare id.
+            let reference = match param.modifier.as_deref() {
+                Some(target) if !value.contains('/') => format!("{target}/{value}"),
+                _ => value.to_string(),
+            };
+            Some(reference_condition("s", &reference, &mut args))
+        }
+        SearchParamType::Number => number_range(value)
+            .map(|range| numeric_condition("s.value_number", prefix, range, &mut args)),
+        SearchParamType::Quantity => {
+            // `number`, `number||code` or `number|system|code`.
+            let mut parts = value.splitn(3, '|');
+            let number = parts.next().unwrap_or("");
+            let system = parts.next().filter(|s| !s.is_empty());
+            let code = parts.next().filter(|c| !c.is_empty());
+            quantity_condition("s", prefix, number, system, code, &mut args)
+        }
+        SearchParamType::Uri => Some(uri_condition("s", value, param.modifier.as_deref(), &mut args)),
+        SearchParamType::Composite(part_types) => {
+            // `code$value`: one `$`-separated value per part, each with its
+            // own comparator prefix where the part type takes one.
+            let values: Vec<&str> = value.split('$').collect();
+            if values.len() != part_types.len() {
+                return Err(format!(
+                    "'{}' expects {} '$'-separated values, got {}",
+                    param.name,
+                    part_types.len(),
+                    values.len()
+                ));
+            }
+            let parsed: Vec<(Option<String>, String)> = part_types
+                .iter()
+                .zip(&values)
+                .map(|(t, v)| {
+                    if t.takes_prefix() {
+                        parse_prefix(v)
+                    } else {
+                        (None, v.to_string())
+                    }
+                })
+                .collect();
+            let parts: Vec<(SearchParamType, Option<&str>, &str)> = part_types
+                .iter()
+                .zip(&parsed)
+                .map(|(t, (p, v))| (t.clone(), p.as_deref(), v.as_str()))
+                .collect();
+            return Ok(IdSet::composite(resource_type, name, &parts).unwrap_or_else(IdSet::empty));
+        }
+    };
+    // A value that doesn't parse for the type (e.g. a malformed date) matches nothing.
+    let Some(cond) = cond else {
+        return Ok(IdSet::empty());
+    };
+    let set = IdSet::matching(resource_type, name, &cond, args);
+    // Matches on a single value, as opposed to ranges and patterns.
+    let exact = match &param.param_type {
+        SearchParamType::Token => !value.ends_with('|'),
+        SearchParamType::Reference => true,
+        SearchParamType::Uri => param.modifier.is_none(),
+        SearchParamType::String => param.modifier.as_deref() == Some("exact"),
+        _ => false,
+    };
+    Ok(if exact { set.exact() } else { set })
+}
 
-        Ok(included)
+/// Resolve a (possibly multi-level) chained search. The terminal parameter
+/// matches the final target type, and each reference hop nests that set one
+/// level back toward `resource_type`. For
+/// `Observation?subject:Patient.organization:Organization.name=Acme`:
+///   1. `Organization?name=Acme` -> org ids
+///   2. `Patient` whose `organization` references those orgs -> patient ids
+///   3. `Observation` whose `subject` references those patients -> result
+///
+/// A hop with several target types (a type-less `subject.name=Doe`, resolved
+/// to Patient and Group) is the union of the chain through each of them.
+fn chain_set(dialect: Dialect, resource_type: &str, chain: &ChainParameter) -> Result<IdSet, String> {
+    chain_hops(dialect, resource_type, &chain.links, &chain.param)
+}
+
+/// The `source_type` resources that reach a match for `terminal` through the
+/// reference hops in `links`.
+fn chain_hops(dialect: Dialect, source_type: &str, links: &[ChainLink], terminal: &SearchParameter) -> Result<IdSet, String> {
+    let Some((link, rest)) = links.split_first() else {
+        return parameter_set(dialect, source_type, terminal);
+    };
+    if link.target_types.is_empty() {
+        return Err(format!(
+            "Chain hop '{}' must have its target types resolved before searching",
+            link.reference_param
+        ));
     }
+    let mut sets = Vec::with_capacity(link.target_types.len());
+    for target_type in &link.target_types {
+        let targets = chain_hops(dialect, target_type, rest, terminal)?;
+        sets.push(IdSet::referencing(source_type, &link.reference_param, target_type, targets));
+    }
+    Ok(sets.into_iter().reduce(IdSet::union).unwrap_or_else(IdSet::empty))
+}
+
+/// Resolve a (possibly nested) `_has` reverse chain: the innermost source
+/// resources matching the inner search parameter, mapped hop by hop back to
+/// the searched-type resources they reference. The mirror of [`chain_set`].
+///
+/// `Patient?_has:Observation:patient:_has:AuditEvent:entity:agent=Practitioner/1`:
+/// 1. `AuditEvent?agent=Practitioner/1` -> matching audit event ids
+/// 2. follow each audit event's `entity` reference -> the Observation ids
+/// 3. follow each observation's `patient` reference -> the Patient ids
+fn has_set(dialect: Dialect, resource_type: &str, has: &HasParameter) -> Result<IdSet, String> {
+    let Some(innermost) = has.links.last() else {
+        return Ok(IdSet::empty());
+    };
+    let mut set = parameter_set(dialect, &innermost.source_type, &has.param)?;
+    // Walk hops outward. Hop i's sources point back at the previous hop's
+    // source type (or `resource_type` at i == 0).
+    for (i, link) in has.links.iter().enumerate().rev() {
+        let target_type: &str = if i == 0 {
+            resource_type
+        } else {
+            &has.links[i - 1].source_type
+        };
+        set = IdSet::referenced_by(&link.source_type, &link.reference_param, set, target_type);
+    }
+    Ok(set)
 }
 
 /// Extract the reference strings an `_include`/`_revinclude` search param points
@@ -587,6 +687,24 @@ fn extract_references(resource: &Value, field: &str) -> Vec<String> {
     }
 }
 
+/// Every literal reference anywhere in `value`, for `_include=*`. Local
+/// references into `contained` (`#id`) are skipped, and so is `contained`.
+fn collect_references(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|v| collect_references(v, out)),
+        Value::Object(fields) => {
+            for (key, v) in fields {
+                match (key.as_str(), v) {
+                    ("contained", _) => {}
+                    ("reference", Value::String(r)) if !r.starts_with('#') => out.push(r.clone()),
+                    _ => collect_references(v, out),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
 /// Convert a hyphenated search-param name to the camelCase JSON element it most
 /// likely maps to (`general-practitioner` → `generalPractitioner`).
 fn hyphen_to_camel(s: &str) -> String {
@@ -618,6 +736,7 @@ fn parse_reference(reference: &str) -> Option<(&str, &str)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{IndexWrite, SearchIndex, SqliteStore};
 
     #[test]
     fn test_parse_reference() {
@@ -683,9 +802,11 @@ mod tests {
 
     // --- Integration tests over an in-memory store + index ---
 
-    fn put(store: &SqliteStore, rt: &str, id: &str, body: serde_json::Value) {
+    /// Store a resource and index its `_id`, which every indexed resource has.
+    fn put(store: &SqliteStore, index: &SearchIndex, rt: &str, id: &str, body: serde_json::Value) {
         let data = serde_json::to_vec(&body).unwrap();
         store.put_with_version(rt, id, "1", &data).unwrap();
+        index.add_index(rt, id, "_id", "token", Some(id), None).unwrap();
     }
 
     fn sorted(mut v: Vec<String>) -> Vec<String> {
@@ -698,8 +819,8 @@ mod tests {
         let store = SqliteStore::open(":memory:").unwrap();
         let index = SearchIndex::open(":memory:").unwrap();
         // o1 in [2024-06-15], o2 in [2023-01-01]
-        put(&store, "Observation", "o1", serde_json::json!({"resourceType":"Observation","id":"o1"}));
-        put(&store, "Observation", "o2", serde_json::json!({"resourceType":"Observation","id":"o2"}));
+        put(&store, &index, "Observation", "o1", serde_json::json!({"resourceType":"Observation","id":"o1"}));
+        put(&store, &index, "Observation", "o2", serde_json::json!({"resourceType":"Observation","id":"o2"}));
         index.add_index("Observation", "o1", "date", "date", Some("2024-06-15"), None).unwrap();
         index.add_index("Observation", "o2", "date", "date", Some("2023-01-01"), None).unwrap();
 
@@ -713,9 +834,9 @@ mod tests {
     fn test_missing_and_not_modifiers() {
         let store = SqliteStore::open(":memory:").unwrap();
         let index = SearchIndex::open(":memory:").unwrap();
-        put(&store, "Patient", "p1", serde_json::json!({"resourceType":"Patient","id":"p1"}));
-        put(&store, "Patient", "p2", serde_json::json!({"resourceType":"Patient","id":"p2"}));
-        put(&store, "Patient", "p3", serde_json::json!({"resourceType":"Patient","id":"p3"}));
+        put(&store, &index, "Patient", "p1", serde_json::json!({"resourceType":"Patient","id":"p1"}));
+        put(&store, &index, "Patient", "p2", serde_json::json!({"resourceType":"Patient","id":"p2"}));
+        put(&store, &index, "Patient", "p3", serde_json::json!({"resourceType":"Patient","id":"p3"}));
         // Only p1, p2 have a gender indexed.
         index.add_index("Patient", "p1", "gender", "token", Some("male"), None).unwrap();
         index.add_index("Patient", "p2", "gender", "token", Some("female"), None).unwrap();
@@ -734,4 +855,520 @@ mod tests {
         let q = SearchQuery::parse_for_resource("gender:not=male", Some("Patient")).unwrap();
         assert_eq!(sorted(exec.search("Patient", &q).unwrap()), vec!["p2", "p3"]);
     }
+
+    #[test]
+    fn test_sort_multi_key_with_id_tiebreak() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        for (id, date, status) in [
+            ("o3", "2024-01-01", "final"),
+            ("o1", "2024-01-01", "amended"),
+            ("o2", "2023-06-01", "final"),
+            ("o4", "2025-03-01", "final"),
+        ] {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            index.add_index("Observation", id, "date", "date", Some(date), None).unwrap();
+            index.add_index("Observation", id, "status", "token", Some(status), None).unwrap();
+        }
+        // o5 has no date: it sorts last in both directions.
+        put(&store, &index, "Observation", "o5", serde_json::json!({"resourceType":"Observation","id":"o5"}));
+        let exec = SearchExecutor::new(&store, &index);
+
+        let q = SearchQuery::parse_for_resource("_sort=date", Some("Observation")).unwrap();
+        assert_eq!(exec.search("Observation", &q).unwrap(), vec!["o2", "o1", "o3", "o4", "o5"]);
+
+        let q = SearchQuery::parse_for_resource("_sort=-date,-status", Some("Observation")).unwrap();
+        assert_eq!(exec.search("Observation", &q).unwrap(), vec!["o4", "o3", "o1", "o2", "o5"]);
+
+        // Paging over a sorted result is stable: pages partition the full order.
+        let page = |offset: usize| {
+            let q = SearchQuery::parse_for_resource(
+                &format!("_sort=date&_count=2&_offset={offset}"),
+                Some("Observation"),
+            )
+            .unwrap();
+            exec.search_with_total("Observation", &q).unwrap()
+        };
+        assert_eq!(page(0), (vec!["o2".to_string(), "o1".to_string()], 5));
+        assert_eq!(page(2).0, vec!["o3", "o4"]);
+        assert_eq!(page(4).0, vec!["o5"]);
+    }
+
+    #[test]
+    fn test_composite_matches_within_one_component() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let bp = |id: &str, systolic: i64, diastolic: i64| {
+            serde_json::json!({
+                "resourceType": "Observation",
+                "id": id,
+                "code": {"coding": [{"system": "http://loinc.org", "code": "85354-9"}]},
+                "component": [
+                    {"code": {"coding": [{"system": "http://loinc.org", "code": "8480-6"}]},
+                     "valueQuantity": {"value": systolic, "system": "http://unitsofmeasure.org", "code": "mm[Hg]"}},
+                    {"code": {"coding": [{"system": "http://loinc.org", "code": "8462-4"}]},
+                     "valueQuantity": {"value": diastolic, "system": "http://unitsofmeasure.org", "code": "mm[Hg]"}}
+                ]
+            })
+        };
+        for (id, resource) in [("high", bp("high", 150, 95)), ("normal", bp("normal", 118, 76))] {
+            put(&store, &index, "Observation", id, resource.clone());
+            for (name, t, v, sys) in crate::IndexBuilder::extract_indices("Observation", &resource) {
+                index.add_index("Observation", id, &name, &t, Some(&v), sys.as_deref()).unwrap();
+            }
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Observation")).unwrap();
+            let mut ids = exec.search("Observation", &q).unwrap();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("component-code-value-quantity=http://loinc.org|8480-6$gt140"), vec!["high"]);
+        assert_eq!(
+            search("component-code-value-quantity=http://loinc.org|8480-6$gt100"),
+            vec!["high", "normal"]
+        );
+        // "normal" has a component above 100 (systolic) but its diastolic
+        // component is not: the parts must match in the same component.
+        assert_eq!(search("component-code-value-quantity=http://loinc.org|8462-4$gt90"), vec!["high"]);
+        assert!(search("component-code-value-quantity=http://loinc.org|8462-4$gt100").is_empty());
+        // The top-level composite pairs Observation.code with valueQuantity,
+        // which these panels don't have.
+        assert!(search("code-value-quantity=http://loinc.org|85354-9$gt0").is_empty());
+    }
+
+    #[test]
+    fn test_chain_has_and_or_values_compile_to_one_query() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        for (id, name) in [("p1", "doe"), ("p2", "roe")] {
+            put(&store, &index, "Patient", id, serde_json::json!({"resourceType":"Patient","id":id}));
+            index.add_index("Patient", id, "name", "string", Some(name), None).unwrap();
+        }
+        for (id, subject, code) in [("o1", "Patient/p1", "a"), ("o2", "Patient/p2", "b"), ("o3", "Patient/p1", "c")] {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            index.add_index("Observation", id, "subject", "reference", Some(subject), None).unwrap();
+            index.add_index("Observation", id, "code", "token", Some(code), None).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |rt: &str, q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some(rt)).unwrap();
+            exec.search_with_total(rt, &q).unwrap()
+        };
+
+        assert_eq!(search("Observation", "subject:Patient.name=doe").0, vec!["o1", "o3"]);
+        assert_eq!(search("Observation", "subject:Patient.name=doe&code=b,c").0, vec!["o3"]);
+        assert_eq!(search("Patient", "_has:Observation:subject:code=b").0, vec!["p2"]);
+        assert_eq!(
+            search("Observation", "code=a,b,c&_count=1&_offset=1"),
+            (vec!["o2".to_string()], 3)
+        );
+
+        let q = SearchQuery::parse_for_resource("code:not=a", Some("Observation")).unwrap();
+        assert_eq!(exec.count("Observation", &q, TotalMode::Accurate).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_chain_prefix_modifier_and_typeless_fan_out() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        for (id, name, birthdate) in [("p1", "Doe", "1940-02-01"), ("p2", "Doelle", "1980-07-04")] {
+            put(&store, &index, "Patient", id, serde_json::json!({"resourceType":"Patient","id":id}));
+            index.add_index("Patient", id, "name", "string", Some(&name.to_lowercase()), None).unwrap();
+            index.add_index("Patient", id, "birthdate", "date", Some(birthdate), None).unwrap();
+        }
+        put(&store, &index, "Group", "g1", serde_json::json!({"resourceType":"Group","id":"g1"}));
+        index.add_index("Group", "g1", "name", "string", Some("doe family"), None).unwrap();
+        for (id, subject) in [("o1", "Patient/p1"), ("o2", "Patient/p2"), ("o3", "Group/g1")] {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            index.add_index("Observation", id, "subject", "reference", Some(subject), None).unwrap();
+            if subject.starts_with("Patient/") {
+                index.add_index("Observation", id, "patient", "reference", Some(subject), None).unwrap();
+            }
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Observation")).unwrap();
+            sorted(exec.search("Observation", &q).unwrap())
+        };
+
+        assert_eq!(search("subject:Patient.birthdate=lt1950"), vec!["o1"]);
+        assert_eq!(search("subject:Patient.birthdate=ge1950"), vec!["o2"]);
+        assert_eq!(search("subject:Patient.name=doe"), vec!["o1", "o2"]);
+        assert_eq!(search("subject:Patient.name:exact=Doe"), vec!["o1"]);
+        // Without a type the chain fans out over Patient and Group subjects.
+        assert_eq!(search("subject.name=doe"), vec!["o1", "o2", "o3"]);
+        assert_eq!(search("subject.name:exact=Doe"), vec!["o1"]);
+        assert_eq!(search("patient.name=doe"), vec!["o1", "o2"]);
+
+        // A hop left unresolved can't be searched.
+        let mut q = SearchQuery::parse("subject.name=doe").unwrap();
+        assert!(q.chain_parameters[0].links[0].target_types.is_empty());
+        assert!(exec.search("Observation", &q).is_err());
+        q.chain_parameters[0].links[0].target_types = vec!["Group".to_string()];
+        assert_eq!(sorted(exec.search("Observation", &q).unwrap()), vec!["o3"]);
+    }
+
+    #[test]
+    fn test_include_iterate_wildcard_and_cap() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        put(&store, &index, "Encounter", "e1", serde_json::json!({"resourceType":"Encounter","id":"e1"}));
+        for id in ["pr1", "pr2"] {
+            put(&store, &index, "Practitioner", id, serde_json::json!({"resourceType":"Practitioner","id":id}));
+        }
+        // o1 and o2 refer to each other through `hasMember`.
+        for (id, performer, member) in [("o1", "pr1", "o2"), ("o2", "pr2", "o1")] {
+            let body = serde_json::json!({
+                "resourceType": "Observation", "id": id,
+                "encounter": {"reference": "Encounter/e1"},
+                "performer": [{"reference": format!("Practitioner/{performer}")}],
+                "hasMember": [{"reference": format!("Observation/{member}")}]
+            });
+            put(&store, &index, "Observation", id, body);
+            index.add_index("Observation", id, "encounter", "reference", Some("Encounter/e1"), None).unwrap();
+            let performer = format!("Practitioner/{performer}");
+            index.add_index("Observation", id, "performer", "reference", Some(&performer), None).unwrap();
+        }
+        let registry = sazare_core::SearchParamRegistry::new();
+        let exec = SearchExecutor::new(&store, &index);
+        let included = |rt: &str, q: &str, limit: usize| {
+            let query = SearchQuery::parse_for_resource(q, Some(rt)).unwrap();
+            let ids = exec.search(rt, &query).unwrap();
+            let matches = exec.load_matches(rt, &ids, ContainedType::Container).unwrap();
+            let included = exec.resolve_includes(rt, &matches, &query, &registry, limit).unwrap();
+            let mut references: Vec<String> = included
+                .resources
+                .iter()
+                .map(|r| format!("{}/{}", r["resourceType"].as_str().unwrap(), r["id"].as_str().unwrap()))
+                .collect();
+            references.sort();
+            (references, included.truncated)
+        };
+
+        // One level: the encounter's observations, but not their performers.
+        assert_eq!(
+            included("Encounter", "_id=e1&_revinclude=Observation:encounter", 100).0,
+            vec!["Observation/o1", "Observation/o2"]
+        );
+        // Iterating reaches the practitioners those observations reference.
+        assert_eq!(
+            included("Encounter", "_id=e1&_revinclude=Observation:encounter&_include:iterate=Observation:performer", 100).0,
+            vec!["Observation/o1", "Observation/o2", "Practitioner/pr1", "Practitioner/pr2"]
+        );
+        // `*` follows every reference; the o1 <-> o2 cycle ends, and the
+        // match itself is never included.
+        assert_eq!(
+            included("Observation", "_id=o1&_include:iterate=*", 100).0,
+            vec!["Encounter/e1", "Observation/o2", "Practitioner/pr1", "Practitioner/pr2"]
+        );
+        assert_eq!(
+            included("Practitioner", "_id=pr1&_revinclude=*", 100).0,
+            vec!["Observation/o1"]
+        );
+        assert_eq!(
+            included("Observation", "_id=o1&_include=Observation:*:Practitioner", 100).0,
+            vec!["Practitioner/pr1"]
+        );
+        // A wildcard source type reads the parameter from every type that has it.
+        put(&store, &index, "Condition", "c1", serde_json::json!({"resourceType":"Condition","id":"c1"}));
+        index.add_index("Condition", "c1", "encounter", "reference", Some("Encounter/e1"), None).unwrap();
+        assert_eq!(
+            included("Encounter", "_id=e1&_revinclude=*:encounter", 100).0,
+            vec!["Condition/c1", "Observation/o1", "Observation/o2"]
+        );
+        // The cap stops the walk and says so.
+        let (capped, truncated) = included("Observation", "_id=o1&_include:iterate=*", 2);
+        assert_eq!(capped.len(), 2);
+        assert!(truncated);
+        assert!(!included("Observation", "_id=o1&_include:iterate=*", 4).1);
+    }
+
+    #[test]
+    fn test_nested_has_with_prefix_and_modifier() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        for id in ["p1", "p2", "p3"] {
+            put(&store, &index, "Patient", id, serde_json::json!({"resourceType":"Patient","id":id}));
+        }
+        let observations = [
+            ("o1", "Patient/p1", "2023-06-01", "a"),
+            ("o2", "Patient/p2", "2024-03-01", "b"),
+            ("o3", "Patient/p3", "2024-05-01", "c"),
+        ];
+        for (id, subject, date, code) in observations {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            index.add_index("Observation", id, "subject", "reference", Some(subject), None).unwrap();
+            index.add_index("Observation", id, "date", "date", Some(date), None).unwrap();
+            index.add_index("Observation", id, "code", "token", Some(code), None).unwrap();
+        }
+        // Audit events touching o1 and o3, by different agents.
+        for (id, entity, agent) in [("a1", "Observation/o1", "Practitioner/x"), ("a2", "Observation/o3", "Practitioner/y")] {
+            put(&store, &index, "AuditEvent", id, serde_json::json!({"resourceType":"AuditEvent","id":id}));
+            index.add_index("AuditEvent", id, "entity", "reference", Some(entity), None).unwrap();
+            index.add_index("AuditEvent", id, "agent", "reference", Some(agent), None).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Patient")).unwrap();
+            sorted(exec.search("Patient", &q).unwrap())
+        };
+
+        assert_eq!(search("_has:Observation:subject:date=ge2024-01-01"), vec!["p2", "p3"]);
+        assert_eq!(search("_has:Observation:subject:date=lt2024"), vec!["p1"]);
+        assert_eq!(search("_has:Observation:subject:code=a,c"), vec!["p1", "p3"]);
+        assert_eq!(search("_has:Observation:subject:code:not=a"), vec!["p2", "p3"]);
+        assert_eq!(search("_has:Observation:subject:_has:AuditEvent:entity:agent=Practitioner/y"), vec!["p3"]);
+        assert_eq!(
+            search("_has:Observation:subject:_has:AuditEvent:entity:agent=Practitioner/x,Practitioner/y"),
+            vec!["p1", "p3"]
+        );
+        // Each `_has` is ANDed with the rest of the query.
+        assert_eq!(
+            search("_has:Observation:subject:_has:AuditEvent:entity:agent:missing=false&_has:Observation:subject:date=ge2024"),
+            vec!["p3"]
+        );
+    }
+
+    #[test]
+    fn test_keyset_pages_resume_from_cursor() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let add = |id: &str, date: Option<&str>| {
+            put(&store, &index, "Observation", id, serde_json::json!({"resourceType":"Observation","id":id}));
+            if let Some(date) = date {
+                index.add_index("Observation", id, "date", "date", Some(date), None).unwrap();
+            }
+        };
+        for (id, date) in [("o1", Some("2024-03-01")), ("o2", Some("2024-01-01")), ("o3", None), ("o4", Some("2024-02-01")), ("o5", None)] {
+            add(id, date);
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let q = SearchQuery::parse_for_resource("_sort=-date&_count=2", Some("Observation")).unwrap();
+        let page = |start: &PageStart| exec.search_page("Observation", &q, start, None, TotalMode::Accurate).unwrap();
+
+        // Order: o1, o4, o2, then the undated o3, o5.
+        let (p1, total) = page(&PageStart::First);
+        assert_eq!((p1.ids.clone(), total, p1.more), (vec!["o1".to_string(), "o4".to_string()], Some(5), true));
+        let p2 = page(&PageStart::After(p1.last.clone().unwrap())).0;
+        assert_eq!(p2.ids, vec!["o2", "o3"]);
+
+        // A resource written between fetches ahead of the cursor does not
+        // shift the next page (an offset would now repeat o3).
+        add("o0", Some("2025-01-01"));
+        let p3 = page(&PageStart::After(p2.last.clone().unwrap())).0;
+        assert_eq!((p3.ids.clone(), p3.more), (vec!["o5".to_string()], false));
+
+        // Walking back from the last page, through the NULL boundary.
+        let back = page(&PageStart::Before(p3.first.unwrap())).0;
+        assert_eq!((back.ids.clone(), back.more), (vec!["o2".to_string(), "o3".to_string()], true));
+        let back = page(&PageStart::Before(back.first.unwrap())).0;
+        assert_eq!(back.ids, vec!["o1", "o4"]);
+        assert!(back.more, "o0 now precedes o1");
+    }
+
+    #[test]
+    fn test_total_modes() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        for i in 0..30 {
+            let id = format!("o{i}");
+            put(&store, &index, "Observation", &id, serde_json::json!({"resourceType":"Observation","id":id}));
+            let status = if i < 20 { "final" } else { "amended" };
+            index.add_index("Observation", &id, "status", "token", Some(status), None).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let count = |q: &str, mode| {
+            let q = SearchQuery::parse_for_resource(q, Some("Observation")).unwrap();
+            exec.count("Observation", &q, mode).unwrap()
+        };
+
+        assert_eq!(count("status=final", TotalMode::None), None);
+        // Without statistics an estimate is an exact count.
+        assert_eq!(count("status=final", TotalMode::Estimate), Some(20));
+
+        // From statistics: 30 resources over 2 distinct statuses.
+        index.refresh_statistics().unwrap();
+        assert_eq!(count("status=final", TotalMode::Estimate), Some(15));
+        assert_eq!(count("", TotalMode::Estimate), Some(30));
+        assert_eq!(count("status:not=final", TotalMode::Estimate), Some(15));
+        assert_eq!(count("status=final", TotalMode::Accurate), Some(20));
+    }
+
+    #[test]
+    fn test_contained_search_and_load() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let code = |c: &str| serde_json::json!({"coding": [{"system": "http://rxnorm", "code": c}]});
+        let resources = [
+            ("Medication", "m1", serde_json::json!({"resourceType": "Medication", "id": "m1", "code": code("111")})),
+            ("MedicationRequest", "mr1", serde_json::json!({
+                "resourceType": "MedicationRequest", "id": "mr1", "status": "active",
+                "contained": [
+                    {"resourceType": "Medication", "id": "a", "code": code("111")},
+                    {"resourceType": "Medication", "id": "b", "code": code("111")},
+                    {"resourceType": "Medication", "id": "c", "code": code("222")}
+                ],
+                "medicationReference": {"reference": "#a"}
+            })),
+        ];
+        for (rt, id, body) in &resources {
+            store.put_with_version(rt, id, "1", &serde_json::to_vec(body).unwrap()).unwrap();
+            for (name, t, v, sys) in crate::IndexBuilder::extract_indices(rt, body) {
+                index.add_index(rt, id, &name, &t, Some(&v), sys.as_deref()).unwrap();
+            }
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Medication")).unwrap();
+            sorted(exec.search("Medication", &q).unwrap())
+        };
+
+        assert_eq!(search("code=111"), vec!["m1"]);
+        assert_eq!(search("code=111&_contained=true"), vec!["MedicationRequest/mr1#a", "MedicationRequest/mr1#b"]);
+        assert_eq!(search("code=111&_contained=both").len(), 3);
+        assert_eq!(search("_contained=true").len(), 3);
+
+        let ids = search("code=111&_contained=both");
+        let matches = exec.load_matches("Medication", &ids, ContainedType::Container).unwrap();
+        let references: Vec<&str> = matches.iter().map(|(r, _)| r.as_str()).collect();
+        assert_eq!(references, vec!["MedicationRequest/mr1", "Medication/m1"]);
+        assert_eq!(matches[0].1["resourceType"], "MedicationRequest");
+        let matches = exec.load_matches("Medication", &ids, ContainedType::Contained).unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[1].0, "MedicationRequest/mr1#b");
+        assert_eq!(matches[1].1["id"], "b");
+
+        // Reindexing or deleting the container drops its contained resources.
+        index.remove_index("MedicationRequest", "mr1").unwrap();
+        assert!(search("_contained=true").is_empty());
+        assert_eq!(search("code=111"), vec!["m1"]);
+    }
+
+    #[test]
+    fn test_full_text_search() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let notes = [
+            ("c1", "Diabetic foot ulcer", "Dressing changed daily"),
+            ("c2", "Chest pain, cardiology referral", "Sharp pain on exertion"),
+            ("c3", "Cardiac arrhythmia", "Follow-up with cardiology"),
+        ];
+        for (id, narrative, note) in notes {
+            let body = serde_json::json!({
+                "resourceType": "Condition", "id": id,
+                "text": {"status": "generated", "div": format!("<div>{narrative}</div>")},
+                "note": [{"text": note}]
+            });
+            put(&store, &index, "Condition", id, body.clone());
+            let (narrative, content) = crate::IndexBuilder::extract_text(&body);
+            index.index_text("Condition", id, &narrative, &content).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Condition")).unwrap();
+            sorted(exec.search("Condition", &q).unwrap())
+        };
+
+        assert_eq!(search("_text=ulcer"), vec!["c1"]);
+        // `_text` sees only the narrative; `_content` sees the notes too.
+        assert!(search("_text=exertion").is_empty());
+        assert_eq!(search("_content=exertion"), vec!["c2"]);
+        assert_eq!(search("_content=cardi*"), vec!["c2", "c3"]);
+        assert_eq!(search("_content=%22sharp%20pain%22"), vec!["c2"]);
+        assert!(search("_content=%22pain%20sharp%22").is_empty());
+        assert_eq!(search("_content=cardiology%20NOT%20pain"), vec!["c3"]);
+        assert_eq!(search("_text=ulcer%20OR%20arrhythmia"), vec!["c1", "c3"]);
+        assert_eq!(search("_content=(ulcer%20OR%20pain)%20daily"), vec!["c1"]);
+        // FTS5 syntax inside a word is not interpreted: `foot:ulcer` is no
+        // column filter, just the words "foot ulcer".
+        assert_eq!(search("_content=foot:ulcer"), vec!["c1"]);
+
+        index.remove_index("Condition", "c1").unwrap();
+        assert!(search("_text=ulcer").is_empty());
+    }
+
+    #[test]
+    fn test_reference_identifier_modifier() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let mrn = "http://hospital.org/mrn";
+        let observations = [
+            ("o1", serde_json::json!({"identifier": {"system": mrn, "value": "12345"}})),
+            ("o2", serde_json::json!({"reference": "Patient/p2", "identifier": {"system": mrn, "value": "67890"}})),
+            ("o3", serde_json::json!({"reference": "Patient/p1"})),
+        ];
+        for (id, subject) in observations {
+            let body = serde_json::json!({"resourceType": "Observation", "id": id, "subject": subject});
+            put(&store, &index, "Observation", id, body.clone());
+            for (name, t, v, sys) in crate::IndexBuilder::extract_indices("Observation", &body) {
+                index.add_index("Observation", id, &name, &t, Some(&v), sys.as_deref()).unwrap();
+            }
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some("Observation")).unwrap();
+            sorted(exec.search("Observation", &q).unwrap())
+        };
+
+        assert_eq!(search(&format!("subject:identifier={mrn}|12345")), vec!["o1"]);
+        assert_eq!(search("patient:identifier=67890"), vec!["o2"]);
+        assert_eq!(search(&format!("subject:identifier={mrn}|12345,{mrn}|67890")), vec!["o1", "o2"]);
+        assert!(search("subject:identifier=http://other.org/ids|12345").is_empty());
+        // The literal reference still matches as before.
+        assert_eq!(search("subject=Patient/p2"), vec!["o2"]);
+        assert!(search("subject:missing=true").is_empty());
+    }
+
+    #[test]
+    fn test_token_text_and_of_type_modifiers() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let index = SearchIndex::open(":memory:").unwrap();
+        let v2 = "http://terminology.hl7.org/CodeSystem/v2-0203";
+        let patients = [
+            ("p1", "MR", "1001"),
+            ("p2", "SS", "1001"),
+        ];
+        for (id, type_code, value) in patients {
+            let body = serde_json::json!({
+                "resourceType": "Patient", "id": id,
+                "identifier": [{
+                    "type": {"coding": [{"system": v2, "code": type_code}]},
+                    "system": "http://hospital.example/ids", "value": value
+                }]
+            });
+            put(&store, &index, "Patient", id, body.clone());
+            for (name, t, v, sys) in crate::IndexBuilder::extract_indices("Patient", &body) {
+                index.add_index("Patient", id, &name, &t, Some(&v), sys.as_deref()).unwrap();
+            }
+        }
+        let observation = serde_json::json!({
+            "resourceType": "Observation", "id": "o1",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "2339-0", "display": "Glucose [Mass/volume] in Blood"}]},
+            "subject": {"reference": "Patient/p1"}
+        });
+        put(&store, &index, "Observation", "o1", observation.clone());
+        for (name, t, v, sys) in crate::IndexBuilder::extract_indices("Observation", &observation) {
+            index.add_index("Observation", "o1", &name, &t, Some(&v), sys.as_deref()).unwrap();
+        }
+        let exec = SearchExecutor::new(&store, &index);
+        let search = |rt: &str, q: &str| {
+            let q = SearchQuery::parse_for_resource(q, Some(rt)).unwrap();
+            sorted(exec.search(rt, &q).unwrap())
+        };
+
+        assert_eq!(search("Observation", "code:text=glucose"), vec!["o1"]);
+        assert!(search("Observation", "code:text=insulin").is_empty());
+        assert_eq!(search("Patient", &format!("identifier:of-type={v2}|MR|1001")), vec!["p1"]);
+        assert_eq!(search("Patient", &format!("identifier:of-type={v2}|SS|1001")), vec!["p2"]);
+        assert!(search("Patient", &format!("identifier:of-type={v2}|MR|1002")).is_empty());
+        assert_eq!(search("Observation", "subject:Patient=p1"), vec!["o1"]);
+        assert!(search("Observation", "subject:Group=p1").is_empty());
+
+        // Terminology modifiers must have been expanded before reaching here.
+        let q = SearchQuery::parse_for_resource("code:below=http://loinc.org|2339-0", Some("Observation")).unwrap();
+        assert!(exec.search("Observation", &q).is_err());
+    }
 }
@@ -0,0 +1,792 @@
+//! Compilation of FHIR search filters into SQL over the `search_index` table.
+//!
+//! An [`IdSet`] is a SELECT yielding the distinct ids of the resources of one
+//! type that satisfy a filter. Leaf sets read `search_index` rows; sets combine
+//! with INTERSECT / UNION / EXCEPT and nest inside reference hops, so a whole
+//! search compiles into a single statement that the database evaluates,
+//! orders and pages itself (see [`page_query`]) instead of materializing id
+//! lists. The SQL is common to SQLite and PostgreSQL except where a
+//! [`Dialect`] is asked for.
+
+use crate::error::{Result, StoreError};
+use crate::sqlite_index::{
+    contained_namespace, escape_like, fhir_date_range, number_range, Page, PageCursor, PageStart, StringMatch,
+};
+use rusqlite::types::Value as SqlValue;
+use sazare_core::{SearchParamType, SortKey, TextToken};
+
+/// The database a statement is written for. Id sets are plain SQL that both
+/// accept, save for full-text matching and an unlimited page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+/// A set of resource ids as SQL: `sql` selects one `resource_id` column, and
+/// `args` binds its anonymous `?` placeholders in order. `shape` mirrors how
+/// the set was built, for estimating its size without running it.
+pub struct IdSet {
+    pub(crate) sql: String,
+    pub(crate) args: SqlArgs,
+    pub(crate) shape: Shape,
+}
+
+/// The structure of an [`IdSet`] over its own resource type, as far as
+/// cardinality estimation cares (see `SearchBackend::estimate`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    All,
+    Empty,
+    /// Resources with any value for the parameter.
+    Present(String),
+    /// Resources with a value of the parameter matching a condition; `exact`
+    /// for an equality match on one value, otherwise a range or pattern.
+    Matching { param: String, exact: bool },
+    /// Resources reached through a reference hop from another set.
+    Hop,
+    /// Resources whose text matches a full-text query.
+    Text,
+    And(Box<Shape>, Box<Shape>),
+    Or(Box<Shape>, Box<Shape>),
+    Except(Box<Shape>, Box<Shape>),
+}
+
+impl IdSet {
+    /// Every resource of the type. Each indexed resource carries exactly one
+    /// `_id` row (a base parameter of every resource type), so the index alone
+    /// enumerates them without consulting the resource store.
+    pub(crate) fn all(resource_type: &str) -> Self {
+        Self {
+            sql: "SELECT resource_id FROM search_index \
+                  WHERE resource_type = ? AND param_name = '_id'"
+                .to_string(),
+            args: vec![SqlValue::from(resource_type.to_string())],
+            shape: Shape::All,
+        }
+    }
+
+    /// No resources, for a query value that can never match.
+    pub(crate) fn empty() -> Self {
+        Self {
+            sql: "SELECT resource_id FROM search_index WHERE 1 = 0".to_string(),
+            args: Vec::new(),
+            shape: Shape::Empty,
+        }
+    }
+
+    /// Resources whose `column` of `search_text` (`narrative` for `_text`,
+    /// `content` for `_content`) matches the full-text query: through the
+    /// FTS5 table in SQLite, the column's `tsvector` in PostgreSQL.
+    pub(crate) fn text(dialect: Dialect, resource_type: &str, column: &str, query: &[TextToken]) -> Self {
+        let (sql, query) = match dialect {
+            Dialect::Sqlite => (
+                "SELECT t.resource_id FROM search_text t \
+                 JOIN search_text_fts ON search_text_fts.rowid = t.id \
+                 WHERE t.resource_type = ? AND search_text_fts MATCH ?"
+                    .to_string(),
+                format!("{column} : ({})", fts_expression(query)),
+            ),
+            Dialect::Postgres => (
+                format!(
+                    "SELECT t.resource_id FROM search_text t \
+                     WHERE t.resource_type = ? AND t.{column}_tsv @@ to_tsquery('simple', ?)"
+                ),
+                tsquery_expression(query),
+            ),
+        };
+        Self {
+            sql,
+            args: vec![SqlValue::from(resource_type.to_string()), SqlValue::from(query)],
+            shape: Shape::Text,
+        }
+    }
+
+    /// Exactly the listed ids, whether indexed or not.
+    pub(crate) fn of(ids: &[String]) -> Self {
+        if ids.is_empty() {
+            return Self::empty();
+        }
+        Self {
+            sql: vec!["SELECT ? AS resource_id"; ids.len()].join(" UNION ALL "),
+            args: ids.iter().map(|id| SqlValue::from(id.clone())).collect(),
+            shape: Shape::Hop,
+        }
+    }
+
+    /// Resources with at least one index entry for `param_name`.
+    pub(crate) fn with_param(resource_type: &str, param_name: &str) -> Self {
+        Self {
+            sql: "SELECT DISTINCT resource_id FROM search_index \
+                  WHERE resource_type = ? AND param_name = ?"
+                .to_string(),
+            args: vec![
+                SqlValue::from(resource_type.to_string()),
+                SqlValue::from(param_name.to_string()),
+            ],
+            shape: Shape::Present(param_name.to_string()),
+        }
+    }
+
+    /// Resources with a `param_name` entry satisfying `cond`, a condition over
+    /// the table aliased `s` whose placeholders are bound from `args`.
+    pub(crate) fn matching(resource_type: &str, param_name: &str, cond: &str, args: SqlArgs) -> Self {
+        let mut bound: SqlArgs = vec![
+            SqlValue::from(resource_type.to_string()),
+            SqlValue::from(param_name.to_string()),
+        ];
+        bound.extend(args);
+        Self {
+            sql: format!(
+                "SELECT DISTINCT s.resource_id FROM search_index s \
+                 WHERE s.resource_type = ? AND s.param_name = ? AND ({cond})"
+            ),
+            args: bound,
+            shape: Shape::Matching { param: param_name.to_string(), exact: false },
+        }
+    }
+
+    /// Mark a single-parameter match as an equality match on one value (a
+    /// token code, reference or exact string), which estimates as far more
+    /// selective than a range.
+    pub(crate) fn exact(mut self) -> Self {
+        if let Shape::Matching { exact, .. } = &mut self.shape {
+            *exact = true;
+        }
+        self
+    }
+
+    /// Composite match. Each part is `(type, prefix, value)` and is matched
+    /// against the `<param_name>$<i>` rows; a resource matches only if one
+    /// group (one repeating element, see `ExtractionMode::Composite`) matches
+    /// every part. `None` if a part value is malformed.
+    pub(crate) fn composite(
+        resource_type: &str,
+        param_name: &str,
+        parts: &[(SearchParamType, Option<&str>, &str)],
+    ) -> Option<Self> {
+        if parts.is_empty() {
+            return None;
+        }
+        let mut joins = String::new();
+        let mut conds = Vec::new();
+        let mut args: SqlArgs = Vec::new();
+        for (i, (param_type, prefix, value)) in parts.iter().enumerate() {
+            let t = format!("p{i}");
+            if i > 0 {
+                joins.push_str(&format!(
+                    " JOIN search_index {t} ON {t}.resource_type = p0.resource_type \
+                     AND {t}.resource_id = p0.resource_id AND {t}.value_group = p0.value_group"
+                ));
+            }
+            args.push(SqlValue::from(format!("{param_name}${i}")));
+            let cond = part_condition(&t, param_type, *prefix, value, &mut args)?;
+            conds.push(format!("{t}.param_name = ? AND ({cond})"));
+        }
+        args.push(SqlValue::from(resource_type.to_string()));
+        Some(Self {
+            sql: format!(
+                "SELECT DISTINCT p0.resource_id FROM search_index p0{joins} \
+                 WHERE {} AND p0.resource_type = ? AND p0.value_group >= 0",
+                conds.join(" AND ")
+            ),
+            args,
+            shape: Shape::Matching { param: format!("{param_name}$0"), exact: true },
+        })
+    }
+
+    /// Resources of `source_type` whose `param_name` reference points at a
+    /// `target_type` resource in `targets` — one hop of a chained search.
+    pub(crate) fn referencing(
+        source_type: &str,
+        param_name: &str,
+        target_type: &str,
+        targets: IdSet,
+    ) -> Self {
+        let mut args: SqlArgs = vec![
+            SqlValue::from(source_type.to_string()),
+            SqlValue::from(param_name.to_string()),
+            SqlValue::from(format!("{target_type}/")),
+        ];
+        args.extend(targets.args);
+        Self {
+            sql: format!(
+                "SELECT DISTINCT r.resource_id FROM search_index r \
+                 WHERE r.resource_type = ? AND r.param_name = ? AND r.param_type = 'reference' \
+                 AND r.value_string IN (SELECT ? || resource_id FROM ({}) AS targets)",
+                targets.sql
+            ),
+            args,
+            shape: Shape::Hop,
+        }
+    }
+
+    /// The `target_type` resources referenced by the `param_name` reference of
+    /// the `source_type` resources in `sources` — the inverse of
+    /// [`IdSet::referencing`], used for `_has`. References are stored like
+    /// `"Patient/123"`, so the `"{target_type}/"` prefix is matched and
+    /// stripped to recover the bare id.
+    pub(crate) fn referenced_by(
+        source_type: &str,
+        param_name: &str,
+        sources: IdSet,
+        target_type: &str,
+    ) -> Self {
+        let prefix = format!("{target_type}/");
+        let mut args: SqlArgs = vec![
+            SqlValue::from(prefix.chars().count() as i64 + 1),
+            SqlValue::from(source_type.to_string()),
+            SqlValue::from(param_name.to_string()),
+            SqlValue::from(format!("{}%", escape_like(&prefix))),
+        ];
+        args.extend(sources.args);
+        Self {
+            sql: format!(
+                "SELECT DISTINCT substr(r.value_string, CAST(? AS INTEGER)) AS resource_id FROM search_index r \
+                 WHERE r.resource_type = ? AND r.param_name = ? AND r.param_type = 'reference' \
+                 AND r.value_string LIKE ? ESCAPE '\\' AND r.resource_id IN ({})",
+                sources.sql
+            ),
+            args,
+            shape: Shape::Hop,
+        }
+    }
+
+    /// Ids in both sets (AND).
+    pub(crate) fn intersect(self, other: IdSet) -> Self {
+        self.compound("INTERSECT", other, Shape::And)
+    }
+
+    /// Ids in either set (OR).
+    pub(crate) fn union(self, other: IdSet) -> Self {
+        self.compound("UNION", other, Shape::Or)
+    }
+
+    /// Ids in this set but not the other.
+    pub(crate) fn except(self, other: IdSet) -> Self {
+        self.compound("EXCEPT", other, Shape::Except)
+    }
+
+    fn compound(
+        mut self,
+        op: &str,
+        other: IdSet,
+        shape: fn(Box<Shape>, Box<Shape>) -> Shape,
+    ) -> Self {
+        // Each operand is wrapped in a subquery: SQLite evaluates compound
+        // operators left to right with no precedence, and nested operands may
+        // be compounds themselves. PostgreSQL wants every subquery named.
+        self.sql = format!(
+            "SELECT resource_id FROM ({}) AS a {op} SELECT resource_id FROM ({}) AS b",
+            self.sql, other.sql
+        );
+        self.args.extend(other.args);
+        self.shape = shape(Box::new(self.shape), Box::new(other.shape));
+        self
+    }
+}
+
+/// An FTS5 MATCH expression for a checked full-text query. Every word and
+/// phrase is quoted, so characters FTS5 gives meaning to are matched literally,
+/// and implicit ANDs are spelled out (FTS5 only infers them between phrases).
+fn fts_expression(query: &[TextToken]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let mut parts = Vec::new();
+    let mut after_operand = false;
+    for token in query {
+        let word = matches!(
+            token,
+            TextToken::Term(_) | TextToken::Prefix(_) | TextToken::Phrase(_)
+        );
+        if after_operand && (word || *token == TextToken::Open) {
+            parts.push("AND".to_string());
+        }
+        after_operand = word || *token == TextToken::Close;
+        parts.push(match token {
+            TextToken::Term(w) | TextToken::Phrase(w) => quote(w),
+            TextToken::Prefix(w) => format!("{}*", quote(w)),
+            TextToken::And => "AND".to_string(),
+            TextToken::Or => "OR".to_string(),
+            TextToken::Not => "NOT".to_string(),
+            TextToken::Open => "(".to_string(),
+            TextToken::Close => ")".to_string(),
+        });
+    }
+    parts.join(" ")
+}
+
+/// A `to_tsquery` expression for a checked full-text query, with the same
+/// meaning as [`fts_expression`]'s: every word is quoted, a phrase's words
+/// must be adjacent, and FTS5's binary `a NOT b` becomes `a & !b`.
+fn tsquery_expression(query: &[TextToken]) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut parts = Vec::new();
+    let mut after_operand = false;
+    for token in query {
+        let word = matches!(
+            token,
+            TextToken::Term(_) | TextToken::Prefix(_) | TextToken::Phrase(_)
+        );
+        if after_operand && (word || *token == TextToken::Open) {
+            parts.push("&".to_string());
+        }
+        after_operand = word || *token == TextToken::Close;
+        parts.push(match token {
+            TextToken::Term(w) => quote(w),
+            TextToken::Phrase(w) => format!(
+                "({})",
+                w.split_whitespace().map(quote).collect::<Vec<_>>().join(" <-> ")
+            ),
+            TextToken::Prefix(w) => format!("{}:*", quote(w)),
+            TextToken::And => "&".to_string(),
+            TextToken::Or => "|".to_string(),
+            TextToken::Not => "& !".to_string(),
+            TextToken::Open => "(".to_string(),
+            TextToken::Close => ")".to_string(),
+        });
+    }
+    parts.join(" ")
+}
+
+/// Per-parameter index statistics of one resource type: for each parameter,
+/// how many resources have a value for it and how many distinct values it
+/// takes (see `SearchBackend::refresh_statistics`).
+pub(crate) type ParamStats = std::collections::HashMap<String, (f64, f64)>;
+
+/// Estimated size of a set of the given shape, from the type's statistics
+/// alone. Values are assumed uniform (an equality match selects
+/// `resources / distinct values`), a range or pattern match a third of the
+/// resources with the parameter, a reference hop or a full-text match a third
+/// of the type, and parameters independent of each other.
+pub(crate) fn estimate(shape: &Shape, stats: &ParamStats) -> f64 {
+    let population = stats.get("_id").map_or(0.0, |(resources, _)| *resources);
+    let fraction = |n: f64| if population > 0.0 { n / population } else { 0.0 };
+    match shape {
+        Shape::All => population,
+        Shape::Empty => 0.0,
+        Shape::Present(param) => stats.get(param).map_or(0.0, |(resources, _)| *resources),
+        Shape::Matching { param, exact } => match stats.get(param) {
+            Some((resources, distinct)) if *exact => resources / distinct.max(1.0),
+            Some((resources, _)) => resources / 3.0,
+            None => 0.0,
+        },
+        Shape::Hop | Shape::Text => population / 3.0,
+        Shape::And(a, b) => estimate(a, stats) * fraction(estimate(b, stats)),
+        Shape::Or(a, b) => (estimate(a, stats) + estimate(b, stats)).min(population),
+        Shape::Except(a, b) => estimate(a, stats) * (1.0 - fraction(estimate(b, stats))).max(0.0),
+    }
+}
+
+/// Value one `_sort` key orders by, as an expression over the outer alias
+/// `m`, or `None` if the key's type has no comparable column. A resource with
+/// several values sorts by its lowest one ascending and its highest one
+/// descending (FHIR's "first/last value" reading of multi-valued keys); one
+/// with no value yields NULL.
+pub(crate) fn sort_expr(resource_type: &str, key: &SortKey, args: &mut SqlArgs) -> Option<String> {
+    if key.name == "_id" {
+        return Some("m.resource_id".to_string());
+    }
+    let column = match key.param_type {
+        SearchParamType::Date => "value_date_start",
+        SearchParamType::Token | SearchParamType::String => "value_string_lower",
+        _ => return None,
+    };
+    let agg = if key.descending { "MAX" } else { "MIN" };
+    // Contained matches (`_contained`) keep their keys under the contained
+    // namespace; their ids never collide with top-level ones.
+    args.push(SqlValue::from(resource_type.to_string()));
+    args.push(SqlValue::from(contained_namespace(resource_type)));
+    args.push(SqlValue::from(key.name.clone()));
+    Some(format!(
+        "(SELECT {agg}(k.{column}) FROM search_index k \
+          WHERE k.resource_type IN (?, ?) AND k.param_name = ? AND k.resource_id = m.resource_id)"
+    ))
+}
+
+/// Keyset condition selecting the rows strictly after (or, `backward`, before)
+/// the cursor row in the order `k0, k1, …, resource_id`, where `descending[i]`
+/// gives each key's direction and NULL key values sort last. Expands to the
+/// lexicographic "first differing key" disjunction.
+pub(crate) fn cursor_condition(
+    descending: &[bool],
+    keys: &[SqlValue],
+    id: &str,
+    backward: bool,
+    args: &mut SqlArgs,
+) -> String {
+    let mut branches = Vec::new();
+    // Conditions (and their values) holding the keys so far equal to the cursor's.
+    let mut equal: Vec<String> = Vec::new();
+    let mut equal_values: Vec<SqlValue> = Vec::new();
+    for (i, (value, desc)) in keys.iter().zip(descending).enumerate() {
+        let k = format!("k{i}");
+        // Rows past the cursor on this key: on the far side of its value
+        // (NULLs come after every value), or, from a NULL, nothing forward and
+        // every non-NULL value backward.
+        let past = match (value, backward) {
+            (SqlValue::Null, false) => None,
+            (SqlValue::Null, true) => Some(format!("{k} IS NOT NULL")),
+            (_, false) => Some(format!("({k} {} ? OR {k} IS NULL)", if *desc { "<" } else { ">" })),
+            (_, true) => Some(format!("{k} {} ?", if *desc { ">" } else { "<" })),
+        };
+        if let Some(past) = past {
+            let mut branch = equal.clone();
+            branch.push(past);
+            branches.push(branch.join(" AND "));
+            args.extend(equal_values.iter().cloned());
+            if *value != SqlValue::Null {
+                args.push(value.clone());
+            }
+        }
+        if *value == SqlValue::Null {
+            equal.push(format!("{k} IS NULL"));
+        } else {
+            equal.push(format!("{k} = ?"));
+            equal_values.push(value.clone());
+        }
+    }
+    equal.push(format!("resource_id {} ?", if backward { "<" } else { ">" }));
+    branches.push(equal.join(" AND "));
+    args.extend(equal_values);
+    args.push(SqlValue::from(id.to_string()));
+    branches
+        .iter()
+        .map(|b| format!("({b})"))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// One page of an [`IdSet`] as a single statement (see
+/// `SearchBackend::page`). Its rows are `resource_id` followed by the value of
+/// each applied sort key, read in page order.
+pub(crate) struct PageQuery {
+    pub(crate) sql: String,
+    pub(crate) args: SqlArgs,
+    /// Number of sort key columns after `resource_id`.
+    pub(crate) keys: usize,
+    backward: bool,
+    count: Option<usize>,
+}
+
+/// The statement reading one page of `set`, ordered by the `sort` keys and
+/// then by id. Keys with no comparable column are ignored. The page starts at
+/// `start` (keyset paging from a cursor row, see [`PageStart`]), skips
+/// `offset` rows and holds at most `count` (all if `None`).
+pub(crate) fn page_query(
+    dialect: Dialect,
+    resource_type: &str,
+    set: &IdSet,
+    sort: &[SortKey],
+    start: &PageStart,
+    offset: usize,
+    count: Option<usize>,
+) -> Result<PageQuery> {
+    let mut args: SqlArgs = Vec::new();
+    let mut columns = String::new();
+    let mut descending = Vec::new();
+    for key in sort {
+        if let Some(expr) = sort_expr(resource_type, key, &mut args) {
+            columns.push_str(&format!(", {expr} AS k{}", descending.len()));
+            descending.push(key.descending);
+        }
+    }
+    args.extend(set.args.iter().cloned());
+
+    // A backward page is read in reverse order and flipped afterwards.
+    let (cursor, backward) = match start {
+        PageStart::First => (None, false),
+        PageStart::After(c) => (Some(c), false),
+        PageStart::Before(c) => (Some(c), true),
+    };
+    let filter = match cursor {
+        None => String::new(),
+        Some(c) => {
+            if c.keys.len() != descending.len() {
+                return Err(StoreError::Other(format!(
+                    "page cursor has {} sort values, the query sorts by {}",
+                    c.keys.len(),
+                    descending.len()
+                )));
+            }
+            let keys = c.keys.iter().map(json_to_sql).collect::<Result<Vec<_>>>()?;
+            format!("WHERE {}", cursor_condition(&descending, &keys, &c.id, backward, &mut args))
+        }
+    };
+    let nulls = if backward { "FIRST" } else { "LAST" };
+    let mut order: Vec<String> = descending
+        .iter()
+        .enumerate()
+        .map(|(i, desc)| {
+            let direction = if desc ^ backward { "DESC" } else { "ASC" };
+            format!("k{i} {direction} NULLS {nulls}")
+        })
+        .collect();
+    order.push(format!("resource_id {}", if backward { "DESC" } else { "ASC" }));
+
+    // One row past the page tells whether there is more beyond it. SQLite
+    // has no OFFSET without a LIMIT, and PostgreSQL no negative LIMIT.
+    let limit = match (count, dialect) {
+        (Some(c), _) => {
+            args.push(SqlValue::from(c as i64 + 1));
+            "LIMIT ? "
+        }
+        (None, Dialect::Sqlite) => "LIMIT -1 ",
+        (None, Dialect::Postgres) => "",
+    };
+    args.push(SqlValue::from(offset as i64));
+    let sql = format!(
+        "SELECT * FROM (SELECT m.resource_id{columns} FROM ({}) m) p {filter} \
+         ORDER BY {} {limit}OFFSET ?",
+        set.sql,
+        order.join(", ")
+    );
+    Ok(PageQuery { sql, args, keys: descending.len(), backward, count })
+}
+
+impl PageQuery {
+    /// The page, from the cursors of the rows the statement returned.
+    pub(crate) fn finish(&self, mut cursors: Vec<PageCursor>) -> Page {
+        let more = self.count.is_some_and(|c| cursors.len() > c);
+        if let Some(c) = self.count {
+            cursors.truncate(c);
+        }
+        if self.backward {
+            cursors.reverse();
+        }
+        Page {
+            ids: cursors.iter().map(|c| c.id.clone()).collect(),
+            first: cursors.first().cloned(),
+            last: cursors.last().cloned(),
+            more,
+        }
+    }
+}
+
+/// The number of ids in `set`.
+pub(crate) fn count_query(set: &IdSet) -> String {
+    format!("SELECT COUNT(*) FROM ({}) AS c", set.sql)
+}
+
+/// A page cursor's sort value as a bind value.
+fn json_to_sql(value: &serde_json::Value) -> Result<SqlValue> {
+    match value {
+        serde_json::Value::Null => Ok(SqlValue::Null),
+        serde_json::Value::String(s) => Ok(SqlValue::Text(s.clone())),
+        serde_json::Value::Number(n) if n.is_i64() => Ok(SqlValue::Integer(n.as_i64().unwrap_or(0))),
+        other => Err(StoreError::Other(format!("unsupported page cursor value {other}"))),
+    }
+}
+
+// --- SQL conditions ---
+//
+// Each builder returns a condition over the columns of table alias `t`, with
+// anonymous `?` placeholders whose values it appends to `args` in the order
+// they appear in the text, so conditions compose into larger queries.
+
+pub(crate) type SqlArgs = Vec<SqlValue>;
+
+/// Condition for one composite part, by the part's type.
+pub(crate) fn part_condition(
+    t: &str,
+    param_type: &SearchParamType,
+    prefix: Option<&str>,
+    value: &str,
+    args: &mut SqlArgs,
+) -> Option<String> {
+    let prefix = prefix.unwrap_or("eq");
+    match param_type {
+        SearchParamType::Token => Some(token_condition(t, value, args)),
+        SearchParamType::String => Some(string_condition(t, value, StringMatch::Prefix, args)),
+        SearchParamType::Reference => {
+            args.push(SqlValue::from(value.to_string()));
+            Some(format!("{t}.value_string = ?"))
+        }
+        SearchParamType::Uri => Some(uri_condition(t, value, None, args)),
+        SearchParamType::Date => date_condition(t, prefix, value, args),
+        SearchParamType::Number => {
+            let range = number_range(value)?;
+            Some(numeric_condition(&format!("{t}.value_number"), prefix, range, args))
+        }
+        SearchParamType::Quantity => {
+            let mut parts = value.splitn(3, '|');
+            let number = parts.next().unwrap_or("");
+            let system = parts.next().filter(|s| !s.is_empty());
+            let code = parts.next().filter(|c| !c.is_empty());
+            quantity_condition(t, prefix, number, system, code, args)
+        }
+        SearchParamType::Composite(_) => None,
+    }
+}
+
+/// String match under the `:exact` / `:contains` modifiers (case-insensitive
+/// prefix match by default).
+pub(crate) fn string_condition(t: &str, value: &str, mode: StringMatch, args: &mut SqlArgs) -> String {
+    let lower = value.to_lowercase();
+    match mode {
+        StringMatch::Exact => {
+            args.push(SqlValue::from(lower));
+            format!("{t}.value_string_lower = ?")
+        }
+        StringMatch::Prefix => {
+            args.push(SqlValue::from(format!("{}%", escape_like(&lower))));
+            format!("{t}.value_string_lower LIKE ? ESCAPE '\\'")
+        }
+        StringMatch::Contains => {
+            args.push(SqlValue::from(format!("%{}%", escape_like(&lower))));
+            format!("{t}.value_string_lower LIKE ? ESCAPE '\\'")
+        }
+    }
+}
+
+/// Reference match on the stored `"Type/id"` (or bare id) value.
+pub(crate) fn reference_condition(t: &str, reference: &str, args: &mut SqlArgs) -> String {
+    args.push(SqlValue::from(reference.to_string()));
+    format!("{t}.value_string = ? AND {t}.param_type = 'reference'")
+}
+
+/// Token forms: `system|code`, `code` (any system), `|code` (no system),
+/// `system|` (any code in the system).
+pub(crate) fn token_condition(t: &str, value: &str, args: &mut SqlArgs) -> String {
+    match value.split_once('|') {
+        Some((system, "")) => {
+            args.push(SqlValue::from(system.to_string()));
+            format!("{t}.value_system = ?")
+        }
+        Some(("", code)) => {
+            args.push(SqlValue::from(code.to_string()));
+            format!("{t}.value_string = ? AND {t}.value_system IS NULL")
+        }
+        Some((system, code)) => {
+            args.push(SqlValue::from(system.to_string()));
+            args.push(SqlValue::from(code.to_string()));
+            format!("{t}.value_system = ? AND {t}.value_string = ?")
+        }
+        None => {
+            args.push(SqlValue::from(value.to_string()));
+            format!("{t}.value_string = ?")
+        }
+    }
+}
+
+pub(crate) fn uri_condition(t: &str, value: &str, modifier: Option<&str>, args: &mut SqlArgs) -> String {
+    match modifier {
+        Some("below") => {
+            args.push(SqlValue::from(format!("{}%", escape_like(value))));
+            format!("{t}.value_string LIKE ? ESCAPE '\\'")
+        }
+        Some("above") => {
+            args.push(SqlValue::from(value.to_string()));
+            format!(
+                "{t}.value_string IS NOT NULL \
+                 AND substr(?, 1, length({t}.value_string)) = {t}.value_string"
+            )
+        }
+        _ => {
+            args.push(SqlValue::from(value.to_string()));
+            format!("{t}.value_string = ?")
+        }
+    }
+}
+
+/// Date comparison of the indexed range `[start, end)` against the query
+/// value's range `[qs, qe)`. `None` if the value isn't a date.
+pub(crate) fn date_condition(t: &str, prefix: &str, value: &str, args: &mut SqlArgs) -> Option<String> {
+    let (qs, qe) = fhir_date_range(value)?;
+    let start = format!("{t}.value_date_start");
+    let end = format!("{t}.value_date_end");
+    let mut bind = |x: i64| {
+        args.push(SqlValue::from(x));
+        "?"
+    };
+    let cond = match prefix {
+        "ne" => format!("({start} < {} OR {end} > {})", bind(qs), bind(qe)),
+        "gt" => format!("{end} > {}", bind(qe)),
+        "ge" => format!("{end} > {}", bind(qs)),
+        "lt" => format!("{start} < {}", bind(qs)),
+        "le" => format!("{start} < {}", bind(qe)),
+        // `sa` (starts after): the resource range begins at/after the query
+        // range end. `eb` (ends before): the resource range ends at/before
+        // the query range start.
+        "sa" => format!("{start} >= {}", bind(qe)),
+        "eb" => format!("{end} <= {}", bind(qs)),
+        // `ap` (approximately): any overlap with the query range.
+        "ap" => format!("{start} < {} AND {end} > {}", bind(qe), bind(qs)),
+        _ => format!("{start} >= {} AND {end} <= {}", bind(qs), bind(qe)),
+    };
+    Some(format!("{start} IS NOT NULL AND ({cond})"))
+}
+
+/// Quantity comparison; see [`crate::SearchBackend::search_quantity`] for the unit
+/// rules. `None` if the value isn't a number.
+pub(crate) fn quantity_condition(
+    t: &str,
+    prefix: &str,
+    value: &str,
+    system: Option<&str>,
+    code: Option<&str>,
+    args: &mut SqlArgs,
+) -> Option<String> {
+    let (v, lo, hi) = number_range(value)?;
+
+    if system == Some(sazare_core::ucum::UCUM_SYSTEM)
+        && let Some(code) = code
+        && let Some((cv, unit)) = sazare_core::ucum::canonicalize(v, code)
+        && let Some((clo, _)) = sazare_core::ucum::canonicalize(lo, code)
+        && let Some((chi, _)) = sazare_core::ucum::canonicalize(hi, code)
+    {
+        let range = (cv, clo, chi);
+        let same = numeric_condition(&format!("{t}.value_canonical"), prefix, range, args);
+        let counterpart = sazare_core::ucum::molar_counterpart(&unit);
+        args.push(SqlValue::from(unit));
+        let mut cond = format!("({same} AND {t}.value_canonical_unit = ?)");
+        if let Some((other, power)) = counterpart {
+            let op = if power > 0 { "*" } else { "/" };
+            let expr = format!("({t}.value_canonical {op} {t}.value_molar_mass)");
+            let converted = numeric_condition(&expr, prefix, range, args);
+            args.push(SqlValue::from(other));
+            cond = format!(
+                "({cond} OR ({converted} AND {t}.value_molar_mass IS NOT NULL \
+                 AND {t}.value_canonical_unit = ?))"
+            );
+        }
+        return Some(cond);
+    }
+
+    let mut cond = numeric_condition(&format!("{t}.value_number"), prefix, (v, lo, hi), args);
+    if let Some(system) = system {
+        args.push(SqlValue::from(system.to_string()));
+        cond.push_str(&format!(" AND {t}.value_system = ?"));
+    }
+    if let Some(code) = code {
+        args.push(SqlValue::from(code.to_string()));
+        cond.push_str(&format!(" AND {t}.value_code = ?"));
+    }
+    Some(cond)
+}
+
+/// SQL condition comparing the numeric expression `col` against a query value
+/// `(value, low, high)` (see [`number_range`]) under a comparator prefix.
+pub(crate) fn numeric_condition(
+    col: &str,
+    prefix: &str,
+    (v, lo, hi): (f64, f64, f64),
+    args: &mut SqlArgs,
+) -> String {
+    let mut bind = |x: f64| {
+        args.push(SqlValue::from(x));
+        "?"
+    };
+    let cond = match prefix {
+        "ne" => format!("({col} < {} OR {col} >= {})", bind(lo), bind(hi)),
+        "gt" | "sa" => format!("{col} > {}", bind(v)),
+        "ge" => format!("{col} >= {}", bind(v)),
+        "lt" | "eb" => format!("{col} < {}", bind(v)),
+        "le" => format!("{col} <= {}", bind(v)),
+        "ap" => {
+            let d = (v.abs() * 0.1).max((hi - lo) / 2.0);
+            format!("{col} >= {} AND {col} <= {}", bind(v - d), bind(v + d))
+        }
+        _ => format!("{col} >= {} AND {col} < {}", bind(lo), bind(hi)),
+    };
+    format!("{col} IS NOT NULL AND ({cond})")
+}
@@ -14,6 +14,7 @@ pub enum Operation {
     Update,
     Delete,
     Search,
+    Expunge,
 }
 
 impl Operation {
@@ -24,6 +25,7 @@ impl Operation {
             Operation::Update => "update",
             Operation::Delete => "delete",
             Operation::Search => "search",
+            Operation::Expunge => "expunge",
         }
     }
 }
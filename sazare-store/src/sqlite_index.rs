@@ -2,9 +2,14 @@
 //!
 //! Single file with tables per resource type for performance.
 
+use crate::backend::{IndexWrite, SearchBackend};
 use crate::error::Result;
+use crate::search_sql::{count_query, estimate, page_query, Dialect, IdSet, ParamStats, Shape};
+use serde::{Deserialize, Serialize};
+use sazare_core::SortKey;
 use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use rusqlite::{params, Connection};
+use std::borrow::Cow;
 use std::path::Path;
 
 /// Parse a FHIR date/dateTime string into a half-open epoch-**microsecond**
@@ -62,6 +67,26 @@ pub(crate) fn fhir_date_range(value: &str) -> Option<(i64, i64)> {
     }
 }
 
+/// Parse a FHIR decimal search value into `(value, low, high)`, where
+/// `[low, high)` is the range implied by its precision: the value plus or minus
+/// half a unit in its last significant digit. `100` spans `[99.5, 100.5)`,
+/// `5.40` spans `[5.395, 5.405)`, and `1e2` (one significant digit) spans
+/// `[50, 150)`. Returns `None` if the value is not a number.
+pub(crate) fn number_range(value: &str) -> Option<(f64, f64, f64)> {
+    let v = value.trim();
+    let n: f64 = v.parse().ok()?;
+    if !n.is_finite() {
+        return None;
+    }
+    let (mantissa, exponent) = match v.find(['e', 'E']) {
+        Some(i) => (&v[..i], v[i + 1..].parse::<i32>().ok()?),
+        None => (v, 0),
+    };
+    let decimals = mantissa.split_once('.').map_or(0, |(_, f)| f.len() as i32);
+    let half = 0.5 * 10f64.powi(exponent - decimals);
+    Some((n, n - half, n + half))
+}
+
 /// String search matching mode.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum StringMatch {
@@ -73,9 +98,51 @@ pub enum StringMatch {
     Contains,
 }
 
+/// A row's position in a sorted search result: its value for each applied
+/// `_sort` key (null where it has none) and its id. Page links carry one so
+/// the next request resumes after (or before) that row rather than at a row
+/// count, which shifts when resources are written between requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub keys: Vec<serde_json::Value>,
+    pub id: String,
+}
+
+/// Where a page of search results begins.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageStart {
+    /// The start of the result.
+    First,
+    /// The rows following the cursor row (a `next` link).
+    After(PageCursor),
+    /// The rows preceding the cursor row (a `previous` link).
+    Before(PageCursor),
+}
+
+/// One page of search results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub ids: Vec<String>,
+    /// Cursors of the first and last rows (`None` for an empty page).
+    pub first: Option<PageCursor>,
+    pub last: Option<PageCursor>,
+    /// Whether more rows lie beyond the page in the direction it was read.
+    pub more: bool,
+}
+
+fn sql_to_json(value: rusqlite::types::Value) -> serde_json::Value {
+    use rusqlite::types::Value as SqlValue;
+    match value {
+        SqlValue::Integer(i) => i.into(),
+        SqlValue::Real(f) => f.into(),
+        SqlValue::Text(s) => s.into(),
+        SqlValue::Null | SqlValue::Blob(_) => serde_json::Value::Null,
+    }
+}
+
 /// Escape SQL LIKE metacharacters (`%`, `_`, and the `\` escape char itself) in
 /// a user-supplied value so they are matched literally under `ESCAPE '\'`.
-fn escape_like(s: &str) -> String {
+pub(crate) fn escape_like(s: &str) -> String {
     let mut out = String::with_capacity(s.len());
     for c in s.chars() {
         if matches!(c, '\\' | '%' | '_') {
@@ -86,6 +153,337 @@ fn escape_like(s: &str) -> String {
     out
 }
 
+/// The index namespace holding resources of `resource_type` that are
+/// contained in another resource. Their ids name the container:
+/// `<container type>/<container id>#<local id>`.
+pub(crate) fn contained_namespace(resource_type: &str) -> String {
+    format!("#{resource_type}")
+}
+
+/// A `$reindex` run, kept in the index database so an interrupted one can be
+/// resumed (see [`SearchBackend::begin_reindex`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReindexJob {
+    pub id: String,
+    /// Only resources of this type are reindexed, when set.
+    pub resource_type: Option<String>,
+    /// Only this search parameter is reindexed, when set.
+    pub param_name: Option<String>,
+    /// The last resource written to the shadow table; the run carries on
+    /// after it.
+    pub cursor: Option<(String, String)>,
+    pub status: ReindexStatus,
+    pub resources_indexed: usize,
+    pub entries_written: usize,
+    /// RFC 3339 start time.
+    pub started: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReindexStatus {
+    InProgress,
+    Complete,
+    Failed(String),
+}
+
+/// The rows of `search_index` (aliased `x`) a reindex replaces: everything, a
+/// resource type's rows and those of the resources they contain, or one
+/// parameter's rows (composite parts included), optionally of one type.
+/// `?1` is the type and `?2` the parameter, either NULL.
+pub(crate) const REINDEX_SCOPE: &str = "CASE WHEN ?2 IS NULL THEN \
+        ?1 IS NULL OR x.resource_type = ?1 \
+        OR (x.resource_type LIKE '#%' AND x.resource_id >= ?1 || '/' AND x.resource_id < ?1 || '0') \
+    ELSE \
+        x.resource_type NOT LIKE '#%' AND (?1 IS NULL OR x.resource_type = ?1) \
+        AND (x.param_name = ?2 OR substr(x.param_name, 1, length(?2) + 1) = ?2 || '$') \
+    END";
+
+/// Rows of `x` belonging to a resource written while a reindex ran (or a
+/// resource it contains): those rows are already current.
+pub(crate) const REINDEX_DIRTY: &str = "EXISTS (SELECT 1 FROM reindex_dirty d WHERE \
+        (d.resource_type = x.resource_type AND d.resource_id = x.resource_id) \
+        OR (x.resource_type LIKE '#%' \
+            AND substr(x.resource_id, 1, length(d.resource_type) + length(d.resource_id) + 2) \
+                = d.resource_type || '/' || d.resource_id || '#'))";
+
+pub(crate) const INDEX_COLUMNS: &str = "resource_type, resource_id, param_name, param_type, \
+    value_string, value_string_lower, value_system, value_date_start, value_date_end, \
+    value_number, value_code, value_canonical, value_canonical_unit, value_molar_mass, value_group";
+
+/// One row of the search index tables (columns [`INDEX_COLUMNS`]), as an
+/// `add_index` call is stored.
+pub(crate) struct IndexRow<'a> {
+    pub(crate) resource_type: Cow<'a, str>,
+    pub(crate) resource_id: Cow<'a, str>,
+    pub(crate) param_name: &'a str,
+    pub(crate) param_type: &'a str,
+    pub(crate) value_string: Option<&'a str>,
+    pub(crate) value_string_lower: Option<String>,
+    pub(crate) value_system: Option<&'a str>,
+    pub(crate) date_start: Option<i64>,
+    pub(crate) date_end: Option<i64>,
+    pub(crate) number: Option<f64>,
+    pub(crate) code: Option<&'a str>,
+    pub(crate) canonical_value: Option<f64>,
+    pub(crate) canonical_unit: Option<String>,
+    pub(crate) molar_mass: Option<f64>,
+    pub(crate) group: i64,
+}
+
+impl<'a> IndexRow<'a> {
+    pub(crate) fn new(
+        resource_type: &'a str,
+        resource_id: &'a str,
+        param_name: &'a str,
+        param_type: &'a str,
+        value_string: Option<&'a str>,
+        value_system: Option<&'a str>,
+    ) -> Self {
+        // Rows of a contained resource arrive as "#<type>/<local id>/<param>"
+        // (see `IndexBuilder::extract_contained`) and are filed under the
+        // contained type, keyed by their container.
+        let (resource_type, resource_id, param_name) = match param_name
+            .strip_prefix('#')
+            .and_then(|rest| rest.split_once('/'))
+            .and_then(|(t, rest)| rest.split_once('/').map(|(id, name)| (t, id, name)))
+        {
+            Some((inner_type, local_id, name)) => {
+                let contained_type = contained_namespace(inner_type);
+                let contained_id = format!("{resource_type}/{resource_id}#{local_id}");
+                (Cow::Owned(contained_type), Cow::Owned(contained_id), name)
+            }
+            None => (Cow::Borrowed(resource_type), Cow::Borrowed(resource_id), param_name),
+        };
+
+        // Composite part rows arrive as "<name>$<part>#<group>" (see
+        // `IndexBuilder::extract_composite`); the group is stored separately.
+        let (param_name, group): (&str, i64) = match param_name.rsplit_once('#') {
+            Some((name, g)) if name.contains('$') => match g.parse() {
+                Ok(g) => (name, g),
+                Err(_) => (param_name, -1),
+            },
+            _ => (param_name, -1),
+        };
+
+        // For date params, derive a [start, end) epoch-second range so searches
+        // can apply FHIR range semantics. A Period is encoded by the extractor as
+        // "start/end"; a plain date/dateTime spans a single precision window.
+        let (date_start, date_end): (Option<i64>, Option<i64>) = if param_type == "date" {
+            match value_string {
+                Some(s) => {
+                    if let Some((lo, hi)) = s.split_once('/') {
+                        let start = fhir_date_range(lo).map(|(a, _)| a);
+                        let end = if hi.is_empty() {
+                            Some(i64::MAX)
+                        } else {
+                            fhir_date_range(hi).map(|(_, b)| b)
+                        };
+                        (start, end)
+                    } else {
+                        match fhir_date_range(s) {
+                            Some((a, b)) => (Some(a), Some(b)),
+                            None => (None, None),
+                        }
+                    }
+                }
+                None => (None, None),
+            }
+        } else {
+            (None, None)
+        };
+
+        // Number values are stored as-is. A quantity is encoded by the
+        // extractor as "number|code", or "number|code|molar mass" when the
+        // analyte's molar mass is known; UCUM-coded quantities also get their
+        // canonical value and unit.
+        let mut number: Option<f64> = None;
+        let mut code: Option<&str> = None;
+        let mut molar_mass: Option<f64> = None;
+        let mut canonical: Option<(f64, String)> = None;
+        match (param_type, value_string) {
+            ("number", Some(s)) => number = s.trim().parse().ok(),
+            ("quantity", Some(s)) => {
+                let mut parts = s.split('|');
+                number = parts.next().and_then(|n| n.trim().parse().ok());
+                code = parts.next().filter(|c| !c.is_empty());
+                molar_mass = parts.next().and_then(|m| m.parse().ok());
+                if value_system == Some(sazare_core::ucum::UCUM_SYSTEM)
+                    && let (Some(n), Some(c)) = (number, code)
+                {
+                    canonical = sazare_core::ucum::canonicalize(n, c);
+                }
+            }
+            _ => {}
+        }
+        let (canonical_value, canonical_unit) = canonical.unzip();
+
+        Self {
+            resource_type,
+            resource_id,
+            param_name,
+            param_type,
+            value_string,
+            value_string_lower: value_string.map(|s| s.to_lowercase()),
+            value_system,
+            date_start,
+            date_end,
+            number,
+            code,
+            canonical_value,
+            canonical_unit,
+            molar_mass,
+            group,
+        }
+    }
+}
+
+/// Writes to the search index tables, through a borrowed connection: the
+/// index's own (see [`SearchIndex::writer`]), or the resource store's
+/// transaction when the index shares its database, so a resource and its
+/// entries commit together (see `TransactionOps::index`).
+pub struct IndexWriter<'c> {
+    conn: &'c Connection,
+}
+
+#[allow(clippy::result_large_err)]
+impl<'c> IndexWriter<'c> {
+    pub(crate) fn new(conn: &'c Connection) -> Self {
+        Self { conn }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn insert_row(
+        &self,
+        table: &str,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        let row = IndexRow::new(resource_type, resource_id, param_name, param_type, value_string, value_system);
+        self.conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {table} ({INDEX_COLUMNS}) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"
+            ),
+            params![
+                row.resource_type,
+                row.resource_id,
+                row.param_name,
+                row.param_type,
+                row.value_string,
+                row.value_string_lower,
+                row.value_system,
+                row.date_start,
+                row.date_end,
+                row.number,
+                row.code,
+                row.canonical_value,
+                row.canonical_unit,
+                row.molar_mass,
+                row.group,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Drop a resource's full-text entry. The FTS index stores no text of its
+    /// own, so it is told which text to forget before the row goes.
+    fn remove_text(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO search_text_fts (search_text_fts, rowid, narrative, content) \
+             SELECT 'delete', id, narrative, content FROM search_text \
+             WHERE resource_type = ?1 AND resource_id = ?2",
+            params![resource_type, resource_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM search_text WHERE resource_type = ?1 AND resource_id = ?2",
+            params![resource_type, resource_id],
+        )?;
+        Ok(())
+    }
+
+    /// While a reindex runs, note a resource whose rows are being rewritten
+    /// so the swap keeps them rather than the shadow table's, which may have
+    /// been built from an older version.
+    fn mark_dirty(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO reindex_dirty (resource_type, resource_id) \
+             SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM reindex_job WHERE status = 'in-progress')",
+            params![resource_type, resource_id],
+        )?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl IndexWrite for IndexWriter<'_> {
+    fn add_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        self.insert_row("search_index", resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
+
+    fn index_text(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        narrative: &str,
+        content: &str,
+    ) -> Result<()> {
+        self.remove_text(resource_type, resource_id)?;
+        self.conn.execute(
+            "INSERT INTO search_text (resource_type, resource_id, narrative, content) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![resource_type, resource_id, narrative, content],
+        )?;
+        self.conn.execute(
+            "INSERT INTO search_text_fts (rowid, narrative, content) \
+             VALUES (last_insert_rowid(), ?1, ?2)",
+            params![narrative, content],
+        )?;
+        Ok(())
+    }
+
+    fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.mark_dirty(resource_type, resource_id)?;
+        self.conn.execute(
+            "DELETE FROM search_index WHERE resource_type = ?1 AND resource_id = ?2",
+            params![resource_type, resource_id],
+        )?;
+        // Contained ids are "<type>/<id>#<local id>": the range ['…#', '…$')
+        // holds exactly this container's.
+        self.conn.execute(
+            "DELETE FROM search_index WHERE resource_type LIKE '#%' \
+             AND resource_id >= ?1 AND resource_id < ?2",
+            params![
+                format!("{resource_type}/{resource_id}#"),
+                format!("{resource_type}/{resource_id}$"),
+            ],
+        )?;
+        self.remove_text(resource_type, resource_id)
+    }
+
+    fn remove_param_index(&self, resource_type: &str, resource_id: &str, param_name: &str) -> Result<()> {
+        self.mark_dirty(resource_type, resource_id)?;
+        self.conn.execute(
+            "DELETE FROM search_index WHERE resource_type = ?1 AND resource_id = ?2 \
+             AND (param_name = ?3 OR substr(param_name, 1, length(?3) + 1) = ?3 || '$')",
+            params![resource_type, resource_id, param_name],
+        )?;
+        Ok(())
+    }
+}
+
 /// SQLite-backed search index
 pub struct SearchIndex {
     conn: Connection,
@@ -119,6 +517,166 @@ impl SearchIndex {
         CREATE INDEX IF NOT EXISTS idx_resource
             ON search_index(resource_type, resource_id);
         "#,
+        // v2 — number/quantity columns. Rows written before this version carry
+        // no quantity entries, so the index is cleared and startup rebuilds it
+        // from the resource store.
+        r#"
+        ALTER TABLE search_index ADD COLUMN value_number REAL;
+        ALTER TABLE search_index ADD COLUMN value_code TEXT;
+        CREATE INDEX IF NOT EXISTS idx_type_param_number
+            ON search_index(resource_type, param_name, value_number);
+        DELETE FROM search_index;
+        "#,
+        // v3 — UCUM-canonical quantity columns, plus the analyte's molar mass
+        // where known so mass and molar concentrations compare. Cleared and
+        // rebuilt on startup like v2.
+        r#"
+        ALTER TABLE search_index ADD COLUMN value_canonical REAL;
+        ALTER TABLE search_index ADD COLUMN value_canonical_unit TEXT;
+        ALTER TABLE search_index ADD COLUMN value_molar_mass REAL;
+        CREATE INDEX IF NOT EXISTS idx_type_param_canonical
+            ON search_index(resource_type, param_name, value_canonical_unit, value_canonical);
+        DELETE FROM search_index;
+        "#,
+        // v4 — composite parameters: part rows carry the number of the
+        // repeating element they came from (-1 on ordinary rows), and equal
+        // values from different elements must not replace each other, so the
+        // group joins the unique key. SQLite can't alter a constraint, so the
+        // table is recreated; it is rebuilt on startup like v2.
+        r#"
+        DROP TABLE search_index;
+        CREATE TABLE search_index (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            resource_type TEXT NOT NULL,
+            resource_id TEXT NOT NULL,
+            param_name TEXT NOT NULL,
+            param_type TEXT NOT NULL,
+            value_string TEXT,
+            value_string_lower TEXT,
+            value_system TEXT,
+            value_date_start INTEGER,
+            value_date_end INTEGER,
+            value_number REAL,
+            value_code TEXT,
+            value_canonical REAL,
+            value_canonical_unit TEXT,
+            value_molar_mass REAL,
+            value_group INTEGER NOT NULL DEFAULT -1,
+            UNIQUE(resource_type, resource_id, param_name, value_string, value_system, value_group)
+        );
+        CREATE INDEX idx_type_param_string
+            ON search_index(resource_type, param_name, value_string);
+        CREATE INDEX idx_type_param_token
+            ON search_index(resource_type, param_name, value_system, value_string);
+        CREATE INDEX idx_type_param_date
+            ON search_index(resource_type, param_name, value_date_start, value_date_end);
+        CREATE INDEX idx_resource
+            ON search_index(resource_type, resource_id);
+        CREATE INDEX idx_type_param_number
+            ON search_index(resource_type, param_name, value_number);
+        CREATE INDEX idx_type_param_canonical
+            ON search_index(resource_type, param_name, value_canonical_unit, value_canonical);
+        "#,
+        // v5 — per-parameter statistics for `_total=estimate`, filled by
+        // `refresh_statistics`.
+        r#"
+        CREATE TABLE IF NOT EXISTS search_index_stats (
+            resource_type TEXT NOT NULL,
+            param_name TEXT NOT NULL,
+            resources INTEGER NOT NULL,
+            distinct_values INTEGER NOT NULL,
+            PRIMARY KEY (resource_type, param_name)
+        );
+        "#,
+        // v6 — contained resources, filed under `#<type>` with the id
+        // `<container type>/<container id>#<local id>` (see `add_index`). The
+        // index finds them again when their container is reindexed or deleted.
+        // Cleared and rebuilt on startup like v2 so existing containers get
+        // their contained resources indexed.
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_contained
+            ON search_index(resource_id) WHERE resource_type LIKE '#%';
+        DELETE FROM search_index;
+        "#,
+        // v7 — full-text search (`_text`, `_content`): one row of text per
+        // resource, with an FTS5 index over it kept in step by `index_text`.
+        // Cleared like v2 so startup rebuilds the index, text included.
+        r#"
+        CREATE TABLE IF NOT EXISTS search_text (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            resource_type TEXT NOT NULL,
+            resource_id TEXT NOT NULL,
+            narrative TEXT NOT NULL,
+            content TEXT NOT NULL,
+            UNIQUE(resource_type, resource_id)
+        );
+        CREATE VIRTUAL TABLE IF NOT EXISTS search_text_fts USING fts5(
+            narrative, content, content='search_text', content_rowid='id'
+        );
+        DELETE FROM search_index;
+        "#,
+        // v8 — companion rows for the `:text` and `:of-type` token modifiers
+        // and the `:identifier` reference modifier (`<param>:<modifier>`, see
+        // `IndexBuilder`). Cleared like v2 so startup writes them for existing
+        // resources.
+        r#"
+        DELETE FROM search_index;
+        "#,
+        // v9 — references by value alone, for `_revinclude=*`, which looks for
+        // any parameter of any type pointing at a resource. Cleared like v2 so
+        // Observations get their new `encounter` and `performer` rows.
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_reference
+            ON search_index(value_string) WHERE param_type = 'reference';
+        DELETE FROM search_index;
+        "#,
+        // v10 — background `$reindex`: rows are built into a shadow table and
+        // swapped in when the run completes; the run itself, with a cursor to
+        // resume from, and the resources written meanwhile are kept alongside.
+        r#"
+        CREATE TABLE IF NOT EXISTS search_index_shadow (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            resource_type TEXT NOT NULL,
+            resource_id TEXT NOT NULL,
+            param_name TEXT NOT NULL,
+            param_type TEXT NOT NULL,
+            value_string TEXT,
+            value_string_lower TEXT,
+            value_system TEXT,
+            value_date_start INTEGER,
+            value_date_end INTEGER,
+            value_number REAL,
+            value_code TEXT,
+            value_canonical REAL,
+            value_canonical_unit TEXT,
+            value_molar_mass REAL,
+            value_group INTEGER NOT NULL DEFAULT -1,
+            UNIQUE(resource_type, resource_id, param_name, value_string, value_system, value_group)
+        );
+        CREATE TABLE IF NOT EXISTS reindex_job (
+            id TEXT PRIMARY KEY,
+            resource_type TEXT,
+            param_name TEXT,
+            cursor_type TEXT,
+            cursor_id TEXT,
+            status TEXT NOT NULL,
+            error TEXT,
+            resources_indexed INTEGER NOT NULL DEFAULT 0,
+            entries_written INTEGER NOT NULL DEFAULT 0,
+            started TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS reindex_dirty (
+            resource_type TEXT NOT NULL,
+            resource_id TEXT NOT NULL,
+            PRIMARY KEY (resource_type, resource_id)
+        );
+        "#,
+        // v11 — one worker per `$reindex` run: the one that claimed it, and
+        // when it last showed signs of life.
+        r#"
+        ALTER TABLE reindex_job ADD COLUMN owner TEXT;
+        ALTER TABLE reindex_job ADD COLUMN heartbeat TEXT;
+        "#,
     ];
 
     /// Open the index (create if not exists)
@@ -129,8 +687,71 @@ impl SearchIndex {
         Ok(Self { conn })
     }
 
-    /// Add an index entry
-    pub fn add_index(
+    /// Open the index inside the resource store's database file, so a
+    /// resource and its index entries can be written in one transaction
+    /// (see `TransactionOps::index`). The store owns the file's
+    /// `user_version`; the index schema is versioned by name instead.
+    pub fn open_shared(path: impl AsRef<Path>) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+        crate::migrate::run_named_migrations(&mut conn, "search_index", Self::MIGRATIONS)?;
+        Ok(Self { conn })
+    }
+
+    /// Writes through this index's own connection.
+    pub fn writer(&self) -> IndexWriter<'_> {
+        IndexWriter::new(&self.conn)
+    }
+
+    /// Run `f` in a transaction: all of its writes land, or none do. It takes
+    /// the write lock up front, so that in a database shared with the resource
+    /// store it waits for the store's writer rather than failing part-way.
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Self) -> Result<T>,
+    {
+        let tx = rusqlite::Transaction::new_unchecked(&self.conn, rusqlite::TransactionBehavior::Immediate)?;
+        let result = f(self)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    fn query_reindex_job(&self, condition: &str, arg: &str) -> Result<Option<ReindexJob>> {
+        let sql = format!(
+            "SELECT id, resource_type, param_name, cursor_type, cursor_id, status, error, \
+             resources_indexed, entries_written, started FROM reindex_job WHERE {condition}"
+        );
+        let result = self.conn.query_row(&sql, params![arg], |row| {
+            let cursor_type: Option<String> = row.get(3)?;
+            let cursor_id: Option<String> = row.get(4)?;
+            let status: String = row.get(5)?;
+            let status = match status.as_str() {
+                "in-progress" => ReindexStatus::InProgress,
+                "complete" => ReindexStatus::Complete,
+                _ => ReindexStatus::Failed(row.get::<_, Option<String>>(6)?.unwrap_or_default()),
+            };
+            Ok(ReindexJob {
+                id: row.get(0)?,
+                resource_type: row.get(1)?,
+                param_name: row.get(2)?,
+                cursor: cursor_type.zip(cursor_id),
+                status,
+                resources_indexed: row.get::<_, i64>(7)? as usize,
+                entries_written: row.get::<_, i64>(8)? as usize,
+                started: row.get(9)?,
+            })
+        });
+        match result {
+            Ok(job) => Ok(Some(job)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl IndexWrite for SearchIndex {
+    fn add_index(
         &self,
         resource_type: &str,
         resource_id: &str,
@@ -139,61 +760,48 @@ impl SearchIndex {
         value_string: Option<&str>,
         value_system: Option<&str>,
     ) -> Result<()> {
-        let value_string_lower = value_string.map(|s| s.to_lowercase());
+        self.writer().add_index(resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
 
-        // For date params, derive a [start, end) epoch-second range so searches
-        // can apply FHIR range semantics. A Period is encoded by the extractor as
-        // "start/end"; a plain date/dateTime spans a single precision window.
-        let (date_start, date_end): (Option<i64>, Option<i64>) = if param_type == "date" {
-            match value_string {
-                Some(s) => {
-                    if let Some((lo, hi)) = s.split_once('/') {
-                        let start = fhir_date_range(lo).map(|(a, _)| a);
-                        let end = if hi.is_empty() {
-                            Some(i64::MAX)
-                        } else {
-                            fhir_date_range(hi).map(|(_, b)| b)
-                        };
-                        (start, end)
-                    } else {
-                        match fhir_date_range(s) {
-                            Some((a, b)) => (Some(a), Some(b)),
-                            None => (None, None),
-                        }
-                    }
-                }
-                None => (None, None),
-            }
-        } else {
-            (None, None)
-        };
+    fn index_text(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        narrative: &str,
+        content: &str,
+    ) -> Result<()> {
+        self.writer().index_text(resource_type, resource_id, narrative, content)
+    }
 
-        self.conn.execute(
-            r#"
-            INSERT OR REPLACE INTO search_index
-            (resource_type, resource_id, param_name, param_type,
-             value_string, value_string_lower, value_system,
-             value_date_start, value_date_end)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-            "#,
-            params![
-                resource_type,
-                resource_id,
-                param_name,
-                param_type,
-                value_string,
-                value_string_lower,
-                value_system,
-                date_start,
-                date_end,
-            ],
-        )?;
+    fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()> {
+        self.writer().remove_index(resource_type, resource_id)
+    }
 
-        Ok(())
+    fn remove_param_index(&self, resource_type: &str, resource_id: &str, param_name: &str) -> Result<()> {
+        self.writer().remove_param_index(resource_type, resource_id, param_name)
     }
+}
 
-    /// Count total entries in the search index (for reindex decisions)
-    pub fn row_count(&self) -> Result<usize> {
+#[allow(clippy::result_large_err)]
+impl SearchBackend for SearchIndex {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn add_shadow_index(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+        param_name: &str,
+        param_type: &str,
+        value_string: Option<&str>,
+        value_system: Option<&str>,
+    ) -> Result<()> {
+        self.writer()
+            .insert_row("search_index_shadow", resource_type, resource_id, param_name, param_type, value_string, value_system)
+    }
+
+    fn row_count(&self) -> Result<usize> {
         let count: i64 = self.conn.query_row(
             "SELECT COUNT(*) FROM search_index",
             [],
@@ -202,154 +810,177 @@ impl SearchIndex {
         Ok(count as usize)
     }
 
-    /// Drop all entries from the search index
-    pub fn clear_all(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM search_index", [])?;
+    fn clear_all(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "DELETE FROM search_index;
+             DELETE FROM search_text;
+             INSERT INTO search_text_fts(search_text_fts) VALUES ('delete-all');",
+        )?;
         Ok(())
     }
 
-    /// Remove all index entries for a resource
-    pub fn remove_index(&self, resource_type: &str, resource_id: &str) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM search_index WHERE resource_type = ?1 AND resource_id = ?2",
+    fn indexed_last_updated(&self, resource_type: &str, resource_id: &str) -> Result<Option<Option<String>>> {
+        let (rows, last_updated): (i64, Option<String>) = self.conn.query_row(
+            "SELECT COUNT(*), MAX(CASE WHEN param_name = '_lastUpdated' THEN value_string END) \
+             FROM search_index WHERE resource_type = ?1 AND resource_id = ?2",
             params![resource_type, resource_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((rows > 0).then_some(last_updated))
+    }
+
+    fn resource_keys_page(&self, after: Option<(&str, &str)>, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT resource_type, resource_id FROM search_index \
+             WHERE resource_type NOT LIKE '#%' AND (?1 IS NULL OR (resource_type, resource_id) > (?1, ?2)) \
+             UNION \
+             SELECT resource_type, resource_id FROM search_text \
+             WHERE ?1 IS NULL OR (resource_type, resource_id) > (?1, ?2) \
+             ORDER BY 1, 2 LIMIT ?3",
+        )?;
+        let (after_type, after_id) = after.unzip();
+        let rows = stmt.query_map(params![after_type, after_id, limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut keys = Vec::new();
+        for row in rows {
+            keys.push(row?);
+        }
+        Ok(keys)
+    }
+
+    fn refresh_statistics(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "BEGIN;
+             DELETE FROM search_index_stats;
+             INSERT INTO search_index_stats
+                 SELECT resource_type, param_name, COUNT(DISTINCT resource_id),
+                        COUNT(DISTINCT value_string)
+                 FROM search_index GROUP BY resource_type, param_name;
+             COMMIT;",
         )?;
         Ok(())
     }
 
-    /// Token search (code, identifier, etc.)
-    pub fn search_token(
-        &self,
-        resource_type: &str,
-        param_name: &str,
-        system: Option<&str>,
-        code: &str,
-    ) -> Result<Vec<String>> {
-        let mut ids = Vec::new();
+    fn transaction(&self, f: &mut dyn FnMut(&dyn SearchBackend) -> Result<()>) -> Result<()> {
+        self.in_transaction(|index| f(index))
+    }
 
-        if let Some(sys) = system {
-            let mut stmt = self.conn.prepare(
-                r#"
-                SELECT DISTINCT resource_id FROM search_index
-                WHERE resource_type = ?1
-                  AND param_name = ?2
-                  AND value_system = ?3
-                  AND value_string = ?4
-                "#,
-            )?;
-            let rows = stmt.query_map(params![resource_type, param_name, sys, code], |row| {
-                row.get(0)
-            })?;
-            for row in rows {
-                ids.push(row?);
+    fn begin_reindex(
+        &self,
+        id: &str,
+        resource_type: Option<&str>,
+        param_name: Option<&str>,
+        started: &str,
+    ) -> Result<bool> {
+        self.in_transaction(|index| {
+            if index.active_reindex()?.is_some() {
+                return Ok(false);
             }
-        } else {
-            let mut stmt = self.conn.prepare(
-                r#"
-                SELECT DISTINCT resource_id FROM search_index
-                WHERE resource_type = ?1
-                  AND param_name = ?2
-                  AND value_string = ?3
-                "#,
+            index.conn.execute_batch("DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            index.conn.execute(
+                "INSERT INTO reindex_job (id, resource_type, param_name, status, started) \
+                 VALUES (?1, ?2, ?3, 'in-progress', ?4)",
+                params![id, resource_type, param_name, started],
             )?;
-            let rows = stmt.query_map(params![resource_type, param_name, code], |row| {
-                row.get(0)
-            })?;
-            for row in rows {
-                ids.push(row?);
-            }
-        }
-
-        Ok(ids)
+            Ok(true)
+        })
     }
 
-    /// String search (name, etc.) honoring the `:exact` / `:contains` modifiers.
-    pub fn search_string(
-        &self,
-        resource_type: &str,
-        param_name: &str,
-        value: &str,
-        mode: StringMatch,
-    ) -> Result<Vec<String>> {
-        let lower = value.to_lowercase();
-        let (sql, bound): (&str, String) = match mode {
-            StringMatch::Exact => (
-                "SELECT DISTINCT resource_id FROM search_index \
-                 WHERE resource_type = ?1 AND param_name = ?2 AND value_string_lower = ?3",
-                lower,
-            ),
-            StringMatch::Prefix => (
-                "SELECT DISTINCT resource_id FROM search_index \
-                 WHERE resource_type = ?1 AND param_name = ?2 \
-                   AND value_string_lower LIKE ?3 ESCAPE '\\'",
-                format!("{}%", escape_like(&lower)),
-            ),
-            StringMatch::Contains => (
-                "SELECT DISTINCT resource_id FROM search_index \
-                 WHERE resource_type = ?1 AND param_name = ?2 \
-                   AND value_string_lower LIKE ?3 ESCAPE '\\'",
-                format!("%{}%", escape_like(&lower)),
-            ),
-        };
+    fn reindex_job(&self, id: &str) -> Result<Option<ReindexJob>> {
+        self.query_reindex_job("id = ?1", id)
+    }
 
-        let mut stmt = self.conn.prepare(sql)?;
-        let rows = stmt.query_map(params![resource_type, param_name, bound], |row| row.get(0))?;
+    fn active_reindex(&self) -> Result<Option<ReindexJob>> {
+        self.query_reindex_job("status = ?1", "in-progress")
+    }
 
-        let mut ids = Vec::new();
-        for row in rows {
-            ids.push(row?);
-        }
-        Ok(ids)
+    fn claim_reindex(&self, id: &str, owner: &str, stale_before: &str) -> Result<bool> {
+        let claimed = self.conn.execute(
+            "UPDATE reindex_job SET owner = ?2, heartbeat = ?3 \
+             WHERE id = ?1 AND status = 'in-progress' AND (owner IS NULL OR owner = ?2 OR heartbeat < ?4)",
+            params![id, owner, Utc::now().to_rfc3339(), stale_before],
+        )?;
+        Ok(claimed == 1)
     }
 
-    /// Token search with NO system (`|code`): the resource's value must carry
-    /// the code and have been indexed without a system.
-    pub fn search_token_no_system(
+    fn checkpoint_reindex(
         &self,
-        resource_type: &str,
-        param_name: &str,
-        code: &str,
-    ) -> Result<Vec<String>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT DISTINCT resource_id FROM search_index \
-             WHERE resource_type = ?1 AND param_name = ?2 \
-               AND value_string = ?3 AND value_system IS NULL",
+        id: &str,
+        owner: &str,
+        cursor: (&str, &str),
+        resources: usize,
+        entries: usize,
+    ) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE reindex_job SET cursor_type = ?3, cursor_id = ?4, \
+             resources_indexed = resources_indexed + ?5, entries_written = entries_written + ?6, heartbeat = ?7 \
+             WHERE id = ?1 AND owner = ?2 AND status = 'in-progress'",
+            params![id, owner, cursor.0, cursor.1, resources as i64, entries as i64, Utc::now().to_rfc3339()],
         )?;
-        let rows = stmt.query_map(params![resource_type, param_name, code], |row| row.get(0))?;
-        let mut ids = Vec::new();
-        for row in rows {
-            ids.push(row?);
-        }
-        Ok(ids)
+        Ok(updated == 1)
     }
 
-    /// Token search by system only (`system|`): any code within the system.
-    pub fn search_token_system_only(
-        &self,
-        resource_type: &str,
-        param_name: &str,
-        system: &str,
-    ) -> Result<Vec<String>> {
+    fn finish_reindex(&self, id: &str, owner: &str) -> Result<bool> {
+        self.in_transaction(|index| {
+            let owned = index.conn.execute(
+                "UPDATE reindex_job SET status = 'complete' WHERE id = ?1 AND owner = ?2 AND status = 'in-progress'",
+                params![id, owner],
+            )?;
+            let Some(job) = index.reindex_job(id)?.filter(|_| owned == 1) else {
+                return Ok(false);
+            };
+            let scope = params![job.resource_type, job.param_name];
+            index.conn.execute(
+                &format!("DELETE FROM search_index AS x WHERE ({REINDEX_SCOPE}) AND NOT {REINDEX_DIRTY}"),
+                scope,
+            )?;
+            index.conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO search_index ({INDEX_COLUMNS}) \
+                     SELECT {INDEX_COLUMNS} FROM search_index_shadow AS x WHERE NOT {REINDEX_DIRTY}"
+                ),
+                [],
+            )?;
+            index.conn.execute_batch("DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            Ok(true)
+        })
+    }
+
+    fn fail_reindex(&self, id: &str, owner: &str, error: &str) -> Result<bool> {
+        self.in_transaction(|index| {
+            let owned = index.conn.execute(
+                "UPDATE reindex_job SET status = 'failed', error = ?3 \
+                 WHERE id = ?1 AND owner = ?2 AND status = 'in-progress'",
+                params![id, owner, error],
+            )?;
+            if owned == 0 {
+                return Ok(false);
+            }
+            index.conn.execute_batch("DELETE FROM search_index_shadow; DELETE FROM reindex_dirty;")?;
+            Ok(true)
+        })
+    }
+
+    fn search_referencing(&self, reference: &str) -> Result<Vec<(String, String)>> {
         let mut stmt = self.conn.prepare(
-            "SELECT DISTINCT resource_id FROM search_index \
-             WHERE resource_type = ?1 AND param_name = ?2 AND value_system = ?3",
+            r#"
+            SELECT DISTINCT resource_type, resource_id FROM search_index
+            WHERE value_string = ?1
+              AND param_type = 'reference'
+              AND resource_type NOT LIKE '#%'
+            ORDER BY resource_type, resource_id
+            "#,
         )?;
-        let rows = stmt.query_map(params![resource_type, param_name, system], |row| row.get(0))?;
-        let mut ids = Vec::new();
+        let rows = stmt.query_map(params![reference], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut found = Vec::new();
         for row in rows {
-            ids.push(row?);
+            found.push(row?);
         }
-        Ok(ids)
+        Ok(found)
     }
 
-    /// Distinct resource ids that have at least one index entry for `param_name`
-    /// (used to implement the `:missing` and `:not` modifiers).
-    pub fn ids_with_param(&self, resource_type: &str, param_name: &str) -> Result<Vec<String>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT DISTINCT resource_id FROM search_index \
-             WHERE resource_type = ?1 AND param_name = ?2",
-        )?;
-        let rows = stmt.query_map(params![resource_type, param_name], |row| row.get(0))?;
+    fn ids(&self, set: &IdSet) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(&set.sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(set.args.iter()), |row| row.get(0))?;
         let mut ids = Vec::new();
         for row in rows {
             ids.push(row?);
@@ -357,145 +988,56 @@ impl SearchIndex {
         Ok(ids)
     }
 
-    /// Reference search (subject, patient, etc.)
-    pub fn search_reference(
-        &self,
-        resource_type: &str,
-        param_name: &str,
-        reference: &str,
-    ) -> Result<Vec<String>> {
+    fn estimate(&self, resource_type: &str, shape: &Shape) -> Result<Option<usize>> {
         let mut stmt = self.conn.prepare(
-            r#"
-            SELECT DISTINCT resource_id FROM search_index
-            WHERE resource_type = ?1
-              AND param_name = ?2
-              AND value_string = ?3
-              AND param_type = 'reference'
-            "#,
+            "SELECT param_name, resources, distinct_values FROM search_index_stats \
+             WHERE resource_type = ?1",
         )?;
-        let rows = stmt.query_map(params![resource_type, param_name, reference], |row| {
-            row.get(0)
+        let rows = stmt.query_map(params![resource_type], |row| {
+            Ok((row.get::<_, String>(0)?, (row.get::<_, f64>(1)?, row.get::<_, f64>(2)?)))
         })?;
-
-        let mut ids = Vec::new();
-        for row in rows {
-            ids.push(row?);
+        let stats = rows.collect::<rusqlite::Result<ParamStats>>()?;
+        if stats.is_empty() {
+            return Ok(None);
         }
-
-        Ok(ids)
+        Ok(Some(estimate(shape, &stats).round() as usize))
     }
 
-    /// Resolve the references held by a set of source resources — the inverse of
-    /// [`search_reference`]. Given source resources of `source_type` and their
-    /// `param_name` reference parameter, return the ids of every referenced
-    /// resource of `target_type`. Used to evaluate `_has` reverse chains.
-    ///
-    /// References are stored as `value_string` like `"Patient/123"`, so we match
-    /// the `"{target_type}/"` prefix and strip it to recover the bare id.
-    pub fn referenced_targets(
-        &self,
-        source_type: &str,
-        param_name: &str,
-        source_ids: &[String],
-        target_type: &str,
-    ) -> Result<Vec<String>> {
-        if source_ids.is_empty() {
-            return Ok(Vec::new());
-        }
-        let prefix = format!("{target_type}/");
-        let mut out: Vec<String> = Vec::new();
-        let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-        // Chunk the IN list to stay clear of SQLite's bound-variable limit.
-        for chunk in source_ids.chunks(500) {
-            let placeholders = std::iter::repeat_n("?", chunk.len())
-                .collect::<Vec<_>>()
-                .join(",");
-            let sql = format!(
-                "SELECT DISTINCT value_string FROM search_index \
-                 WHERE resource_type = ? AND param_name = ? AND param_type = 'reference' \
-                 AND resource_id IN ({placeholders})"
-            );
-            let mut stmt = self.conn.prepare(&sql)?;
-
-            let mut args: Vec<&str> = Vec::with_capacity(chunk.len() + 2);
-            args.push(source_type);
-            args.push(param_name);
-            args.extend(chunk.iter().map(|s| s.as_str()));
-
-            let rows = stmt.query_map(rusqlite::params_from_iter(args), |row| {
-                row.get::<_, String>(0)
-            })?;
-            for row in rows {
-                let value = row?;
-                if let Some(id) = value.strip_prefix(&prefix)
-                    && seen.insert(id.to_string())
-                {
-                    out.push(id.to_string());
-                }
-            }
-        }
-        Ok(out)
+    fn count(&self, set: &IdSet) -> Result<usize> {
+        let n: i64 = self
+            .conn
+            .query_row(&count_query(set), rusqlite::params_from_iter(set.args.iter()), |row| row.get(0))?;
+        Ok(n as usize)
     }
 
-    /// Date search (with prefix: eq, ne, ge, le, gt, lt).
-    ///
-    /// Indexed values are half-open epoch ranges `[start, end)` reflecting their
-    /// precision (and the full span of a Period). The query value is likewise
-    /// expanded to a range `[qs, qe)`, then compared per FHIR date semantics so
-    /// that, e.g., an instant `eq` search does not match a wider Period.
-    pub fn search_date_with_prefix(
+    fn page(
         &self,
         resource_type: &str,
-        param_name: &str,
-        prefix: &str,
-        value: &str,
-    ) -> Result<Vec<String>> {
-        let Some((qs, qe)) = fhir_date_range(value) else {
-            return Ok(Vec::new());
-        };
-
-        // Each arm compares the resource range [start, end) to the query [qs, qe).
-        // qs is bound to ?3 and qe to ?4; the trailing `?n = ?n` tautologies keep
-        // both placeholders referenced so the bound parameter count always matches.
-        let cond = match prefix {
-            "eq" => "value_date_start >= ?3 AND value_date_end <= ?4",
-            "ne" => "(value_date_start < ?3 OR value_date_end > ?4)",
-            "gt" => "value_date_end > ?4 AND ?3 = ?3",
-            "ge" => "value_date_end > ?3 AND ?4 = ?4",
-            "lt" => "value_date_start < ?3 AND ?4 = ?4",
-            "le" => "value_date_start < ?4 AND ?3 = ?3",
-            // `sa` (starts after): the resource range begins at/after the query
-            // range end. `eb` (ends before): the resource range ends at/before
-            // the query range start.
-            "sa" => "value_date_start >= ?4",
-            "eb" => "value_date_end <= ?3 AND ?4 = ?4",
-            // `ap` (approximately): any overlap with the query range.
-            "ap" => "value_date_start < ?4 AND value_date_end > ?3",
-            _ => "value_date_start >= ?3 AND value_date_end <= ?4",
-        };
-        let query = format!(
-            "SELECT DISTINCT resource_id FROM search_index
-             WHERE resource_type = ?1 AND param_name = ?2
-               AND value_date_start IS NOT NULL AND ({cond})"
-        );
-        let mut stmt = self.conn.prepare(&query)?;
-        let rows = stmt.query_map(params![resource_type, param_name, qs, qe], |row| {
-            row.get(0)
-        })?;
-
-        let mut ids = Vec::new();
-        for row in rows {
-            ids.push(row?);
+        set: &IdSet,
+        sort: &[SortKey],
+        start: &PageStart,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Result<Page> {
+        let query = page_query(Dialect::Sqlite, resource_type, set, sort, start, offset, count)?;
+        let mut stmt = self.conn.prepare(&query.sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(query.args.iter()))?;
+        let mut cursors = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut keys = Vec::with_capacity(query.keys);
+            for i in 0..query.keys {
+                keys.push(sql_to_json(row.get(i + 1)?));
+            }
+            cursors.push(PageCursor { keys, id: row.get(0)? });
         }
-
-        Ok(ids)
+        Ok(query.finish(cursors))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sazare_core::SearchParamType;
 
     #[test]
     fn test_token_search() {
@@ -524,6 +1066,134 @@ mod tests {
         assert_eq!(results, vec!["123"]);
     }
 
+    #[test]
+    fn test_remove_param_index() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        index.add_index("Patient", "p1", "nickname", "string", Some("Annie"), None).unwrap();
+        index.add_index("Patient", "p1", "nickname-code$0#0", "token", Some("a"), None).unwrap();
+        index.add_index("Patient", "p1", "nickname$0#0", "token", Some("a"), None).unwrap();
+        index.add_index("Patient", "p1", "family", "string", Some("Davis"), None).unwrap();
+        index.add_index("Patient", "p2", "nickname", "string", Some("Bo"), None).unwrap();
+
+        index.remove_param_index("Patient", "p1", "nickname").unwrap();
+        assert_eq!(index.ids_with_param("Patient", "nickname").unwrap(), vec!["p2".to_string()]);
+        assert!(index.ids_with_param("Patient", "nickname$0").unwrap().is_empty());
+        assert_eq!(index.ids_with_param("Patient", "nickname-code$0").unwrap(), vec!["p1".to_string()]);
+        assert_eq!(index.ids_with_param("Patient", "family").unwrap(), vec!["p1".to_string()]);
+    }
+
+    #[test]
+    fn test_reindex_run_belongs_to_its_claimant() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        index.add_index("Patient", "p1", "family", "string", Some("Live"), None).unwrap();
+        assert!(index.begin_reindex("job1", None, None, "2026-01-01T00:00:00Z").unwrap());
+        assert!(index.claim_reindex("job1", "a", "2000-01-01T00:00:00Z").unwrap());
+        // "a" is still renewing its claim, so "b" can't have the run.
+        assert!(!index.claim_reindex("job1", "b", "2000-01-01T00:00:00Z").unwrap());
+        assert!(!index.checkpoint_reindex("job1", "b", ("Patient", "p1"), 1, 0).unwrap());
+        assert!(!index.finish_reindex("job1", "b").unwrap());
+        assert!(!index.fail_reindex("job1", "b", "boom").unwrap());
+        let live = |index: &SearchIndex| index.search_string("Patient", "family", "live", StringMatch::Exact).unwrap();
+        assert_eq!(live(&index), vec!["p1".to_string()]);
+        assert_eq!(index.reindex_job("job1").unwrap().unwrap().status, ReindexStatus::InProgress);
+
+        // Once "a" stops renewing, "b" takes over and "a" is shut out.
+        assert!(index.claim_reindex("job1", "b", "2999-01-01T00:00:00Z").unwrap());
+        assert!(!index.checkpoint_reindex("job1", "a", ("Patient", "p1"), 1, 0).unwrap());
+        assert!(!index.finish_reindex("job1", "a").unwrap());
+        assert!(index.checkpoint_reindex("job1", "b", ("Patient", "p1"), 1, 0).unwrap());
+        assert!(index.finish_reindex("job1", "b").unwrap());
+        assert_eq!(index.reindex_job("job1").unwrap().unwrap().status, ReindexStatus::Complete);
+        assert!(live(&index).is_empty());
+
+        // A finished run can't be claimed, swapped or failed again.
+        index.add_index("Patient", "p1", "family", "string", Some("Live"), None).unwrap();
+        assert!(!index.claim_reindex("job1", "b", "2999-01-01T00:00:00Z").unwrap());
+        assert!(!index.finish_reindex("job1", "b").unwrap());
+        assert!(!index.fail_reindex("job1", "b", "boom").unwrap());
+        assert_eq!(live(&index), vec!["p1".to_string()]);
+        assert_eq!(index.reindex_job("job1").unwrap().unwrap().status, ReindexStatus::Complete);
+    }
+
+    #[test]
+    fn test_reindex_swaps_scope_and_keeps_concurrent_writes() {
+        let path = std::env::temp_dir().join(format!("sazare-reindex-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let index = SearchIndex::open(&path).unwrap();
+        index.add_index("Patient", "p1", "family", "string", Some("Old"), None).unwrap();
+        index.add_index("Patient", "p2", "family", "string", Some("Old"), None).unwrap();
+        index.add_index("Observation", "o1", "code", "token", Some("x"), None).unwrap();
+        index.add_index("Observation", "o1", "#Patient/c1/family", "string", Some("Kept"), None).unwrap();
+        index.add_index("Patient", "p9", "family", "string", Some("Deleted"), None).unwrap();
+
+        assert!(index.begin_reindex("job1", Some("Patient"), None, "2026-01-01T00:00:00Z").unwrap());
+        assert!(!index.begin_reindex("job2", None, None, "2026-01-01T00:00:00Z").unwrap());
+        assert!(index.claim_reindex("job1", "w1", "2000-01-01T00:00:00Z").unwrap());
+        index
+            .in_transaction(|index| {
+                index.add_shadow_index("Patient", "p1", "family", "string", Some("New"), None)?;
+                assert!(index.checkpoint_reindex("job1", "w1", ("Patient", "p1"), 1, 1)?);
+                Ok(())
+            })
+            .unwrap();
+
+        // A restart picks the run up where it stopped.
+        drop(index);
+        let index = SearchIndex::open(&path).unwrap();
+        let job = index.active_reindex().unwrap().unwrap();
+        assert_eq!(job.id, "job1");
+        assert_eq!(job.cursor, Some(("Patient".to_string(), "p1".to_string())));
+        assert_eq!((job.resources_indexed, job.entries_written), (1, 1));
+
+        // p2 is written while the run is going; its shadow rows are stale.
+        index.add_shadow_index("Patient", "p2", "family", "string", Some("Stale"), None).unwrap();
+        index.remove_index("Patient", "p2").unwrap();
+        index.add_index("Patient", "p2", "family", "string", Some("Current"), None).unwrap();
+        assert!(index.finish_reindex("job1", "w1").unwrap());
+
+        let family = |v: &str| index.search_string("Patient", "family", v, StringMatch::Exact).unwrap();
+        assert_eq!(index.reindex_job("job1").unwrap().unwrap().status, ReindexStatus::Complete);
+        assert!(index.active_reindex().unwrap().is_none());
+        assert_eq!(family("new"), vec!["p1".to_string()]);
+        assert_eq!(family("current"), vec!["p2".to_string()]);
+        assert!(family("old").is_empty());
+        assert!(family("stale").is_empty());
+        // Gone from the store, so not rebuilt.
+        assert!(family("deleted").is_empty());
+        // Other types are outside the scope; so are another type's contained rows.
+        assert_eq!(index.search_token("Observation", "code", None, "x").unwrap(), vec!["o1".to_string()]);
+        assert_eq!(index.ids_with_param("#Patient", "family").unwrap(), vec!["Observation/o1#c1".to_string()]);
+
+        drop(index);
+        for ext in ["sqlite", "sqlite-wal", "sqlite-shm"] {
+            let _ = std::fs::remove_file(path.with_extension(ext));
+        }
+    }
+
+    #[test]
+    fn test_indexed_last_updated_and_resource_keys() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        index.add_index("Patient", "p1", "_id", "token", Some("p1"), None).unwrap();
+        index.add_index("Patient", "p1", "_lastUpdated", "date", Some("2026-01-01T00:00:00Z"), None).unwrap();
+        index.add_index("Patient", "p2", "_id", "token", Some("p2"), None).unwrap();
+        index.add_index("Observation", "o1", "#Patient/c1/family", "string", Some("X"), None).unwrap();
+        index.index_text("Encounter", "e1", "", "text").unwrap();
+
+        assert_eq!(
+            index.indexed_last_updated("Patient", "p1").unwrap(),
+            Some(Some("2026-01-01T00:00:00Z".to_string()))
+        );
+        assert_eq!(index.indexed_last_updated("Patient", "p2").unwrap(), Some(None));
+        assert_eq!(index.indexed_last_updated("Patient", "p3").unwrap(), None);
+
+        let key = |t: &str, i: &str| (t.to_string(), i.to_string());
+        assert_eq!(
+            index.resource_keys_page(None, 10).unwrap(),
+            vec![key("Encounter", "e1"), key("Patient", "p1"), key("Patient", "p2")]
+        );
+        assert_eq!(index.resource_keys_page(Some(("Encounter", "e1")), 1).unwrap(), vec![key("Patient", "p1")]);
+    }
+
     #[test]
     fn test_string_search() {
         let index = SearchIndex::open(":memory:").unwrap();
@@ -559,6 +1229,38 @@ mod tests {
         assert_eq!(results, vec!["o1"]);
     }
 
+    #[test]
+    fn test_page_orders_multi_valued_keys_and_pages_in_sql() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        for id in ["p1", "p2", "p3"] {
+            index.add_index("Patient", id, "_id", "token", Some(id), None).unwrap();
+        }
+        index.add_index("Patient", "p1", "given", "string", Some("Zoe"), None).unwrap();
+        index.add_index("Patient", "p1", "given", "string", Some("Amy"), None).unwrap();
+        index.add_index("Patient", "p2", "given", "string", Some("Bob"), None).unwrap();
+
+        let key = |descending| SortKey {
+            name: "given".to_string(),
+            descending,
+            param_type: SearchParamType::String,
+        };
+        let all = IdSet::all("Patient");
+        // p1 sorts by "amy" ascending and by "zoe" descending; p3 has no
+        // value and comes last either way.
+        assert_eq!(index.page("Patient", &all, &[key(false)], &PageStart::First, 0, None).unwrap().ids, ["p1", "p2", "p3"]);
+        assert_eq!(index.page("Patient", &all, &[key(true)], &PageStart::First, 0, None).unwrap().ids, ["p1", "p2", "p3"]);
+        assert_eq!(index.page("Patient", &all, &[key(false)], &PageStart::First, 1, Some(1)).unwrap().ids, ["p2"]);
+        assert_eq!(index.count(&all).unwrap(), 3);
+
+        let not_bob = IdSet::all("Patient").except(IdSet::matching(
+            "Patient",
+            "given",
+            "s.value_string_lower = 'bob'",
+            Vec::new(),
+        ));
+        assert_eq!(index.page("Patient", &not_bob, &[], &PageStart::First, 0, None).unwrap().ids, ["p1", "p3"]);
+    }
+
     #[test]
     fn test_referenced_targets() {
         let index = SearchIndex::open(":memory:").unwrap();
@@ -709,4 +1411,85 @@ mod tests {
             .unwrap();
         assert_eq!(eq, vec!["b"]);
     }
+
+    #[test]
+    fn test_number_range_implicit_precision() {
+        assert_eq!(number_range("100"), Some((100.0, 99.5, 100.5)));
+        assert_eq!(number_range("1e2"), Some((100.0, 50.0, 150.0)));
+        let (_, lo, hi) = number_range("5.40").unwrap();
+        assert!((lo - 5.395).abs() < 1e-9 && (hi - 5.405).abs() < 1e-9);
+        assert_eq!(number_range("abc"), None);
+    }
+
+    #[test]
+    fn test_quantity_search_prefixes_and_units() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        let ucum = "http://unitsofmeasure.org";
+        for (id, value) in [("a", "5.4|mmol/L"), ("b", "6.1|mmol/L"), ("c", "99.7|mmol/L")] {
+            index
+                .add_index("Observation", id, "value-quantity", "quantity", Some(value), Some(ucum))
+                .unwrap();
+        }
+        // Same number, different unit: must not match a unit-qualified search.
+        index
+            .add_index("Observation", "d", "value-quantity", "quantity", Some("6.1|mg/dL"), Some(ucum))
+            .unwrap();
+
+        let search = |prefix: &str, value: &str, system: Option<&str>, code: Option<&str>| {
+            let mut ids = index
+                .search_quantity("Observation", "value-quantity", prefix, value, system, code)
+                .unwrap();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("gt", "5.4", Some(ucum), Some("mmol/L")), vec!["b", "c"]);
+        assert_eq!(search("ge", "5.4", Some(ucum), Some("mmol/L")), vec!["a", "b", "c"]);
+        assert_eq!(search("lt", "6.1", None, None), vec!["a"]);
+        assert_eq!(search("gt", "6", None, Some("mg/dL")), vec!["d"]);
+        // `100` covers [99.5, 100.5), so 99.7 matches eq; `100.0` does not.
+        assert_eq!(search("eq", "100", None, None), vec!["c"]);
+        assert!(search("eq", "100.0", None, None).is_empty());
+        assert_eq!(search("ne", "6.1", Some(ucum), Some("mmol/L")), vec!["a", "c"]);
+        // ap: within 10% of 6 → [5.4, 6.6].
+        assert_eq!(search("ap", "6", None, Some("mmol/L")), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_quantity_search_ucum_canonical() {
+        let index = SearchIndex::open(":memory:").unwrap();
+        let ucum = "http://unitsofmeasure.org";
+        // Glucose (molar mass 180.156) reported by three labs in three units.
+        for (id, value) in [
+            ("mmol", "5.4|mmol/L|180.156"),
+            ("mol", "0.0054|mol/L|180.156"),
+            ("mass", "97.3|mg/dL|180.156"),
+        ] {
+            index
+                .add_index("Observation", id, "value-quantity", "quantity", Some(value), Some(ucum))
+                .unwrap();
+        }
+        // A mass concentration with no known analyte can't become molar.
+        index
+            .add_index("Observation", "unknown", "value-quantity", "quantity", Some("97.3|mg/dL"), Some(ucum))
+            .unwrap();
+
+        let search = |prefix: &str, value: &str, system: Option<&str>, code: &str| {
+            let mut ids = index
+                .search_quantity("Observation", "value-quantity", prefix, value, system, Some(code))
+                .unwrap();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search("eq", "5.4", Some(ucum), "mmol/L"), vec!["mass", "mmol", "mol"]);
+        // Precision carries through conversion: `5.40e3` is [5395, 5405) umol/L,
+        // wide enough for 97.3 mg/dL (5400.9 umol/L); `5400` is not.
+        assert_eq!(search("eq", "5.40e3", Some(ucum), "umol/L"), vec!["mass", "mmol", "mol"]);
+        assert_eq!(search("eq", "5400", Some(ucum), "umol/L"), vec!["mmol", "mol"]);
+        assert_eq!(search("eq", "97.3", Some(ucum), "mg/dL"), vec!["mass", "mmol", "mol", "unknown"]);
+        assert!(search("gt", "5.5", Some(ucum), "mmol/L").is_empty());
+        // Without the UCUM system the unit is matched exactly, unconverted.
+        assert_eq!(search("eq", "5.4", None, "mmol/L"), vec!["mmol"]);
+    }
 }
@@ -2,10 +2,17 @@
 //!
 //! Schema:
 //!   - resources: Current version only (resource_type, id)
-//!   - resource_history: Version history (resource_type, id, version_id)
+//!   - resource_history: Version history (resource_type, id, version_id),
+//!     with each version's `meta.lastUpdated` for the `_history` interactions.
+//!     A delete adds a tombstone version (`deleted`), a bare resource with
+//!     the deletion's version and time.
 
+use crate::backend::{IndexWrite, ResourceStore, ResourceTransaction};
 use crate::error::Result;
-use rusqlite::{params, Connection, OpenFlags, Transaction};
+use crate::search_sql::SqlArgs;
+use crate::sqlite_index::IndexWriter;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, OpenFlags, Transaction, TransactionBehavior};
 use std::ops::Deref;
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -15,6 +22,206 @@ use std::sync::{Mutex, MutexGuard};
 /// these read concurrently with each other and with the single writer.
 const READ_POOL_SIZE: usize = 4;
 
+/// The `meta.versionId` of a `resources` row aliased `r`, for
+/// [`expunge_statements`] and [`prune_history_statement`].
+const CURRENT_VERSION: &str = "json_extract(r.value, '$.meta.versionId')";
+
+/// A `_history` query: the versions of every resource, of one type, or of
+/// one resource, newest first (see [`ResourceStore::history`]).
+///
+/// Times are compared as strings, so `since` and `at` must be written the way
+/// the server writes `meta.lastUpdated` (`chrono`'s `to_rfc3339()` in UTC).
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery<'a> {
+    pub resource_type: Option<&'a str>,
+    pub id: Option<&'a str>,
+    /// Only versions last updated at or after this time (`_since`).
+    pub since: Option<&'a str>,
+    /// Only the version of each resource current at this time (`_at`).
+    pub at: Option<&'a str>,
+    /// Resume after this version (the last one of the previous page).
+    pub after: Option<HistoryKey>,
+    pub limit: usize,
+}
+
+/// A version's position in history order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryKey {
+    /// `meta.lastUpdated`, or empty when the version has none.
+    pub last_updated: String,
+    pub resource_type: String,
+    pub id: String,
+    pub version_id: String,
+}
+
+/// One version returned by [`ResourceStore::history`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub key: HistoryKey,
+    pub value: Vec<u8>,
+    /// The version is a deletion's tombstone.
+    pub deleted: bool,
+}
+
+/// The statement for a [`HistoryQuery`], common to SQLite and PostgreSQL:
+/// `resource_history` ordered by `(last_updated, resource_type, id,
+/// version_id)` descending, which is unique, so a page resumes exactly after
+/// its predecessor's last row. Selects those four columns, `value` and
+/// `deleted`.
+pub(crate) fn history_query(query: &HistoryQuery<'_>) -> (String, SqlArgs) {
+    let mut conds = Vec::new();
+    let mut args: SqlArgs = Vec::new();
+    if let Some(resource_type) = query.resource_type {
+        conds.push("h.resource_type = ?".to_string());
+        args.push(SqlValue::from(resource_type.to_string()));
+    }
+    if let Some(id) = query.id {
+        conds.push("h.id = ?".to_string());
+        args.push(SqlValue::from(id.to_string()));
+    }
+    if let Some(since) = query.since {
+        conds.push("h.last_updated >= ?".to_string());
+        args.push(SqlValue::from(since.to_string()));
+    }
+    if let Some(at) = query.at {
+        // The newest version at or before `at`: no later one also precedes it.
+        conds.push(
+            "h.last_updated <= ? AND NOT EXISTS (SELECT 1 FROM resource_history n \
+             WHERE n.resource_type = h.resource_type AND n.id = h.id \
+             AND n.last_updated > h.last_updated AND n.last_updated <= ?)"
+                .to_string(),
+        );
+        args.push(SqlValue::from(at.to_string()));
+        args.push(SqlValue::from(at.to_string()));
+    }
+    if let Some(after) = &query.after {
+        conds.push("(h.last_updated, h.resource_type, h.id, h.version_id) < (?, ?, ?, ?)".to_string());
+        for key in [&after.last_updated, &after.resource_type, &after.id, &after.version_id] {
+            args.push(SqlValue::from(key.clone()));
+        }
+    }
+    let filter = if conds.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conds.join(" AND "))
+    };
+    args.push(SqlValue::from(query.limit as i64));
+    let sql = format!(
+        "SELECT h.resource_type, h.id, h.version_id, h.last_updated, h.value, h.deleted FROM resource_history h{filter} \
+         ORDER BY h.last_updated DESC, h.resource_type DESC, h.id DESC, h.version_id DESC LIMIT ?"
+    );
+    (sql, args)
+}
+
+/// What [`ResourceStore::expunge`] removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpungeMode {
+    /// Every version but each resource's current one (a deleted resource's
+    /// current version is its tombstone).
+    PreviousVersions,
+    /// The whole history of each deleted resource.
+    DeletedResources,
+    /// Current resources and all of their history.
+    Everything,
+}
+
+/// What an expunge removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expunged {
+    /// Resources with at least one version (or their current row) removed.
+    pub resources: usize,
+    /// Versions removed from `resource_history`.
+    pub versions: usize,
+}
+
+/// The statements for [`ResourceStore::expunge`], common to SQLite and
+/// PostgreSQL. All take `args`.
+pub(crate) struct ExpungeStatements {
+    /// Counts the resources `history` touches; run first.
+    pub count: String,
+    /// Deletes from `resource_history`.
+    pub history: String,
+    /// Deletes from `resources` (only for [`ExpungeMode::Everything`]).
+    pub current: Option<String>,
+    pub args: SqlArgs,
+}
+
+/// The `resource_history` rows [`ExpungeMode::PreviousVersions`] removes:
+/// all but a resource's current version, which is the one stored in
+/// `resources` (`current_version` reads it from a row aliased `r`) or, for a
+/// deleted resource, its newest.
+fn previous_version_condition(current_version: &str) -> String {
+    format!(
+        "EXISTS (SELECT 1 FROM resource_history n \
+         WHERE n.resource_type = resource_history.resource_type AND n.id = resource_history.id \
+         AND n.last_updated > resource_history.last_updated) \
+         AND NOT EXISTS (SELECT 1 FROM resources r \
+         WHERE r.resource_type = resource_history.resource_type AND r.id = resource_history.id \
+         AND {current_version} = resource_history.version_id)"
+    )
+}
+
+/// The statements expunging `mode` from the whole store, a resource type or
+/// one resource. `current_version` is the backend's expression for the
+/// `meta.versionId` of a `resources` row aliased `r`.
+pub(crate) fn expunge_statements(
+    resource_type: Option<&str>,
+    id: Option<&str>,
+    mode: ExpungeMode,
+    current_version: &str,
+) -> ExpungeStatements {
+    // Unqualified, so they apply to `resources` and `resource_history` alike.
+    let mut scope = Vec::new();
+    let mut args: SqlArgs = Vec::new();
+    if let Some(resource_type) = resource_type {
+        scope.push("resource_type = ?".to_string());
+        args.push(SqlValue::from(resource_type.to_string()));
+    }
+    if let Some(id) = id {
+        scope.push("id = ?".to_string());
+        args.push(SqlValue::from(id.to_string()));
+    }
+    let mut history_conds = scope.clone();
+    match mode {
+        ExpungeMode::PreviousVersions => history_conds.push(previous_version_condition(current_version)),
+        ExpungeMode::DeletedResources => history_conds.push(
+            "NOT EXISTS (SELECT 1 FROM resources r \
+             WHERE r.resource_type = resource_history.resource_type AND r.id = resource_history.id)"
+                .to_string(),
+        ),
+        ExpungeMode::Everything => {}
+    }
+    let filter = |conds: &[String]| {
+        if conds.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conds.join(" AND "))
+        }
+    };
+    let history_filter = filter(&history_conds);
+    ExpungeStatements {
+        count: format!(
+            "SELECT COUNT(*) FROM (SELECT DISTINCT resource_type, id FROM resource_history{history_filter}) touched"
+        ),
+        history: format!("DELETE FROM resource_history{history_filter}"),
+        current: (mode == ExpungeMode::Everything).then(|| format!("DELETE FROM resources{}", filter(&scope))),
+        args,
+    }
+}
+
+/// The statement for [`ResourceStore::prune_history`]: deletes the versions
+/// last updated before `before`, other than the current version of each
+/// stored resource (`current_version` as for [`expunge_statements`]).
+pub(crate) fn prune_history_statement(before: &str, current_version: &str) -> (String, SqlArgs) {
+    let sql = format!(
+        "DELETE FROM resource_history WHERE last_updated < ? \
+         AND NOT EXISTS (SELECT 1 FROM resources r \
+         WHERE r.resource_type = resource_history.resource_type AND r.id = resource_history.id \
+         AND {current_version} = resource_history.version_id)"
+    );
+    (sql, vec![SqlValue::from(before.to_string())])
+}
+
 /// SQLite-based resource store.
 ///
 /// All writes go through a single connection (`conn`); SQLite allows only one
@@ -49,6 +256,19 @@ impl SqliteStore {
         CREATE INDEX IF NOT EXISTS idx_resources_type ON resources(resource_type);
         CREATE INDEX IF NOT EXISTS idx_history_type ON resource_history(resource_type);
         "#,
+        // v2 — each version's last-updated time, for type- and system-level
+        // `_history` ordered and filtered by it. A virtual column costs
+        // nothing to add; only the indexes store it.
+        r#"
+        ALTER TABLE resource_history ADD COLUMN last_updated TEXT
+            GENERATED ALWAYS AS (COALESCE(json_extract(value, '$.meta.lastUpdated'), '')) VIRTUAL;
+        CREATE INDEX IF NOT EXISTS idx_history_last_updated ON resource_history(last_updated);
+        CREATE INDEX IF NOT EXISTS idx_history_type_last_updated ON resource_history(resource_type, last_updated);
+        "#,
+        // v3 — deletions recorded as tombstone versions.
+        r#"
+        ALTER TABLE resource_history ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;
+        "#,
     ];
 
     /// Open the store (create if not exists)
@@ -115,8 +335,26 @@ impl SqliteStore {
             .unwrap_or_else(|poisoned| poisoned.into_inner())
     }
 
-    /// Get a resource
-    pub fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
+    /// Execute multiple operations atomically within an SQLite transaction.
+    /// The write lock is taken up front, so a read-then-write (a CAS) can't be
+    /// refused half-way by another connection writing the same database (the
+    /// search index, when it shares the file).
+    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&TransactionOps<'_>) -> Result<T>,
+    {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let ops = TransactionOps { tx: &tx };
+        let result = f(&ops)?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl ResourceStore for SqliteStore {
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
         let conn = self.reader();
 
         let mut stmt = conn.prepare(
@@ -131,8 +369,7 @@ impl SqliteStore {
         }
     }
 
-    /// Store a resource (current version)
-    pub fn put(&self, resource_type: &str, id: &str, data: &[u8]) -> Result<()> {
+    fn put(&self, resource_type: &str, id: &str, data: &[u8]) -> Result<()> {
         let value = std::str::from_utf8(data)
             .map_err(|e| crate::error::StoreError::Other(format!("Invalid UTF-8: {}", e)))?;
         let conn = self.conn();
@@ -145,8 +382,7 @@ impl SqliteStore {
         Ok(())
     }
 
-    /// Store a resource with version history
-    pub fn put_with_version(
+    fn put_with_version(
         &self,
         resource_type: &str,
         id: &str,
@@ -176,15 +412,7 @@ impl SqliteStore {
         Ok(())
     }
 
-    /// Compare-and-swap write: persist `data` as `new_version` only if the
-    /// resource's current stored version still matches `expected_current`
-    /// (`Some(v)` for an update of a resource last seen at version `v`, `None`
-    /// for a create that requires the resource to be absent). Returns `false`
-    /// without writing when the precondition fails — the caller maps that to a
-    /// 409/412. The read-compare-write happens under the single writer lock, so
-    /// two concurrent updates can no longer both bump to the same version and
-    /// clobber each other's history (a lost update).
-    pub fn put_with_version_cas(
+    fn put_with_version_cas(
         &self,
         resource_type: &str,
         id: &str,
@@ -192,48 +420,10 @@ impl SqliteStore {
         new_version: &str,
         data: &[u8],
     ) -> Result<bool> {
-        let value = std::str::from_utf8(data)
-            .map_err(|e| crate::error::StoreError::Other(format!("Invalid UTF-8: {}", e)))?;
-
-        let conn = self.conn();
-        let tx = conn.unchecked_transaction()?;
-
-        let current: Option<String> = tx
-            .query_row(
-                "SELECT json_extract(value, '$.meta.versionId') FROM resources \
-                 WHERE resource_type = ? AND id = ?",
-                params![resource_type, id],
-                |row| row.get::<_, Option<String>>(0),
-            )
-            .or_else(|e| match e {
-                rusqlite::Error::QueryReturnedNoRows => Ok(None),
-                other => Err(other),
-            })?;
-
-        let ok = match (expected_current, current.as_deref()) {
-            (None, None) => true,                       // create, still absent
-            (None, Some(_)) => false,                   // create, but now exists
-            (Some(exp), Some(cur)) => exp == cur,       // update, version unchanged
-            (Some(_), None) => false,                   // update, but vanished
-        };
-        if !ok {
-            return Ok(false);
-        }
-
-        tx.execute(
-            "INSERT OR REPLACE INTO resources (resource_type, id, value) VALUES (?, ?, ?)",
-            params![resource_type, id, value],
-        )?;
-        tx.execute(
-            "INSERT OR REPLACE INTO resource_history (resource_type, id, version_id, value) VALUES (?, ?, ?, ?)",
-            params![resource_type, id, new_version, value],
-        )?;
-        tx.commit()?;
-        Ok(true)
+        self.in_transaction(|ops| ops.put_with_version_cas(resource_type, id, expected_current, new_version, data))
     }
 
-    /// Get a specific version
-    pub fn get_version(
+    fn get_version(
         &self,
         resource_type: &str,
         id: &str,
@@ -242,7 +432,8 @@ impl SqliteStore {
         let conn = self.reader();
 
         let mut stmt = conn.prepare(
-            "SELECT value FROM resource_history WHERE resource_type = ? AND id = ? AND version_id = ?"
+            "SELECT value FROM resource_history \
+             WHERE resource_type = ? AND id = ? AND version_id = ? AND deleted = 0"
         )?;
         let result = stmt.query_row(params![resource_type, id, version_id], |row| row.get::<_, String>(0));
 
@@ -253,10 +444,7 @@ impl SqliteStore {
         }
     }
 
-    /// True if the resource has no current row but does have history — i.e. it
-    /// was deleted (a tombstone). Used to answer reads of deleted resources with
-    /// 410 Gone rather than 404 Not Found.
-    pub fn is_deleted(&self, resource_type: &str, id: &str) -> Result<bool> {
+    fn is_deleted(&self, resource_type: &str, id: &str) -> Result<bool> {
         let conn = self.reader();
         let current: i64 = conn.query_row(
             "SELECT COUNT(*) FROM resources WHERE resource_type = ? AND id = ?",
@@ -274,19 +462,20 @@ impl SqliteStore {
         Ok(history > 0)
     }
 
-    /// Delete a resource (current version only, history is preserved)
-    pub fn delete(&self, resource_type: &str, id: &str) -> Result<bool> {
-        let conn = self.conn();
-
-        let rows = conn.execute(
-            "DELETE FROM resources WHERE resource_type = ? AND id = ?",
-            params![resource_type, id]
-        )?;
-        Ok(rows > 0)
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool> {
+        self.in_transaction(|ops| {
+            ops.delete_with_version_cas(resource_type, id, expected_current, new_version, tombstone)
+        })
     }
 
-    /// List version history (list of version_ids)
-    pub fn list_versions(&self, resource_type: &str, id: &str) -> Result<Vec<String>> {
+    fn list_versions(&self, resource_type: &str, id: &str) -> Result<Vec<String>> {
         let conn = self.reader();
 
         // Order numerically: version_id is TEXT, so a lexical sort would put
@@ -306,8 +495,7 @@ impl SqliteStore {
         Ok(versions)
     }
 
-    /// Get resource counts by type
-    pub fn count_by_type(&self) -> Result<Vec<(String, i64)>> {
+    fn count_by_type(&self) -> Result<Vec<(String, i64)>> {
         let conn = self.reader();
         let mut stmt = conn.prepare(
             "SELECT resource_type, COUNT(*) FROM resources GROUP BY resource_type ORDER BY resource_type",
@@ -322,8 +510,7 @@ impl SqliteStore {
         Ok(counts)
     }
 
-    /// List all resources (optionally filtered by resource type)
-    pub fn list_all(&self, resource_type: Option<&str>) -> Result<Vec<(String, String, Vec<u8>)>> {
+    fn list_all(&self, resource_type: Option<&str>) -> Result<Vec<(String, String, Vec<u8>)>> {
         let conn = self.reader();
 
         let mut results = Vec::new();
@@ -363,12 +550,68 @@ impl SqliteStore {
         Ok(results)
     }
 
-    /// List resource IDs of a type (id column only).
-    ///
-    /// Used by search when no parameters are given: avoids loading every
-    /// resource body into memory just to extract IDs (the caller fetches only
-    /// the page it needs afterwards).
-    pub fn list_ids(&self, resource_type: &str) -> Result<Vec<String>> {
+    fn list_page(
+        &self,
+        resource_type: Option<&str>,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Vec<u8>)>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT resource_type, id, value FROM resources \
+             WHERE (?1 IS NULL OR resource_type = ?1) \
+             AND (?2 IS NULL OR (resource_type, id) > (?2, ?3)) \
+             ORDER BY resource_type, id LIMIT ?4",
+        )?;
+        let (after_type, after_id) = after.unzip();
+        let rows = stmt.query_map(params![resource_type, after_type, after_id, limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut results = Vec::new();
+        for row in rows {
+            let (rt, id, val) = row?;
+            results.push((rt, id, val.into_bytes()));
+        }
+        Ok(results)
+    }
+
+    fn list_last_updated_page(
+        &self,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Option<String>)>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT resource_type, id, json_extract(value, '$.meta.lastUpdated') FROM resources \
+             WHERE (?1 IS NULL OR (resource_type, id) > (?1, ?2)) \
+             ORDER BY resource_type, id LIMIT ?3",
+        )?;
+        let (after_type, after_id) = after.unzip();
+        let rows = stmt.query_map(params![after_type, after_id, limit as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        let mut results = Vec::new();
+        for row in rows {
+            results.push(row?);
+        }
+        Ok(results)
+    }
+
+    fn contains(&self, resource_type: &str, id: &str) -> Result<bool> {
+        let conn = self.reader();
+        let n: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM resources WHERE resource_type = ? AND id = ?",
+            params![resource_type, id],
+            |row| row.get(0),
+        )?;
+        Ok(n > 0)
+    }
+
+    fn list_ids(&self, resource_type: &str) -> Result<Vec<String>> {
         let conn = self.reader();
         let mut stmt = conn
             .prepare("SELECT id FROM resources WHERE resource_type = ? ORDER BY id")?;
@@ -380,10 +623,8 @@ impl SqliteStore {
         Ok(ids)
     }
 
-    /// List resources sorted by meta.lastUpdated descending with pagination.
-    /// Returns (entries as (id, value), total_count).
     #[allow(clippy::type_complexity)]
-    pub fn list_by_last_updated(
+    fn list_by_last_updated(
         &self,
         resource_type: &str,
         count: usize,
@@ -416,17 +657,93 @@ impl SqliteStore {
         Ok((entries, total))
     }
 
-    /// Execute multiple operations atomically within an SQLite transaction
-    pub fn in_transaction<F, T>(&self, f: F) -> Result<T>
-    where
-        F: FnOnce(&TransactionOps<'_>) -> Result<T>,
-    {
-        let mut conn = self.conn();
-        let tx = conn.transaction()?;
-        let ops = TransactionOps { tx: &tx };
-        let result = f(&ops)?;
-        tx.commit()?;
-        Ok(result)
+    fn history(&self, query: &HistoryQuery<'_>) -> Result<Vec<HistoryEntry>> {
+        let conn = self.reader();
+        let (sql, args) = history_query(query);
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(args.iter()), |row| {
+            Ok(HistoryEntry {
+                key: HistoryKey {
+                    resource_type: row.get(0)?,
+                    id: row.get(1)?,
+                    version_id: row.get(2)?,
+                    last_updated: row.get(3)?,
+                },
+                value: row.get::<_, String>(4)?.into_bytes(),
+                deleted: row.get(5)?,
+            })
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+        Ok(entries)
+    }
+
+    fn created_after(&self, resource_type: &str, time: &str) -> Result<Vec<String>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT h.id FROM resource_history h \
+             WHERE h.resource_type = ?1 AND h.last_updated > ?2 AND NOT EXISTS (SELECT 1 FROM resource_history o \
+             WHERE o.resource_type = h.resource_type AND o.id = h.id AND o.last_updated <= ?2)",
+        )?;
+        let rows = stmt.query_map(params![resource_type, time], |row| row.get::<_, String>(0))?;
+        let mut ids = Vec::new();
+        for id in rows {
+            ids.push(id?);
+        }
+        Ok(ids)
+    }
+
+    fn type_last_updated(&self, resource_type: &str) -> Result<Option<String>> {
+        let conn = self.reader();
+        Ok(conn.query_row(
+            "SELECT MAX(last_updated) FROM resource_history WHERE resource_type = ?",
+            params![resource_type],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn expunge(&self, resource_type: Option<&str>, id: Option<&str>, mode: ExpungeMode) -> Result<Expunged> {
+        let statements = expunge_statements(resource_type, id, mode, CURRENT_VERSION);
+        let args = || rusqlite::params_from_iter(statements.args.iter());
+        self.in_transaction(|ops| {
+            let resources: i64 = ops.tx.query_row(&statements.count, args(), |row| row.get(0))?;
+            let versions = ops.tx.execute(&statements.history, args())?;
+            if let Some(current) = &statements.current {
+                ops.tx.execute(current, args())?;
+            }
+            Ok(Expunged {
+                resources: resources as usize,
+                versions,
+            })
+        })
+    }
+
+    fn prune_history(&self, before: &str) -> Result<usize> {
+        let (sql, args) = prune_history_statement(before, CURRENT_VERSION);
+        let conn = self.conn();
+        Ok(conn.execute(&sql, rusqlite::params_from_iter(args.iter()))?)
+    }
+
+    fn transaction(&self, f: &mut dyn FnMut(&dyn ResourceTransaction) -> Result<()>) -> Result<()> {
+        self.in_transaction(|ops| f(ops))
+    }
+}
+
+/// The `meta.versionId` of a resource's current row (`None` when there is no
+/// row, or it has no version).
+fn current_version(conn: &Connection, resource_type: &str, id: &str) -> Result<Option<String>> {
+    let result = conn.query_row(
+        "SELECT json_extract(value, '$.meta.versionId') FROM resources \
+         WHERE resource_type = ? AND id = ?",
+        params![resource_type, id],
+        |row| row.get::<_, Option<String>>(0),
+    );
+    match result {
+        Ok(version) => Ok(version),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -436,9 +753,8 @@ pub struct TransactionOps<'a> {
 }
 
 #[allow(clippy::result_large_err)]
-impl<'a> TransactionOps<'a> {
-    /// Store a resource with version history
-    pub fn put_with_version(
+impl ResourceTransaction for TransactionOps<'_> {
+    fn put_with_version(
         &self,
         resource_type: &str,
         id: &str,
@@ -460,8 +776,42 @@ impl<'a> TransactionOps<'a> {
         Ok(())
     }
 
-    /// Get a resource
-    pub fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
+    fn put_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: Option<&str>,
+        new_version: &str,
+        data: &[u8],
+    ) -> Result<bool> {
+        let value = std::str::from_utf8(data)
+            .map_err(|e| crate::error::StoreError::Other(format!("Invalid UTF-8: {}", e)))?;
+
+        let conn = self.tx.deref();
+        let current = current_version(conn, resource_type, id)?;
+
+        let ok = match (expected_current, current.as_deref()) {
+            (None, None) => true,                       // create, still absent
+            (None, Some(_)) => false,                   // create, but now exists
+            (Some(exp), Some(cur)) => exp == cur,       // update, version unchanged
+            (Some(_), None) => false,                   // update, but vanished
+        };
+        if !ok {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "INSERT OR REPLACE INTO resources (resource_type, id, value) VALUES (?, ?, ?)",
+            params![resource_type, id, value],
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO resource_history (resource_type, id, version_id, value) VALUES (?, ?, ?, ?)",
+            params![resource_type, id, new_version, value],
+        )?;
+        Ok(true)
+    }
+
+    fn get(&self, resource_type: &str, id: &str) -> Result<Option<Vec<u8>>> {
         let conn = self.tx.deref();
         let mut stmt = conn.prepare(
             "SELECT value FROM resources WHERE resource_type = ? AND id = ?",
@@ -474,20 +824,55 @@ impl<'a> TransactionOps<'a> {
         }
     }
 
-    /// Delete a resource (current version only, history is preserved)
-    pub fn delete(&self, resource_type: &str, id: &str) -> Result<bool> {
+    fn delete_with_version_cas(
+        &self,
+        resource_type: &str,
+        id: &str,
+        expected_current: &str,
+        new_version: &str,
+        tombstone: &[u8],
+    ) -> Result<bool> {
+        let value = std::str::from_utf8(tombstone)
+            .map_err(|e| crate::error::StoreError::Other(format!("Invalid UTF-8: {}", e)))?;
         let conn = self.tx.deref();
-        let rows = conn.execute(
+        if current_version(conn, resource_type, id)?.as_deref() != Some(expected_current) {
+            return Ok(false);
+        }
+        conn.execute(
             "DELETE FROM resources WHERE resource_type = ? AND id = ?",
             params![resource_type, id],
         )?;
-        Ok(rows > 0)
+        conn.execute(
+            "INSERT OR REPLACE INTO resource_history (resource_type, id, version_id, value, deleted) \
+             VALUES (?, ?, ?, ?, 1)",
+            params![resource_type, id, new_version, value],
+        )?;
+        Ok(true)
+    }
+
+    fn latest_version(&self, resource_type: &str, id: &str) -> Result<Option<String>> {
+        let result = self.tx.query_row(
+            "SELECT version_id FROM resource_history WHERE resource_type = ? AND id = ? \
+             ORDER BY CAST(version_id AS INTEGER) DESC LIMIT 1",
+            params![resource_type, id],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(version) => Ok(Some(version)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn index(&self) -> Box<dyn IndexWrite + '_> {
+        Box::new(IndexWriter::new(self.tx))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::SearchBackend;
 
     #[test]
     fn test_put_and_get() {
@@ -550,14 +935,63 @@ mod tests {
     }
 
     #[test]
-    fn test_delete() {
+    fn test_delete_writes_tombstone() {
         let store = SqliteStore::open(":memory:").unwrap();
 
-        let data = br#"{"resourceType":"Patient","id":"123"}"#;
-        store.put("Patient", "123", data).unwrap();
+        let v1 = br#"{"resourceType":"Patient","id":"123","meta":{"versionId":"1"}}"#;
+        let v2 = br#"{"resourceType":"Patient","id":"123","meta":{"versionId":"2"}}"#;
+        store.put_with_version("Patient", "123", "1", v1).unwrap();
 
-        assert!(store.delete("Patient", "123").unwrap());
+        // Only from the version it was read at.
+        assert!(!store.delete_with_version_cas("Patient", "123", "7", "8", v2).unwrap());
+        assert!(store.delete_with_version_cas("Patient", "123", "1", "2", v2).unwrap());
+        assert!(!store.delete_with_version_cas("Patient", "123", "2", "3", v2).unwrap());
         assert_eq!(store.get("Patient", "123").unwrap(), None);
+        assert!(store.is_deleted("Patient", "123").unwrap());
+
+        // The tombstone is in history, but is no version to read.
+        assert_eq!(store.list_versions("Patient", "123").unwrap(), vec!["2", "1"]);
+        assert_eq!(store.get_version("Patient", "123", "2").unwrap(), None);
+        assert_eq!(store.get_version("Patient", "123", "1").unwrap(), Some(v1.to_vec()));
+        let history = store.history(&HistoryQuery { limit: 10, ..Default::default() }).unwrap();
+        assert_eq!(history.iter().map(|e| e.deleted).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(store.in_transaction(|ops| ops.latest_version("Patient", "123")).unwrap().as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_expunge_and_prune_history() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let version = |rt: &str, id: &str, v: &str, updated: &str| {
+            serde_json::to_vec(&serde_json::json!({"resourceType": rt, "id": id, "meta": {"versionId": v, "lastUpdated": updated}})).unwrap()
+        };
+        store.put_with_version("Patient", "a", "1", &version("Patient", "a", "1", "2024-01-01T00:00:00+00:00")).unwrap();
+        store.put_with_version("Patient", "b", "1", &version("Patient", "b", "1", "2024-02-01T00:00:00+00:00")).unwrap();
+        store.put_with_version("Group", "g", "1", &version("Group", "g", "1", "2024-02-15T00:00:00+00:00")).unwrap();
+        store.put_with_version("Patient", "a", "2", &version("Patient", "a", "2", "2024-03-01T00:00:00+00:00")).unwrap();
+        let tombstone = version("Patient", "b", "2", "2024-04-01T00:00:00+00:00");
+        assert!(store.delete_with_version_cas("Patient", "b", "1", "2", &tombstone).unwrap());
+        let keys = || -> Vec<String> {
+            let entries = store.history(&HistoryQuery { limit: 10, ..Default::default() }).unwrap();
+            entries.iter().map(|e| format!("{}/{}/{}", e.key.resource_type, e.key.id, e.key.version_id)).collect()
+        };
+        let expunged = |resources, versions| Expunged { resources, versions };
+
+        // The current version stays, a tombstone included.
+        assert_eq!(store.expunge(Some("Patient"), Some("a"), ExpungeMode::PreviousVersions).unwrap(), expunged(1, 1));
+        assert_eq!(store.expunge(None, None, ExpungeMode::PreviousVersions).unwrap(), expunged(1, 1));
+        assert_eq!(keys(), ["Patient/b/2", "Patient/a/2", "Group/g/1"]);
+
+        assert_eq!(store.expunge(Some("Patient"), None, ExpungeMode::DeletedResources).unwrap(), expunged(1, 1));
+        assert!(!store.is_deleted("Patient", "b").unwrap());
+        assert_eq!(store.expunge(Some("Group"), None, ExpungeMode::Everything).unwrap(), expunged(1, 1));
+        assert_eq!(store.get("Group", "g").unwrap(), None);
+        assert_eq!(keys(), ["Patient/a/2"]);
+
+        // Retention spares a stored resource's current version, however old.
+        store.put_with_version("Patient", "a", "3", &version("Patient", "a", "3", "2024-05-01T00:00:00+00:00")).unwrap();
+        assert_eq!(store.prune_history("2024-04-01T00:00:00+00:00").unwrap(), 1);
+        assert_eq!(store.prune_history("2025-01-01T00:00:00+00:00").unwrap(), 0);
+        assert_eq!(keys(), ["Patient/a/3"]);
     }
 
     #[test]
@@ -583,6 +1017,63 @@ mod tests {
         assert_eq!(empty.len(), 0);
     }
 
+    #[test]
+    fn test_history_since_at_and_paging() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        for (rt, id, v, updated) in [
+            ("Patient", "a", "1", "2024-01-01T00:00:00+00:00"),
+            ("Patient", "b", "1", "2024-02-01T00:00:00+00:00"),
+            ("Group", "g", "1", "2024-02-15T00:00:00+00:00"),
+            ("Patient", "a", "2", "2024-03-01T00:00:00.250+00:00"),
+        ] {
+            let body = format!(r#"{{"resourceType":"{rt}","id":"{id}","meta":{{"versionId":"{v}","lastUpdated":"{updated}"}}}}"#);
+            store.put_with_version(rt, id, v, body.as_bytes()).unwrap();
+        }
+        let keys = |query: HistoryQuery| -> Vec<String> {
+            let entries = store.history(&HistoryQuery { limit: 10, ..query }).unwrap();
+            entries.iter().map(|e| format!("{}/{}/{}", e.key.resource_type, e.key.id, e.key.version_id)).collect()
+        };
+
+        assert_eq!(keys(HistoryQuery::default()), ["Patient/a/2", "Group/g/1", "Patient/b/1", "Patient/a/1"]);
+        assert_eq!(keys(HistoryQuery { resource_type: Some("Patient"), ..Default::default() }), ["Patient/a/2", "Patient/b/1", "Patient/a/1"]);
+        assert_eq!(keys(HistoryQuery { resource_type: Some("Patient"), id: Some("a"), ..Default::default() }), ["Patient/a/2", "Patient/a/1"]);
+        assert_eq!(keys(HistoryQuery { since: Some("2024-02-01T00:00:00+00:00"), ..Default::default() }), ["Patient/a/2", "Group/g/1", "Patient/b/1"]);
+        assert_eq!(keys(HistoryQuery { since: Some("2024-03-01T00:00:00+00:00"), ..Default::default() }), ["Patient/a/2"]);
+        // At 20 Feb, `a` was still at version 1.
+        assert_eq!(keys(HistoryQuery { at: Some("2024-02-20T00:00:00+00:00"), ..Default::default() }), ["Group/g/1", "Patient/b/1", "Patient/a/1"]);
+
+        let first = store.history(&HistoryQuery { limit: 2, ..Default::default() }).unwrap();
+        assert_eq!(first.len(), 2);
+        let after = first.last().map(|e| e.key.clone());
+        assert_eq!(keys(HistoryQuery { after, ..Default::default() }), ["Patient/b/1", "Patient/a/1"]);
+
+        // Since 15 Jan, `b` was created; `a` was only updated.
+        assert_eq!(store.created_after("Patient", "2024-01-15T00:00:00+00:00").unwrap(), ["b"]);
+        assert!(store.created_after("Patient", "2024-02-01T00:00:00+00:00").unwrap().is_empty());
+        assert_eq!(store.type_last_updated("Patient").unwrap().as_deref(), Some("2024-03-01T00:00:00.250+00:00"));
+        assert_eq!(store.type_last_updated("Observation").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_page() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        for (rt, id) in [("Patient", "p1"), ("Patient", "p2"), ("Observation", "o1")] {
+            store.put(rt, id, b"{}").unwrap();
+        }
+        let keys = |page: Vec<(String, String, Vec<u8>)>| -> Vec<(String, String)> {
+            page.into_iter().map(|(rt, id, _)| (rt, id)).collect()
+        };
+
+        let first = keys(store.list_page(None, None, 2).unwrap());
+        assert_eq!(first, vec![("Observation".into(), "o1".into()), ("Patient".into(), "p1".into())]);
+        let rest = keys(store.list_page(None, Some(("Patient", "p1")), 2).unwrap());
+        assert_eq!(rest, vec![("Patient".into(), "p2".into())]);
+        assert!(store.list_page(None, Some(("Patient", "p2")), 2).unwrap().is_empty());
+
+        let patients = keys(store.list_page(Some("Patient"), Some(("Patient", "p1")), 10).unwrap());
+        assert_eq!(patients, vec![("Patient".into(), "p2".into())]);
+    }
+
     #[test]
     fn test_in_transaction_commit() {
         let store = SqliteStore::open(":memory:").unwrap();
@@ -617,6 +1108,49 @@ mod tests {
         assert!(store.get("Patient", "p1").unwrap().is_none());
     }
 
+    #[test]
+    fn test_in_transaction_writes_shared_index() {
+        let path = std::env::temp_dir().join(format!("sazare-shared-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path).unwrap();
+        let index = crate::SearchIndex::open_shared(&path).unwrap();
+
+        let d1 = br#"{"resourceType":"Patient","id":"p1","meta":{"versionId":"1"}}"#;
+        store
+            .in_transaction(|ops| {
+                ops.put_with_version("Patient", "p1", "1", d1)?;
+                ops.index().add_index("Patient", "p1", "family", "string", Some("Davis"), None)
+            })
+            .unwrap();
+        assert!(store.get("Patient", "p1").unwrap().is_some());
+        assert_eq!(index.ids_with_param("Patient", "family").unwrap(), vec!["p1".to_string()]);
+
+        // A failed write leaves neither the resource nor its entries.
+        let d2 = br#"{"resourceType":"Patient","id":"p2","meta":{"versionId":"1"}}"#;
+        let result: Result<()> = store.in_transaction(|ops| {
+            ops.put_with_version("Patient", "p2", "1", d2)?;
+            ops.index().add_index("Patient", "p2", "family", "string", Some("Evans"), None)?;
+            Err(crate::error::StoreError::Other("forced error".into()))
+        });
+        assert!(result.is_err());
+        assert!(store.get("Patient", "p2").unwrap().is_none());
+        assert_eq!(index.ids_with_param("Patient", "family").unwrap(), vec!["p1".to_string()]);
+
+        // The store keeps `user_version`; reopening migrates neither schema again.
+        drop(index);
+        drop(store);
+        let store = SqliteStore::open(&path).unwrap();
+        let index = crate::SearchIndex::open_shared(&path).unwrap();
+        assert!(store.get("Patient", "p1").unwrap().is_some());
+        assert_eq!(index.ids_with_param("Patient", "family").unwrap(), vec!["p1".to_string()]);
+        drop(index);
+        drop(store);
+
+        for ext in ["sqlite", "sqlite-wal", "sqlite-shm"] {
+            let _ = std::fs::remove_file(path.with_extension(ext));
+        }
+    }
+
     #[test]
     fn test_recovers_from_poisoned_lock() {
         let store = SqliteStore::open(":memory:").unwrap();